@@ -1,5 +1,9 @@
+use std::time::Duration;
+
 use clap::{Parser, ValueEnum};
 
+use crate::trace::{EvalPolicy, HostProfile};
+
 #[derive(Parser, Debug)]
 #[command(name = "envtrace")]
 #[command(
@@ -33,6 +37,33 @@ pub struct Args {
     #[arg(short, long)]
     pub context: Option<ContextArg>,
 
+    /// Shell whose startup files are traced (default: from $SHELL)
+    #[arg(long)]
+    pub shell: Option<ShellArg>,
+
+    /// Simulate another machine (e.g. os=darwin,arch=arm64,hostname=build01,user=ci)
+    #[arg(long, value_name = "KEY=VALUE,...")]
+    pub simulate: Option<HostProfile>,
+
+    /// Run command substitutions in assignments to resolve their values
+    /// (sandboxed: traced variables only, with a timeout)
+    #[arg(long)]
+    pub eval_commands: bool,
+
+    /// Also run these programs, with any arguments, with --eval-commands
+    /// (comma-separated; by default only read-only queries run)
+    #[arg(long, value_name = "PROGRAM,...", value_delimiter = ',')]
+    pub eval_allow: Option<Vec<String>>,
+
+    /// Never run these programs with --eval-commands, even read-only queries
+    /// (comma-separated)
+    #[arg(long, value_name = "PROGRAM,...", value_delimiter = ',')]
+    pub eval_deny: Option<Vec<String>>,
+
+    /// Seconds a command may run with --eval-commands
+    #[arg(long, value_name = "SECONDS", default_value_t = 2.0)]
+    pub eval_timeout: f64,
+
     /// Run environment sanity checks
     #[arg(long)]
     pub check: bool,
@@ -70,6 +101,15 @@ pub enum ContextArg {
     Launchd,
 }
 
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum ShellArg {
+    Bash,
+    Zsh,
+    Fish,
+    #[value(alias = "nushell")]
+    Nu,
+}
+
 impl Args {
     pub fn validate(&self) -> Result<(), String> {
         // Must provide a variable unless running --check
@@ -110,8 +150,25 @@ impl Args {
             return Err("--function --compare requires a function name".to_string());
         }
 
+        // The evaluation settings only apply with --eval-commands
+        if !self.eval_commands && (self.eval_allow.is_some() || self.eval_deny.is_some()) {
+            return Err("--eval-allow and --eval-deny require --eval-commands".to_string());
+        }
+        if !(self.eval_timeout.is_finite() && self.eval_timeout > 0.0) {
+            return Err("--eval-timeout must be a positive number of seconds".to_string());
+        }
+
         Ok(())
     }
+
+    /// The policy for running command substitutions, if enabled
+    pub fn eval_policy(&self) -> Option<EvalPolicy> {
+        self.eval_commands.then(|| EvalPolicy {
+            timeout: Duration::from_secs_f64(self.eval_timeout),
+            allow: self.eval_allow.clone().unwrap_or_default(),
+            deny: self.eval_deny.clone().unwrap_or_default(),
+        })
+    }
 }
 
 fn is_valid_identifier(s: &str) -> bool {
@@ -1,11 +1,11 @@
 use clap::Parser;
 
-use envtrace::cli::{Args, ContextArg, OutputFormat};
+use envtrace::cli::{Args, ContextArg, OutputFormat, ShellArg};
 use envtrace::output::{
     compare_function, compare_variable, format_function_trace, format_function_trace_json,
     format_trace, format_trace_json, run_checks,
 };
-use envtrace::platform::Platform;
+use envtrace::platform::{Platform, Shell};
 use envtrace::trace::{Context, TraceConfig, TraceEngine};
 
 fn main() {
@@ -22,6 +22,8 @@ fn main() {
     let config = TraceConfig {
         follow_sources: true,
         verbose: args.verbose,
+        host: args.simulate.clone().unwrap_or_default(),
+        eval: args.eval_policy(),
     };
     let mut engine = TraceEngine::new(platform).with_config(config);
 
@@ -32,11 +34,15 @@ fn main() {
 
     let var_name = args.variable.as_ref().unwrap();
 
-    // Determine context
-    let context = match args.context {
-        Some(ctx) => context_from_arg(ctx, platform),
-        None => Context::default_for_platform(),
+    // Determine shell and context
+    let shell = match args.shell {
+        Some(ShellArg::Bash) => Shell::Bash,
+        Some(ShellArg::Zsh) => Shell::Zsh,
+        Some(ShellArg::Fish) => Shell::Fish,
+        Some(ShellArg::Nu) => Shell::Nu,
+        None => Shell::detect(platform),
     };
+    let context = context_from_arg(args.context.unwrap_or(ContextArg::Login), platform, shell);
 
     if args.function {
         // Function tracing mode
@@ -65,7 +71,7 @@ fn main() {
         } else if let Some(ref contexts) = args.compare {
             print!(
                 "{}",
-                compare_function(&mut engine, var_name, contexts, platform)
+                compare_function(&mut engine, var_name, contexts, platform, shell)
             );
         } else {
             let trace = engine.trace_function(var_name, context);
@@ -101,7 +107,7 @@ fn main() {
         // --compare mode: show variable across multiple contexts
         print!(
             "{}",
-            compare_variable(&mut engine, var_name, contexts, platform)
+            compare_variable(&mut engine, var_name, contexts, platform, shell)
         );
     } else {
         // Standard trace mode
@@ -119,16 +125,32 @@ fn main() {
 }
 
 /// Convert CLI context argument to internal Context enum
-fn context_from_arg(arg: ContextArg, platform: Platform) -> Context {
-    match (arg, platform) {
-        (ContextArg::Login, Platform::MacOS) => Context::MacInteractiveLogin,
-        (ContextArg::Login, Platform::Linux) => Context::InteractiveLogin,
-        (ContextArg::Interactive, Platform::MacOS) => Context::MacInteractiveNonLogin,
-        (ContextArg::Interactive, Platform::Linux) => Context::InteractiveNonLogin,
-        (ContextArg::Cron, _) => Context::NonInteractiveNonLogin,
-        (ContextArg::Systemd, _) => Context::SystemdService,
-        (ContextArg::SystemdUser, _) => Context::SystemdUser,
-        (ContextArg::Uwsm, _) => Context::Uwsm,
-        (ContextArg::Launchd, _) => Context::LaunchdAgent,
+fn context_from_arg(arg: ContextArg, platform: Platform, shell: Shell) -> Context {
+    match (arg, platform, shell) {
+        (ContextArg::Login, _, Shell::Fish) => Context::FishInteractiveLogin,
+        (ContextArg::Interactive, _, Shell::Fish) => Context::FishInteractiveNonLogin,
+        (ContextArg::Login, _, Shell::Nu) => Context::NuInteractiveLogin,
+        (ContextArg::Interactive, _, Shell::Nu) => Context::NuInteractiveNonLogin,
+        (ContextArg::Login, Platform::MacOS, Shell::Zsh) => Context::MacInteractiveLogin,
+        (ContextArg::Login, Platform::MacOS, Shell::Bash) => Context::MacBashInteractiveLogin,
+        (ContextArg::Login, Platform::Linux, Shell::Zsh) => Context::ZshInteractiveLogin,
+        (ContextArg::Login, Platform::Linux, Shell::Bash) => Context::InteractiveLogin,
+        (ContextArg::Interactive, Platform::MacOS, Shell::Zsh) => Context::MacInteractiveNonLogin,
+        (ContextArg::Interactive, Platform::MacOS, Shell::Bash) => {
+            Context::MacBashInteractiveNonLogin
+        }
+        (ContextArg::Interactive, Platform::Linux, Shell::Zsh) => Context::ZshInteractiveNonLogin,
+        (ContextArg::Interactive, Platform::Linux, Shell::Bash) => Context::InteractiveNonLogin,
+        // A non-interactive shell of the chosen kind, as cron jobs and scripts run
+        (ContextArg::Cron, _, Shell::Fish) => Context::FishNonInteractive,
+        (ContextArg::Cron, _, Shell::Nu) => Context::NuNonInteractive,
+        (ContextArg::Cron, Platform::MacOS, Shell::Zsh) => Context::MacNonInteractive,
+        (ContextArg::Cron, Platform::MacOS, Shell::Bash) => Context::MacBashNonInteractive,
+        (ContextArg::Cron, Platform::Linux, Shell::Zsh) => Context::ZshNonInteractive,
+        (ContextArg::Cron, Platform::Linux, Shell::Bash) => Context::NonInteractiveNonLogin,
+        (ContextArg::Systemd, _, _) => Context::SystemdService,
+        (ContextArg::SystemdUser, _, _) => Context::SystemdUser,
+        (ContextArg::Uwsm, _, _) => Context::Uwsm,
+        (ContextArg::Launchd, _, _) => Context::LaunchdAgent,
     }
 }
@@ -2,7 +2,7 @@
 
 use tabled::{Table, Tabled};
 
-use crate::platform::Platform;
+use crate::platform::{Platform, Shell};
 use crate::trace::function::FunctionOperation;
 use crate::trace::{Context, TraceEngine};
 
@@ -20,10 +20,11 @@ pub fn compare_variable(
     var_name: &str,
     context_names: &[String],
     platform: Platform,
+    shell: Shell,
 ) -> String {
     let contexts: Vec<Context> = context_names
         .iter()
-        .filter_map(|name| parse_context_name(name, platform))
+        .filter_map(|name| parse_context_name(name, platform, shell))
         .collect();
 
     if contexts.is_empty() {
@@ -34,11 +35,32 @@ pub fn compare_variable(
 
     for context in contexts {
         let trace = engine.trace(var_name, context);
-        let value = trace.final_value.unwrap_or_else(|| "(not set)".to_string());
+        let values: Vec<String> = if trace.final_value.is_empty() {
+            vec!["(not set)".to_string()]
+        } else {
+            trace
+                .final_value
+                .iter()
+                .map(|candidate| {
+                    let value = candidate.value.as_deref().unwrap_or("(not set)");
+                    let value = super::truncate(value, 60);
+                    if candidate.conditions.is_empty() {
+                        value
+                    } else {
+                        format!("{} (when {})", value, candidate.conditions.join(" && "))
+                    }
+                })
+                .collect()
+        };
+
+        let mut value = values.join("\n");
+        if trace.exported == Some(false) {
+            value.push_str("\n(set but not exported)");
+        }
 
         rows.push(ContextRow {
             context: context.to_string(),
-            value: super::truncate(&value, 60),
+            value,
         });
     }
 
@@ -48,19 +70,38 @@ pub fn compare_variable(
 }
 
 /// Parse a context name string into a Context enum
-fn parse_context_name(name: &str, platform: Platform) -> Option<Context> {
-    match (name.to_lowercase().as_str(), platform) {
-        ("login", Platform::MacOS) => Some(Context::MacInteractiveLogin),
-        ("login", Platform::Linux) => Some(Context::InteractiveLogin),
-        ("interactive", Platform::MacOS) => Some(Context::MacInteractiveNonLogin),
-        ("interactive", Platform::Linux) => Some(Context::InteractiveNonLogin),
-        ("cron", _) => Some(Context::NonInteractiveNonLogin),
-        ("systemd", _) => Some(Context::SystemdService),
-        ("systemd-user", _) => Some(Context::SystemdUser),
-        ("uwsm", _) => Some(Context::Uwsm),
-        ("launchd", _) => Some(Context::LaunchdAgent),
-        ("noninteractive", Platform::MacOS) => Some(Context::MacNonInteractive),
-        ("noninteractive", Platform::Linux) => Some(Context::NonInteractiveNonLogin),
+fn parse_context_name(name: &str, platform: Platform, shell: Shell) -> Option<Context> {
+    match (name.to_lowercase().as_str(), platform, shell) {
+        ("login", _, Shell::Fish) => Some(Context::FishInteractiveLogin),
+        ("interactive", _, Shell::Fish) => Some(Context::FishInteractiveNonLogin),
+        ("noninteractive" | "cron", _, Shell::Fish) => Some(Context::FishNonInteractive),
+        ("login", _, Shell::Nu) => Some(Context::NuInteractiveLogin),
+        ("interactive", _, Shell::Nu) => Some(Context::NuInteractiveNonLogin),
+        ("noninteractive" | "cron", _, Shell::Nu) => Some(Context::NuNonInteractive),
+        ("login", Platform::MacOS, Shell::Zsh) => Some(Context::MacInteractiveLogin),
+        ("login", Platform::MacOS, Shell::Bash) => Some(Context::MacBashInteractiveLogin),
+        ("login", Platform::Linux, Shell::Zsh) => Some(Context::ZshInteractiveLogin),
+        ("login", Platform::Linux, Shell::Bash) => Some(Context::InteractiveLogin),
+        ("interactive", Platform::MacOS, Shell::Zsh) => Some(Context::MacInteractiveNonLogin),
+        ("interactive", Platform::MacOS, Shell::Bash) => Some(Context::MacBashInteractiveNonLogin),
+        ("interactive", Platform::Linux, Shell::Zsh) => Some(Context::ZshInteractiveNonLogin),
+        ("interactive", Platform::Linux, Shell::Bash) => Some(Context::InteractiveNonLogin),
+        ("systemd", _, _) => Some(Context::SystemdService),
+        ("systemd-user", _, _) => Some(Context::SystemdUser),
+        ("uwsm", _, _) => Some(Context::Uwsm),
+        ("launchd", _, _) => Some(Context::LaunchdAgent),
+        ("noninteractive" | "cron", Platform::MacOS, Shell::Zsh) => {
+            Some(Context::MacNonInteractive)
+        }
+        ("noninteractive" | "cron", Platform::MacOS, Shell::Bash) => {
+            Some(Context::MacBashNonInteractive)
+        }
+        ("noninteractive" | "cron", Platform::Linux, Shell::Zsh) => {
+            Some(Context::ZshNonInteractive)
+        }
+        ("noninteractive" | "cron", Platform::Linux, Shell::Bash) => {
+            Some(Context::NonInteractiveNonLogin)
+        }
         _ => None,
     }
 }
@@ -71,10 +112,11 @@ pub fn compare_function(
     func_name: &str,
     context_names: &[String],
     platform: Platform,
+    shell: Shell,
 ) -> String {
     let contexts: Vec<Context> = context_names
         .iter()
-        .filter_map(|name| parse_context_name(name, platform))
+        .filter_map(|name| parse_context_name(name, platform, shell))
         .collect();
 
     if contexts.is_empty() {
@@ -49,6 +49,14 @@ pub fn format_function_trace(trace: &FunctionTrace) -> String {
             // The actual line content
             output.push_str(&format!("    {}\n", change.line_content.dimmed()));
 
+            if !change.conditions.is_empty() {
+                output.push_str(&format!(
+                    "    {} {}\n",
+                    "when".cyan(),
+                    change.conditions.join(" && ")
+                ));
+            }
+
             // Effect description
             match change.operation {
                 FunctionOperation::Define => {
@@ -145,6 +153,7 @@ mod tests {
                 operation: FunctionOperation::Define,
                 body: Some("    echo hello\n}".to_string()),
                 body_lines: 3,
+                conditions: Vec::new(),
             }],
             context: Context::MacInteractiveLogin,
         };
@@ -169,6 +178,7 @@ mod tests {
                 operation: FunctionOperation::Autoload,
                 body: None,
                 body_lines: 0,
+                conditions: Vec::new(),
             }],
             context: Context::MacInteractiveLogin,
         };
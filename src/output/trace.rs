@@ -2,7 +2,9 @@
 
 use owo_colors::OwoColorize;
 
-use crate::trace::{Operation, VariableTrace};
+use crate::trace::{
+    Diagnostic, DiagnosticKind, Operation, ShellEnv, StopKind, StopPoint, VariableTrace,
+};
 
 /// Format a variable trace as human-readable text
 pub fn format_trace(trace: &VariableTrace) -> String {
@@ -14,7 +16,18 @@ pub fn format_trace(trace: &VariableTrace) -> String {
     // Header with current value
     output.push_str(&format!("{}", trace.name.bold()));
 
-    if let Some(ref value) = trace.final_value {
+    if trace.is_ambiguous() {
+        let count = if trace.truncated {
+            format!(
+                "(more than {} possible values; {} shown)",
+                ShellEnv::MAX_CANDIDATES,
+                trace.final_value.len()
+            )
+        } else {
+            format!("({} possible values)", trace.final_value.len())
+        };
+        output.push_str(&format!(" {}\n", count.yellow()));
+    } else if let Some(value) = trace.value() {
         output.push_str(&format!("={}\n", value.green()));
     } else {
         output.push_str(&format!(" {}\n", "(not set)".dimmed()));
@@ -23,7 +36,14 @@ pub fn format_trace(trace: &VariableTrace) -> String {
     output.push('\n');
 
     // Context info
-    output.push_str(&format!("TRACE ({}):\n", trace.context.to_string().cyan()));
+    match trace.simulated {
+        Some(ref host) => output.push_str(&format!(
+            "TRACE ({}, simulating {}):\n",
+            trace.context.to_string().cyan(),
+            host.to_string().cyan()
+        )),
+        None => output.push_str(&format!("TRACE ({}):\n", trace.context.to_string().cyan())),
+    }
     output.push_str(&format!("{}\n\n", "━".repeat(60).dimmed()));
 
     if trace.changes.is_empty() {
@@ -32,7 +52,7 @@ pub fn format_trace(trace: &VariableTrace) -> String {
             "No modifications found in config files.".dimmed()
         ));
 
-        if trace.final_value.is_some() {
+        if !trace.final_value.is_empty() {
             output.push_str(&format!(
                 "{}\n",
                 "Value may be inherited from parent process or set by the system.".dimmed()
@@ -56,6 +76,30 @@ pub fn format_trace(trace: &VariableTrace) -> String {
             // The actual line content
             output.push_str(&format!("    {}\n", change.line_content.dimmed()));
 
+            if let Some(ref function) = change.function {
+                let defined_in = function.file.to_string_lossy().replace(&home_prefix, "~");
+                output.push_str(&format!(
+                    "    {}\n",
+                    format!(
+                        "via {}() defined at {}:{}",
+                        function.name, defined_in, function.line_number
+                    )
+                    .dimmed()
+                ));
+            }
+
+            if let Some(ref tool) = change.emulated {
+                output.push_str(&format!("    {}\n", format!("emulates {}", tool).dimmed()));
+            }
+
+            if !change.conditions.is_empty() {
+                output.push_str(&format!(
+                    "    {} {}\n",
+                    "when".cyan(),
+                    change.conditions.join(" && ")
+                ));
+            }
+
             // Effect description
             let effect = describe_effect(
                 change.operation,
@@ -64,18 +108,161 @@ pub fn format_trace(trace: &VariableTrace) -> String {
             );
             output.push_str(&format!("    {} {}\n", "→".green(), effect));
 
+            if !change.evaluated.is_empty() {
+                output.push_str(&format!(
+                    "    {} {}\n",
+                    "evaluated:".cyan(),
+                    change.evaluated.join(", ")
+                ));
+            }
+
+            if !change.unresolved.is_empty() {
+                output.push_str(&format!(
+                    "    {} {}\n",
+                    "unresolved:".yellow(),
+                    change.unresolved.join(", ")
+                ));
+            }
+
             output.push('\n');
         }
     }
 
+    // Where reading stopped early
+    for stop in &trace.stops {
+        let file_display = stop.file.to_string_lossy().replace(&home_prefix, "~");
+        output.push_str(&format!(
+            "{} {}:{}\n",
+            "[stop]".red(),
+            file_display.blue(),
+            stop.line_number
+        ));
+        output.push_str(&format!("    {}\n", stop.line_content.dimmed()));
+        if !stop.conditions.is_empty() {
+            output.push_str(&format!(
+                "    {} {}\n",
+                "when".cyan(),
+                stop.conditions.join(" && ")
+            ));
+        }
+        output.push_str(&format!("    {} {}\n\n", "→".red(), describe_stop(stop)));
+    }
+
+    // Lines that couldn't be followed
+    for diagnostic in &trace.diagnostics {
+        let file_display = diagnostic.file.to_string_lossy().replace(&home_prefix, "~");
+        output.push_str(&format!(
+            "{} {}:{}\n",
+            "[!]".yellow(),
+            file_display.blue(),
+            diagnostic.line_number
+        ));
+        output.push_str(&format!("    {}\n", diagnostic.line_content.dimmed()));
+        output.push_str(&format!(
+            "    {} {}\n\n",
+            "→".yellow(),
+            describe_diagnostic(diagnostic)
+        ));
+    }
+
     // Final value
-    if let Some(ref value) = trace.final_value {
+    if trace.is_ambiguous() {
+        output.push_str(&format!(
+            "{} {}\n",
+            "FINAL:".bold(),
+            "depends on conditions that couldn't be decided".yellow()
+        ));
+        for candidate in &trace.final_value {
+            match candidate.value {
+                Some(ref value) => output.push_str(&format!("  • {}\n", value.green())),
+                None => output.push_str(&format!("  • {}\n", "(not set)".dimmed())),
+            }
+            if !candidate.conditions.is_empty() {
+                output.push_str(&format!(
+                    "    {} {}\n",
+                    "when".cyan(),
+                    candidate.conditions.join(" && ")
+                ));
+            }
+        }
+    } else if let Some(value) = trace.value() {
         output.push_str(&format!("{} {}\n", "FINAL:".bold(), value.green()));
     }
 
+    match trace.exported {
+        Some(true) => output.push_str(&format!("{} {}\n", "EXPORTED:".bold(), "yes".green())),
+        Some(false) => output.push_str(&format!(
+            "{} {}\n",
+            "EXPORTED:".bold(),
+            "no (set but not exported; programs started from the shell won't see it)".yellow()
+        )),
+        None => {}
+    }
+
     output
 }
 
+/// Describe what a return/exit/exec does to the rest of the startup sequence
+fn describe_stop(stop: &StopPoint) -> String {
+    let may = if stop.conditional { "may " } else { "" };
+    match stop.kind {
+        StopKind::Return => {
+            let file = stop.file.file_name().unwrap_or_default().to_string_lossy();
+            let verb = if stop.conditional { "stop" } else { "stops" };
+            format!("{}{} reading {}", may, verb, file)
+        }
+        StopKind::Exit => {
+            let verb = if stop.conditional { "exit" } else { "exits" };
+            format!("{}{} the shell; later files are not read", may, verb)
+        }
+        StopKind::Exec => {
+            let verb = if stop.conditional {
+                "replace"
+            } else {
+                "replaces"
+            };
+            format!("{}{} the shell; later files are not read", may, verb)
+        }
+    }
+}
+
+/// Describe why a line couldn't be followed
+fn describe_diagnostic(diagnostic: &Diagnostic) -> String {
+    match diagnostic.kind {
+        DiagnosticKind::UnresolvedSource => format!(
+            "not followed: source path depends on {}",
+            diagnostic.unresolved.join(", ")
+        ),
+        DiagnosticKind::SourcedCommandOutput => {
+            "not followed: sources the output of a command".to_string()
+        }
+        DiagnosticKind::DynamicEval => format!(
+            "not analyzed: eval runs code that depends on {}",
+            diagnostic.unresolved.join(", ")
+        ),
+        DiagnosticKind::DynamicDeclaration => format!(
+            "not analyzed: the variables set depend on {}",
+            diagnostic.unresolved.join(", ")
+        ),
+        DiagnosticKind::DynamicPositional => format!(
+            "not analyzed: positional parameters set from {}",
+            diagnostic.unresolved.join(", ")
+        ),
+        DiagnosticKind::ClearedEnvironment => {
+            "not analyzed: runs a command with an empty environment".to_string()
+        }
+        DiagnosticKind::RuntimeValue => "not analyzed: value read at runtime".to_string(),
+        DiagnosticKind::ReadonlyAssignment => format!(
+            "not applied: {} is readonly",
+            diagnostic.unresolved.join(", ")
+        ),
+        DiagnosticKind::RecursiveCall => format!(
+            "not followed: recursive call to {}",
+            diagnostic.unresolved.join(", ")
+        ),
+    }
+}
+
 /// Describe the effect of an operation
 fn describe_effect(operation: Operation, value_after: &str, value_before: Option<&str>) -> String {
     match operation {
@@ -108,6 +295,7 @@ fn describe_effect(operation: Operation, value_after: &str, value_before: Option
             "prepends to value".to_string()
         }
         Operation::Unset => "unsets the variable".to_string(),
+        Operation::Unexport => "stops exporting the variable".to_string(),
         Operation::Conditional => {
             format!(
                 "conditionally sets to \"{}\"",
@@ -125,16 +313,21 @@ pub fn format_trace_json(trace: &VariableTrace) -> String {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::trace::{Context, VariableChange};
+    use crate::trace::{Context, ValueCandidate, VariableChange};
     use std::path::PathBuf;
 
     #[test]
     fn test_format_trace_empty() {
         let trace = VariableTrace {
             name: "TEST".to_string(),
-            final_value: None,
+            final_value: vec![],
+            truncated: false,
             changes: vec![],
             context: Context::MacInteractiveLogin,
+            stops: vec![],
+            diagnostics: vec![],
+            exported: None,
+            simulated: None,
         };
 
         let output = format_trace(&trace);
@@ -147,7 +340,11 @@ mod tests {
     fn test_format_trace_with_changes() {
         let trace = VariableTrace {
             name: "PATH".to_string(),
-            final_value: Some("/usr/local/bin:/usr/bin".to_string()),
+            final_value: vec![ValueCandidate {
+                value: Some("/usr/local/bin:/usr/bin".to_string()),
+                conditions: vec![],
+            }],
+            truncated: false,
             changes: vec![
                 VariableChange {
                     file: PathBuf::from("/etc/zprofile"),
@@ -156,6 +353,11 @@ mod tests {
                     operation: Operation::Export,
                     value_before: None,
                     value_after: "/usr/bin".to_string(),
+                    unresolved: Vec::new(),
+                    evaluated: Vec::new(),
+                    conditions: Vec::new(),
+                    function: None,
+                    emulated: None,
                 },
                 VariableChange {
                     file: PathBuf::from("/Users/test/.zshrc"),
@@ -164,9 +366,18 @@ mod tests {
                     operation: Operation::Prepend,
                     value_before: Some("/usr/bin".to_string()),
                     value_after: "/usr/local/bin:/usr/bin".to_string(),
+                    unresolved: Vec::new(),
+                    evaluated: Vec::new(),
+                    conditions: Vec::new(),
+                    function: None,
+                    emulated: None,
                 },
             ],
             context: Context::MacInteractiveLogin,
+            stops: vec![],
+            diagnostics: vec![],
+            exported: None,
+            simulated: None,
         };
 
         let output = format_trace(&trace);
@@ -181,13 +392,62 @@ mod tests {
     fn test_format_trace_json() {
         let trace = VariableTrace {
             name: "TEST".to_string(),
-            final_value: Some("value".to_string()),
+            final_value: vec![ValueCandidate {
+                value: Some("value".to_string()),
+                conditions: vec![],
+            }],
+            truncated: false,
             changes: vec![],
             context: Context::MacInteractiveLogin,
+            stops: vec![],
+            diagnostics: vec![],
+            exported: None,
+            simulated: None,
         };
 
         let json = format_trace_json(&trace);
         assert!(json.contains("\"name\": \"TEST\""));
-        assert!(json.contains("\"final_value\": \"value\""));
+        assert!(json.contains("\"value\": \"value\""));
+    }
+
+    #[test]
+    fn test_format_trace_candidates() {
+        let trace = VariableTrace {
+            name: "EDITOR".to_string(),
+            final_value: vec![
+                ValueCandidate {
+                    value: Some("vi".to_string()),
+                    conditions: vec!["! command -v nvim".to_string()],
+                },
+                ValueCandidate {
+                    value: Some("nvim".to_string()),
+                    conditions: vec!["command -v nvim".to_string()],
+                },
+            ],
+            truncated: false,
+            changes: vec![],
+            context: Context::InteractiveLogin,
+            stops: vec![],
+            diagnostics: vec![],
+            exported: None,
+            simulated: None,
+        };
+
+        let output = format_trace(&trace);
+        assert!(output.contains("2 possible values"));
+        assert!(output.contains("nvim"));
+        assert!(output.contains("! command -v nvim"));
+
+        let json = format_trace_json(&trace);
+        assert!(json.contains("\"conditions\": ["));
+        assert!(json.contains("\"truncated\": false"));
+
+        let trace = VariableTrace {
+            truncated: true,
+            ..trace
+        };
+        let output = format_trace(&trace);
+        assert!(output.contains("more than 32 possible values; 2 shown"));
+        assert!(format_trace_json(&trace).contains("\"truncated\": true"));
     }
 }
@@ -1,34 +1,434 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-/// Strip surrounding quotes from a value
-pub fn strip_quotes(value: &str) -> String {
-    let value = value.trim();
-    if (value.starts_with('"') && value.ends_with('"'))
-        || (value.starts_with('\'') && value.ends_with('\''))
+use super::condition::Truth;
+use super::expand::{Lookup, VarLookup, expand_argument};
+use super::glob::{path_command_output, resolve_path};
+use super::lexer::Word;
+use crate::trace::{ShellEnv, StopKind};
+
+/// The home directory for `~` (empty user) or `~user`, looked up in passwd
+pub fn home_dir_of(user: &str) -> Option<PathBuf> {
+    if user.is_empty() {
+        return dirs::home_dir();
+    }
+    #[cfg(unix)]
     {
-        value[1..value.len() - 1].to_string()
-    } else {
-        value.to_string()
+        nix::unistd::User::from_name(user)
+            .ok()
+            .flatten()
+            .map(|u| u.dir)
+    }
+    #[cfg(not(unix))]
+    {
+        None
+    }
+}
+
+/// The conditions a file is read under: the `&&`/`||` guards and enclosing
+/// branches of the `source` command that reads it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Guard {
+    /// Every enclosing condition, outermost first
+    pub conditions: Vec<String>,
+    /// The conditions that couldn't be decided statically
+    pub undecided: Vec<String>,
+}
+
+/// The negation of a condition's text
+pub fn negate(condition: &str) -> String {
+    match condition.strip_prefix("! ") {
+        Some(inner) => inner
+            .strip_prefix('(')
+            .and_then(|i| i.strip_suffix(')'))
+            .unwrap_or(inner)
+            .to_string(),
+        None => format!("! {}", condition),
     }
 }
 
-/// Expand ~ in source paths and strip quotes
-pub fn expand_source_path(path: &str) -> Option<PathBuf> {
-    let path = strip_quotes(path);
+/// Why the current path stopped running statements
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stopped {
+    /// `return`, `exit` or `exec`
+    File(StopKind),
+    /// `break` or `continue`, which the enclosing loop picks up
+    Loop,
+}
+
+/// The paths that left a loop with `break` and those that went on to its
+/// next iteration with `continue`
+type LoopPaths = (Vec<(Vec<String>, ShellEnv)>, Vec<(Vec<String>, ShellEnv)>);
+
+/// Where a walk through a file is: the branches it's in and whether the
+/// current path stopped reading the file
+#[derive(Debug, Default)]
+pub struct PathState {
+    /// Conditions of the enclosing branches, outermost first
+    pub conditions: Vec<String>,
+    /// The enclosing conditions that couldn't be decided, which the possible
+    /// values of variables assigned here depend on
+    pub path_conditions: Vec<String>,
+    /// Number of enclosing branches that can't be decided statically
+    pub undecided: usize,
+    /// Set when the current path stopped reading the file or left a loop
+    pub stopped: Option<Stopped>,
+    /// Paths that returned from the file (or function) early, with their conditions
+    pub returns: Vec<(Vec<String>, ShellEnv)>,
+    /// Paths that left the innermost loop, and those that went on to its
+    /// next iteration
+    pub loop_exits: LoopPaths,
+    /// Number of enclosing loops
+    pub loops: usize,
+}
+
+impl PathState {
+    /// The state at the start of a file read under `guard`
+    pub fn new(guard: &Guard) -> Self {
+        Self {
+            conditions: guard.conditions.clone(),
+            path_conditions: guard.undecided.clone(),
+            undecided: usize::from(!guard.undecided.is_empty()),
+            ..Default::default()
+        }
+    }
 
-    if let Some(rest) = path.strip_prefix('~') {
-        if let Some(home) = dirs::home_dir()
-            && (rest.is_empty() || rest.starts_with('/'))
-        {
-            return Some(home.join(rest.trim_start_matches('/')));
+    /// Continue the rest of the enclosing block assuming `condition`, because
+    /// the paths where it doesn't hold stopped reading the file
+    pub fn assume(&mut self, condition: String) {
+        self.conditions.push(condition.clone());
+        self.path_conditions.push(condition);
+        self.undecided += 1;
+    }
+
+    /// Stop reading the file on the current path: a `return` is merged back
+    /// in by [`Self::finish_returns`], an `exit` ends the shell
+    pub fn stop(&mut self, kind: StopKind, env: &mut ShellEnv) {
+        match kind {
+            StopKind::Return => self
+                .returns
+                .push((self.path_conditions.clone(), env.clone())),
+            StopKind::Exit | StopKind::Exec => env.exit(self.path_conditions.clone()),
         }
-        return None; // Can't expand ~user
+        self.stopped = Some(Stopped::File(kind));
     }
 
-    // Skip paths with unexpanded variables
-    if path.contains('$') {
+    /// Start walking a loop, returning the exits of the enclosing one
+    pub fn enter_loop(&mut self) -> LoopPaths {
+        self.loops += 1;
+        std::mem::take(&mut self.loop_exits)
+    }
+
+    /// Leave the innermost loop on the current path (`break`), or go on to
+    /// its next iteration (`continue`). Outside loops they do nothing.
+    pub fn leave_loop(&mut self, next_iteration: bool, env: &ShellEnv) {
+        if self.loops == 0 {
+            return;
+        }
+        let path = (self.path_conditions.clone(), env.clone());
+        if next_iteration {
+            self.loop_exits.1.push(path);
+        } else {
+            self.loop_exits.0.push(path);
+        }
+        self.stopped = Some(Stopped::Loop);
+    }
+
+    /// After a loop body: the paths that continued carry on, with the one
+    /// that reached the end of the body
+    pub fn next_iteration(&mut self, env: &mut ShellEnv) {
+        let mut paths = std::mem::take(&mut self.loop_exits.1);
+        if paths.is_empty() {
+            return;
+        }
+        if self.stopped.is_none() {
+            paths.push((self.path_conditions.clone(), env.clone()));
+        }
+        if paths.len() > 1 {
+            self.undecided += 1;
+        }
+        env.merge(paths);
+        self.stopped = None;
+    }
+
+    /// After a loop: the paths that broke out of it carry on, with the one
+    /// that finished it
+    pub fn exit_loop(&mut self, env: &mut ShellEnv, outer: LoopPaths) {
+        let mut paths = std::mem::replace(&mut self.loop_exits, outer).0;
+        self.loops -= 1;
+        if paths.is_empty() {
+            return;
+        }
+        if self.stopped.is_none() {
+            paths.push((self.path_conditions.clone(), env.clone()));
+        }
+        if paths.len() > 1 {
+            self.undecided += 1;
+        }
+        env.merge(paths);
+        self.stopped = None;
+    }
+
+    /// Combine the paths that returned early with the one that reached the
+    /// end of the file or function body
+    pub fn finish_returns(&mut self, env: &mut ShellEnv) {
+        let mut paths = std::mem::take(&mut self.returns);
+        if paths.is_empty() {
+            return;
+        }
+        if self.stopped.is_none() {
+            paths.push((self.path_conditions.clone(), env.clone()));
+        }
+        env.merge(paths);
+        // The paths that returned carry on
+        self.stopped = None;
+    }
+}
+
+/// A branch of an `if`, `case` or `switch` to walk
+pub struct BranchWalk<'s, B: ?Sized> {
+    /// Conditions under which the branch runs (the last one is its own test)
+    pub conditions: Vec<String>,
+    /// The `if`/`elif` test, which runs before the branch is chosen
+    pub test: Option<&'s B>,
+    /// Whether the branch matches, for branches without a test
+    pub matched: Truth,
+    pub body: &'s B,
+}
+
+/// A walker that follows the branches of compound commands
+pub trait Branching {
+    /// The statements of a branch body or test
+    type Block: ?Sized;
+
+    fn state(&mut self) -> &mut PathState;
+
+    fn env(&mut self) -> &mut ShellEnv;
+
+    /// Walk statements in order; returns the exit status of the last one
+    fn walk_block(&mut self, block: &Self::Block) -> Truth;
+
+    /// Walk the branches of an `if`, `case` or `switch`
+    ///
+    /// Branches are tried in order. A branch whose condition is decided
+    /// false is skipped, and one decided true ends the search. Branches that
+    /// can't be decided are all walked, each from the environment as it was
+    /// before the compound command, and their changes are recorded as
+    /// conditional. Afterwards the environments of all possible paths are
+    /// merged, so variables carry every value they may have.
+    fn branches(&mut self, branches: Vec<BranchWalk<'_, Self::Block>>) -> Truth {
+        let before = self.env().clone();
+        let undecided = self.state().undecided;
+        let outer = self.state().path_conditions.len();
+        // Whether every earlier branch was skipped
+        let mut reachable = Truth::True;
+        let mut walked = false;
+        let mut status = Truth::True;
+        // Negations of the earlier branches that couldn't be decided
+        let mut failed = Vec::new();
+        let mut paths = Vec::new();
+        // How the last branch that stopped reading the file did so
+        let mut stopped = None;
+
+        for branch in branches {
+            if reachable == Truth::False {
+                break;
+            }
+            if walked {
+                self.env().resume(before.clone());
+            }
+            let depth = self.state().conditions.len();
+            let (own, earlier) = branch.conditions.split_last().unwrap();
+            // The test itself runs after the earlier tests have failed
+            let state = self.state();
+            state.conditions.extend(earlier.iter().cloned());
+            if reachable == Truth::Unknown {
+                state.undecided += 1;
+            }
+            let matched = match branch.test {
+                Some(test) => self.walk_block(test),
+                None => branch.matched,
+            };
+            let taken = reachable.and(matched);
+            if taken != Truth::False {
+                let state = self.state();
+                if taken == Truth::Unknown {
+                    state.undecided += 1;
+                }
+                state.conditions.push(own.clone());
+                state.path_conditions.extend(failed.iter().cloned());
+                if matched == Truth::Unknown {
+                    state.path_conditions.push(own.clone());
+                }
+                let result = self.walk_block(branch.body);
+                status = if taken == Truth::True {
+                    result
+                } else {
+                    Truth::Unknown
+                };
+                walked = true;
+                match self.state().stopped.take() {
+                    Some(kind) => stopped = Some(kind),
+                    None => {
+                        let conditions = self.state().path_conditions.clone();
+                        paths.push((conditions, self.env().clone()));
+                    }
+                }
+                self.state().path_conditions.truncate(outer);
+            }
+            let state = self.state();
+            state.conditions.truncate(depth);
+            state.undecided = undecided;
+            if matched == Truth::Unknown {
+                failed.push(negate(own));
+            }
+            reachable = reachable.and(matched.not());
+        }
+        if reachable == Truth::Unknown {
+            // Possibly no branch ran at all
+            status = Truth::Unknown;
+            let mut skipped = self.state().path_conditions.clone();
+            skipped.extend(failed);
+            paths.insert(0, (skipped, before.clone()));
+        }
+        if stopped.is_none() {
+            if paths.len() > 1 {
+                self.env().merge(paths);
+            }
+            return status;
+        }
+
+        // Some paths stopped reading the file; the rest go on
+        match paths.len() {
+            0 => self.state().stopped = stopped,
+            1 => {
+                let (conditions, env) = paths.pop().unwrap();
+                self.env().resume(env);
+                for condition in conditions.into_iter().skip(outer) {
+                    self.state().assume(condition);
+                }
+            }
+            _ => {
+                self.env().merge(paths);
+                self.state().undecided += 1;
+            }
+        }
+        status
+    }
+}
+
+/// Quote an argument for `sh` if it has special characters
+pub fn shell_quote(arg: &str) -> String {
+    let plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:=+,@%".contains(c));
+    if plain {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+/// A file read with `source` or `.`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourcedFile {
+    pub path: PathBuf,
+    pub guard: Guard,
+}
+
+/// The operands of a `source`/`.` command, looking through `builtin` and
+/// `command` prefixes (`builtin source x`, `command . x`)
+pub fn source_arguments(words: &[Word]) -> Option<&[Word]> {
+    let (name, rest) = words.split_first()?;
+    match name.as_literal()?.as_str() {
+        "." | "source" => Some(rest),
+        "builtin" => source_arguments(rest),
+        // `command -v .` looks the command up instead of running it
+        "command" if rest.first()?.as_literal()?.starts_with('-') => None,
+        "command" => source_arguments(rest),
+        _ => None,
+    }
+}
+
+/// Returns true if the file is read by zsh, where `$0` names the file being
+/// sourced (in bash it's the shell's own name)
+pub fn is_zsh_file(path: &Path) -> bool {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().trim_start_matches('.').to_string())
+        .unwrap_or_default();
+    let startup = ["zshenv", "zprofile", "zshrc", "zlogin", "zlogout"];
+    path.extension().is_some_and(|ext| ext == "zsh")
+        || startup.iter().any(|file| name.starts_with(file))
+        || path
+            .components()
+            .any(|c| c.as_os_str().to_string_lossy().contains("zsh"))
+}
+
+/// Resolve the argument of a source/. command in `script` to a path without
+/// traced variables: only the script's own path (`${BASH_SOURCE[0]}`, `$0`
+/// in zsh) and HOME are known, and relative paths are read from HOME
+pub fn source_path(word: &Word, script: &Path) -> Option<PathBuf> {
+    let lookup = ScriptLookup { script };
+    let expansion = expand_argument(word, &lookup);
+    if !expansion.unresolved.is_empty() {
         return None;
     }
+    let home = dirs::home_dir()?;
+    Some(PathBuf::from(resolve_path(
+        &home.to_string_lossy(),
+        &expansion.value,
+    )))
+}
 
-    Some(PathBuf::from(path))
+/// Variables known while reading a script without tracing it
+struct ScriptLookup<'a> {
+    script: &'a Path,
+}
+
+impl VarLookup for ScriptLookup<'_> {
+    fn lookup(&self, name: &str) -> Lookup {
+        let known = match name {
+            "BASH_SOURCE" => Some(self.script.to_path_buf()),
+            "0" if is_zsh_file(self.script) => Some(self.script.to_path_buf()),
+            "HOME" | "PWD" => dirs::home_dir(),
+            _ => None,
+        };
+        match known {
+            Some(path) => Lookup::Set(path.to_string_lossy().to_string()),
+            None => Lookup::Unknown,
+        }
+    }
+
+    fn command_output(&self, command: &str) -> Option<String> {
+        path_command_output(command, self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::lexer::lex_operand;
+
+    #[test]
+    fn test_source_path() {
+        let home = dirs::home_dir().unwrap();
+        let script = Path::new("/etc/zsh/zshrc");
+        let source = |text: &str| source_path(&lex_operand(text), script);
+        assert_eq!(source("~/.aliases"), Some(home.join(".aliases")));
+        assert_eq!(
+            source("~root/.profile"),
+            Some(PathBuf::from("/root/.profile"))
+        );
+        assert_eq!(source("./local.sh"), Some(home.join("local.sh")));
+        assert_eq!(
+            source(r#""$(dirname "${BASH_SOURCE[0]}")/lib.sh""#),
+            Some(PathBuf::from("/etc/zsh/lib.sh"))
+        );
+        assert_eq!(
+            source("${0:h}/aliases.zsh"),
+            Some(PathBuf::from("/etc/zsh/aliases.zsh"))
+        );
+        assert_eq!(source("$NVM_DIR/nvm.sh"), None);
+    }
 }
@@ -0,0 +1,508 @@
+//! Static evaluation of branch conditions
+//!
+//! Decides the common tests found in startup files (`[ -d dir ]`,
+//! `[[ $- == *i* ]]`, `command -v tool`, ...) from the traced environment,
+//! the shell's mode and the real filesystem. Anything that depends on
+//! runtime state, or on the files of a simulated machine, is
+//! [`Truth::Unknown`].
+
+use std::path::PathBuf;
+
+use super::expand::{Lookup, VarLookup, expand_argument, word_pattern};
+use super::lexer::Word;
+
+/// The outcome of a condition
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Truth {
+    True,
+    False,
+    /// Can't be decided without running the shell
+    Unknown,
+}
+
+impl Truth {
+    pub fn from_bool(value: bool) -> Self {
+        if value { Truth::True } else { Truth::False }
+    }
+
+    pub fn not(self) -> Self {
+        match self {
+            Truth::True => Truth::False,
+            Truth::False => Truth::True,
+            Truth::Unknown => Truth::Unknown,
+        }
+    }
+
+    pub fn and(self, other: Truth) -> Self {
+        match (self, other) {
+            (Truth::False, _) | (_, Truth::False) => Truth::False,
+            (Truth::True, Truth::True) => Truth::True,
+            _ => Truth::Unknown,
+        }
+    }
+
+    pub fn or(self, other: Truth) -> Self {
+        self.not().and(other.not()).not()
+    }
+}
+
+/// What a condition can ask about the shell being traced
+pub trait ShellState: VarLookup {
+    /// A shell option such as `interactive` or `login`, if known
+    fn option(&self, name: &str) -> Option<bool>;
+
+    /// True if tracing for a simulated machine, whose files and programs
+    /// can't be looked up here
+    fn foreign_host(&self) -> bool {
+        false
+    }
+}
+
+/// Builtins that `command -v` and `type` always find
+const BUILTINS: &[&str] = &[
+    ".", ":", "alias", "autoload", "bind", "builtin", "cd", "command", "compdef", "declare",
+    "echo", "eval", "exec", "exit", "export", "hash", "local", "printf", "pwd", "read", "readonly",
+    "return", "set", "setopt", "shift", "shopt", "source", "test", "type", "typeset", "ulimit",
+    "umask", "unalias", "unset", "unsetopt", "whence",
+];
+
+/// Evaluate the exit status of a simple command (without its leading assignments)
+pub fn evaluate_command(words: &[Word], state: &dyn ShellState) -> Truth {
+    let Some(name) = words.first().and_then(|w| w.as_literal()) else {
+        return Truth::Unknown;
+    };
+    let args = &words[1..];
+    match name.as_str() {
+        "true" | ":" => Truth::True,
+        "false" => Truth::False,
+        "test" => TestExpr::new(args, false, state).evaluate(),
+        "[" | "[[" => {
+            let close = if name == "[" { "]" } else { "]]" };
+            match args.split_last() {
+                Some((last, inner)) if last.is_literal(close) => {
+                    TestExpr::new(inner, name == "[[", state).evaluate()
+                }
+                _ => Truth::Unknown,
+            }
+        }
+        "command" => match args.first().and_then(|w| w.as_literal()).as_deref() {
+            Some("-v" | "-V") => commands_exist(&args[1..], state),
+            _ => Truth::Unknown,
+        },
+        "type" | "which" | "hash" | "whence" => commands_exist(args, state),
+        // shopt -q login_shell
+        "shopt" if args.first().is_some_and(|w| w.is_literal("-q")) => args[1..]
+            .iter()
+            .map(|w| match w.as_literal().as_deref() {
+                Some("login_shell") => option_truth(state, "login"),
+                _ => Truth::Unknown,
+            })
+            .fold(Truth::True, Truth::and),
+        _ => Truth::Unknown,
+    }
+}
+
+fn option_truth(state: &dyn ShellState, name: &str) -> Truth {
+    state.option(name).map_or(Truth::Unknown, Truth::from_bool)
+}
+
+/// Expand a word to a single known value
+fn value(word: &Word, state: &dyn ShellState) -> Option<String> {
+    let expansion = expand_argument(word, state);
+    expansion.unresolved.is_empty().then_some(expansion.value)
+}
+
+/// True if every (non-option) argument names an existing command
+fn commands_exist(args: &[Word], state: &dyn ShellState) -> Truth {
+    let names: Vec<&Word> = args
+        .iter()
+        .filter(|w| !w.as_literal().is_some_and(|a| a.starts_with('-')))
+        .collect();
+    if names.is_empty() {
+        return Truth::Unknown;
+    }
+    names
+        .into_iter()
+        .map(|w| match value(w, state) {
+            Some(name) => command_exists(&name, state),
+            None => Truth::Unknown,
+        })
+        .fold(Truth::True, Truth::and)
+}
+
+/// Look a command up on the traced PATH (falling back to our own PATH)
+fn command_exists(name: &str, state: &dyn ShellState) -> Truth {
+    if BUILTINS.contains(&name) {
+        return Truth::True;
+    }
+    if state.foreign_host() {
+        return Truth::Unknown;
+    }
+    if name.contains('/') {
+        return Truth::from_bool(is_executable(&resolve_path(name, state)));
+    }
+    let path = match state.lookup("PATH") {
+        Lookup::Set(path) => path,
+        Lookup::Unset => return Truth::False,
+        Lookup::Unknown => std::env::var("PATH").unwrap_or_default(),
+    };
+    let found = path
+        .split(':')
+        .filter(|dir| !dir.is_empty())
+        .any(|dir| is_executable(&PathBuf::from(dir).join(name)));
+    if found {
+        Truth::True
+    } else if path.contains('$') {
+        // Part of PATH is unresolved, so the command may still be there
+        Truth::Unknown
+    } else {
+        Truth::False
+    }
+}
+
+fn is_executable(path: &std::path::Path) -> bool {
+    let Ok(metadata) = path.metadata() else {
+        return false;
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        metadata.is_file() && metadata.permissions().mode() & 0o111 != 0
+    }
+    #[cfg(not(unix))]
+    {
+        metadata.is_file()
+    }
+}
+
+/// Relative paths are relative to the startup directory, which is HOME
+fn resolve_path(path: &str, state: &dyn ShellState) -> PathBuf {
+    let path = PathBuf::from(path);
+    if path.is_relative()
+        && let Lookup::Set(home) = state.lookup("HOME")
+    {
+        return PathBuf::from(home).join(path);
+    }
+    path
+}
+
+/// A `test`/`[`/`[[` expression
+struct TestExpr<'a> {
+    words: &'a [Word],
+    pos: usize,
+    /// `[[ ]]`: `&&`/`||` connectives and pattern matching with `==`
+    extended: bool,
+    state: &'a dyn ShellState,
+}
+
+impl<'a> TestExpr<'a> {
+    fn new(words: &'a [Word], extended: bool, state: &'a dyn ShellState) -> Self {
+        Self {
+            words,
+            pos: 0,
+            extended,
+            state,
+        }
+    }
+
+    fn evaluate(mut self) -> Truth {
+        if self.words.is_empty() {
+            return Truth::False;
+        }
+        let result = self.or();
+        if self.pos == self.words.len() {
+            result
+        } else {
+            Truth::Unknown
+        }
+    }
+
+    fn literal(&self, offset: usize) -> Option<String> {
+        self.words
+            .get(self.pos + offset)
+            .and_then(|w| w.as_literal())
+    }
+
+    fn eat(&mut self, op: &str) -> bool {
+        let found = self.literal(0).as_deref() == Some(op);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn or(&mut self) -> Truth {
+        let op = if self.extended { "||" } else { "-o" };
+        let mut result = self.and();
+        while self.eat(op) {
+            result = result.or(self.and());
+        }
+        result
+    }
+
+    fn and(&mut self) -> Truth {
+        let op = if self.extended { "&&" } else { "-a" };
+        let mut result = self.not();
+        while self.eat(op) {
+            result = result.and(self.not());
+        }
+        result
+    }
+
+    fn not(&mut self) -> Truth {
+        if self.eat("!") {
+            return self.not().not();
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Truth {
+        if self.eat("(") {
+            let result = self.or();
+            self.eat(")");
+            return result;
+        }
+        let remaining = self.words.len() - self.pos;
+        if remaining >= 3
+            && let Some(op) = self.literal(1)
+            && is_binary(&op)
+        {
+            let (left, right) = (&self.words[self.pos], &self.words[self.pos + 2]);
+            self.pos += 3;
+            return self.binary(left, &op, right);
+        }
+        if remaining >= 2
+            && let Some(op) = self.literal(0)
+            && op.len() == 2
+            && op.starts_with('-')
+        {
+            let operand = &self.words[self.pos + 1];
+            self.pos += 2;
+            return self.unary(&op, operand);
+        }
+        let word = &self.words[self.pos];
+        self.pos += 1;
+        match value(word, self.state) {
+            Some(v) => Truth::from_bool(!v.is_empty()),
+            None => Truth::Unknown,
+        }
+    }
+
+    fn unary(&self, op: &str, operand: &Word) -> Truth {
+        let state = self.state;
+        match op {
+            "-v" => match operand.as_literal().map(|name| state.lookup(&name)) {
+                Some(Lookup::Set(_)) => Truth::True,
+                Some(Lookup::Unset) => Truth::False,
+                _ => Truth::Unknown,
+            },
+            "-o" => match operand.as_literal() {
+                Some(name) => option_truth(state, &name),
+                None => Truth::Unknown,
+            },
+            "-t" => Truth::Unknown,
+            _ => {
+                let Some(text) = value(operand, state) else {
+                    return Truth::Unknown;
+                };
+                match op {
+                    "-n" => Truth::from_bool(!text.is_empty()),
+                    "-z" => Truth::from_bool(text.is_empty()),
+                    _ if state.foreign_host() => Truth::Unknown,
+                    _ => file_test(op, &resolve_path(&text, state)),
+                }
+            }
+        }
+    }
+
+    fn binary(&self, left: &Word, op: &str, right: &Word) -> Truth {
+        let state = self.state;
+        let Some(left) = value(left, state) else {
+            return Truth::Unknown;
+        };
+        match op {
+            "=" | "==" | "!=" => {
+                let matched = if self.extended {
+                    match word_pattern(right, state) {
+                        Some(pattern) => Truth::from_bool(pattern.matches(&left)),
+                        None => Truth::Unknown,
+                    }
+                } else {
+                    match value(right, state) {
+                        Some(right) => Truth::from_bool(left == right),
+                        None => Truth::Unknown,
+                    }
+                };
+                if op == "!=" { matched.not() } else { matched }
+            }
+            "<" | ">" => match value(right, state) {
+                Some(right) if op == "<" => Truth::from_bool(left < right),
+                Some(right) => Truth::from_bool(left > right),
+                None => Truth::Unknown,
+            },
+            "-eq" | "-ne" | "-lt" | "-le" | "-gt" | "-ge" => {
+                let right = value(right, state);
+                let (Ok(a), Some(Ok(b))) = (
+                    left.trim().parse::<i64>(),
+                    right.map(|r| r.trim().parse::<i64>()),
+                ) else {
+                    return Truth::Unknown;
+                };
+                Truth::from_bool(match op {
+                    "-eq" => a == b,
+                    "-ne" => a != b,
+                    "-lt" => a < b,
+                    "-le" => a <= b,
+                    "-gt" => a > b,
+                    _ => a >= b,
+                })
+            }
+            // =~, -nt, -ot, -ef
+            _ => Truth::Unknown,
+        }
+    }
+}
+
+fn is_binary(op: &str) -> bool {
+    matches!(
+        op,
+        "=" | "=="
+            | "!="
+            | "=~"
+            | "<"
+            | ">"
+            | "-eq"
+            | "-ne"
+            | "-lt"
+            | "-le"
+            | "-gt"
+            | "-ge"
+            | "-nt"
+            | "-ot"
+            | "-ef"
+    )
+}
+
+/// `-e`, `-f`, `-d`, ... against the real filesystem
+fn file_test(op: &str, path: &std::path::Path) -> Truth {
+    let metadata = path.metadata();
+    match op {
+        "-e" | "-a" => Truth::from_bool(metadata.is_ok()),
+        "-f" => Truth::from_bool(metadata.is_ok_and(|m| m.is_file())),
+        "-d" => Truth::from_bool(metadata.is_ok_and(|m| m.is_dir())),
+        "-s" => Truth::from_bool(metadata.is_ok_and(|m| m.len() > 0)),
+        "-r" | "-w" => Truth::from_bool(metadata.is_ok()),
+        "-x" => Truth::from_bool(metadata.is_ok_and(|m| m.is_dir()) || is_executable(path)),
+        "-L" | "-h" => Truth::from_bool(path.symlink_metadata().is_ok_and(|m| m.is_symlink())),
+        _ => Truth::Unknown,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::script::{Command, Script};
+    use std::collections::HashMap;
+
+    struct State {
+        vars: HashMap<&'static str, &'static str>,
+        interactive: Option<bool>,
+        foreign: bool,
+    }
+
+    impl VarLookup for State {
+        fn lookup(&self, name: &str) -> Lookup {
+            match self.vars.get(name) {
+                Some(v) => Lookup::Set(v.to_string()),
+                None => Lookup::Unknown,
+            }
+        }
+    }
+
+    impl ShellState for State {
+        fn option(&self, name: &str) -> Option<bool> {
+            (name == "interactive")
+                .then_some(self.interactive)
+                .flatten()
+        }
+
+        fn foreign_host(&self) -> bool {
+            self.foreign
+        }
+    }
+
+    fn eval(text: &str, state: &State) -> Truth {
+        let script = Script::parse(text);
+        let Command::Simple(ref cmd) = script.statements[0].commands[0].1 else {
+            panic!("Expected simple command");
+        };
+        evaluate_command(&cmd.words, state)
+    }
+
+    fn state() -> State {
+        State {
+            vars: [("HOME", "/root"), ("-", "himBH"), ("EMPTY", "")]
+                .into_iter()
+                .collect(),
+            interactive: Some(true),
+            foreign: false,
+        }
+    }
+
+    #[test]
+    fn test_file_tests() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let file = dir.path().join("env");
+        std::fs::write(&file, "x").unwrap();
+        let state = state();
+        let d = dir.path().display();
+        assert_eq!(eval(&format!("[ -d {} ]", d), &state), Truth::True);
+        assert_eq!(eval(&format!("[ -f {}/env ]", d), &state), Truth::True);
+        assert_eq!(
+            eval(&format!("test -f {}/missing", d), &state),
+            Truth::False
+        );
+        assert_eq!(
+            eval(&format!("[ ! -e {}/missing ]", d), &state),
+            Truth::True
+        );
+        assert_eq!(eval("[ -f $UNKNOWN/env ]", &state), Truth::Unknown);
+
+        let foreign = State {
+            foreign: true,
+            ..state
+        };
+        assert_eq!(eval(&format!("[ -d {} ]", d), &foreign), Truth::Unknown);
+        assert_eq!(eval("command -v sh", &foreign), Truth::Unknown);
+        assert_eq!(eval("command -v export", &foreign), Truth::True);
+        assert_eq!(eval("[ -n \"$HOME\" ]", &foreign), Truth::True);
+    }
+
+    #[test]
+    fn test_string_tests() {
+        let state = state();
+        assert_eq!(eval(r#"[ -n "$EMPTY" ]"#, &state), Truth::False);
+        assert_eq!(eval(r#"[ -z "$EMPTY" ]"#, &state), Truth::True);
+        assert_eq!(eval(r#"[ -n "$OTHER" ]"#, &state), Truth::Unknown);
+        assert_eq!(eval("[[ $- == *i* ]]", &state), Truth::True);
+        assert_eq!(eval("[[ $- != *i* ]]", &state), Truth::False);
+        assert_eq!(eval(r#"[[ "$-" == "*i*" ]]"#, &state), Truth::False);
+        assert_eq!(
+            eval("[[ -o interactive && -n $HOME ]]", &state),
+            Truth::True
+        );
+        assert_eq!(eval("[ 3 -gt 2 -a abc = abc ]", &state), Truth::True);
+    }
+
+    #[test]
+    fn test_command_lookup() {
+        let state = state();
+        assert_eq!(eval("command -v sh >/dev/null", &state), Truth::True);
+        assert_eq!(
+            eval("command -v no-such-tool-xyz >/dev/null 2>&1", &state),
+            Truth::False
+        );
+        assert_eq!(eval("type source", &state), Truth::True);
+        assert_eq!(eval("some_function", &state), Truth::Unknown);
+    }
+}
@@ -43,6 +43,11 @@ fn parse_environment_content(content: &str, path: &Path, target_var: &str) -> Ve
                 operation: Operation::Set,
                 value_before: None,
                 value_after: value,
+                unresolved: Vec::new(),
+                evaluated: Vec::new(),
+                conditions: Vec::new(),
+                function: None,
+                emulated: None,
             });
         }
     }
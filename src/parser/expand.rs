@@ -0,0 +1,590 @@
+//! Word expansion against tracked variable values
+//!
+//! Expands parameter references in a [`Word`] the way the shell would,
+//! including the POSIX operators (`${VAR:-default}`, `${VAR:+alt}`,
+//! `${VAR#prefix}`, `${VAR%suffix}`, ...). Variables whose value isn't known
+//! are left in their source form and reported as unresolved.
+
+use super::common::home_dir_of;
+use super::lexer::{ParamExpansion, Word, WordPart, lex_operand};
+use super::pattern::Pattern;
+
+/// The result of looking up a variable
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Lookup {
+    /// The variable is set to this value
+    Set(String),
+    /// The variable is known to be unset
+    Unset,
+    /// Nothing is known about the variable
+    Unknown,
+}
+
+/// Source of variable values for expansion
+pub trait VarLookup {
+    fn lookup(&self, name: &str) -> Lookup;
+
+    /// The output of a command substitution, if it is known
+    fn command_output(&self, _command: &str) -> Option<String> {
+        None
+    }
+
+    /// Run a command substitution nothing else could answer
+    /// (`--eval-commands`); `source` is the substitution as written
+    fn evaluate(&self, _command: &str, _source: &str) -> Option<String> {
+        None
+    }
+}
+
+/// The result of expanding a word
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Expansion {
+    pub value: String,
+    /// Source text of expansions that couldn't be resolved (left as-is in `value`)
+    pub unresolved: Vec<String>,
+    /// Variables assigned as a side effect of `${VAR:=default}`
+    pub assignments: Vec<(String, String)>,
+    /// Command substitutions whose output came from actually running them
+    pub evaluated: Vec<String>,
+}
+
+impl Expansion {
+    fn push_unresolved(&mut self, part: &WordPart) {
+        let text = part.source_text();
+        self.value.push_str(&text);
+        self.unresolved.push(text);
+    }
+}
+
+/// Expand a word's parameter references
+pub fn expand_word(word: &Word, vars: &dyn VarLookup) -> Expansion {
+    let mut expansion = Expansion::default();
+    expand_parts(&word.parts, vars, &mut expansion);
+    expansion
+}
+
+/// Expand the value of an assignment: besides parameters, an unquoted `~` at
+/// the start of the value or after a `:` expands to a home directory
+pub fn expand_assignment(word: &Word, vars: &dyn VarLookup) -> Expansion {
+    expand_with_tilde(word, vars, true)
+}
+
+/// Expand a command argument: parameters plus a leading `~`
+pub fn expand_argument(word: &Word, vars: &dyn VarLookup) -> Expansion {
+    expand_with_tilde(word, vars, false)
+}
+
+/// Expand a word with tilde expansion at its start (and after `:` when
+/// `after_colons` is set)
+fn expand_with_tilde(word: &Word, vars: &dyn VarLookup, after_colons: bool) -> Expansion {
+    let mut expansion = Expansion::default();
+    for (i, part) in word.parts.iter().enumerate() {
+        match part {
+            WordPart::Literal(s) => {
+                let last = i + 1 == word.parts.len();
+                let segments: Vec<&str> = if after_colons {
+                    s.split(':').collect()
+                } else {
+                    vec![s.as_str()]
+                };
+                for (j, segment) in segments.iter().enumerate() {
+                    if j > 0 {
+                        expansion.value.push(':');
+                    }
+                    // The tilde prefix must be followed by `/`, `:` or the end of the word
+                    let ends_prefix = last || j + 1 < segments.len() || segment.contains('/');
+                    let at_start = i == 0 || j > 0;
+                    match segment.strip_prefix('~') {
+                        Some(rest) if at_start && ends_prefix => {
+                            let (user, rest) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+                            match tilde_home(user, vars) {
+                                Some(home) => {
+                                    expansion.value.push_str(&home);
+                                    expansion.value.push_str(rest);
+                                }
+                                None => expansion.value.push_str(segment),
+                            }
+                        }
+                        _ => expansion.value.push_str(segment),
+                    }
+                }
+            }
+            _ => expand_parts(std::slice::from_ref(part), vars, &mut expansion),
+        }
+    }
+    expansion
+}
+
+/// The directory `~user` expands to: HOME for a bare `~`, passwd otherwise
+fn tilde_home(user: &str, vars: &dyn VarLookup) -> Option<String> {
+    if user.is_empty()
+        && let Lookup::Set(home) = vars.lookup("HOME")
+    {
+        return Some(home);
+    }
+    home_dir_of(user).map(|dir| dir.to_string_lossy().to_string())
+}
+
+/// Expand the elements of an array literal, dropping elements that expand to
+/// nothing (as zsh does for unquoted empty expansions)
+pub fn expand_elements(
+    elements: &[Word],
+    vars: &dyn VarLookup,
+    out: &mut Expansion,
+) -> Vec<String> {
+    let mut values = Vec::new();
+    for element in elements {
+        let inner = expand_with_tilde(element, vars, false);
+        out.unresolved.extend(inner.unresolved);
+        out.assignments.extend(inner.assignments);
+        if !inner.value.is_empty() {
+            values.push(inner.value);
+        }
+    }
+    values
+}
+
+/// Returns true if the word references `name` anywhere (including in operands)
+pub fn references(word: &Word, name: &str) -> bool {
+    fn in_parts(parts: &[WordPart], name: &str) -> bool {
+        parts.iter().any(|part| match part {
+            WordPart::DoubleQuoted(inner) => in_parts(inner, name),
+            WordPart::Array(elements) => elements.iter().any(|w| in_parts(&w.parts, name)),
+            WordPart::Param(p) => {
+                p.name == name
+                    || (!p.modifier.is_empty() && in_parts(&lex_operand(&p.modifier).parts, name))
+            }
+            _ => false,
+        })
+    }
+    in_parts(&word.parts, name)
+}
+
+fn expand_parts(parts: &[WordPart], vars: &dyn VarLookup, out: &mut Expansion) {
+    for part in parts {
+        match part {
+            WordPart::Literal(s) | WordPart::Quoted(s) => out.value.push_str(s),
+            WordPart::DoubleQuoted(inner) => expand_parts(inner, vars, out),
+            WordPart::Param(p) => match expand_param(p, vars, out) {
+                Some(value) => out.value.push_str(&value),
+                None => out.push_unresolved(part),
+            },
+            WordPart::Command(c) => {
+                let output = vars.command_output(c).or_else(|| {
+                    let source = part.source_text();
+                    let output = vars.evaluate(c, &source)?;
+                    out.evaluated.push(source);
+                    Some(output)
+                });
+                match output {
+                    Some(output) => out.value.push_str(output.trim_end_matches('\n')),
+                    None => out.push_unresolved(part),
+                }
+            }
+            WordPart::Process(_) | WordPart::Arithmetic(_) => out.push_unresolved(part),
+            WordPart::Array(elements) => {
+                let values = expand_elements(elements, vars, out);
+                out.value.push_str(&values.join(" "));
+            }
+        }
+    }
+}
+
+/// Expand a nested operand word, merging unresolved references and side effects.
+/// Returns None if any part of the operand was unresolved.
+fn expand_operand(text: &str, vars: &dyn VarLookup, out: &mut Expansion) -> Option<String> {
+    let inner = expand_word(&lex_operand(text), vars);
+    out.assignments.extend(inner.assignments);
+    out.evaluated.extend(inner.evaluated);
+    inner.unresolved.is_empty().then_some(inner.value)
+}
+
+/// Build a glob pattern from an operand: unquoted text keeps its wildcards,
+/// quoted text and expansion results inside quotes match literally
+fn expand_pattern(text: &str, vars: &dyn VarLookup, out: &mut Expansion) -> Option<Pattern> {
+    pattern_from_parts(&lex_operand(text).parts, vars, out)
+}
+
+/// Build a glob pattern from a word (e.g. a `case` pattern or the right side
+/// of `==` in `[[ ]]`). Returns None if any part couldn't be resolved.
+pub fn word_pattern(word: &Word, vars: &dyn VarLookup) -> Option<Pattern> {
+    pattern_from_parts(&word.parts, vars, &mut Expansion::default())
+}
+
+/// The pattern text of a word that undergoes pathname expansion (one with
+/// an unquoted `*`, `?` or `[`), with a leading `~` expanded. Quoted parts
+/// are escaped, so the text is meant for [`Pattern::new`]. A trailing zsh
+/// glob qualifier such as `(N)` is dropped. Returns None for words without
+/// wildcards or with parts that couldn't be resolved.
+pub fn glob_word(word: &Word, vars: &dyn VarLookup) -> Option<String> {
+    let mut parts = word.parts.clone();
+    if let Some(WordPart::Literal(last)) = parts.last_mut()
+        && let Some(stripped) = strip_glob_qualifier(last)
+    {
+        *last = stripped.to_string();
+    }
+    let wildcard = parts
+        .iter()
+        .any(|part| matches!(part, WordPart::Literal(s) if s.contains(['*', '?', '['])));
+    if !wildcard {
+        return None;
+    }
+    // `~` and `~user` are expanded before matching
+    if let Some(WordPart::Literal(first)) = parts.first_mut()
+        && let Some(rest) = first.strip_prefix('~')
+    {
+        let (user, rest) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+        let home = tilde_home(user, vars)?;
+        *first = format!("{}{}", Pattern::escape(&home), rest);
+    }
+    pattern_text(&parts, vars, &mut Expansion::default())
+}
+
+/// The text before a zsh glob qualifier like `(N)` or `(.N)`, if there is one
+fn strip_glob_qualifier(text: &str) -> Option<&str> {
+    let inner = text.strip_suffix(')')?;
+    let (before, qualifier) = inner.rsplit_once('(')?;
+    let valid = !qualifier.is_empty()
+        && qualifier
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || ".-@/^*:".contains(c));
+    valid.then_some(before)
+}
+
+fn pattern_from_parts(
+    parts: &[WordPart],
+    vars: &dyn VarLookup,
+    out: &mut Expansion,
+) -> Option<Pattern> {
+    pattern_text(parts, vars, out).map(|text| Pattern::new(&text))
+}
+
+/// Pattern text for word parts: unquoted text keeps its wildcards, quoted
+/// text is escaped
+fn pattern_text(parts: &[WordPart], vars: &dyn VarLookup, out: &mut Expansion) -> Option<String> {
+    fn build(
+        parts: &[WordPart],
+        quoted: bool,
+        vars: &dyn VarLookup,
+        out: &mut Expansion,
+        pattern: &mut String,
+    ) -> bool {
+        for part in parts {
+            match part {
+                WordPart::Literal(s) if !quoted => pattern.push_str(s),
+                WordPart::Literal(s) | WordPart::Quoted(s) => pattern.push_str(&Pattern::escape(s)),
+                WordPart::DoubleQuoted(inner) => {
+                    if !build(inner, true, vars, out, pattern) {
+                        return false;
+                    }
+                }
+                WordPart::Param(p) => match expand_param(p, vars, out) {
+                    Some(value) if quoted => pattern.push_str(&Pattern::escape(&value)),
+                    Some(value) => pattern.push_str(&value),
+                    None => return false,
+                },
+                WordPart::Command(c) => match vars.command_output(c) {
+                    Some(output) if quoted => {
+                        pattern.push_str(&Pattern::escape(output.trim_end_matches('\n')))
+                    }
+                    Some(output) => pattern.push_str(output.trim_end_matches('\n')),
+                    None => return false,
+                },
+                _ => return false,
+            }
+        }
+        true
+    }
+    let mut pattern = String::new();
+    build(parts, false, vars, out, &mut pattern).then_some(pattern)
+}
+
+/// Expand a single parameter. Returns None if it can't be resolved.
+fn expand_param(p: &ParamExpansion, vars: &dyn VarLookup, out: &mut Expansion) -> Option<String> {
+    let mut lookup = vars.lookup(&p.name);
+
+    // Scalars behave like one-element arrays: ${VAR[0]} and ${VAR[@]} are the value
+    if let Some(ref index) = p.index
+        && !matches!(index.as_str(), "0" | "@" | "*")
+    {
+        lookup = Lookup::Unknown;
+    }
+
+    match p.prefix {
+        Some('#') => {
+            return match lookup {
+                Lookup::Set(v) => Some(v.chars().count().to_string()),
+                Lookup::Unset => Some("0".to_string()),
+                Lookup::Unknown => None,
+            };
+        }
+        Some('!') => {
+            lookup = match lookup {
+                Lookup::Set(target) if !target.is_empty() => vars.lookup(&target),
+                Lookup::Set(_) | Lookup::Unset => Lookup::Unset,
+                Lookup::Unknown => Lookup::Unknown,
+            };
+        }
+        _ => {}
+    }
+
+    let modifier = p.modifier.as_str();
+    if modifier.is_empty() {
+        return match lookup {
+            Lookup::Set(v) => Some(v),
+            Lookup::Unset => Some(String::new()),
+            Lookup::Unknown => None,
+        };
+    }
+
+    // ${VAR:-w} ${VAR-w} ${VAR:=w} ${VAR=w} ${VAR:?w} ${VAR?w} ${VAR:+w} ${VAR+w}
+    let (check_null, rest) = match modifier.strip_prefix(':') {
+        Some(rest) => (true, rest),
+        None => (false, modifier),
+    };
+    if let Some(op) = rest.chars().next()
+        && "-=?+".contains(op)
+    {
+        let operand = &rest[1..];
+        let is_set = match &lookup {
+            Lookup::Set(v) => !(check_null && v.is_empty()),
+            Lookup::Unset => false,
+            Lookup::Unknown => return None,
+        };
+        let current = match lookup {
+            Lookup::Set(v) => v,
+            _ => String::new(),
+        };
+        return match op {
+            '+' if is_set => expand_operand(operand, vars, out),
+            '+' => Some(String::new()),
+            _ if is_set => Some(current),
+            '=' => {
+                let value = expand_operand(operand, vars, out)?;
+                out.assignments.push((p.name.clone(), value.clone()));
+                Some(value)
+            }
+            // Unset ${VAR:?msg} aborts the shell; treat the value as empty
+            '?' => Some(String::new()),
+            _ => expand_operand(operand, vars, out),
+        };
+    }
+
+    let value = match lookup {
+        Lookup::Set(v) => v,
+        Lookup::Unset => String::new(),
+        Lookup::Unknown => return None,
+    };
+
+    // ${VAR##pat} ${VAR#pat} ${VAR%%pat} ${VAR%pat}
+    for (op, prefix, longest) in [
+        ("##", true, true),
+        ("#", true, false),
+        ("%%", false, true),
+        ("%", false, false),
+    ] {
+        if let Some(operand) = modifier.strip_prefix(op) {
+            let pattern = expand_pattern(operand, vars, out)?;
+            let result = if prefix {
+                pattern.strip_prefix(&value, longest)
+            } else {
+                pattern.strip_suffix(&value, longest)
+            };
+            return Some(result.to_string());
+        }
+    }
+
+    // ${VAR/pat/rep} ${VAR//pat/rep}
+    if let Some(rest) = modifier.strip_prefix('/') {
+        let (all, rest) = match rest.strip_prefix('/') {
+            Some(r) => (true, r),
+            None => (false, rest),
+        };
+        let (pattern_text, replacement) = split_unescaped(rest, '/');
+        let pattern = expand_pattern(pattern_text, vars, out)?;
+        let replacement = expand_operand(replacement.unwrap_or(""), vars, out)?;
+        return Some(pattern.replace(&value, &replacement, all));
+    }
+
+    // zsh modifiers: ${file:h}, ${0:A:h}, ...
+    if let Some(value) = zsh_modifiers(&value, modifier) {
+        return Some(value);
+    }
+
+    // Substrings, case modification and other modifiers aren't evaluated
+    None
+}
+
+/// Apply zsh path modifiers (`:h` head, `:t` tail, `:r` root, `:e`
+/// extension, `:a`/`:A` absolute path) in order. Returns None if any
+/// modifier isn't one of these or the path can't be made absolute.
+fn zsh_modifiers(value: &str, modifiers: &str) -> Option<String> {
+    let mut value = value.to_string();
+    for modifier in modifiers.strip_prefix(':')?.split(':') {
+        value = match modifier {
+            "h" => match value.trim_end_matches('/').rsplit_once('/') {
+                Some(("", _)) => "/".to_string(),
+                Some((head, _)) => head.to_string(),
+                None => ".".to_string(),
+            },
+            "t" => value
+                .trim_end_matches('/')
+                .rsplit('/')
+                .next()
+                .unwrap_or_default()
+                .to_string(),
+            "r" | "e" => {
+                let tail_start = value.rfind('/').map_or(0, |i| i + 1);
+                match value[tail_start..].rfind('.') {
+                    Some(dot) if modifier == "r" => value[..tail_start + dot].to_string(),
+                    Some(dot) => value[tail_start + dot + 1..].to_string(),
+                    None if modifier == "r" => value,
+                    None => String::new(),
+                }
+            }
+            // Relative paths would need the shell's working directory
+            "a" | "A" if !value.starts_with('/') => return None,
+            "a" => value,
+            "A" => std::fs::canonicalize(&value)
+                .map(|p| p.to_string_lossy().to_string())
+                .unwrap_or(value),
+            _ => return None,
+        };
+    }
+    Some(value)
+}
+
+/// Split `text` at the first `sep` that isn't escaped or quoted
+fn split_unescaped(text: &str, sep: char) -> (&str, Option<&str>) {
+    let mut escaped = false;
+    let mut quote: Option<char> = None;
+    for (i, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '\'' | '"' if quote == Some(c) => quote = None,
+            '\'' | '"' if quote.is_none() => quote = Some(c),
+            _ if c == sep && quote.is_none() => return (&text[..i], Some(&text[i + 1..])),
+            _ => {}
+        }
+    }
+    (text, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    struct Vars(HashMap<&'static str, Option<&'static str>>);
+
+    impl VarLookup for Vars {
+        fn lookup(&self, name: &str) -> Lookup {
+            match self.0.get(name) {
+                Some(Some(v)) => Lookup::Set(v.to_string()),
+                Some(None) => Lookup::Unset,
+                None => Lookup::Unknown,
+            }
+        }
+    }
+
+    fn expand(text: &str, vars: &[(&'static str, Option<&'static str>)]) -> Expansion {
+        let vars = Vars(vars.iter().cloned().collect());
+        expand_word(&lex_operand(text), &vars)
+    }
+
+    #[test]
+    fn test_default_values() {
+        assert_eq!(expand("${EDITOR:-vim}", &[("EDITOR", None)]).value, "vim");
+        assert_eq!(
+            expand("${EDITOR:-vim}", &[("EDITOR", Some(""))]).value,
+            "vim"
+        );
+        assert_eq!(expand("${EDITOR-vim}", &[("EDITOR", Some(""))]).value, "");
+        assert_eq!(
+            expand("${EDITOR:-vim}", &[("EDITOR", Some("nano"))]).value,
+            "nano"
+        );
+    }
+
+    #[test]
+    fn test_alternate_value() {
+        let text = "/opt/x/lib${LD:+:$LD}";
+        assert_eq!(expand(text, &[("LD", None)]).value, "/opt/x/lib");
+        assert_eq!(
+            expand(text, &[("LD", Some("/usr/lib"))]).value,
+            "/opt/x/lib:/usr/lib"
+        );
+    }
+
+    #[test]
+    fn test_assign_default_side_effect() {
+        let result = expand("${EDITOR:=vi}", &[("EDITOR", None)]);
+        assert_eq!(result.value, "vi");
+        assert_eq!(
+            result.assignments,
+            vec![("EDITOR".to_string(), "vi".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_pattern_removal() {
+        let vars = [("P", Some("/usr/local/bin:/usr/bin"))];
+        assert_eq!(expand("${P#*:}", &vars).value, "/usr/bin");
+        assert_eq!(expand("${P%:*}", &vars).value, "/usr/local/bin");
+        assert_eq!(expand("${P##*/}", &vars).value, "bin");
+        assert_eq!(expand("${P%%/bin*}", &vars).value, "/usr/local");
+        assert_eq!(expand("${P#/usr/local/bin:}", &vars).value, "/usr/bin");
+    }
+
+    #[test]
+    fn test_substitution_and_length() {
+        let vars = [("P", Some("/a:/b"))];
+        assert_eq!(expand("${P//:/ }", &vars).value, "/a /b");
+        assert_eq!(expand("${#P}", &vars).value, "5");
+    }
+
+    #[test]
+    fn test_zsh_path_modifiers() {
+        let vars = [
+            ("0", Some("/home/u/.zsh/lib.tar.zsh")),
+            ("F", Some("lib.zsh")),
+        ];
+        assert_eq!(expand("${0:h}", &vars).value, "/home/u/.zsh");
+        assert_eq!(expand("${0:A:h}/x", &vars).value, "/home/u/.zsh/x");
+        assert_eq!(expand("${0:t:r}", &vars).value, "lib.tar");
+        assert_eq!(expand("${F:e}", &vars).value, "zsh");
+        assert_eq!(expand("${F:h}", &vars).value, ".");
+        assert!(!expand("${F:A}", &vars).unresolved.is_empty());
+        assert!(!expand("${F:1:2}", &vars).unresolved.is_empty());
+    }
+
+    #[test]
+    fn test_unknown_left_unresolved() {
+        let result = expand("$FOO/bin:${BAR:-x}:$(cmd)", &[]);
+        assert_eq!(result.value, "$FOO/bin:${BAR:-x}:$(cmd)");
+        assert_eq!(result.unresolved, vec!["$FOO", "${BAR:-x}", "$(cmd)"]);
+    }
+
+    #[test]
+    fn test_references() {
+        let word = lex_operand("/x${LD:+:$LD}");
+        assert!(references(&word, "LD"));
+        assert!(!references(&word, "PATH"));
+    }
+
+    #[test]
+    fn test_tilde_in_assignment() {
+        let vars = Vars([("HOME", Some("/home/me"))].into_iter().collect());
+        let expand = |text: &str| expand_assignment(&lex_operand(text), &vars).value;
+        assert_eq!(
+            expand("~/bin:~/.local/bin"),
+            "/home/me/bin:/home/me/.local/bin"
+        );
+        assert_eq!(expand("/usr/bin:~"), "/usr/bin:/home/me");
+        assert_eq!(expand("~root/bin"), "/root/bin");
+        assert_eq!(expand("'~'/bin"), "~/bin");
+        assert_eq!(expand("a~/bin"), "a~/bin");
+        assert_eq!(expand("~nosuchuser-xyz/bin"), "~nosuchuser-xyz/bin");
+    }
+}
@@ -0,0 +1,2334 @@
+//! fish script parsing
+//!
+//! fish has its own syntax: variables hold lists, `set` replaces
+//! assignments, blocks end with `end` and `(cmd)` substitutes a command's
+//! output. Scripts are read into jobs and blocks here and walked against
+//! the traced environment the way [`super::shell`] walks bash and zsh,
+//! producing the same [`ParsedShellEntry`] values.
+//!
+//! Lists are stored joined, with `:` for path variables (names ending in
+//! `PATH`, as fish exports them) and spaces otherwise.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use super::common::{
+    BranchWalk, Branching, Guard, PathState, SourcedFile, Stopped, home_dir_of, negate, shell_quote,
+};
+use super::condition::{ShellState, Truth, evaluate_command};
+use super::expand::{Expansion, Lookup, VarLookup};
+use super::glob::{expand_glob, path_command_output, resolve_path};
+use super::lexer::{Span, Word, WordPart};
+use super::pattern::Pattern;
+use super::shell::ParsedShellEntry;
+use crate::trace::{
+    Diagnostic, DiagnosticKind, Operation, ShellEnv, StopKind, StopPoint, ValueCandidate,
+    VariableChange,
+};
+
+/// Most arguments a single word is expanded to
+const MAX_ITEMS: usize = 256;
+
+/// The fish function that rebuilds PATH when `fish_user_paths` changes
+const RECONSTRUCT_PATH: &str = "__fish_reconstruct_path";
+
+/// Parse a fish script for variable changes
+///
+/// Recognized:
+/// - set [-g|-U|-l] [-x|-u] NAME values... (`$NAME` among the values
+///   prepends or appends)
+/// - set -a / set -p NAME values... (append, prepend)
+/// - set -e NAME (erase)
+/// - fish_add_path [-P] [-a|-p] [-m] dirs... (through `fish_user_paths`,
+///   which fish copies to the front of PATH)
+/// - if / else if / else, switch, for, while and begin blocks
+/// - `and` / `or` / `&&` / `||` / `not` guards
+/// - source file / . file
+/// - return / exit / exec (stop reading the file)
+///
+/// `cmd | source` and `eval` of unknown code are passed on as diagnostics.
+/// Entries are passed to `on_entry` as they are reached, as for
+/// [`super::parse_shell_file`].
+pub fn parse_fish_file(
+    path: &Path,
+    target_var: &str,
+    env: &mut ShellEnv,
+    guard: &Guard,
+    on_entry: &mut dyn FnMut(ParsedShellEntry, &mut ShellEnv),
+) -> std::io::Result<()> {
+    let content = std::fs::read_to_string(path)?;
+    let statements = parse(&content);
+    let mut walker = FishWalker::new(path, target_var, env, guard, on_entry);
+    walker.walk(&statements);
+    walker.finish();
+    Ok(())
+}
+
+/// Load fish universal variables (`fish_variables`)
+///
+/// Each `SETUVAR [--export] [--path] NAME:value` line sets a variable; list
+/// elements are separated by `\x1e` and special characters are escaped as
+/// `\xHH`. Setting `fish_user_paths` puts its directories at the front of
+/// PATH, as fish does at startup.
+pub fn parse_fish_variables(
+    path: &Path,
+    target_var: &str,
+    env: &mut ShellEnv,
+    on_entry: &mut dyn FnMut(ParsedShellEntry, &mut ShellEnv),
+) -> std::io::Result<()> {
+    let content = std::fs::read_to_string(path)?;
+    let mut walker = FishWalker::new(path, target_var, env, &Guard::default(), on_entry);
+    for (i, text) in content.lines().enumerate() {
+        let Some(mut rest) = text.strip_prefix("SETUVAR ") else {
+            continue;
+        };
+        let mut export = false;
+        loop {
+            if let Some(r) = rest.strip_prefix("--export ") {
+                export = true;
+                rest = r;
+            } else if let Some(r) = rest.strip_prefix("--path ") {
+                rest = r;
+            } else {
+                break;
+            }
+        }
+        let Some((name, value)) = rest.split_once(':') else {
+            continue;
+        };
+        let line = Line {
+            number: i + 1,
+            text: text.to_string(),
+        };
+        let elements = decode_universal(value);
+        let operation = if export {
+            walker.env.set_exported(name, true);
+            Operation::Export
+        } else {
+            Operation::Set
+        };
+        let expansion = Expansion {
+            value: join_list(name, &elements),
+            ..Default::default()
+        };
+        walker.store(name, operation, expansion, &line);
+    }
+    Ok(())
+}
+
+/// The elements of a universal variable's value
+fn decode_universal(value: &str) -> Vec<String> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\' && i + 1 < bytes.len() {
+            let hex = (bytes[i + 1] == b'x')
+                .then(|| value.get(i + 2..i + 4))
+                .flatten()
+                .and_then(|h| u8::from_str_radix(h, 16).ok());
+            match hex {
+                Some(byte) => {
+                    decoded.push(byte);
+                    i += 4;
+                }
+                None => {
+                    decoded.push(bytes[i + 1]);
+                    i += 2;
+                }
+            }
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    let decoded = String::from_utf8_lossy(&decoded);
+    if decoded.is_empty() {
+        return Vec::new();
+    }
+    decoded.split('\x1e').map(str::to_string).collect()
+}
+
+/// Returns true for variables fish treats as path lists, which are joined
+/// with `:` when exported or quoted
+fn is_path_var(name: &str) -> bool {
+    name.ends_with("PATH")
+}
+
+fn join_list(name: &str, elements: &[String]) -> String {
+    elements.join(if is_path_var(name) { ":" } else { " " })
+}
+
+fn split_list(name: &str, value: &str) -> Vec<String> {
+    if value.is_empty() {
+        return Vec::new();
+    }
+    let separator = if is_path_var(name) { ':' } else { ' ' };
+    value.split(separator).map(str::to_string).collect()
+}
+
+/// The positions an index like `1`, `-1` or `2..-1` selects in a list of
+/// `len` elements (fish counts from 1, negative indices from the end), or
+/// None if it isn't a literal
+fn positions(len: usize, index: &str) -> Option<Vec<usize>> {
+    let position = |text: &str| -> Option<Option<usize>> {
+        let n: i64 = text.trim().parse().ok()?;
+        let len = len as i64;
+        let i = if n < 0 { len + n + 1 } else { n };
+        Some((1..=len).contains(&i).then(|| (i - 1) as usize))
+    };
+    let mut selected = Vec::new();
+    for item in index.split_whitespace() {
+        match item.split_once("..") {
+            Some((from, to)) => {
+                if let (Some(from), Some(to)) = (position(from)?, position(to)?) {
+                    if from <= to {
+                        selected.extend(from..=to);
+                    } else {
+                        selected.extend((to..=from).rev());
+                    }
+                }
+            }
+            None => selected.extend(position(item)?),
+        }
+    }
+    Some(selected)
+}
+
+/// One piece of a fish word
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    /// Text with quotes and escapes removed; unquoted `*` matches files
+    Text { text: String, quoted: bool },
+    /// A leading unquoted `~` or `~user`
+    Home(String),
+    /// `$name` or `$name[index]`; in double quotes the list is joined
+    Var {
+        name: String,
+        index: Option<String>,
+        quoted: bool,
+    },
+    /// `(cmd)` or `$(cmd)`; unquoted output is split into lines
+    Command { text: String, quoted: bool },
+}
+
+/// A fish word and its source text
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct FishWord {
+    parts: Vec<Part>,
+    text: String,
+}
+
+impl FishWord {
+    /// The word if it's plain unquoted text, such as a keyword
+    fn keyword(&self) -> Option<&str> {
+        match self.parts.as_slice() {
+            [
+                Part::Text {
+                    text,
+                    quoted: false,
+                },
+            ] if *text == self.text => Some(text),
+            _ => None,
+        }
+    }
+
+    /// The word's text if it has no expansions
+    fn literal(&self) -> Option<String> {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Text { text, .. } => Some(text.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Returns true if the word is exactly `$name`
+    fn is_var(&self, name: &str) -> bool {
+        matches!(
+            self.parts.as_slice(),
+            [Part::Var { name: n, index: None, quoted: false }] if n == name
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Word(FishWord),
+    Pipe,
+    And,
+    Or,
+    /// A newline, `;` or `&`
+    End,
+}
+
+#[derive(Debug, Clone)]
+struct Lexed {
+    token: Token,
+    line: usize,
+    start: usize,
+    end: usize,
+}
+
+struct Lexer<'a> {
+    src: &'a str,
+    chars: Vec<(usize, char)>,
+    pos: usize,
+    line: usize,
+}
+
+impl<'a> Lexer<'a> {
+    fn new(src: &'a str) -> Self {
+        Self {
+            src,
+            chars: src.char_indices().collect(),
+            pos: 0,
+            line: 1,
+        }
+    }
+
+    fn peek(&self, ahead: usize) -> Option<char> {
+        self.chars.get(self.pos + ahead).map(|&(_, c)| c)
+    }
+
+    fn offset(&self) -> usize {
+        self.chars
+            .get(self.pos)
+            .map_or(self.src.len(), |&(offset, _)| offset)
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek(0)?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn tokenize(mut self) -> Vec<Lexed> {
+        let mut tokens = Vec::new();
+        loop {
+            // Blanks and line continuations
+            while let Some(c) = self.peek(0) {
+                if c == ' ' || c == '\t' || c == '\r' {
+                    self.bump();
+                } else if c == '\\' && self.peek(1) == Some('\n') {
+                    self.bump();
+                    self.bump();
+                } else {
+                    break;
+                }
+            }
+            let (start, line) = (self.offset(), self.line);
+            let token = match self.peek(0) {
+                None => break,
+                Some('#') => {
+                    while self.peek(0).is_some_and(|c| c != '\n') {
+                        self.bump();
+                    }
+                    continue;
+                }
+                Some('\n' | ';') => {
+                    self.bump();
+                    Token::End
+                }
+                Some('|') if self.peek(1) == Some('|') => {
+                    self.pos += 2;
+                    Token::Or
+                }
+                Some('|') => {
+                    self.bump();
+                    Token::Pipe
+                }
+                Some('&') if self.peek(1) == Some('&') => {
+                    self.pos += 2;
+                    Token::And
+                }
+                Some('&') if self.peek(1) != Some('>') => {
+                    self.bump();
+                    Token::End
+                }
+                _ if self.at_redirection() => {
+                    self.redirection();
+                    continue;
+                }
+                _ => {
+                    let word = self.word();
+                    // A stray `)` can't start a word; skip it
+                    if self.offset() == start {
+                        self.bump();
+                        continue;
+                    }
+                    Token::Word(word)
+                }
+            };
+            tokens.push(Lexed {
+                token,
+                line,
+                start,
+                end: self.offset(),
+            });
+        }
+        tokens
+    }
+
+    /// Returns true at `>`, `<`, `&>` or a file descriptor followed by one
+    fn at_redirection(&self) -> bool {
+        let mut i = 0;
+        while self.peek(i).is_some_and(|c| c.is_ascii_digit()) {
+            i += 1;
+        }
+        if i == 0 && self.peek(0) == Some('&') {
+            i = 1;
+        }
+        matches!(self.peek(i), Some('>' | '<'))
+    }
+
+    /// Skip a redirection and its target; they don't change the environment
+    fn redirection(&mut self) {
+        while self.peek(0).is_some_and(|c| c.is_ascii_digit() || c == '&') {
+            self.bump();
+        }
+        while self.peek(0).is_some_and(|c| matches!(c, '>' | '<' | '?')) {
+            self.bump();
+        }
+        // `2>&1`, `>&-`
+        if self.peek(0) == Some('&') {
+            self.bump();
+            while self.peek(0).is_some_and(|c| c.is_ascii_digit() || c == '-') {
+                self.bump();
+            }
+            return;
+        }
+        while self.peek(0).is_some_and(|c| c == ' ' || c == '\t') {
+            self.bump();
+        }
+        if self
+            .peek(0)
+            .is_some_and(|c| !matches!(c, '\n' | ';' | '|' | '&'))
+        {
+            self.word();
+        }
+    }
+
+    fn word(&mut self) -> FishWord {
+        let start = self.offset();
+        let mut parts = Vec::new();
+        while let Some(c) = self.peek(0) {
+            match c {
+                ' ' | '\t' | '\r' | '\n' | ';' | '|' | '&' | '<' | '>' | ')' => break,
+                '\'' => {
+                    self.bump();
+                    while let Some(c) = self.bump() {
+                        match c {
+                            '\'' => break,
+                            '\\' if matches!(self.peek(0), Some('\'' | '\\')) => {
+                                let escaped = self.bump().unwrap();
+                                push_text(&mut parts, escaped, true);
+                            }
+                            c => push_text(&mut parts, c, true),
+                        }
+                    }
+                    // An empty quoted word is still an argument
+                    if parts.is_empty() {
+                        parts.push(Part::Text {
+                            text: String::new(),
+                            quoted: true,
+                        });
+                    }
+                }
+                '"' => {
+                    self.bump();
+                    self.double_quoted(&mut parts);
+                }
+                '\\' => {
+                    self.bump();
+                    match self.bump() {
+                        Some('\n') | None => {}
+                        Some('n') => push_text(&mut parts, '\n', true),
+                        Some('t') => push_text(&mut parts, '\t', true),
+                        Some(c) => push_text(&mut parts, c, true),
+                    }
+                }
+                '$' => {
+                    self.bump();
+                    self.variable(&mut parts, false);
+                }
+                '(' => {
+                    let text = self.substitution();
+                    parts.push(Part::Command {
+                        text,
+                        quoted: false,
+                    });
+                }
+                '~' if parts.is_empty() => {
+                    self.bump();
+                    let mut user = String::new();
+                    while let Some(c) = self
+                        .peek(0)
+                        .filter(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.'))
+                    {
+                        user.push(c);
+                        self.bump();
+                    }
+                    parts.push(Part::Home(user));
+                }
+                c => {
+                    self.bump();
+                    push_text(&mut parts, c, false);
+                }
+            }
+        }
+        FishWord {
+            parts,
+            text: self.src[start..self.offset()].to_string(),
+        }
+    }
+
+    fn double_quoted(&mut self, parts: &mut Vec<Part>) {
+        let before = parts.len();
+        while let Some(c) = self.bump() {
+            match c {
+                '"' => break,
+                '\\' => match self.peek(0) {
+                    Some('\n') => {
+                        self.bump();
+                    }
+                    Some(c @ ('"' | '$' | '\\')) => {
+                        self.bump();
+                        push_text(parts, c, true);
+                    }
+                    _ => push_text(parts, '\\', true),
+                },
+                '$' => self.variable(parts, true),
+                c => push_text(parts, c, true),
+            }
+        }
+        if parts.len() == before {
+            parts.push(Part::Text {
+                text: String::new(),
+                quoted: true,
+            });
+        }
+    }
+
+    /// After a `$`: a variable reference, `$(cmd)`, or a literal `$`
+    fn variable(&mut self, parts: &mut Vec<Part>, quoted: bool) {
+        if self.peek(0) == Some('(') {
+            let text = self.substitution();
+            parts.push(Part::Command { text, quoted });
+            return;
+        }
+        let mut name = String::new();
+        // `$$name` looks up the variable named by another
+        while self.peek(0) == Some('$') {
+            name.push('$');
+            self.bump();
+        }
+        while let Some(c) = self
+            .peek(0)
+            .filter(|c| c.is_ascii_alphanumeric() || *c == '_')
+        {
+            name.push(c);
+            self.bump();
+        }
+        if name.trim_start_matches('$').is_empty() {
+            push_text(parts, '$', quoted);
+            for _ in 0..name.len() {
+                push_text(parts, '$', quoted);
+            }
+            return;
+        }
+        let index = (self.peek(0) == Some('[')).then(|| {
+            self.bump();
+            let mut index = String::new();
+            while let Some(c) = self.bump() {
+                if c == ']' {
+                    break;
+                }
+                index.push(c);
+            }
+            index
+        });
+        parts.push(Part::Var {
+            name,
+            index,
+            quoted,
+        });
+    }
+
+    /// Read `(...)` up to its closing parenthesis and return the text inside
+    fn substitution(&mut self) -> String {
+        self.bump();
+        let start = self.offset();
+        let mut depth = 1;
+        let mut quote = None;
+        while let Some(c) = self.peek(0) {
+            match (c, quote) {
+                ('\\', _) => {
+                    self.bump();
+                }
+                ('\'' | '"', None) => quote = Some(c),
+                (c, Some(q)) if c == q => quote = None,
+                ('(', None) => depth += 1,
+                (')', None) => {
+                    depth -= 1;
+                    if depth == 0 {
+                        let text = self.src[start..self.offset()].to_string();
+                        self.bump();
+                        return text;
+                    }
+                }
+                _ => {}
+            }
+            self.bump();
+        }
+        self.src[start..].to_string()
+    }
+}
+
+fn push_text(parts: &mut Vec<Part>, c: char, quoted: bool) {
+    if let Some(Part::Text { text, quoted: q }) = parts.last_mut()
+        && *q == quoted
+    {
+        text.push(c);
+        return;
+    }
+    parts.push(Part::Text {
+        text: c.to_string(),
+        quoted,
+    });
+}
+
+/// The line a statement starts on and its source text
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Line {
+    number: usize,
+    text: String,
+}
+
+/// How a command is joined to the one before it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Connector {
+    First,
+    Pipe,
+    /// `&&`, or a job starting with `and`
+    And,
+    /// `||`, or a job starting with `or`
+    Or,
+}
+
+#[derive(Debug, Clone)]
+struct JobCommand {
+    connector: Connector,
+    words: Vec<FishWord>,
+    /// Source text of the commands before this one in the job
+    guard: String,
+}
+
+/// Commands joined by pipes, `&&` and `||`, ending at a newline or `;`
+#[derive(Debug, Clone)]
+struct Job {
+    line: Line,
+    commands: Vec<JobCommand>,
+}
+
+#[derive(Debug, Clone)]
+enum Statement {
+    Job(Job),
+    If {
+        line: Line,
+        /// Each `if`/`else if` test (a job and the `and`/`or` jobs after it) and its body
+        branches: Vec<(Vec<Statement>, Vec<Statement>)>,
+        else_body: Option<Vec<Statement>>,
+    },
+    Begin(Line, Vec<Statement>),
+    For {
+        line: Line,
+        var: String,
+        items: Vec<FishWord>,
+        body: Vec<Statement>,
+    },
+    While {
+        line: Line,
+        condition: Vec<Statement>,
+        body: Vec<Statement>,
+    },
+    Switch {
+        line: Line,
+        word: FishWord,
+        cases: Vec<(Vec<FishWord>, Vec<Statement>)>,
+    },
+}
+
+impl Statement {
+    fn line(&self) -> &Line {
+        match self {
+            Statement::Job(job) => &job.line,
+            Statement::If { line, .. }
+            | Statement::Begin(line, _)
+            | Statement::For { line, .. }
+            | Statement::While { line, .. }
+            | Statement::Switch { line, .. } => line,
+        }
+    }
+}
+
+fn parse(src: &str) -> Vec<Statement> {
+    let mut parser = Parser {
+        src,
+        tokens: Lexer::new(src).tokenize(),
+        pos: 0,
+    };
+    let mut statements = Vec::new();
+    // A stray `end` or `else` is skipped rather than ending the file
+    while parser.pos < parser.tokens.len() {
+        statements.extend(parser.block(&[]).0);
+        parser.skip_statement();
+    }
+    statements
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    tokens: Vec<Lexed>,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek_keyword(&self) -> Option<&str> {
+        match &self.tokens.get(self.pos)?.token {
+            Token::Word(word) => word.keyword(),
+            _ => None,
+        }
+    }
+
+    fn skip_ends(&mut self) {
+        while self
+            .tokens
+            .get(self.pos)
+            .is_some_and(|t| t.token == Token::End)
+        {
+            self.pos += 1;
+        }
+    }
+
+    /// Skip to the end of the current statement (`end | cmd`, `end > file`)
+    fn skip_statement(&mut self) {
+        while let Some(lexed) = self.tokens.get(self.pos) {
+            self.pos += 1;
+            if lexed.token == Token::End {
+                break;
+            }
+        }
+    }
+
+    /// Statements up to one of the `ends` keywords, which is consumed and returned
+    fn block(&mut self, ends: &[&str]) -> (Vec<Statement>, Option<String>) {
+        let mut statements = Vec::new();
+        loop {
+            self.skip_ends();
+            if self.pos >= self.tokens.len() {
+                return (statements, None);
+            }
+            if let Some(keyword) = self.peek_keyword()
+                && ends.contains(&keyword)
+            {
+                let keyword = keyword.to_string();
+                self.pos += 1;
+                return (statements, Some(keyword));
+            }
+            statements.extend(self.statement());
+        }
+    }
+
+    /// The source text from the current token to the end of the line
+    fn header(&self) -> Line {
+        let first = &self.tokens[self.pos];
+        let end = self.tokens[self.pos..]
+            .iter()
+            .take_while(|t| t.token != Token::End)
+            .last()
+            .map_or(first.end, |t| t.end);
+        Line {
+            number: first.line,
+            text: self.src[first.start..end].trim().to_string(),
+        }
+    }
+
+    /// The next statement; None for one that has no effect (a function definition)
+    fn statement(&mut self) -> Option<Statement> {
+        let keyword = self.peek_keyword().map(str::to_string);
+        let statement = match keyword.as_deref() {
+            Some("if") => {
+                let line = self.header();
+                self.pos += 1;
+                let mut branches = Vec::new();
+                let mut else_body = None;
+                let mut condition = self.condition();
+                loop {
+                    let (body, end) = self.block(&["else", "end"]);
+                    branches.push((condition, body));
+                    if end.as_deref() != Some("else") {
+                        break;
+                    }
+                    if self.peek_keyword() == Some("if") {
+                        self.pos += 1;
+                        condition = self.condition();
+                    } else {
+                        else_body = Some(self.block(&["end"]).0);
+                        break;
+                    }
+                }
+                Statement::If {
+                    line,
+                    branches,
+                    else_body,
+                }
+            }
+            Some("while") => {
+                let line = self.header();
+                self.pos += 1;
+                let condition = self.condition();
+                let body = self.block(&["end"]).0;
+                Statement::While {
+                    line,
+                    condition,
+                    body,
+                }
+            }
+            Some("for") => {
+                let line = self.header();
+                self.pos += 1;
+                let words = self.words();
+                let var = words.first().and_then(|w| w.literal()).unwrap_or_default();
+                let items = words.into_iter().skip(2).collect();
+                let body = self.block(&["end"]).0;
+                Statement::For {
+                    line,
+                    var,
+                    items,
+                    body,
+                }
+            }
+            Some("begin") => {
+                let line = self.header();
+                self.pos += 1;
+                Statement::Begin(line, self.block(&["end"]).0)
+            }
+            Some("switch") => {
+                let line = self.header();
+                self.pos += 1;
+                let word = self.words().into_iter().next()?;
+                let mut cases = Vec::new();
+                let mut patterns = None;
+                loop {
+                    let (body, end) = self.block(&["case", "end"]);
+                    if let Some(patterns) = patterns.take() {
+                        cases.push((patterns, body));
+                    }
+                    if end.as_deref() != Some("case") {
+                        break;
+                    }
+                    patterns = Some(self.words());
+                }
+                Statement::Switch { line, word, cases }
+            }
+            // Function bodies only run when called
+            Some("function") => {
+                self.skip_statement();
+                self.block(&["end"]);
+                self.skip_statement();
+                return None;
+            }
+            _ => return self.job().map(Statement::Job),
+        };
+        self.skip_statement();
+        Some(statement)
+    }
+
+    /// An `if`/`while` test: a job and the `and`/`or` jobs right after it
+    fn condition(&mut self) -> Vec<Statement> {
+        let mut jobs: Vec<Statement> = self.job().map(Statement::Job).into_iter().collect();
+        loop {
+            self.skip_ends();
+            if !matches!(self.peek_keyword(), Some("and" | "or")) {
+                return jobs;
+            }
+            jobs.extend(self.job().map(Statement::Job));
+        }
+    }
+
+    /// The words up to the end of the line
+    fn words(&mut self) -> Vec<FishWord> {
+        let mut words = Vec::new();
+        while let Some(lexed) = self.tokens.get(self.pos) {
+            self.pos += 1;
+            match &lexed.token {
+                Token::Word(word) => words.push(word.clone()),
+                Token::End => break,
+                _ => {}
+            }
+        }
+        words
+    }
+
+    fn job(&mut self) -> Option<Job> {
+        let first = self.tokens.get(self.pos)?;
+        let (line, start) = (first.line, first.start);
+        let mut connector = match self.peek_keyword() {
+            Some("and") => Connector::And,
+            Some("or") => Connector::Or,
+            _ => Connector::First,
+        };
+        if connector != Connector::First {
+            self.pos += 1;
+        }
+        let commands_start = self.tokens.get(self.pos).map_or(first.end, |t| t.start);
+        let mut end = first.end;
+        let mut previous_end = commands_start;
+        let mut commands = Vec::new();
+        let mut words = Vec::new();
+        while let Some(lexed) = self.tokens.get(self.pos) {
+            self.pos += 1;
+            let next = match &lexed.token {
+                Token::Word(word) => {
+                    words.push(word.clone());
+                    end = lexed.end;
+                    continue;
+                }
+                Token::End => None,
+                Token::Pipe => Some(Connector::Pipe),
+                Token::And => Some(Connector::And),
+                Token::Or => Some(Connector::Or),
+            };
+            commands.push(JobCommand {
+                connector,
+                words: std::mem::take(&mut words),
+                guard: self.src[commands_start..previous_end].trim().to_string(),
+            });
+            previous_end = end;
+            match next {
+                Some(next) => connector = next,
+                None => break,
+            }
+        }
+        if !words.is_empty() {
+            commands.push(JobCommand {
+                connector,
+                words,
+                guard: self.src[commands_start..previous_end].trim().to_string(),
+            });
+        }
+        Some(Job {
+            line: Line {
+                number: line,
+                text: self.src[start..end].trim().to_string(),
+            },
+            commands,
+        })
+    }
+}
+
+/// A word expanded to the arguments it stands for
+#[derive(Debug, Default)]
+struct Arguments {
+    values: Vec<String>,
+    /// Source text of references that couldn't be resolved (left as-is in `values`)
+    unresolved: Vec<String>,
+    /// Command substitutions whose output came from actually running them
+    evaluated: Vec<String>,
+}
+
+/// A variable's state before it was made local, restored when the file ends
+#[derive(Debug)]
+enum Shadowed {
+    Untracked,
+    Unset,
+    Set(String, Vec<String>),
+}
+
+/// Options given to `set`
+#[derive(Debug, Default)]
+struct SetOptions {
+    /// `-l`/`-f`: local to the file
+    local: bool,
+    /// `-x` (true) or `-u` (false)
+    export: Option<bool>,
+    erase: bool,
+    append: bool,
+    prepend: bool,
+    query: bool,
+    /// `-n`, `-S`, `-L`: only lists variables
+    list: bool,
+}
+
+/// Walks fish statements in order, tracking every assigned variable
+struct FishWalker<'a> {
+    path: &'a Path,
+    target_var: &'a str,
+    env: &'a mut ShellEnv,
+    on_entry: &'a mut dyn FnMut(ParsedShellEntry, &mut ShellEnv),
+    /// The enclosing branches and how the current path ended
+    state: PathState,
+    /// Variables made local to the file (`set -l`, loop variables)
+    locals: Vec<(String, Shadowed)>,
+    /// The `eval` line changes are reported at while its code runs
+    site: Option<Line>,
+}
+
+impl<'a> FishWalker<'a> {
+    fn new(
+        path: &'a Path,
+        target_var: &'a str,
+        env: &'a mut ShellEnv,
+        guard: &Guard,
+        on_entry: &'a mut dyn FnMut(ParsedShellEntry, &mut ShellEnv),
+    ) -> Self {
+        Self {
+            path,
+            target_var,
+            env,
+            on_entry,
+            state: PathState::new(guard),
+            locals: Vec::new(),
+            site: None,
+        }
+    }
+
+    /// Merge the paths that returned early and restore the file's locals
+    fn finish(&mut self) {
+        self.state.finish_returns(self.env);
+        for (name, shadowed) in std::mem::take(&mut self.locals).into_iter().rev() {
+            match shadowed {
+                Shadowed::Untracked => self.env.forget(&name),
+                Shadowed::Unset => self.env.unset(&name),
+                Shadowed::Set(value, unresolved) => {
+                    self.env.set_with_unresolved(&name, value, unresolved)
+                }
+            }
+        }
+    }
+
+    /// Walk statements in order; returns the exit status of the last one
+    fn walk(&mut self, statements: &[Statement]) -> Truth {
+        let mut status = Truth::True;
+        let mut previous = "";
+        for statement in statements {
+            if self.state.stopped.is_some() {
+                break;
+            }
+            status = self.statement(statement, status, previous);
+            previous = &statement.line().text;
+        }
+        status
+    }
+
+    /// Walk a statement; `status` and `previous` are those of the statement
+    /// before it, which a leading `and`/`or` depends on
+    fn statement(&mut self, statement: &Statement, status: Truth, previous: &str) -> Truth {
+        match statement {
+            Statement::Job(job) => self.job(job, status, previous),
+            Statement::If {
+                branches,
+                else_body,
+                ..
+            } => {
+                let mut walks = Vec::new();
+                let mut failed: Vec<String> = Vec::new();
+                for (test, body) in branches {
+                    let condition = test
+                        .iter()
+                        .map(|s| s.line().text.as_str())
+                        .collect::<Vec<_>>()
+                        .join("; ");
+                    let mut conditions = failed.clone();
+                    conditions.push(condition.clone());
+                    walks.push(BranchWalk {
+                        conditions,
+                        test: Some(test.as_slice()),
+                        matched: Truth::Unknown,
+                        body: body.as_slice(),
+                    });
+                    failed.push(format!("! {}", condition));
+                }
+                if let Some(body) = else_body {
+                    walks.push(BranchWalk {
+                        conditions: failed,
+                        test: None,
+                        matched: Truth::True,
+                        body,
+                    });
+                }
+                self.branches(walks)
+            }
+            Statement::Switch { word, cases, .. } => {
+                let value = {
+                    let args = self.expand(word, false);
+                    match args.values.as_slice() {
+                        [value] if args.unresolved.is_empty() => Some(value.clone()),
+                        _ => None,
+                    }
+                };
+                let walks = cases
+                    .iter()
+                    .map(|(patterns, body)| {
+                        let texts: Vec<&str> = patterns.iter().map(|p| p.text.as_str()).collect();
+                        let matched = match &value {
+                            Some(value) => patterns
+                                .iter()
+                                .map(|p| match self.pattern(p) {
+                                    Some(pattern) => Truth::from_bool(pattern.matches(value)),
+                                    None => Truth::Unknown,
+                                })
+                                .fold(Truth::False, Truth::or),
+                            None => Truth::Unknown,
+                        };
+                        BranchWalk {
+                            conditions: vec![format!(
+                                "switch {}: case {}",
+                                word.text,
+                                texts.join(" ")
+                            )],
+                            test: None,
+                            matched,
+                            body: body.as_slice(),
+                        }
+                    })
+                    .collect();
+                self.branches(walks)
+            }
+            Statement::Begin(_, body) => self.walk(body),
+            Statement::For {
+                line,
+                var,
+                items,
+                body,
+            } => self.in_loop(line, |walker| walker.for_loop(var, items, body)),
+            Statement::While {
+                line,
+                condition,
+                body,
+            } => self.in_loop(line, |walker| {
+                // The body runs an unknown number of times
+                if walker.walk(condition) != Truth::False {
+                    walker.state.undecided += 1;
+                    walker.walk(body);
+                }
+            }),
+        }
+    }
+
+    /// Walk a loop under its header's condition
+    fn in_loop(&mut self, line: &Line, walk: impl FnOnce(&mut Self)) -> Truth {
+        let depth = (
+            self.state.conditions.len(),
+            self.state.path_conditions.len(),
+        );
+        self.state.conditions.push(line.text.clone());
+        let undecided = self.state.undecided;
+        walk(self);
+        // A body that may not run can't stop the file for certain
+        if self.state.undecided > undecided {
+            self.state.stopped = None;
+        }
+        self.state.undecided = undecided;
+        self.state.conditions.truncate(depth.0);
+        self.state.path_conditions.truncate(depth.1);
+        Truth::Unknown
+    }
+
+    /// Walk a for loop body once per item, with the loop variable bound
+    fn for_loop(&mut self, var: &str, items: &[FishWord], body: &[Statement]) {
+        let mut values = Vec::new();
+        let mut known = !var.is_empty();
+        for item in items {
+            let args = self.expand(item, false);
+            known &= args.unresolved.is_empty();
+            values.extend(args.values);
+        }
+        if !var.is_empty() {
+            self.make_local(var);
+        }
+        if !known {
+            // Unknown items: walk the body once without knowing the variable
+            if !var.is_empty() {
+                self.env.forget(var);
+            }
+            self.state.undecided += 1;
+            self.walk(body);
+            return;
+        }
+        for value in values {
+            if self.state.stopped.is_some() {
+                break;
+            }
+            self.env.set(var, value);
+            self.walk(body);
+        }
+    }
+
+    /// Walk the commands of a job, following `&&`/`||` and `and`/`or`
+    fn job(&mut self, job: &Job, mut status: Truth, previous: &str) -> Truth {
+        let commands = &job.commands;
+        for (i, command) in commands.iter().enumerate() {
+            // Pipeline members can't change our environment
+            let piped = command.connector == Connector::Pipe
+                || commands
+                    .get(i + 1)
+                    .is_some_and(|next| next.connector == Connector::Pipe);
+            if piped {
+                // `cmd | source` runs the output of a command
+                if command.connector == Connector::Pipe
+                    && matches!(
+                        command.words.first().and_then(FishWord::keyword),
+                        Some("source" | ".")
+                    )
+                {
+                    self.diagnose(DiagnosticKind::SourcedCommandOutput, Vec::new(), &job.line);
+                }
+                status = Truth::Unknown;
+                continue;
+            }
+
+            let runs = match command.connector {
+                Connector::And => status,
+                Connector::Or => status.not(),
+                _ => Truth::True,
+            };
+            if runs == Truth::False {
+                continue;
+            }
+            let text = if i == 0 { previous } else { &command.guard };
+            let guard = match command.connector {
+                Connector::And if !text.is_empty() => Some(text.to_string()),
+                Connector::Or if !text.is_empty() && i > 1 => Some(format!("! ({})", text)),
+                Connector::Or if !text.is_empty() => Some(format!("! {}", text)),
+                _ => None,
+            };
+
+            let depth = self.state.conditions.len();
+            if let Some(ref guard) = guard {
+                self.state.conditions.push(guard.clone());
+            }
+            // With an undecided guard, the command may or may not have run
+            let fork = match (runs, guard) {
+                (Truth::Unknown, Some(guard)) => {
+                    self.state.undecided += 1;
+                    self.state.path_conditions.push(guard.clone());
+                    Some((
+                        guard,
+                        self.state.path_conditions.len() - 1,
+                        self.env.clone(),
+                    ))
+                }
+                _ => None,
+            };
+            let result = self.command(&command.words, &job.line);
+            if self.state.conditions.len() > depth {
+                self.state.conditions.remove(depth);
+            }
+            if let Some((guard, index, before)) = fork {
+                self.state.undecided -= 1;
+                let ran = self.state.path_conditions.clone();
+                self.state.path_conditions.remove(index);
+                if self.state.stopped.take().is_some() {
+                    // Only the path that skipped the command goes on
+                    self.env.resume(before);
+                    self.state.assume(negate(&guard));
+                    status = Truth::from_bool(command.connector == Connector::Or);
+                    continue;
+                }
+                let mut skipped = self.state.path_conditions.clone();
+                skipped.push(negate(&guard));
+                let after = self.env.clone();
+                self.env.merge(vec![(skipped, before), (ran, after)]);
+            }
+            if self.state.stopped.is_some() {
+                break;
+            }
+
+            status = match command.connector {
+                Connector::And => status.and(result),
+                Connector::Or => status.or(result),
+                _ => result,
+            };
+        }
+        status
+    }
+
+    fn command(&mut self, words: &[FishWord], line: &Line) -> Truth {
+        let Some((name, args)) = words.split_first() else {
+            return Truth::True;
+        };
+        match name.keyword() {
+            Some("not" | "!") => self.command(args, line).not(),
+            Some("builtin") => self.command(args, line),
+            Some("command") if !args.first().is_some_and(|w| w.text.starts_with('-')) => {
+                self.command(args, line)
+            }
+            Some("set") => self.set(args, line),
+            Some("fish_add_path") => self.fish_add_path(args, line),
+            Some("source" | ".") => {
+                self.source(args, line);
+                Truth::True
+            }
+            Some("eval") => self.eval(args, line),
+            Some("return") => {
+                self.stop(StopKind::Return, line);
+                Truth::True
+            }
+            Some("exit") => {
+                self.stop(StopKind::Exit, line);
+                Truth::True
+            }
+            Some("exec") => {
+                self.stop(StopKind::Exec, line);
+                Truth::True
+            }
+            _ => self.test(words),
+        }
+    }
+
+    /// The exit status of a command that only inspects the environment
+    /// (`test`, `status is-login`, `type -q`, `contains`)
+    fn test(&self, words: &[FishWord]) -> Truth {
+        let Some(name) = words[0].literal() else {
+            return Truth::Unknown;
+        };
+        match name.as_str() {
+            "status" => {
+                let option = match words.get(1).and_then(FishWord::literal).as_deref() {
+                    Some("is-interactive" | "--is-interactive" | "-i") => "interactive",
+                    Some("is-login" | "--is-login" | "-l") => "login",
+                    _ => return Truth::Unknown,
+                };
+                return self
+                    .env
+                    .option(option)
+                    .map_or(Truth::Unknown, Truth::from_bool);
+            }
+            "true" | "false" | "test" | "[" | "type" | "command" | "contains" => {}
+            _ => return Truth::Unknown,
+        }
+        let mut argv = Vec::new();
+        for word in words {
+            let args = self.expand(word, false);
+            if !args.unresolved.is_empty() {
+                return Truth::Unknown;
+            }
+            argv.extend(args.values);
+        }
+        match name.as_str() {
+            "contains" => {
+                let operands: Vec<&String> = argv[1..]
+                    .iter()
+                    .skip_while(|a| matches!(a.as_str(), "-i" | "--index"))
+                    .collect();
+                let operands = match operands.split_first() {
+                    Some((first, rest)) if first.as_str() == "--" => rest,
+                    _ => &operands[..],
+                };
+                match operands.split_first() {
+                    Some((needle, list)) => Truth::from_bool(list.contains(needle)),
+                    None => Truth::Unknown,
+                }
+            }
+            // `type -q`, `command -sq` look commands up without running them
+            "type" | "command" => {
+                let mut names = vec!["type".to_string()];
+                names.extend(argv[1..].iter().filter(|a| !a.starts_with('-')).cloned());
+                evaluate_command(&literal_words(names), &self.lookup())
+            }
+            _ => evaluate_command(&literal_words(argv), &self.lookup()),
+        }
+    }
+
+    /// `set [options] NAME[index] values...`
+    fn set(&mut self, args: &[FishWord], line: &Line) -> Truth {
+        let mut options = SetOptions::default();
+        let mut operands = args;
+        while let Some((first, rest)) = operands.split_first() {
+            let Some(text) = first.literal() else {
+                break;
+            };
+            if text == "--" {
+                operands = rest;
+                break;
+            }
+            let flags: Vec<&str> = match text.strip_prefix("--") {
+                Some(long) => vec![long],
+                None if text.len() > 1 && text.starts_with('-') => {
+                    text[1..].split("").filter(|f| !f.is_empty()).collect()
+                }
+                None => break,
+            };
+            for flag in flags {
+                match flag {
+                    "l" | "f" | "local" | "function" => options.local = true,
+                    "x" | "export" => options.export = Some(true),
+                    "u" | "unexport" => options.export = Some(false),
+                    "e" | "erase" => options.erase = true,
+                    "a" | "append" => options.append = true,
+                    "p" | "prepend" => options.prepend = true,
+                    "q" | "query" => options.query = true,
+                    "n" | "S" | "L" | "names" | "show" | "long" => options.list = true,
+                    _ => {}
+                }
+            }
+            operands = rest;
+        }
+
+        if options.list {
+            return Truth::True;
+        }
+        if options.query {
+            return operands
+                .iter()
+                .map(|word| match word.literal().as_deref().map(split_index) {
+                    Some((name, None)) => match self.lookup().lookup(name) {
+                        Lookup::Set(_) => Truth::True,
+                        Lookup::Unset => Truth::False,
+                        Lookup::Unknown => Truth::Unknown,
+                    },
+                    _ => Truth::Unknown,
+                })
+                .fold(Truth::True, Truth::and);
+        }
+        if options.erase {
+            for word in operands {
+                self.erase(word, line);
+            }
+            return Truth::True;
+        }
+        // `set` alone lists every variable
+        let Some((name_word, values)) = operands.split_first() else {
+            return Truth::True;
+        };
+        let Some(name) = name_word.literal() else {
+            self.diagnose(
+                DiagnosticKind::DynamicDeclaration,
+                vec![name_word.text.clone()],
+                line,
+            );
+            return Truth::Unknown;
+        };
+        let (name, index) = split_index(&name);
+        if options.local {
+            // A local copy of the target doesn't change it
+            if name == self.target_var {
+                return Truth::True;
+            }
+            self.make_local(name);
+        }
+
+        let mut args = Arguments::default();
+        let lists: Vec<Vec<String>> = values
+            .iter()
+            .map(|word| {
+                let expanded = self.expand(word, true);
+                args.unresolved.extend(expanded.unresolved);
+                args.evaluated.extend(expanded.evaluated);
+                expanded.values
+            })
+            .collect();
+        let new = lists.concat();
+        let default_op = if options.export == Some(true) {
+            Operation::Export
+        } else {
+            Operation::Set
+        };
+
+        let (operation, list) = if let Some(index) = index {
+            // `set PATH[1] /x` replaces elements
+            let (mut list, unresolved) = self.list(name);
+            let positions = positions(list.len(), index).filter(|p| p.len() == new.len());
+            let Some(positions) = positions.filter(|_| unresolved.is_empty()) else {
+                if name != self.target_var {
+                    self.env.forget(name);
+                }
+                return Truth::Unknown;
+            };
+            for (position, value) in positions.into_iter().zip(new) {
+                list[position] = value;
+            }
+            (Operation::Set, list)
+        } else if options.append || options.prepend {
+            let (current, unresolved) = self.list(name);
+            args.unresolved.extend(unresolved);
+            let mut list = Vec::new();
+            if options.prepend {
+                list.extend(new.iter().cloned());
+            }
+            list.extend(current);
+            if options.append {
+                list.extend(new);
+            }
+            let operation = match (options.append, options.prepend) {
+                (true, false) => Operation::Append,
+                (false, true) => Operation::Prepend,
+                _ => default_op,
+            };
+            (operation, list)
+        } else {
+            // `set PATH /x $PATH` prepends and `set PATH $PATH /x` appends
+            let operation = match values.iter().position(|w| w.is_var(name)) {
+                Some(own) => {
+                    let before = lists[..own].iter().any(|l| !l.is_empty());
+                    let after = lists[own + 1..].iter().any(|l| !l.is_empty());
+                    match (before, after) {
+                        (true, false) => Operation::Prepend,
+                        (false, true) => Operation::Append,
+                        _ => default_op,
+                    }
+                }
+                None => default_op,
+            };
+            (operation, new)
+        };
+
+        if let Some(export) = options.export {
+            self.env.set_exported(name, export);
+        }
+        let expansion = Expansion {
+            value: join_list(name, &list),
+            unresolved: args.unresolved,
+            evaluated: args.evaluated,
+            ..Default::default()
+        };
+        self.store(name, operation, expansion, line);
+        Truth::True
+    }
+
+    /// `set -e NAME` or `set -e NAME[index]`
+    fn erase(&mut self, word: &FishWord, line: &Line) {
+        let Some(text) = word.literal() else {
+            return;
+        };
+        let (name, index) = split_index(&text);
+        let Some(index) = index else {
+            self.store(name, Operation::Unset, Expansion::default(), line);
+            return;
+        };
+        let (list, unresolved) = self.list(name);
+        match positions(list.len(), index).filter(|_| unresolved.is_empty()) {
+            Some(positions) => {
+                let list: Vec<String> = list
+                    .into_iter()
+                    .enumerate()
+                    .filter(|(i, _)| !positions.contains(i))
+                    .map(|(_, value)| value)
+                    .collect();
+                let expansion = Expansion {
+                    value: join_list(name, &list),
+                    ..Default::default()
+                };
+                self.store(name, Operation::Set, expansion, line);
+            }
+            None if name != self.target_var => self.env.forget(name),
+            None => {}
+        }
+    }
+
+    /// `fish_add_path` adds directories that exist and aren't listed yet to
+    /// `fish_user_paths` (or to PATH itself with `--path`), at the front
+    /// unless `--append`; with `--move` listed ones move instead
+    fn fish_add_path(&mut self, args: &[FishWord], line: &Line) -> Truth {
+        let (mut to_path, mut append, mut move_listed, mut dry_run) = (false, false, false, false);
+        let mut operands = args;
+        while let Some((first, rest)) = operands.split_first() {
+            let Some(text) = first.literal() else {
+                break;
+            };
+            if text == "--" {
+                operands = rest;
+                break;
+            }
+            let flags: Vec<&str> = match text.strip_prefix("--") {
+                Some(long) => vec![long],
+                None if text.len() > 1 && text.starts_with('-') => {
+                    text[1..].split("").filter(|f| !f.is_empty()).collect()
+                }
+                None => break,
+            };
+            for flag in flags {
+                match flag {
+                    "P" | "path" => to_path = true,
+                    "a" | "append" => append = true,
+                    "p" | "prepend" => append = false,
+                    "m" | "move" => move_listed = true,
+                    "n" | "dry-run" => dry_run = true,
+                    _ => {}
+                }
+            }
+            operands = rest;
+        }
+
+        let var = if to_path { "PATH" } else { "fish_user_paths" };
+        let (mut list, mut unresolved) = self.list(var);
+        let cwd = match self.lookup().lookup("PWD") {
+            Lookup::Set(cwd) => cwd,
+            _ => "/".to_string(),
+        };
+        let mut added: Vec<String> = Vec::new();
+        for word in operands {
+            let dirs = self.expand(word, false);
+            let known = dirs.unresolved.is_empty();
+            unresolved.extend(dirs.unresolved);
+            for dir in dirs.values {
+                // Directories that don't exist are skipped (unknown ones, or any on a
+                // simulated machine, are kept)
+                let dir = if known {
+                    let dir = resolve_path(&cwd, &dir);
+                    if !self.env.is_foreign_host() && !Path::new(&dir).is_dir() {
+                        continue;
+                    }
+                    dir
+                } else {
+                    dir
+                };
+                if added.contains(&dir) {
+                    continue;
+                }
+                match list.iter().position(|listed| *listed == dir) {
+                    Some(i) if move_listed => {
+                        list.remove(i);
+                    }
+                    Some(_) => continue,
+                    None => {}
+                }
+                added.push(dir);
+            }
+        }
+        if added.is_empty() {
+            return Truth::False;
+        }
+        if dry_run {
+            return Truth::True;
+        }
+        let operation = if append {
+            list.extend(added);
+            Operation::Append
+        } else {
+            added.extend(list);
+            list = added;
+            Operation::Prepend
+        };
+        let expansion = Expansion {
+            value: join_list(var, &list),
+            unresolved,
+            ..Default::default()
+        };
+        self.store(var, operation, expansion, line);
+        Truth::True
+    }
+
+    /// Read a sourced file
+    fn source(&mut self, args: &[FishWord], line: &Line) {
+        // `source` alone reads standard input (see `cmd | source` in `job`)
+        let Some(word) = args.first() else {
+            return;
+        };
+        let path = self.expand(word, false);
+        let mut value = match path.values.as_slice() {
+            [value] if path.unresolved.is_empty() => value.clone(),
+            _ => {
+                let kind = if word.parts.iter().any(|p| matches!(p, Part::Command { .. })) {
+                    DiagnosticKind::SourcedCommandOutput
+                } else {
+                    DiagnosticKind::UnresolvedSource
+                };
+                self.diagnose(kind, path.unresolved, line);
+                return;
+            }
+        };
+        if !value.starts_with('/') {
+            match self.lookup().lookup("PWD") {
+                Lookup::Set(cwd) if cwd.starts_with('/') => value = resolve_path(&cwd, &value),
+                _ => {
+                    self.diagnose(
+                        DiagnosticKind::UnresolvedSource,
+                        vec!["$PWD".to_string()],
+                        line,
+                    );
+                    return;
+                }
+            }
+        }
+        let guard = Guard {
+            conditions: self.state.conditions.clone(),
+            undecided: self.state.path_conditions.clone(),
+        };
+        (self.on_entry)(
+            ParsedShellEntry::Source(SourcedFile {
+                path: PathBuf::from(value),
+                guard,
+            }),
+            self.env,
+        );
+        if self.env.has_exited() {
+            self.state.stopped = Some(Stopped::File(StopKind::Exit));
+        }
+    }
+
+    /// Run code known from traced values in place; changes are reported at
+    /// the `eval` line
+    fn eval(&mut self, args: &[FishWord], line: &Line) -> Truth {
+        let mut code = Vec::new();
+        let mut unresolved = Vec::new();
+        for word in args {
+            let args = self.expand(word, false);
+            code.extend(args.values);
+            unresolved.extend(args.unresolved);
+        }
+        if !unresolved.is_empty() {
+            self.diagnose(DiagnosticKind::DynamicEval, unresolved, line);
+            return Truth::Unknown;
+        }
+        let statements = parse(&code.join(" "));
+        let site = self.site.clone().unwrap_or_else(|| line.clone());
+        let outer = self.site.replace(site);
+        let status = self.walk(&statements);
+        self.site = outer;
+        status
+    }
+
+    /// Stop reading the file on the current path
+    fn stop(&mut self, kind: StopKind, line: &Line) {
+        let line = self.site.as_ref().unwrap_or(line);
+        let stop = StopPoint {
+            file: self.path.to_path_buf(),
+            line_number: line.number,
+            line_content: line.text.clone(),
+            kind,
+            conditions: self.state.conditions.clone(),
+            conditional: self.state.undecided > 0,
+        };
+        (self.on_entry)(ParsedShellEntry::Stop(stop), self.env);
+        self.state.stop(kind, self.env);
+    }
+
+    /// Report a line that couldn't be followed or analyzed
+    fn diagnose(&mut self, kind: DiagnosticKind, unresolved: Vec<String>, line: &Line) {
+        let line = self.site.as_ref().unwrap_or(line);
+        let diagnostic = Diagnostic {
+            file: self.path.to_path_buf(),
+            line_number: line.number,
+            line_content: line.text.clone(),
+            kind,
+            unresolved,
+        };
+        (self.on_entry)(ParsedShellEntry::Diagnostic(diagnostic), self.env);
+    }
+
+    /// Make a variable local to the file, so it's restored when the file ends
+    fn make_local(&mut self, name: &str) {
+        if self.locals.iter().any(|(local, _)| local == name) {
+            return;
+        }
+        let shadowed = match self.env.get(name) {
+            Some(value) => Shadowed::Set(value.to_string(), self.env.unresolved(name).to_vec()),
+            None if self.env.is_tracked(name) => Shadowed::Unset,
+            None => Shadowed::Untracked,
+        };
+        self.locals.push((name.to_string(), shadowed));
+    }
+
+    /// Update a variable: the target is recorded, anything else is only tracked
+    fn store(&mut self, name: &str, operation: Operation, expansion: Expansion, line: &Line) {
+        if name == self.target_var {
+            self.record(operation, expansion, line, None);
+        } else if operation == Operation::Unset {
+            self.env.unset(name);
+        } else {
+            self.env
+                .set_with_unresolved(name, expansion.value, expansion.unresolved);
+        }
+        let value = self.env.get(name).map(str::to_string);
+        self.env.set_candidates(
+            name,
+            vec![ValueCandidate {
+                value,
+                conditions: self.state.path_conditions.clone(),
+            }],
+        );
+        if name == "fish_user_paths" {
+            self.reconstruct_path(line);
+        }
+    }
+
+    /// Emulate `__fish_reconstruct_path`, which fish runs whenever
+    /// `fish_user_paths` changes: the user paths go to the front of PATH,
+    /// replacing the ones it added last time
+    fn reconstruct_path(&mut self, line: &Line) {
+        let (user_paths, mut unresolved) = self.list("fish_user_paths");
+        let (added, _) = self.list("__fish_added_user_paths");
+        let (before, path_unresolved) = self.list("PATH");
+        unresolved.extend(path_unresolved);
+
+        let mut path = before.clone();
+        for dir in &added {
+            if let Some(i) = path.iter().position(|p| p == dir) {
+                path.remove(i);
+            }
+        }
+        let mut newly_added = Vec::new();
+        for dir in user_paths.iter().rev() {
+            match path.iter().position(|p| p == dir) {
+                Some(i) => {
+                    path.remove(i);
+                }
+                None => newly_added.push(dir.clone()),
+            }
+            path.insert(0, dir.clone());
+        }
+        self.env.set(
+            "__fish_added_user_paths",
+            join_list("__fish_added_user_paths", &newly_added),
+        );
+        if path == before {
+            return;
+        }
+
+        let operation = if user_paths.is_empty() {
+            Operation::Set
+        } else {
+            Operation::Prepend
+        };
+        let expansion = Expansion {
+            value: join_list("PATH", &path),
+            unresolved,
+            ..Default::default()
+        };
+        if self.target_var == "PATH" {
+            self.record(operation, expansion, line, Some(RECONSTRUCT_PATH));
+        } else {
+            self.env
+                .set_with_unresolved("PATH", expansion.value, expansion.unresolved);
+        }
+        let value = self.env.get("PATH").map(str::to_string);
+        self.env.set_candidates(
+            "PATH",
+            vec![ValueCandidate {
+                value,
+                conditions: self.state.path_conditions.clone(),
+            }],
+        );
+    }
+
+    fn record(
+        &mut self,
+        operation: Operation,
+        expansion: Expansion,
+        line: &Line,
+        emulated: Option<&str>,
+    ) {
+        // Changes in branches that may or may not run
+        let operation = if self.state.undecided > 0
+            && !matches!(operation, Operation::Unset | Operation::Unexport)
+        {
+            Operation::Conditional
+        } else {
+            operation
+        };
+        let line = self.site.as_ref().unwrap_or(line);
+        let change = VariableChange {
+            file: self.path.to_path_buf(),
+            line_number: line.number,
+            line_content: line.text.clone(),
+            operation,
+            value_before: self.env.get(self.target_var).map(|s| s.to_string()),
+            value_after: expansion.value,
+            unresolved: expansion.unresolved,
+            evaluated: expansion.evaluated,
+            conditions: self.state.conditions.clone(),
+            function: None,
+            emulated: emulated.map(str::to_string),
+        };
+        match operation {
+            Operation::Unset => self.env.unset(self.target_var),
+            _ => self.env.set_with_unresolved(
+                self.target_var,
+                change.value_after.clone(),
+                change.unresolved.clone(),
+            ),
+        }
+        (self.on_entry)(ParsedShellEntry::Assignment(change), self.env);
+    }
+
+    fn lookup(&self) -> FishLookup<'_> {
+        FishLookup {
+            env: self.env,
+            target_var: self.target_var,
+        }
+    }
+
+    /// A variable's elements and the references they couldn't resolve
+    fn list(&self, name: &str) -> (Vec<String>, Vec<String>) {
+        let args = self.variable(name, None, false);
+        (args.values, args.unresolved)
+    }
+
+    /// The arguments `$name` or `$name[index]` expands to
+    fn variable(&self, name: &str, index: Option<&str>, quoted: bool) -> Arguments {
+        let mut args = Arguments::default();
+        let source = match index {
+            Some(index) => format!("${}[{}]", name, index),
+            None => format!("${}", name),
+        };
+        match self.lookup().lookup(name) {
+            Lookup::Set(value) => {
+                if name != self.target_var {
+                    args.unresolved.extend_from_slice(self.env.unresolved(name));
+                }
+                let mut list = split_list(name, &value);
+                if let Some(index) = index {
+                    match positions(list.len(), index) {
+                        Some(positions) => {
+                            list = positions.into_iter().map(|i| list[i].clone()).collect();
+                        }
+                        None => {
+                            args.values.push(source.clone());
+                            args.unresolved.push(source);
+                            return args;
+                        }
+                    }
+                }
+                args.values = if quoted {
+                    vec![join_list(name, &list)]
+                } else {
+                    list
+                };
+            }
+            Lookup::Unset if quoted => args.values.push(String::new()),
+            Lookup::Unset => {}
+            Lookup::Unknown => {
+                args.values.push(source.clone());
+                args.unresolved.push(source);
+            }
+        }
+        args
+    }
+
+    /// Expand a word to its arguments: each part multiplies them (`$dirs/bin`
+    /// gives one argument per element of `dirs`), and an unquoted `*`
+    /// matches files
+    fn expand(&self, word: &FishWord, run_commands: bool) -> Arguments {
+        let mut args = Arguments::default();
+        // Each argument so far, with the glob pattern that matches it
+        let mut combined = vec![(String::new(), String::new())];
+        let mut glob = false;
+        for part in &word.parts {
+            let values: Vec<String> = match part {
+                Part::Text { text, quoted } => {
+                    let pattern = if *quoted {
+                        Pattern::escape(text)
+                    } else {
+                        glob |= text.contains('*');
+                        text.split('*')
+                            .map(Pattern::escape)
+                            .collect::<Vec<_>>()
+                            .join("*")
+                    };
+                    combined = combined
+                        .into_iter()
+                        .map(|(value, p)| (value + text, p + &pattern))
+                        .collect();
+                    continue;
+                }
+                Part::Home(user) => {
+                    let home = match (user.as_str(), self.lookup().lookup("HOME")) {
+                        ("", Lookup::Set(home)) => Some(home),
+                        ("", _) => None,
+                        (user, _) => home_dir_of(user).map(|h| h.to_string_lossy().to_string()),
+                    };
+                    vec![home.unwrap_or_else(|| {
+                        let source = format!("~{}", user);
+                        args.unresolved.push(source.clone());
+                        source
+                    })]
+                }
+                Part::Var {
+                    name,
+                    index,
+                    quoted,
+                } => {
+                    let expanded = self.variable(name, index.as_deref(), *quoted);
+                    args.unresolved.extend(expanded.unresolved);
+                    expanded.values
+                }
+                Part::Command { text, quoted } => match self.command_output(text, run_commands) {
+                    Some((output, evaluated)) => {
+                        if evaluated {
+                            args.evaluated.push(format!("({})", text));
+                        }
+                        let output = output.trim_end_matches('\n');
+                        if *quoted {
+                            vec![output.to_string()]
+                        } else {
+                            output.lines().map(str::to_string).collect()
+                        }
+                    }
+                    None => {
+                        let source = format!("({})", text);
+                        args.unresolved.push(source.clone());
+                        vec![source]
+                    }
+                },
+            };
+            combined = combined
+                .iter()
+                .flat_map(|(value, pattern)| {
+                    values.iter().map(move |v| {
+                        (
+                            format!("{}{}", value, v),
+                            pattern.clone() + &Pattern::escape(v),
+                        )
+                    })
+                })
+                .take(MAX_ITEMS)
+                .collect();
+        }
+        args.values = if glob && args.unresolved.is_empty() {
+            combined
+                .iter()
+                .flat_map(|(_, pattern)| expand_glob(pattern, &self.lookup()))
+                .map(|path| path.to_string_lossy().to_string())
+                .collect()
+        } else {
+            combined.into_iter().map(|(value, _)| value).collect()
+        };
+        args
+    }
+
+    /// A `case` pattern: unquoted `*` and `?` are wildcards
+    fn pattern(&self, word: &FishWord) -> Option<Pattern> {
+        let mut pattern = String::new();
+        for part in &word.parts {
+            match part {
+                Part::Text {
+                    text,
+                    quoted: false,
+                } => pattern.push_str(text),
+                _ => {
+                    let args = self.expand(
+                        &FishWord {
+                            parts: vec![part.clone()],
+                            text: String::new(),
+                        },
+                        false,
+                    );
+                    match args.values.as_slice() {
+                        [value] if args.unresolved.is_empty() => {
+                            pattern.push_str(&Pattern::escape(value))
+                        }
+                        _ => return None,
+                    }
+                }
+            }
+        }
+        Some(Pattern::new(&pattern))
+    }
+
+    /// The output of a command substitution, and whether it came from
+    /// running it (`--eval-commands`)
+    fn command_output(&self, text: &str, run_commands: bool) -> Option<(String, bool)> {
+        let statements = parse(text);
+        let [Statement::Job(job)] = statements.as_slice() else {
+            return None;
+        };
+        let [command] = job.commands.as_slice() else {
+            return None;
+        };
+        let (name, args) = command.words.split_first()?;
+        if name.keyword() == Some("status") {
+            return match args.first().and_then(FishWord::keyword) {
+                Some("filename" | "current-filename" | "-f" | "--current-filename") => {
+                    Some((format!("{}\n", self.path.display()), false))
+                }
+                Some("dirname" | "current-dirname") => self
+                    .path
+                    .parent()
+                    .map(|dir| (format!("{}\n", dir.display()), false)),
+                _ => None,
+            };
+        }
+        let mut argv = Vec::new();
+        for word in &command.words {
+            let args = self.expand(word, run_commands);
+            if !args.unresolved.is_empty() {
+                return None;
+            }
+            argv.extend(args.values);
+        }
+        let command = argv
+            .iter()
+            .map(|arg| shell_quote(arg))
+            .collect::<Vec<_>>()
+            .join(" ");
+        if let Some(output) = self.lookup().command_output(&command) {
+            return Some((output, false));
+        }
+        if !run_commands {
+            return None;
+        }
+        self.env
+            .evaluate(&command, &format!("({})", text), &HashMap::new())
+            .map(|output| (output, true))
+    }
+}
+
+impl Branching for FishWalker<'_> {
+    type Block = [Statement];
+
+    fn state(&mut self) -> &mut PathState {
+        &mut self.state
+    }
+
+    fn env(&mut self) -> &mut ShellEnv {
+        self.env
+    }
+
+    fn walk_block(&mut self, block: &[Statement]) -> Truth {
+        self.walk(block)
+    }
+}
+
+/// Split `NAME[index]` into the name and index
+fn split_index(text: &str) -> (&str, Option<&str>) {
+    match text.split_once('[') {
+        Some((name, rest)) => (name, Some(rest.strip_suffix(']').unwrap_or(rest))),
+        None => (text, None),
+    }
+}
+
+/// Words for [`evaluate_command`] from arguments that are already expanded
+fn literal_words(args: Vec<String>) -> Vec<Word> {
+    args.into_iter()
+        .map(|arg| {
+            let part = if arg == "]" {
+                WordPart::Literal(arg)
+            } else {
+                WordPart::Quoted(arg)
+            };
+            Word {
+                parts: vec![part],
+                span: Span::default(),
+            }
+        })
+        .collect()
+}
+
+/// Variable values for expansions and conditions in a fish script
+struct FishLookup<'a> {
+    env: &'a ShellEnv,
+    target_var: &'a str,
+}
+
+impl VarLookup for FishLookup<'_> {
+    fn lookup(&self, name: &str) -> Lookup {
+        if self.env.is_tracked(name) {
+            return match self.env.get(name) {
+                Some(value) => Lookup::Set(value.to_string()),
+                None => Lookup::Unset,
+            };
+        }
+        // The target and the variables fish manages itself start out unset
+        if name == self.target_var || matches!(name, "fish_user_paths" | "__fish_added_user_paths")
+        {
+            return Lookup::Unset;
+        }
+        if let Some(value) = self.env.session_value(name) {
+            return match value {
+                Some(v) => Lookup::Set(v.to_string()),
+                None => Lookup::Unset,
+            };
+        }
+        match name {
+            "HOME" => match dirs::home_dir() {
+                Some(home) => Lookup::Set(home.to_string_lossy().to_string()),
+                None => Lookup::Unknown,
+            },
+            // Startup files are read in the home directory
+            "PWD" => self.lookup("HOME"),
+            "__fish_config_dir" => match self.lookup("XDG_CONFIG_HOME") {
+                Lookup::Set(dir) if dir.starts_with('/') => Lookup::Set(format!("{}/fish", dir)),
+                _ => match self.lookup("HOME") {
+                    Lookup::Set(home) => Lookup::Set(format!("{}/.config/fish", home)),
+                    other => other,
+                },
+            },
+            _ => Lookup::Unknown,
+        }
+    }
+
+    fn command_output(&self, command: &str) -> Option<String> {
+        self.env
+            .command_output(command)
+            .or_else(|| path_command_output(command, self))
+    }
+}
+
+impl ShellState for FishLookup<'_> {
+    fn option(&self, name: &str) -> Option<bool> {
+        self.env.option(name)
+    }
+
+    fn foreign_host(&self) -> bool {
+        self.env.is_foreign_host()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_fish_content(
+        content: &str,
+        target_var: &str,
+        env: &mut ShellEnv,
+    ) -> Vec<VariableChange> {
+        let mut results = Vec::new();
+        let path = Path::new("/test/config.fish");
+        let guard = Guard::default();
+        let mut on_entry = |entry, _: &mut ShellEnv| {
+            if let ParsedShellEntry::Assignment(change) = entry {
+                results.push(change);
+            }
+        };
+        let mut walker = FishWalker::new(path, target_var, env, &guard, &mut on_entry);
+        walker.walk(&parse(content));
+        walker.finish();
+        results
+    }
+
+    #[test]
+    fn test_set_path() {
+        let mut env = ShellEnv::new();
+        env.set("PATH", "/usr/bin");
+        let content = "set -gx PATH /opt/bin $PATH\nset PATH $PATH /late\nset -a PATH /x /y\nset -e PATH[1]\n";
+        let changes = parse_fish_content(content, "PATH", &mut env);
+        let operations: Vec<Operation> = changes.iter().map(|c| c.operation).collect();
+        assert_eq!(
+            operations,
+            [
+                Operation::Prepend,
+                Operation::Append,
+                Operation::Append,
+                Operation::Set
+            ]
+        );
+        assert_eq!(changes[0].value_after, "/opt/bin:/usr/bin");
+        assert_eq!(changes[3].value_after, "/usr/bin:/late:/x:/y");
+        assert_eq!(env.is_exported("PATH"), Some(true));
+    }
+
+    #[test]
+    fn test_malformed_input_terminates() {
+        for content in [")", "set -gx A 1 )", "echo (", "if )\nend\n", "end\nelse\n"] {
+            let mut env = ShellEnv::new();
+            parse_fish_content(content, "A", &mut env);
+        }
+        let mut env = ShellEnv::new();
+        let changes = parse_fish_content(")\nset -gx A 1\n", "A", &mut env);
+        assert_eq!(changes[0].value_after, "1");
+    }
+
+    #[test]
+    fn test_set_local_and_erase() {
+        let mut env = ShellEnv::new();
+        let content = "set -l dir /opt/tool\nset -gx TOOL_HOME $dir/home\nset -e TOOL_HOME\n";
+        let changes = parse_fish_content(content, "TOOL_HOME", &mut env);
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].operation, Operation::Export);
+        assert_eq!(changes[0].value_after, "/opt/tool/home");
+        assert_eq!(changes[1].operation, Operation::Unset);
+        // Locals don't outlive the file
+        assert!(!env.is_tracked("dir"));
+    }
+
+    #[test]
+    fn test_branches() {
+        let mut env = ShellEnv::new();
+        env.set_option("interactive", false);
+        let content = "\
+if status is-interactive
+    set -gx EDITOR vim
+else if test -n \"$SSH_TTY\"
+    set -gx EDITOR nano
+end
+contains /x $PATH; or set -gx EDITOR ed
+";
+        let changes = parse_fish_content(content, "EDITOR", &mut env);
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].value_after, "nano");
+        assert_eq!(changes[0].operation, Operation::Conditional);
+        assert_eq!(
+            changes[0].conditions,
+            ["! status is-interactive", "test -n \"$SSH_TTY\""]
+        );
+        assert_eq!(changes[1].conditions, ["! contains /x $PATH"]);
+    }
+
+    #[test]
+    fn test_fish_add_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let bin = dir.path().join("bin");
+        std::fs::create_dir(&bin).unwrap();
+        let mut env = ShellEnv::new();
+        env.set("PATH", "/usr/bin");
+        let content = format!(
+            "fish_add_path {bin} /nonexistent\nfish_add_path {bin}\nfish_add_path -aP {bin}\n",
+            bin = bin.display()
+        );
+        let changes = parse_fish_content(&content, "PATH", &mut env);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].operation, Operation::Prepend);
+        assert_eq!(changes[0].emulated.as_deref(), Some(RECONSTRUCT_PATH));
+        assert_eq!(
+            changes[0].value_after,
+            format!("{}:/usr/bin", bin.display())
+        );
+    }
+
+    #[test]
+    fn test_fish_variables() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("fish_variables");
+        std::fs::write(
+            &path,
+            "# This file contains fish universal variable definitions.\n\
+             SETUVAR --export GOPATH:\\x7e/go\n\
+             SETUVAR fish_user_paths:/opt/a\\x1e/opt/b\n",
+        )
+        .unwrap();
+        let mut env = ShellEnv::new();
+        env.set("PATH", "/usr/bin");
+        let mut changes = Vec::new();
+        parse_fish_variables(&path, "PATH", &mut env, &mut |entry, _| {
+            if let ParsedShellEntry::Assignment(change) = entry {
+                changes.push(change);
+            }
+        })
+        .unwrap();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].value_after, "/opt/a:/opt/b:/usr/bin");
+        assert_eq!(env.get("GOPATH"), Some("~/go"));
+        assert_eq!(env.is_exported("GOPATH"), Some(true));
+
+        // Changing the user paths replaces the ones added before
+        let changes = parse_fish_content("set -U fish_user_paths /opt/c", "PATH", &mut env);
+        assert_eq!(changes[0].value_after, "/opt/c:/usr/bin");
+    }
+
+    #[test]
+    fn test_positions() {
+        assert_eq!(positions(3, "1"), Some(vec![0]));
+        assert_eq!(positions(3, "-1"), Some(vec![2]));
+        assert_eq!(positions(3, "2..-1"), Some(vec![1, 2]));
+        assert_eq!(positions(3, "5"), Some(vec![]));
+        assert_eq!(positions(3, "$i"), None);
+    }
+}
@@ -0,0 +1,452 @@
+//! Pathname expansion and commands that work with paths
+//!
+//! Drop-in directories are read with `for rc in ~/.bashrc.d/*` or by looping
+//! over `$(ls ...)`/`$(find ...)`, and files next to a script are found with
+//! `$(dirname "${BASH_SOURCE[0]}")` or `$(cd ... && pwd)`. These are answered
+//! the way the shell would, so the files they source can be followed.
+
+use std::path::{Component, Path, PathBuf};
+
+use super::expand::{Lookup, VarLookup, expand_argument, glob_word};
+use super::lexer::Word;
+use super::pattern::Pattern;
+use super::script::{Command, Connector, Script};
+
+/// Paths matching a glob pattern (as built by [`glob_word`]), sorted the way
+/// the shell sorts them. Names starting with `.` only match a pattern that
+/// starts with a literal `.`, and relative patterns are matched in the
+/// shell's working directory. Returns an empty list if nothing matches.
+pub fn expand_glob(pattern: &str, vars: &dyn VarLookup) -> Vec<PathBuf> {
+    let (root, rest) = match pattern.strip_prefix('/') {
+        Some(rest) => (PathBuf::from("/"), rest),
+        None => (PathBuf::new(), pattern),
+    };
+    let cwd = working_directory(vars).map(PathBuf::from);
+    let on_disk = |path: &Path| match &cwd {
+        _ if path.is_absolute() => Some(path.to_path_buf()),
+        Some(cwd) => Some(cwd.join(path)),
+        None => None,
+    };
+    let mut paths = vec![root];
+    for component in rest.split('/').filter(|c| !c.is_empty()) {
+        let pattern = Pattern::new(component);
+        paths = match pattern.literal() {
+            Some(name) => paths.into_iter().map(|p| p.join(&name)).collect(),
+            None => paths
+                .iter()
+                .flat_map(|dir| matching_entries(dir, on_disk(dir), &pattern))
+                .collect(),
+        };
+    }
+    paths.retain(|p| on_disk(p).is_some_and(|p| p.symlink_metadata().is_ok()));
+    paths
+}
+
+/// Entries of `dir` (found at `location`) whose names match `pattern`, in
+/// byte order (the order the shell uses with `LC_COLLATE=C`)
+fn matching_entries(dir: &Path, location: Option<PathBuf>, pattern: &Pattern) -> Vec<PathBuf> {
+    let Some(Ok(entries)) = location.map(std::fs::read_dir) else {
+        return Vec::new();
+    };
+    let mut names: Vec<String> = entries
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .filter(|name| !name.starts_with('.') || pattern.starts_with_dot())
+        .filter(|name| pattern.matches(name))
+        .collect();
+    names.sort();
+    names.into_iter().map(|name| dir.join(name)).collect()
+}
+
+/// Join a path onto the working directory and drop `.` and `..` components
+/// the way `cd` does (without resolving symlinks)
+pub fn resolve_path(cwd: &str, path: &str) -> String {
+    let mut resolved = PathBuf::from("/");
+    for component in Path::new(cwd).join(path).components() {
+        match component {
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            Component::Normal(name) => resolved.push(name),
+            _ => {}
+        }
+    }
+    resolved.to_string_lossy().to_string()
+}
+
+/// The output of a command that works with paths: `ls` or `find` listing
+/// files (optionally piped to `sort`), `dirname`, `basename`, `realpath`,
+/// `readlink -f`, `pwd` and `cd dir && pwd`. Returns None for anything else,
+/// or if an argument couldn't be resolved.
+pub fn path_command_output(command: &str, vars: &dyn VarLookup) -> Option<String> {
+    let script = Script::parse(command);
+    let [statement] = script.statements.as_slice() else {
+        return None;
+    };
+    let (first, rest) = statement.commands.split_first()?;
+
+    // `cd dir && pwd` prints the directory
+    if let [(Connector::And, Command::Simple(print))] = rest
+        && let Command::Simple(cd) = &first.1
+        && cd.words.first()?.as_literal()? == "cd"
+        && print.words.first()?.as_literal()? == "pwd"
+    {
+        let operands = expand_operands(&cd.words[1..], vars)?;
+        let dir = match operands.iter().find(|op| !op.starts_with('-')) {
+            Some(dir) => absolute_path(dir, vars)?,
+            None => lookup_value(vars, "HOME")?,
+        };
+        let physical = operands.iter().any(|op| op == "-P");
+        if !Path::new(&dir).is_dir() {
+            return None;
+        }
+        return pwd(&dir, physical, &print.words);
+    }
+
+    let Command::Simple(cmd) = &first.1 else {
+        return None;
+    };
+    let (name, args) = cmd.words.split_first()?;
+    match (name.as_literal()?.as_str(), rest) {
+        ("ls" | "find", _) => {}
+        ("pwd", []) => return pwd(&working_directory(vars)?, false, &cmd.words),
+        ("dirname" | "basename" | "realpath" | "readlink", []) => {
+            let operands = expand_operands(args, vars)?;
+            return path_utility(&name.as_literal()?, &operands, vars);
+        }
+        _ => return None,
+    }
+
+    // Output is already sorted, so a trailing `| sort` changes nothing
+    for (connector, command) in rest {
+        let Command::Simple(cmd) = command else {
+            return None;
+        };
+        let words: Vec<String> = cmd.words.iter().filter_map(|w| w.as_literal()).collect();
+        if *connector != Connector::Pipe || words != ["sort"] {
+            return None;
+        }
+    }
+    let operands = expand_operands(args, vars)?;
+    let lines = match name.as_literal()?.as_str() {
+        "ls" => ls(&operands)?,
+        _ => find(&operands)?,
+    };
+    Some(lines.join("\n"))
+}
+
+/// Expand command arguments as the shell would before running the command
+fn expand_operands(args: &[Word], vars: &dyn VarLookup) -> Option<Vec<String>> {
+    let mut operands = Vec::new();
+    for word in args {
+        // A pattern without matches is passed on as it is
+        let matches = glob_word(word, vars)
+            .map(|pattern| expand_glob(&pattern, vars))
+            .unwrap_or_default();
+        if !matches.is_empty() {
+            operands.extend(matches.iter().map(|p| p.to_string_lossy().to_string()));
+        } else {
+            let expansion = expand_argument(word, vars);
+            if !expansion.unresolved.is_empty() {
+                return None;
+            }
+            operands.push(expansion.value);
+        }
+    }
+    Some(operands)
+}
+
+fn lookup_value(vars: &dyn VarLookup, name: &str) -> Option<String> {
+    match vars.lookup(name) {
+        Lookup::Set(value) => Some(value),
+        _ => None,
+    }
+}
+
+/// The shell's working directory
+fn working_directory(vars: &dyn VarLookup) -> Option<String> {
+    lookup_value(vars, "PWD").filter(|pwd| pwd.starts_with('/'))
+}
+
+/// A path made absolute against the shell's working directory
+fn absolute_path(path: &str, vars: &dyn VarLookup) -> Option<String> {
+    if path.starts_with('/') {
+        return Some(resolve_path("/", path));
+    }
+    Some(resolve_path(&working_directory(vars)?, path))
+}
+
+/// `pwd [-P]` run in `dir`
+fn pwd(dir: &str, physical: bool, words: &[Word]) -> Option<String> {
+    let mut physical = physical;
+    for word in &words[1..] {
+        match word.as_literal()?.as_str() {
+            "-P" => physical = true,
+            "-L" => physical = false,
+            _ => return None,
+        }
+    }
+    if physical {
+        let path = std::fs::canonicalize(dir).ok()?;
+        return Some(path.to_string_lossy().to_string());
+    }
+    Some(dir.to_string())
+}
+
+/// `dirname`, `basename`, `realpath` and `readlink -f` on one path
+fn path_utility(name: &str, operands: &[String], vars: &dyn VarLookup) -> Option<String> {
+    let (flags, paths): (Vec<&String>, Vec<&String>) =
+        operands.iter().partition(|op| op.starts_with('-'));
+    let [path] = paths.as_slice() else {
+        return None;
+    };
+    let flags: Vec<&str> = flags
+        .iter()
+        .map(|f| f.as_str())
+        .filter(|f| *f != "--")
+        .collect();
+    match (name, flags.as_slice()) {
+        ("dirname", []) => {
+            let trimmed = path.trim_end_matches('/');
+            Some(match trimmed.rsplit_once('/') {
+                Some(("", _)) => "/".to_string(),
+                Some((dir, _)) => dir.trim_end_matches('/').to_string(),
+                None if path.starts_with('/') => "/".to_string(),
+                None => ".".to_string(),
+            })
+        }
+        ("basename", []) => Some(
+            path.trim_end_matches('/')
+                .rsplit('/')
+                .next()
+                .unwrap_or_default()
+                .to_string(),
+        ),
+        ("realpath", []) | ("readlink", ["-f"] | ["-e"] | ["-m"]) => {
+            let path = absolute_path(path, vars)?;
+            let resolved = std::fs::canonicalize(&path).ok()?;
+            Some(resolved.to_string_lossy().to_string())
+        }
+        _ => None,
+    }
+}
+
+/// `ls [-1Ad] operand...`: files as given, or the contents of one directory
+fn ls(args: &[String]) -> Option<Vec<String>> {
+    let mut all = false;
+    let mut directories = true;
+    let mut operands = Vec::new();
+    for arg in args {
+        match arg.strip_prefix('-') {
+            Some(flags) if !flags.is_empty() => {
+                for flag in flags.chars() {
+                    match flag {
+                        '1' => {}
+                        'A' => all = true,
+                        'd' => directories = false,
+                        _ => return None,
+                    }
+                }
+            }
+            _ => operands.push(arg.as_str()),
+        }
+    }
+
+    let (dirs, mut files): (Vec<&str>, Vec<&str>) = operands
+        .into_iter()
+        .partition(|op| directories && Path::new(op).is_dir());
+    files.sort();
+    let mut lines: Vec<String> = files.into_iter().map(str::to_string).collect();
+    match dirs.as_slice() {
+        [] if lines.is_empty() => return None,
+        [] => {}
+        // Several directories get `dir:` headers; only one is listed plainly
+        [dir] if lines.is_empty() => {
+            let mut names: Vec<String> = std::fs::read_dir(dir)
+                .ok()?
+                .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+                .filter(|name| all || !name.starts_with('.'))
+                .collect();
+            names.sort();
+            lines.extend(names);
+        }
+        _ => return None,
+    }
+    Some(lines)
+}
+
+/// `find start... [-maxdepth N] [-mindepth N] [-type f|d|l] [-name P] [-iname P]`,
+/// listed in sorted order rather than directory order
+fn find(args: &[String]) -> Option<Vec<String>> {
+    let split = args
+        .iter()
+        .position(|a| a.starts_with('-'))
+        .unwrap_or(args.len());
+    let (starts, expression) = args.split_at(split);
+    if starts.is_empty() {
+        return None;
+    }
+
+    let mut filter = FindFilter::default();
+    let mut expression = expression.iter();
+    while let Some(predicate) = expression.next() {
+        match predicate.as_str() {
+            "-print" => {}
+            "-maxdepth" => filter.max_depth = Some(expression.next()?.parse().ok()?),
+            "-mindepth" => filter.min_depth = expression.next()?.parse().ok()?,
+            "-type" => {
+                filter.kind = match expression.next()?.as_str() {
+                    kind @ ("f" | "d" | "l") => Some(kind.chars().next()?),
+                    _ => return None,
+                }
+            }
+            "-name" => filter.name = Some(Pattern::new(expression.next()?)),
+            "-iname" => {
+                filter.name = Some(Pattern::new(&expression.next()?.to_lowercase()));
+                filter.ignore_case = true;
+            }
+            _ => return None,
+        }
+    }
+
+    let mut lines = Vec::new();
+    for start in starts {
+        filter.walk(Path::new(start), 0, &mut lines);
+    }
+    lines.sort();
+    Some(lines)
+}
+
+#[derive(Default)]
+struct FindFilter {
+    max_depth: Option<usize>,
+    min_depth: usize,
+    /// `f`, `d` or `l`
+    kind: Option<char>,
+    name: Option<Pattern>,
+    ignore_case: bool,
+}
+
+impl FindFilter {
+    fn walk(&self, path: &Path, depth: usize, lines: &mut Vec<String>) {
+        let Ok(metadata) = path.symlink_metadata() else {
+            return;
+        };
+        if depth >= self.min_depth && self.matches(path, &metadata) {
+            lines.push(path.to_string_lossy().to_string());
+        }
+        if !metadata.is_dir() || self.max_depth.is_some_and(|max| depth >= max) {
+            return;
+        }
+        let Ok(entries) = std::fs::read_dir(path) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            self.walk(&entry.path(), depth + 1, lines);
+        }
+    }
+
+    fn matches(&self, path: &Path, metadata: &std::fs::Metadata) -> bool {
+        let kind_matches = match self.kind {
+            Some('f') => metadata.is_file(),
+            Some('d') => metadata.is_dir(),
+            Some('l') => metadata.file_type().is_symlink(),
+            _ => true,
+        };
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_else(|| path.to_string_lossy().to_string());
+        let name = if self.ignore_case {
+            name.to_lowercase()
+        } else {
+            name
+        };
+        kind_matches && self.name.as_ref().is_none_or(|p| p.matches(&name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::expand::Lookup;
+
+    struct NoVars;
+
+    impl VarLookup for NoVars {
+        fn lookup(&self, _name: &str) -> Lookup {
+            Lookup::Unknown
+        }
+    }
+
+    fn create_files(dir: &Path, names: &[&str]) {
+        for name in names {
+            std::fs::write(dir.join(name), "").unwrap();
+        }
+    }
+
+    #[test]
+    fn test_resolve_path() {
+        assert_eq!(resolve_path("/home/u", "./local.sh"), "/home/u/local.sh");
+        assert_eq!(resolve_path("/home/u", "../x/./y"), "/home/x/y");
+        assert_eq!(resolve_path("/home/u", "/etc/profile"), "/etc/profile");
+    }
+
+    #[test]
+    fn test_expand_glob_sorted() {
+        let dir = tempfile::TempDir::new().unwrap();
+        create_files(
+            dir.path(),
+            &["b.sh", "a.sh", "10-x.sh", "2-y.sh", ".hidden.sh", "c.txt"],
+        );
+        let pattern = format!("{}/*.sh", Pattern::escape(&dir.path().to_string_lossy()));
+        let names: Vec<String> = expand_glob(&pattern, &NoVars)
+            .iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(names, vec!["10-x.sh", "2-y.sh", "a.sh", "b.sh"]);
+
+        let pattern = format!("{}/.*.sh", Pattern::escape(&dir.path().to_string_lossy()));
+        assert_eq!(expand_glob(&pattern, &NoVars).len(), 1);
+        let pattern = format!("{}/*.zsh", Pattern::escape(&dir.path().to_string_lossy()));
+        assert!(expand_glob(&pattern, &NoVars).is_empty());
+    }
+
+    #[test]
+    fn test_path_command_output() {
+        let dir = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+        create_files(dir.path(), &["b.zsh", "a.zsh", "notes"]);
+        create_files(&dir.path().join("sub"), &["c.zsh"]);
+        let root = dir.path().to_string_lossy();
+
+        let output = path_command_output(&format!("ls {}", root), &NoVars).unwrap();
+        assert_eq!(output, "a.zsh\nb.zsh\nnotes\nsub");
+
+        let output = path_command_output(&format!("ls -1 {}/*.zsh", root), &NoVars).unwrap();
+        assert_eq!(output, format!("{0}/a.zsh\n{0}/b.zsh", root));
+
+        let command = format!("find {} -name '*.zsh' -type f | sort", root);
+        let output = path_command_output(&command, &NoVars).unwrap();
+        assert_eq!(output, format!("{0}/a.zsh\n{0}/b.zsh\n{0}/sub/c.zsh", root));
+
+        let command = format!("find {} -maxdepth 1 -name '*.zsh'", root);
+        assert_eq!(
+            path_command_output(&command, &NoVars)
+                .unwrap()
+                .lines()
+                .count(),
+            2
+        );
+
+        let output = path_command_output(&format!("cd {}/sub && pwd", root), &NoVars);
+        assert_eq!(output, Some(format!("{}/sub", root)));
+        let output = path_command_output(&format!("dirname -- {}/sub/c.zsh", root), &NoVars);
+        assert_eq!(output, Some(format!("{}/sub", root)));
+        let output = path_command_output("basename /etc/profile.d/", &NoVars);
+        assert_eq!(output.as_deref(), Some("profile.d"));
+
+        assert_eq!(path_command_output("ls -l /", &NoVars), None);
+        assert_eq!(path_command_output("find $UNKNOWN -name x", &NoVars), None);
+        assert_eq!(path_command_output("cat /etc/shells", &NoVars), None);
+    }
+}
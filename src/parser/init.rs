@@ -0,0 +1,487 @@
+//! Emulation of well-known tool initializers
+//!
+//! Lines like `eval "$(brew shellenv)"` or `. "$NVM_DIR/nvm.sh"` set much of
+//! a typical PATH, but their effect comes from running the tool. For the
+//! common ones, this module produces the shell code the tool would print (or
+//! the part of the loader script that touches the environment), with
+//! prefixes worked out from the traced variables and the filesystem. The
+//! walker reads that code in place of the line, labelling the changes as
+//! emulated. Tools that aren't installed aren't emulated, since running
+//! them would fail.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::expand::{Lookup, VarLookup};
+
+/// Where Homebrew is installed, in the order `brew` is usually found
+const BREW_PREFIXES: &[&str] = &["/opt/homebrew", "/usr/local", "/home/linuxbrew/.linuxbrew"];
+
+/// Shell code standing in for an initializer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Initializer {
+    /// What is emulated, e.g. `brew shellenv`
+    pub tool: String,
+    /// The code the tool would have the shell run
+    pub script: String,
+}
+
+impl Initializer {
+    fn new(tool: &str, lines: Vec<String>) -> Self {
+        Self {
+            tool: tool.to_string(),
+            script: lines.join("\n"),
+        }
+    }
+}
+
+/// The initializer printed by the command in `eval "$(command)"`, given
+/// its expanded words. `shell` is the shell reading the file (`bash` or
+/// `zsh`), used when the command doesn't name one.
+pub fn eval_initializer(argv: &[String], vars: &dyn VarLookup, shell: &str) -> Option<Initializer> {
+    let (program, args) = argv.split_first()?;
+    let name = program.rsplit('/').next().unwrap_or(program);
+    let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+    // A shell named on the command line; only sh-like output can be read
+    let named = args
+        .iter()
+        .find(|a| matches!(**a, "bash" | "zsh" | "sh" | "fish" | "csh" | "tcsh" | "nu"));
+    if named.is_some_and(|s| !matches!(*s, "bash" | "zsh" | "sh")) {
+        return None;
+    }
+    let shell = named.copied().unwrap_or(shell);
+
+    match (name, args.first().copied()) {
+        ("brew", Some("shellenv")) => brew_shellenv(program, vars),
+        ("path_helper", _) => path_helper(Path::new("/etc"), vars),
+        ("pyenv", Some("init")) => {
+            let root = installed_root(program, vars, "PYENV_ROOT", ".pyenv")?;
+            let mut lines = vec![format!("export PATH=\"{}/shims:${{PATH}}\"", root)];
+            if !args.contains(&"--path") {
+                lines.push(format!("export PYENV_SHELL={}", shell));
+            }
+            Some(Initializer::new("pyenv init", lines))
+        }
+        ("pyenv", Some("virtualenv-init")) => {
+            let root = installed_root(program, vars, "PYENV_ROOT", ".pyenv")?;
+            let lines = vec![
+                format!(
+                    "export PATH=\"{}/plugins/pyenv-virtualenv/shims:${{PATH}}\"",
+                    root
+                ),
+                "export PYENV_VIRTUALENV_INIT=1".to_string(),
+            ];
+            Some(Initializer::new("pyenv virtualenv-init", lines))
+        }
+        ("rbenv", Some("init")) => {
+            let root = installed_root(program, vars, "RBENV_ROOT", ".rbenv")?;
+            let lines = vec![
+                format!("export PATH=\"{}/shims:${{PATH}}\"", root),
+                format!("export RBENV_SHELL={}", shell),
+            ];
+            Some(Initializer::new("rbenv init", lines))
+        }
+        ("mise", Some("activate")) => {
+            let data_dir = mise_data_dir(vars)?;
+            if !Path::new(&data_dir).is_dir() && find_program(program, vars).is_none() {
+                return None;
+            }
+            let lines = if args.contains(&"--shims") {
+                vec![format!("export PATH=\"{}/shims:$PATH\"", data_dir)]
+            } else {
+                // Tool paths are added by the prompt hook, not at startup
+                vec![
+                    format!("export MISE_SHELL={}", shell),
+                    "export __MISE_ORIG_PATH=\"$PATH\"".to_string(),
+                ]
+            };
+            Some(Initializer::new("mise activate", lines))
+        }
+        _ => None,
+    }
+}
+
+/// The initializer a sourced loader script stands for (`sdkman-init.sh`,
+/// `nvm.sh`), if the script exists. `args` are the arguments it's sourced with.
+pub fn sourced_initializer(
+    path: &Path,
+    args: &[String],
+    vars: &dyn VarLookup,
+) -> Option<Initializer> {
+    if !path.is_file() {
+        return None;
+    }
+    let name = path.file_name()?.to_str()?;
+    match name {
+        "sdkman-init.sh" => {
+            let dir = path.parent()?.parent()?;
+            Some(sdkman_init(dir, vars))
+        }
+        "nvm.sh" => {
+            let dir = path.parent()?;
+            let no_use = args.iter().any(|a| a == "--no-use");
+            Some(nvm_init(dir, vars, no_use))
+        }
+        _ => None,
+    }
+}
+
+fn value(vars: &dyn VarLookup, name: &str) -> Option<String> {
+    match vars.lookup(name) {
+        Lookup::Set(v) if !v.is_empty() => Some(v),
+        _ => None,
+    }
+}
+
+/// `$VAR` if it's set, otherwise `~/default`
+fn tool_root(vars: &dyn VarLookup, var: &str, default: &str) -> Option<String> {
+    value(vars, var).or_else(|| Some(format!("{}/{}", value(vars, "HOME")?, default)))
+}
+
+/// The tool's root (see [`tool_root`]), if the tool is installed: the root
+/// exists or the program is found. Otherwise running it would fail.
+fn installed_root(program: &str, vars: &dyn VarLookup, var: &str, default: &str) -> Option<String> {
+    let root = tool_root(vars, var, default)?;
+    (Path::new(&root).is_dir() || find_program(program, vars).is_some()).then_some(root)
+}
+
+/// The file a program runs from: the program itself if it's a path,
+/// otherwise the first match on the traced PATH
+fn find_program(program: &str, vars: &dyn VarLookup) -> Option<PathBuf> {
+    if program.contains('/') {
+        return Path::new(program).is_file().then(|| PathBuf::from(program));
+    }
+    value(vars, "PATH")?
+        .split(':')
+        .filter(|dir| !dir.is_empty())
+        .map(|dir| Path::new(dir).join(program))
+        .find(|path| path.is_file())
+}
+
+fn mise_data_dir(vars: &dyn VarLookup) -> Option<String> {
+    if let Some(dir) = value(vars, "MISE_DATA_DIR") {
+        return Some(dir);
+    }
+    let data = value(vars, "XDG_DATA_HOME")
+        .or_else(|| Some(format!("{}/.local/share", value(vars, "HOME")?)))?;
+    Some(format!("{}/mise", data))
+}
+
+/// `brew shellenv`, for the Homebrew the command runs
+fn brew_shellenv(program: &str, vars: &dyn VarLookup) -> Option<Initializer> {
+    let installed = |prefix: &Path| prefix.join("bin/brew").is_file();
+    let prefix = if program.starts_with('/') {
+        // /opt/homebrew/bin/brew
+        find_program(program, vars)?
+            .parent()?
+            .parent()?
+            .to_path_buf()
+    } else if let Some(prefix) = value(vars, "HOMEBREW_PREFIX").map(PathBuf::from)
+        && installed(&prefix)
+    {
+        prefix
+    } else {
+        let mut prefixes: Vec<PathBuf> = BREW_PREFIXES.iter().map(PathBuf::from).collect();
+        prefixes.extend(value(vars, "HOME").map(|home| Path::new(&home).join(".linuxbrew")));
+        prefixes.into_iter().find(|prefix| installed(prefix))?
+    };
+    let prefix = prefix.to_string_lossy();
+    // Intel macOS and Linux keep the repository in a subdirectory
+    let repository = match Path::new(&*prefix).join("Homebrew") {
+        repository if repository.is_dir() => repository.to_string_lossy().to_string(),
+        _ => prefix.to_string(),
+    };
+    let lines = vec![
+        format!("export HOMEBREW_PREFIX=\"{}\";", prefix),
+        format!("export HOMEBREW_CELLAR=\"{}/Cellar\";", prefix),
+        format!("export HOMEBREW_REPOSITORY=\"{}\";", repository),
+        format!("export PATH=\"{0}/bin:{0}/sbin${{PATH+:$PATH}}\";", prefix),
+        "[ -z \"${MANPATH-}\" ] || export MANPATH=\":${MANPATH#:}\";".to_string(),
+        format!("export INFOPATH=\"{}/share/info:${{INFOPATH:-}}\";", prefix),
+    ];
+    Some(Initializer::new("brew shellenv", lines))
+}
+
+/// `path_helper -s`, as run by macOS's `/etc/profile` and `/etc/zprofile`:
+/// PATH becomes the directories listed in `/etc/paths` and `/etc/paths.d/*`,
+/// followed by the current entries not listed there. MANPATH is rebuilt the
+/// same way from `/etc/manpaths` if it's set.
+fn path_helper(etc: &Path, vars: &dyn VarLookup) -> Option<Initializer> {
+    let mut lines = Vec::new();
+    for (var, list) in [("PATH", "paths"), ("MANPATH", "manpaths")] {
+        let current = value(vars, var);
+        if var == "MANPATH" && current.is_none() {
+            continue;
+        }
+        let drop_ins = etc.join(format!("{list}.d"));
+        let mut files = vec![etc.join(list)];
+        files.extend(subfiles(&drop_ins).iter().map(|name| drop_ins.join(name)));
+        let listed: Vec<String> = files
+            .iter()
+            .filter_map(|file| fs::read_to_string(file).ok())
+            .collect();
+        if var == "PATH" && listed.is_empty() {
+            return None;
+        }
+
+        let current = current.unwrap_or_default();
+        let mut entries: Vec<&str> = Vec::new();
+        let listed = listed.iter().flat_map(|text| text.lines().map(str::trim));
+        for entry in listed.chain(current.split(':')) {
+            if !entry.is_empty() && !entries.contains(&entry) {
+                entries.push(entry);
+            }
+        }
+        lines.push(format!("{var}=\"{}\"; export {var};", entries.join(":")));
+    }
+    Some(Initializer::new("path_helper", lines))
+}
+
+/// `sdkman-init.sh`: every installed candidate's `current` version goes on
+/// PATH and gets a `<CANDIDATE>_HOME`
+fn sdkman_init(dir: &Path, vars: &dyn VarLookup) -> Initializer {
+    let dir = dir.to_string_lossy();
+    let mut lines = Vec::new();
+    if value(vars, "SDKMAN_DIR").is_none() {
+        lines.push(format!("export SDKMAN_DIR=\"{}\"", dir));
+    }
+    let candidates = format!("{}/candidates", dir);
+    lines.push(format!("export SDKMAN_CANDIDATES_DIR=\"{}\"", candidates));
+    for name in subdirectories(Path::new(&candidates)) {
+        let current = format!("{}/{}/current", candidates, name);
+        if !Path::new(&current).is_dir() {
+            continue;
+        }
+        lines.push(format!("export PATH=\"{}/bin:$PATH\"", current));
+        lines.push(format!(
+            "export {}_HOME=\"{}\"",
+            name.to_uppercase().replace('-', "_"),
+            current
+        ));
+    }
+    Initializer::new("sdkman-init.sh", lines)
+}
+
+/// `nvm.sh`: unless sourced with `--no-use`, the default alias's Node
+/// version is put on PATH
+fn nvm_init(dir: &Path, vars: &dyn VarLookup, no_use: bool) -> Initializer {
+    let mut lines = Vec::new();
+    if value(vars, "NVM_DIR").is_none() {
+        lines.push(format!("export NVM_DIR=\"{}\"", dir.to_string_lossy()));
+    }
+    if !no_use && let Some(version) = nvm_default_version(dir) {
+        let node = dir.join("versions/node").join(version);
+        let node = node.to_string_lossy();
+        lines.push(format!("export PATH=\"{}/bin:$PATH\"", node));
+        lines.push(format!("export NVM_BIN=\"{}/bin\"", node));
+        lines.push(format!("export NVM_INC=\"{}/include/node\"", node));
+    }
+    Initializer::new("nvm.sh", lines)
+}
+
+/// The installed Node version (`v20.11.0`) the `default` alias resolves to
+fn nvm_default_version(dir: &Path) -> Option<String> {
+    let mut alias = "default".to_string();
+    // Aliases can point at other aliases (default -> lts/* -> lts/iron -> v20)
+    for _ in 0..8 {
+        let target = fs::read_to_string(dir.join("alias").join(&alias)).ok()?;
+        let target = target.trim().to_string();
+        if !dir.join("alias").join(&target).is_file() {
+            alias = target;
+            break;
+        }
+        alias = target;
+    }
+
+    let installed = subdirectories(&dir.join("versions/node"));
+    let wanted = alias.trim_start_matches('v');
+    installed
+        .into_iter()
+        .filter_map(|name| Some((version_number(&name)?, name)))
+        .filter(|(_, name)| {
+            let number = name.trim_start_matches('v');
+            matches!(wanted, "node" | "stable")
+                || number == wanted
+                || number.starts_with(&format!("{}.", wanted))
+        })
+        .max()
+        .map(|(_, name)| name)
+}
+
+/// `v20.11.0` as numbers, for picking the newest version
+fn version_number(name: &str) -> Option<Vec<u64>> {
+    name.strip_prefix('v')?
+        .split('.')
+        .map(|part| part.parse().ok())
+        .collect()
+}
+
+/// Names of the directories in `dir`, sorted
+fn subdirectories(dir: &Path) -> Vec<String> {
+    entries(dir, Path::is_dir)
+}
+
+/// Names of the files in `dir`, sorted
+fn subfiles(dir: &Path) -> Vec<String> {
+    entries(dir, Path::is_file)
+}
+
+fn entries(dir: &Path, keep: fn(&Path) -> bool) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| keep(&entry.path()))
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .collect();
+    names.sort();
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Vars(Vec<(&'static str, String)>);
+
+    impl VarLookup for Vars {
+        fn lookup(&self, name: &str) -> Lookup {
+            match self.0.iter().find(|(n, _)| *n == name) {
+                Some((_, v)) => Lookup::Set(v.to_string()),
+                None => Lookup::Unknown,
+            }
+        }
+    }
+
+    fn argv(command: &str) -> Vec<String> {
+        command.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_eval_initializers() {
+        let home = tempfile::tempdir().unwrap();
+        let home = home.path();
+        let dir = |name: &str| home.join(name).to_string_lossy().to_string();
+        for installed in ["pyenv", ".rbenv", ".local/share/mise", "homebrew/bin"] {
+            fs::create_dir_all(home.join(installed)).unwrap();
+        }
+        fs::write(home.join("homebrew/bin/brew"), "").unwrap();
+        let vars = Vars(vec![
+            ("HOME", home.to_string_lossy().to_string()),
+            ("PYENV_ROOT", dir("pyenv")),
+        ]);
+
+        let init = eval_initializer(&argv("pyenv init -"), &vars, "zsh").unwrap();
+        assert_eq!(init.tool, "pyenv init");
+        assert_eq!(
+            init.script,
+            format!(
+                "export PATH=\"{}/shims:${{PATH}}\"\nexport PYENV_SHELL=zsh",
+                dir("pyenv")
+            )
+        );
+        let init = eval_initializer(&argv("rbenv init - bash"), &vars, "zsh").unwrap();
+        assert!(init.script.contains(&format!("{}/shims", dir(".rbenv"))));
+        assert!(init.script.contains("RBENV_SHELL=bash"));
+        let init = eval_initializer(&argv("mise activate zsh --shims"), &vars, "zsh").unwrap();
+        assert_eq!(
+            init.script,
+            format!("export PATH=\"{}/shims:$PATH\"", dir(".local/share/mise"))
+        );
+
+        let brew = format!("{}/brew shellenv", dir("homebrew/bin"));
+        let init = eval_initializer(&argv(&brew), &vars, "zsh").unwrap();
+        let prefix = dir("homebrew");
+        assert!(
+            init.script
+                .contains(&format!("HOMEBREW_PREFIX=\"{}\"", prefix))
+        );
+        assert!(init.script.contains(&format!("{0}/bin:{0}/sbin", prefix)));
+
+        assert_eq!(
+            eval_initializer(&argv("rbenv init - fish"), &vars, "zsh"),
+            None
+        );
+        assert_eq!(
+            eval_initializer(&argv("zoxide init zsh"), &vars, "zsh"),
+            None
+        );
+
+        // Tools that aren't installed would only fail
+        let bare = tempfile::tempdir().unwrap();
+        let vars = Vars(vec![
+            ("HOME", bare.path().to_string_lossy().to_string()),
+            ("PATH", "/nonexistent/bin".to_string()),
+        ]);
+        for command in [
+            "pyenv init -",
+            "rbenv init - bash",
+            "mise activate zsh --shims",
+            "/nonexistent/bin/brew shellenv",
+        ] {
+            assert_eq!(eval_initializer(&argv(command), &vars, "zsh"), None);
+        }
+    }
+
+    #[test]
+    fn test_path_helper() {
+        let etc = tempfile::tempdir().unwrap();
+        fs::write(etc.path().join("paths"), "/usr/local/bin\n/usr/bin\n/bin\n").unwrap();
+        fs::create_dir_all(etc.path().join("paths.d")).unwrap();
+        fs::write(etc.path().join("paths.d/20-go"), "/usr/local/go/bin\n").unwrap();
+        fs::write(etc.path().join("paths.d/10-tex"), "/Library/TeX/texbin\n").unwrap();
+
+        let vars = Vars(vec![("PATH", "/bin:/opt/tools/bin".to_string())]);
+        let init = path_helper(etc.path(), &vars).unwrap();
+        assert_eq!(
+            init.script,
+            "PATH=\"/usr/local/bin:/usr/bin:/bin:/Library/TeX/texbin:/usr/local/go/bin:/opt/tools/bin\"; export PATH;"
+        );
+
+        let empty = tempfile::tempdir().unwrap();
+        assert_eq!(path_helper(empty.path(), &vars), None);
+    }
+
+    #[test]
+    fn test_sourced_initializers() {
+        let home = tempfile::tempdir().unwrap();
+        let nvm = home.path().join(".nvm");
+        fs::create_dir_all(nvm.join("alias/lts")).unwrap();
+        for version in ["v18.19.0", "v20.9.0", "v20.11.1"] {
+            fs::create_dir_all(nvm.join("versions/node").join(version)).unwrap();
+        }
+        fs::write(nvm.join("nvm.sh"), "").unwrap();
+        fs::write(nvm.join("alias/default"), "lts/*\n").unwrap();
+        fs::write(nvm.join("alias/lts/*"), "lts/iron\n").unwrap();
+        fs::write(nvm.join("alias/lts/iron"), "v20\n").unwrap();
+
+        let vars = Vars(Vec::new());
+        let init = sourced_initializer(&nvm.join("nvm.sh"), &[], &vars).unwrap();
+        let node = nvm.join("versions/node/v20.11.1");
+        assert!(
+            init.script
+                .contains(&format!("export PATH=\"{}/bin:$PATH\"", node.display()))
+        );
+        let no_use = ["--no-use".to_string()];
+        let init = sourced_initializer(&nvm.join("nvm.sh"), &no_use, &vars).unwrap();
+        assert!(!init.script.contains("PATH"));
+
+        let sdkman = home.path().join(".sdkman");
+        fs::create_dir_all(sdkman.join("bin")).unwrap();
+        fs::create_dir_all(sdkman.join("candidates/java/current/bin")).unwrap();
+        fs::create_dir_all(sdkman.join("candidates/maven")).unwrap();
+        fs::write(sdkman.join("bin/sdkman-init.sh"), "").unwrap();
+        let init = sourced_initializer(&sdkman.join("bin/sdkman-init.sh"), &[], &vars).unwrap();
+        let java = sdkman.join("candidates/java/current");
+        assert!(
+            init.script
+                .contains(&format!("export JAVA_HOME=\"{}\"", java.display()))
+        );
+        assert!(!init.script.contains("MAVEN_HOME"));
+
+        assert_eq!(
+            sourced_initializer(&home.path().join("missing/nvm.sh"), &[], &vars),
+            None
+        );
+    }
+}
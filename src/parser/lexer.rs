@@ -0,0 +1,1165 @@
+//! Tokenizer for bash/zsh scripts
+//!
+//! Splits shell source into words and operators the same way the shell does:
+//! quotes, backslash escapes, line continuations, `$'...'` strings, parameter
+//! expansions, command substitutions and heredocs are all recognized. Every
+//! token carries the span of source text it came from so that changes can be
+//! reported with accurate line numbers.
+
+/// A range of source text (byte offsets) and the 1-indexed line it starts on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+}
+
+impl Span {
+    /// Create a span covering both `self` and `other`
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            start: self.start.min(other.start),
+            end: self.end.max(other.end),
+            line: self.line.min(other.line),
+        }
+    }
+}
+
+/// A parameter expansion such as `$HOME`, `${PATH}` or `${EDITOR:-vim}`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParamExpansion {
+    /// Variable name, positional parameter or special parameter (`@`, `#`, ...)
+    pub name: String,
+    /// Array subscript for `${name[index]}`
+    pub index: Option<String>,
+    /// Everything after the name inside the braces (e.g. `:-vim`)
+    pub modifier: String,
+    /// Prefix operator inside the braces (`#` for length, `!` for indirection)
+    pub prefix: Option<char>,
+    /// Whether the expansion used `${...}` syntax
+    pub braced: bool,
+}
+
+impl ParamExpansion {
+    fn simple(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            index: None,
+            modifier: String::new(),
+            prefix: None,
+            braced: false,
+        }
+    }
+
+    /// Reconstruct the source text of this expansion
+    pub fn source_text(&self) -> String {
+        if !self.braced {
+            return format!("${}", self.name);
+        }
+        let mut s = String::from("${");
+        if let Some(p) = self.prefix {
+            s.push(p);
+        }
+        s.push_str(&self.name);
+        if let Some(ref idx) = self.index {
+            s.push('[');
+            s.push_str(idx);
+            s.push(']');
+        }
+        s.push_str(&self.modifier);
+        s.push('}');
+        s
+    }
+}
+
+/// One piece of a shell word
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WordPart {
+    /// Unquoted literal text
+    Literal(String),
+    /// Text that is quoted and never expanded ('...', $'...', or a backslash escape)
+    Quoted(String),
+    /// The contents of a double-quoted string
+    DoubleQuoted(Vec<WordPart>),
+    /// A parameter expansion
+    Param(ParamExpansion),
+    /// A command substitution: $(...) or `...` (raw command text)
+    Command(String),
+    /// A process substitution: <(...) or >(...) (raw command text)
+    Process(String),
+    /// An arithmetic expansion: $((...)) (raw expression text)
+    Arithmetic(String),
+    /// An array literal on the right of an assignment: name=(a b c)
+    Array(Vec<Word>),
+}
+
+impl WordPart {
+    /// Reconstruct the text of this part with quotes removed
+    pub fn source_text(&self) -> String {
+        let mut out = String::new();
+        self.source_text_into(&mut out);
+        out
+    }
+
+    fn source_text_into(&self, out: &mut String) {
+        match self {
+            WordPart::Literal(s) | WordPart::Quoted(s) => out.push_str(s),
+            WordPart::DoubleQuoted(parts) => {
+                for p in parts {
+                    p.source_text_into(out);
+                }
+            }
+            WordPart::Param(p) => out.push_str(&p.source_text()),
+            WordPart::Command(c) => {
+                out.push_str("$(");
+                out.push_str(c);
+                out.push(')');
+            }
+            WordPart::Process(c) => {
+                out.push_str("<(");
+                out.push_str(c);
+                out.push(')');
+            }
+            WordPart::Arithmetic(e) => {
+                out.push_str("$((");
+                out.push_str(e);
+                out.push_str("))");
+            }
+            WordPart::Array(elements) => {
+                let texts: Vec<String> = elements.iter().map(|w| w.unquoted_text()).collect();
+                out.push('(');
+                out.push_str(&texts.join(" "));
+                out.push(')');
+            }
+        }
+    }
+}
+
+/// A shell word made of literal, quoted and expansion parts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Word {
+    pub parts: Vec<WordPart>,
+    pub span: Span,
+}
+
+impl Word {
+    /// The word's text if it contains no expansions (quotes removed)
+    pub fn as_literal(&self) -> Option<String> {
+        fn collect(parts: &[WordPart], out: &mut String) -> bool {
+            for part in parts {
+                match part {
+                    WordPart::Literal(s) | WordPart::Quoted(s) => out.push_str(s),
+                    WordPart::DoubleQuoted(inner) => {
+                        if !collect(inner, out) {
+                            return false;
+                        }
+                    }
+                    _ => return false,
+                }
+            }
+            true
+        }
+        let mut out = String::new();
+        collect(&self.parts, &mut out).then_some(out)
+    }
+
+    /// The word's text with quotes removed and expansions left in source form
+    pub fn unquoted_text(&self) -> String {
+        let mut out = String::new();
+        for part in &self.parts {
+            part.source_text_into(&mut out);
+        }
+        out
+    }
+
+    /// Returns true if the word is exactly the given unquoted literal
+    pub fn is_literal(&self, text: &str) -> bool {
+        matches!(self.parts.as_slice(), [WordPart::Literal(s)] if s == text)
+    }
+
+    /// Split an assignment word (`NAME=value` or `NAME+=value`) into its
+    /// name, whether it appends, and the value word
+    pub fn split_assignment(&self) -> Option<(String, bool, Word)> {
+        let WordPart::Literal(first) = self.parts.first()? else {
+            return None;
+        };
+        let eq = first.find('=')?;
+        let (name, append) = match first[..eq].strip_suffix('+') {
+            Some(n) => (n, true),
+            None => (&first[..eq], false),
+        };
+        if !is_name(name) {
+            return None;
+        }
+
+        let mut parts = Vec::new();
+        let rest = &first[eq + 1..];
+        if !rest.is_empty() {
+            parts.push(WordPart::Literal(rest.to_string()));
+        }
+        parts.extend(self.parts[1..].iter().cloned());
+        let value = Word {
+            parts,
+            span: self.span,
+        };
+        Some((name.to_string(), append, value))
+    }
+}
+
+/// Returns true if `s` is a valid shell variable name
+pub fn is_name(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Redirection operators
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RedirectOp {
+    /// <
+    Input,
+    /// >
+    Output,
+    /// >>
+    Append,
+    /// >|
+    Clobber,
+    /// <>
+    ReadWrite,
+    /// <&
+    DupInput,
+    /// >&
+    DupOutput,
+    /// &>
+    OutputAll,
+    /// &>>
+    AppendAll,
+    /// <<
+    HereDoc,
+    /// <<-
+    HereDocStrip,
+    /// <<<
+    HereString,
+}
+
+/// Control and redirection operators
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    /// ;
+    Semi,
+    /// ;;
+    DoubleSemi,
+    /// ;& or ;;& or ;| (case fall-through terminators)
+    CaseFallthrough,
+    /// &
+    Amp,
+    /// &&
+    AndIf,
+    /// ||
+    OrIf,
+    /// |
+    Pipe,
+    /// |&
+    PipeAll,
+    /// (
+    LParen,
+    /// )
+    RParen,
+    /// A redirection, optionally with an explicit file descriptor
+    Redirect(RedirectOp, Option<u32>),
+}
+
+/// A token kind
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TokenKind {
+    Word(Word),
+    Op(Operator),
+    Newline,
+}
+
+/// A token with its source span
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+/// A heredoc waiting for its body to start on the next line
+struct PendingHeredoc {
+    delimiter: String,
+    strip_tabs: bool,
+}
+
+/// Normalize line endings so CRLF files lex like LF files
+pub fn normalize(content: &str) -> String {
+    content.replace("\r\n", "\n")
+}
+
+/// Tokenize shell source (which should already be normalized)
+pub fn tokenize(src: &str) -> Vec<Token> {
+    Lexer::new(src).run()
+}
+
+/// Lex the operand of a `${name<op>word}` expansion as a single word
+///
+/// Unlike normal words, blanks and operator characters are literal here.
+pub fn lex_operand(text: &str) -> Word {
+    let mut lexer = Lexer::new(text);
+    let mut parts = Vec::new();
+    while let Some(c) = lexer.peek() {
+        match c {
+            '\\' => {
+                lexer.bump();
+                match lexer.bump() {
+                    Some(escaped) => push_quoted(&mut parts, escaped),
+                    None => push_literal(&mut parts, "\\"),
+                }
+            }
+            '\'' => {
+                lexer.bump();
+                let text = lexer.read_until('\'');
+                parts.push(WordPart::Quoted(text));
+            }
+            '"' => {
+                lexer.bump();
+                parts.push(WordPart::DoubleQuoted(lexer.lex_double_quoted()));
+            }
+            '`' => {
+                lexer.bump();
+                parts.push(WordPart::Command(lexer.read_backtick()));
+            }
+            '$' => {
+                let part = lexer.lex_dollar();
+                push_part(&mut parts, part);
+            }
+            _ => {
+                lexer.bump();
+                let mut buf = [0; 4];
+                push_literal(&mut parts, c.encode_utf8(&mut buf));
+            }
+        }
+    }
+    Word {
+        parts,
+        span: Span::default(),
+    }
+}
+
+struct Lexer<'a> {
+    src: &'a str,
+    pos: usize,
+    line_starts: Vec<usize>,
+    tokens: Vec<Token>,
+    pending_heredocs: Vec<PendingHeredoc>,
+    /// Inside `[[ ... ]]`, where operators are plain words
+    in_test: bool,
+}
+
+impl<'a> Lexer<'a> {
+    fn new(src: &'a str) -> Self {
+        let mut line_starts = vec![0];
+        line_starts.extend(src.match_indices('\n').map(|(i, _)| i + 1));
+        Self {
+            src,
+            pos: 0,
+            line_starts,
+            tokens: Vec::new(),
+            pending_heredocs: Vec::new(),
+            in_test: false,
+        }
+    }
+
+    fn line_of(&self, offset: usize) -> usize {
+        match self.line_starts.binary_search(&offset) {
+            Ok(i) => i + 1,
+            Err(i) => i,
+        }
+    }
+
+    fn span(&self, start: usize) -> Span {
+        Span {
+            start,
+            end: self.pos,
+            line: self.line_of(start),
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.src[self.pos..].chars().next()
+    }
+
+    fn peek_at(&self, n: usize) -> Option<char> {
+        self.src[self.pos..].chars().nth(n)
+    }
+
+    fn starts_with(&self, s: &str) -> bool {
+        self.src[self.pos..].starts_with(s)
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn push(&mut self, kind: TokenKind, start: usize) {
+        let span = self.span(start);
+        self.tokens.push(Token { kind, span });
+    }
+
+    fn run(mut self) -> Vec<Token> {
+        loop {
+            // Skip blanks and line continuations
+            loop {
+                match self.peek() {
+                    Some(' ') | Some('\t') | Some('\r') => {
+                        self.pos += 1;
+                    }
+                    Some('\\') if self.peek_at(1) == Some('\n') => {
+                        self.pos += 2;
+                    }
+                    _ => break,
+                }
+            }
+
+            let start = self.pos;
+            let Some(c) = self.peek() else {
+                break;
+            };
+
+            match c {
+                '\n' => {
+                    self.pos += 1;
+                    self.push(TokenKind::Newline, start);
+                    self.read_heredoc_bodies();
+                }
+                '#' => {
+                    while let Some(c) = self.peek() {
+                        if c == '\n' {
+                            break;
+                        }
+                        self.bump();
+                    }
+                }
+                _ => {
+                    if self.in_test && self.lex_test_operator() {
+                        continue;
+                    }
+                    if let Some(op) = self.lex_operator() {
+                        self.push(TokenKind::Op(op), start);
+                        if matches!(
+                            op,
+                            Operator::Redirect(RedirectOp::HereDoc | RedirectOp::HereDocStrip, _)
+                        ) {
+                            self.lex_heredoc_delimiter(op);
+                        }
+                    } else {
+                        let word = self.lex_word();
+                        if word.is_literal("[[") {
+                            self.in_test = true;
+                        } else if word.is_literal("]]") {
+                            self.in_test = false;
+                        }
+                        self.push(TokenKind::Word(word), start);
+                    }
+                }
+            }
+        }
+        self.tokens
+    }
+
+    /// Inside `[[ ]]`, operators like `&&`, `<` and `(` are ordinary words
+    fn lex_test_operator(&mut self) -> bool {
+        let start = self.pos;
+        for op in ["&&", "||", "<", ">", "(", ")", "!"] {
+            // `!` only negates on its own; `!=` is a comparison word
+            let standalone = op != "!" || self.peek_at(1).is_none_or(|c| c.is_whitespace());
+            if self.starts_with(op) && standalone {
+                self.pos += op.len();
+                let span = self.span(start);
+                let word = Word {
+                    parts: vec![WordPart::Literal(op.to_string())],
+                    span,
+                };
+                self.push(TokenKind::Word(word), start);
+                return true;
+            }
+        }
+        false
+    }
+
+    fn lex_operator(&mut self) -> Option<Operator> {
+        let rest = &self.src[self.pos..];
+
+        // Optional file descriptor before a redirection (e.g. 2>&1)
+        let digits = rest.bytes().take_while(|b| b.is_ascii_digit()).count();
+        let has_fd = digits > 0 && matches!(rest.as_bytes().get(digits), Some(b'<' | b'>'));
+        let (fd, after_fd) = if has_fd {
+            (rest[..digits].parse().ok(), &rest[digits..])
+        } else {
+            (None, rest)
+        };
+
+        // Process substitution is part of a word, not a redirection
+        if !has_fd && (rest.starts_with("<(") || rest.starts_with(">(")) {
+            return None;
+        }
+
+        const REDIRECTS: &[(&str, RedirectOp)] = &[
+            ("<<<", RedirectOp::HereString),
+            ("<<-", RedirectOp::HereDocStrip),
+            ("<<", RedirectOp::HereDoc),
+            ("<&", RedirectOp::DupInput),
+            ("<>", RedirectOp::ReadWrite),
+            ("<", RedirectOp::Input),
+            (">>", RedirectOp::Append),
+            (">&", RedirectOp::DupOutput),
+            (">|", RedirectOp::Clobber),
+            (">", RedirectOp::Output),
+        ];
+        for (text, op) in REDIRECTS {
+            if after_fd.starts_with(text) {
+                self.pos += rest.len() - after_fd.len() + text.len();
+                return Some(Operator::Redirect(*op, fd));
+            }
+        }
+        if has_fd {
+            return None;
+        }
+
+        const OPERATORS: &[(&str, Operator)] = &[
+            ("&>>", Operator::Redirect(RedirectOp::AppendAll, None)),
+            ("&>", Operator::Redirect(RedirectOp::OutputAll, None)),
+            (";;&", Operator::CaseFallthrough),
+            (";;", Operator::DoubleSemi),
+            (";&", Operator::CaseFallthrough),
+            (";|", Operator::CaseFallthrough),
+            (";", Operator::Semi),
+            ("&&", Operator::AndIf),
+            ("&", Operator::Amp),
+            ("||", Operator::OrIf),
+            ("|&", Operator::PipeAll),
+            ("|", Operator::Pipe),
+            ("(", Operator::LParen),
+            (")", Operator::RParen),
+        ];
+        for (text, op) in OPERATORS {
+            if rest.starts_with(text) {
+                self.pos += text.len();
+                return Some(*op);
+            }
+        }
+        None
+    }
+
+    fn lex_heredoc_delimiter(&mut self, op: Operator) {
+        while matches!(self.peek(), Some(' ') | Some('\t')) {
+            self.pos += 1;
+        }
+        if matches!(self.peek(), None | Some('\n')) {
+            return;
+        }
+        let start = self.pos;
+        let word = self.lex_word();
+        let delimiter = word.as_literal().unwrap_or_else(|| word.unquoted_text());
+        self.push(TokenKind::Word(word), start);
+        self.pending_heredocs.push(PendingHeredoc {
+            delimiter,
+            strip_tabs: matches!(op, Operator::Redirect(RedirectOp::HereDocStrip, _)),
+        });
+    }
+
+    /// Skip the bodies of heredocs whose operator appeared on the previous line
+    fn read_heredoc_bodies(&mut self) {
+        for heredoc in std::mem::take(&mut self.pending_heredocs) {
+            while self.pos < self.src.len() {
+                let line_end = self.src[self.pos..]
+                    .find('\n')
+                    .map(|i| self.pos + i)
+                    .unwrap_or(self.src.len());
+                let line = &self.src[self.pos..line_end];
+                let line = if heredoc.strip_tabs {
+                    line.trim_start_matches('\t')
+                } else {
+                    line
+                };
+                let done = line.trim_end_matches('\r') == heredoc.delimiter;
+                self.pos = (line_end + 1).min(self.src.len());
+                if done {
+                    break;
+                }
+            }
+        }
+    }
+
+    fn lex_word(&mut self) -> Word {
+        let start = self.pos;
+        let mut parts: Vec<WordPart> = Vec::new();
+
+        while let Some(c) = self.peek() {
+            match c {
+                ' ' | '\t' | '\n' | '\r' | ';' | '&' => break,
+                '<' | '>' if !self.in_test && self.peek_at(1) == Some('(') => {
+                    self.pos += 2;
+                    let body = self.read_balanced('(', ')');
+                    parts.push(WordPart::Process(body));
+                }
+                '<' | '>' | ')' => break,
+                '|' if !self.in_test || parts.is_empty() => break,
+                '(' if is_array_assignment(&parts) => {
+                    self.bump();
+                    parts.push(WordPart::Array(self.lex_array_elements()));
+                }
+                // `name()` starts a function definition
+                '(' if parts.is_empty() || self.peek_at(1) == Some(')') => break,
+                '(' => {
+                    // Mid-word parens: extglob patterns and zsh glob qualifiers
+                    self.bump();
+                    let body = self.read_balanced('(', ')');
+                    push_literal(&mut parts, &format!("({})", body));
+                }
+                '\\' => {
+                    self.bump();
+                    match self.bump() {
+                        Some('\n') => {}
+                        Some(escaped) => push_quoted(&mut parts, escaped),
+                        None => push_literal(&mut parts, "\\"),
+                    }
+                }
+                '\'' => {
+                    self.bump();
+                    let text = self.read_until('\'');
+                    parts.push(WordPart::Quoted(text));
+                }
+                '"' => {
+                    self.bump();
+                    parts.push(WordPart::DoubleQuoted(self.lex_double_quoted()));
+                }
+                '`' => {
+                    self.bump();
+                    parts.push(WordPart::Command(self.read_backtick()));
+                }
+                '$' => {
+                    if self.peek_at(1) == Some('\'') {
+                        self.pos += 2;
+                        parts.push(WordPart::Quoted(self.read_ansi_c()));
+                    } else if self.peek_at(1) == Some('"') {
+                        self.pos += 2;
+                        parts.push(WordPart::DoubleQuoted(self.lex_double_quoted()));
+                    } else {
+                        let part = self.lex_dollar();
+                        push_part(&mut parts, part);
+                    }
+                }
+                _ => {
+                    self.bump();
+                    let mut buf = [0; 4];
+                    push_literal(&mut parts, c.encode_utf8(&mut buf));
+                }
+            }
+        }
+
+        Word {
+            parts,
+            span: self.span(start),
+        }
+    }
+
+    /// Lex the elements of an array literal up to the closing paren
+    fn lex_array_elements(&mut self) -> Vec<Word> {
+        let mut elements = Vec::new();
+        while let Some(c) = self.peek() {
+            match c {
+                ')' => {
+                    self.bump();
+                    break;
+                }
+                ' ' | '\t' | '\n' | '\r' => {
+                    self.bump();
+                }
+                '#' => {
+                    while self.peek().is_some_and(|c| c != '\n') {
+                        self.bump();
+                    }
+                }
+                '\\' if self.peek_at(1) == Some('\n') => {
+                    self.pos += 2;
+                }
+                _ => {
+                    let word = self.lex_word();
+                    if word.parts.is_empty() {
+                        // Stray operator character inside the array; skip it
+                        self.bump();
+                        continue;
+                    }
+                    elements.push(word);
+                }
+            }
+        }
+        elements
+    }
+
+    fn lex_double_quoted(&mut self) -> Vec<WordPart> {
+        let mut parts = Vec::new();
+        while let Some(c) = self.peek() {
+            match c {
+                '"' => {
+                    self.bump();
+                    break;
+                }
+                '\\' => {
+                    self.bump();
+                    match self.bump() {
+                        Some('\n') => {}
+                        Some(e @ ('$' | '`' | '"' | '\\')) => {
+                            let mut buf = [0; 4];
+                            push_literal(&mut parts, e.encode_utf8(&mut buf));
+                        }
+                        Some(other) => {
+                            push_literal(&mut parts, "\\");
+                            let mut buf = [0; 4];
+                            push_literal(&mut parts, other.encode_utf8(&mut buf));
+                        }
+                        None => push_literal(&mut parts, "\\"),
+                    }
+                }
+                '`' => {
+                    self.bump();
+                    parts.push(WordPart::Command(self.read_backtick()));
+                }
+                '$' => {
+                    let part = self.lex_dollar();
+                    push_part(&mut parts, part);
+                }
+                _ => {
+                    self.bump();
+                    let mut buf = [0; 4];
+                    push_literal(&mut parts, c.encode_utf8(&mut buf));
+                }
+            }
+        }
+        parts
+    }
+
+    /// Lex a `$` expansion. Returns a literal `$` if nothing expandable follows.
+    fn lex_dollar(&mut self) -> WordPart {
+        self.bump(); // $
+        match self.peek() {
+            Some('(') if self.peek_at(1) == Some('(') => {
+                self.pos += 2;
+                let expr = self.read_balanced('(', ')');
+                // Consume the second closing paren of $(( ))
+                if self.peek() == Some(')') {
+                    self.bump();
+                }
+                WordPart::Arithmetic(expr)
+            }
+            Some('(') => {
+                self.bump();
+                WordPart::Command(self.read_balanced('(', ')'))
+            }
+            Some('{') => {
+                self.bump();
+                let inner = self.read_balanced('{', '}');
+                WordPart::Param(parse_braced_param(&inner))
+            }
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+                let start = self.pos;
+                while self
+                    .peek()
+                    .is_some_and(|c| c.is_ascii_alphanumeric() || c == '_')
+                {
+                    self.bump();
+                }
+                WordPart::Param(ParamExpansion::simple(&self.src[start..self.pos]))
+            }
+            Some(c) if c.is_ascii_digit() || "@*#?-$!".contains(c) => {
+                self.bump();
+                WordPart::Param(ParamExpansion::simple(c))
+            }
+            _ => WordPart::Literal("$".to_string()),
+        }
+    }
+
+    /// Read up to (and consume) `close`, honoring nesting and quotes.
+    /// The opening delimiter must already be consumed.
+    fn read_balanced(&mut self, open: char, close: char) -> String {
+        let start = self.pos;
+        let mut depth = 1;
+        while let Some(c) = self.peek() {
+            match c {
+                '\\' => {
+                    self.bump();
+                    self.bump();
+                    continue;
+                }
+                '\'' if open != '{' => {
+                    self.bump();
+                    self.read_until('\'');
+                    continue;
+                }
+                '"' => {
+                    self.bump();
+                    self.lex_double_quoted();
+                    continue;
+                }
+                '`' => {
+                    self.bump();
+                    self.read_backtick();
+                    continue;
+                }
+                _ => {}
+            }
+            if c == open {
+                depth += 1;
+            } else if c == close {
+                depth -= 1;
+                if depth == 0 {
+                    let text = self.src[start..self.pos].to_string();
+                    self.bump();
+                    return text;
+                }
+            }
+            self.bump();
+        }
+        self.src[start..].to_string()
+    }
+
+    /// Read up to (and consume) `end` with no escape processing
+    fn read_until(&mut self, end: char) -> String {
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if c == end {
+                let text = self.src[start..self.pos].to_string();
+                self.bump();
+                return text;
+            }
+            self.bump();
+        }
+        self.src[start..].to_string()
+    }
+
+    fn read_backtick(&mut self) -> String {
+        let mut text = String::new();
+        while let Some(c) = self.bump() {
+            match c {
+                '`' => break,
+                '\\' => match self.bump() {
+                    Some(e @ ('$' | '`' | '\\')) => text.push(e),
+                    Some(other) => {
+                        text.push('\\');
+                        text.push(other);
+                    }
+                    None => text.push('\\'),
+                },
+                _ => text.push(c),
+            }
+        }
+        text
+    }
+
+    /// Read and decode the body of a `$'...'` string
+    fn read_ansi_c(&mut self) -> String {
+        let mut out = String::new();
+        while let Some(c) = self.bump() {
+            match c {
+                '\'' => break,
+                '\\' => {
+                    let Some(e) = self.bump() else {
+                        out.push('\\');
+                        break;
+                    };
+                    match e {
+                        'a' => out.push('\x07'),
+                        'b' => out.push('\x08'),
+                        'e' | 'E' => out.push('\x1b'),
+                        'f' => out.push('\x0c'),
+                        'n' => out.push('\n'),
+                        'r' => out.push('\r'),
+                        't' => out.push('\t'),
+                        'v' => out.push('\x0b'),
+                        '\\' | '\'' | '"' | '?' => out.push(e),
+                        'x' => {
+                            let code = self.read_radix_digits(16, 2);
+                            out.extend(code.and_then(char::from_u32));
+                        }
+                        'u' => {
+                            let code = self.read_radix_digits(16, 4);
+                            out.extend(code.and_then(char::from_u32));
+                        }
+                        'U' => {
+                            let code = self.read_radix_digits(16, 8);
+                            out.extend(code.and_then(char::from_u32));
+                        }
+                        'c' => {
+                            if let Some(ctl) = self.bump() {
+                                out.push(((ctl as u8) & 0x1f) as char);
+                            }
+                        }
+                        '0'..='7' => {
+                            self.pos -= 1;
+                            let code = self.read_radix_digits(8, 3);
+                            out.extend(code.and_then(char::from_u32));
+                        }
+                        other => {
+                            out.push('\\');
+                            out.push(other);
+                        }
+                    }
+                }
+                _ => out.push(c),
+            }
+        }
+        out
+    }
+
+    fn read_radix_digits(&mut self, radix: u32, max: usize) -> Option<u32> {
+        let start = self.pos;
+        let mut count = 0;
+        while count < max && self.peek().is_some_and(|c| c.is_digit(radix)) {
+            self.bump();
+            count += 1;
+        }
+        u32::from_str_radix(&self.src[start..self.pos], radix).ok()
+    }
+}
+
+/// Parse the inside of `${...}` into name, subscript and modifier
+fn parse_braced_param(inner: &str) -> ParamExpansion {
+    let mut rest = inner;
+    let mut prefix = None;
+
+    // ${#NAME} (length) and ${!NAME} (indirection); ${#} alone is a special param
+    if let Some(c @ ('#' | '!')) = rest.chars().next()
+        && rest.len() > 1
+    {
+        let after = &rest[1..];
+        if after
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_ascii_alphanumeric() || c == '_' || "@*".contains(c))
+        {
+            prefix = Some(c);
+            rest = after;
+        }
+    }
+
+    let name_len = if rest
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+    {
+        rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len())
+    } else if rest.starts_with(|c: char| c.is_ascii_digit()) {
+        rest.find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len())
+    } else if rest.starts_with(|c: char| "@*#?-$!".contains(c)) {
+        1
+    } else {
+        0
+    };
+
+    let name = rest[..name_len].to_string();
+    rest = &rest[name_len..];
+
+    let mut index = None;
+    if rest.starts_with('[')
+        && let Some(close) = rest.find(']')
+    {
+        index = Some(rest[1..close].to_string());
+        rest = &rest[close + 1..];
+    }
+
+    ParamExpansion {
+        name,
+        index,
+        modifier: rest.to_string(),
+        prefix,
+        braced: true,
+    }
+}
+
+/// Returns true if the word so far is `NAME=` or `NAME+=`, so `(` starts an array
+fn is_array_assignment(parts: &[WordPart]) -> bool {
+    match parts {
+        [WordPart::Literal(s)] => s
+            .strip_suffix('=')
+            .map(|n| n.strip_suffix('+').unwrap_or(n))
+            .is_some_and(is_name),
+        _ => false,
+    }
+}
+
+fn push_part(parts: &mut Vec<WordPart>, part: WordPart) {
+    match part {
+        WordPart::Literal(s) => push_literal(parts, &s),
+        other => parts.push(other),
+    }
+}
+
+fn push_literal(parts: &mut Vec<WordPart>, s: &str) {
+    if let Some(WordPart::Literal(last)) = parts.last_mut() {
+        last.push_str(s);
+    } else {
+        parts.push(WordPart::Literal(s.to_string()));
+    }
+}
+
+fn push_quoted(parts: &mut Vec<WordPart>, c: char) {
+    if let Some(WordPart::Quoted(last)) = parts.last_mut() {
+        last.push(c);
+    } else {
+        parts.push(WordPart::Quoted(c.to_string()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(src: &str) -> Vec<Word> {
+        tokenize(src)
+            .into_iter()
+            .filter_map(|t| match t.kind {
+                TokenKind::Word(w) => Some(w),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_comment_not_part_of_word() {
+        let w = words("export FOO=bar # comment");
+        assert_eq!(w.len(), 2);
+        assert_eq!(w[1].as_literal().unwrap(), "FOO=bar");
+    }
+
+    #[test]
+    fn test_hash_inside_word_is_literal() {
+        let w = words("FOO=a#b");
+        assert_eq!(w[0].as_literal().unwrap(), "FOO=a#b");
+    }
+
+    #[test]
+    fn test_quotes_and_escapes() {
+        let w = words(r#"FOO="a \"b\" c"'d e'f\ g"#);
+        assert_eq!(w.len(), 1);
+        assert_eq!(w[0].as_literal().unwrap(), r#"FOO=a "b" cd ef g"#);
+    }
+
+    #[test]
+    fn test_ansi_c_string() {
+        let w = words(r"FOO=$'a\tb\'c\x41'");
+        assert_eq!(w[0].as_literal().unwrap(), "FOO=a\tb'cA");
+    }
+
+    #[test]
+    fn test_line_continuation() {
+        let tokens = tokenize("export \\\n  FOO=bar\necho");
+        let first_newline = tokens
+            .iter()
+            .position(|t| t.kind == TokenKind::Newline)
+            .unwrap();
+        assert_eq!(first_newline, 2);
+        assert_eq!(tokens[1].span.line, 2);
+    }
+
+    #[test]
+    fn test_heredoc_body_skipped() {
+        let w = words("cat <<EOF\nexport FOO=bad\nEOF\nexport FOO=good\n");
+        let texts: Vec<String> = w.iter().map(|w| w.unquoted_text()).collect();
+        assert_eq!(texts, vec!["cat", "EOF", "export", "FOO=good"]);
+    }
+
+    #[test]
+    fn test_heredoc_strip_tabs() {
+        let w = words("cat <<-'END'\n\tFOO=bad\n\tEND\nFOO=good\n");
+        assert_eq!(w.last().unwrap().as_literal().unwrap(), "FOO=good");
+    }
+
+    #[test]
+    fn test_parameter_expansions() {
+        let w = words(r#"X="${EDITOR:-vim}$HOME/${arr[0]}""#);
+        let WordPart::DoubleQuoted(ref parts) = w[0].parts[1] else {
+            panic!("expected double quoted part");
+        };
+        let WordPart::Param(ref p) = parts[0] else {
+            panic!("expected param");
+        };
+        assert_eq!(p.name, "EDITOR");
+        assert_eq!(p.modifier, ":-vim");
+        assert!(matches!(&parts[1], WordPart::Param(p) if p.name == "HOME" && !p.braced));
+        assert!(matches!(&parts[3], WordPart::Param(p) if p.index.as_deref() == Some("0")));
+    }
+
+    #[test]
+    fn test_command_substitution() {
+        let w = words("X=$(brew --prefix)/bin Y=`uname -s`");
+        assert_eq!(
+            w[0].parts[1],
+            WordPart::Command("brew --prefix".to_string())
+        );
+        assert_eq!(w[1].parts[1], WordPart::Command("uname -s".to_string()));
+    }
+
+    #[test]
+    fn test_operators_and_redirects() {
+        let tokens = tokenize("[ -f x ] && . x 2>/dev/null || true");
+        let ops: Vec<Operator> = tokens
+            .iter()
+            .filter_map(|t| match t.kind {
+                TokenKind::Op(op) => Some(op),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            ops,
+            vec![
+                Operator::AndIf,
+                Operator::Redirect(RedirectOp::Output, Some(2)),
+                Operator::OrIf
+            ]
+        );
+    }
+
+    #[test]
+    fn test_double_bracket_operators_are_words() {
+        let w = words("[[ -n $x && $y < z ]]");
+        let texts: Vec<String> = w.iter().map(|w| w.unquoted_text()).collect();
+        assert_eq!(texts, vec!["[[", "-n", "$x", "&&", "$y", "<", "z", "]]"]);
+    }
+
+    #[test]
+    fn test_crlf_normalized() {
+        let src = normalize("export FOO=bar\r\nexport BAZ=qux\r\n");
+        let w = words(&src);
+        assert_eq!(w[1].as_literal().unwrap(), "FOO=bar");
+        assert_eq!(w[3].span.line, 2);
+    }
+
+    #[test]
+    fn test_split_assignment() {
+        let w = words(r#"PATH+="$HOME/bin""#);
+        let (name, append, value) = w[0].split_assignment().unwrap();
+        assert_eq!(name, "PATH");
+        assert!(append);
+        assert_eq!(value.unquoted_text(), "$HOME/bin");
+    }
+
+    #[test]
+    fn test_array_assignment() {
+        let w = words("path=(/opt/homebrew/bin $path) fpath+=(~/.zfunc)");
+        assert_eq!(w.len(), 2);
+        let (name, append, value) = w[0].split_assignment().unwrap();
+        assert_eq!(name, "path");
+        assert!(!append);
+        let WordPart::Array(ref elements) = value.parts[0] else {
+            panic!("expected array");
+        };
+        assert_eq!(elements.len(), 2);
+        assert!(w[1].split_assignment().unwrap().1);
+    }
+}
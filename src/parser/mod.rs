@@ -1,10 +1,25 @@
 pub(crate) mod common;
+mod condition;
 mod environment;
+mod expand;
+mod fish;
+mod glob;
+mod init;
+mod lexer;
+mod nushell;
+mod pattern;
 mod plist;
+mod script;
 mod shell;
 mod shell_function;
 
+pub use common::{Guard, SourcedFile};
 pub use environment::parse_environment_file;
+pub use fish::{parse_fish_file, parse_fish_variables};
+pub(crate) use lexer::Word;
+pub use nushell::parse_nushell_file;
 pub use plist::{launchctl_getenv, parse_plist_file};
+pub(crate) use script::{Command as ScriptCommand, Script};
+pub(crate) use shell::ShellFunction;
 pub use shell::{ParsedShellEntry, parse_shell_file};
 pub use shell_function::{ParsedFunctionEntry, parse_shell_file_for_function};
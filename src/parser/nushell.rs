@@ -0,0 +1,2313 @@
+//! Nushell script parsing
+//!
+//! Nushell builds its environment with `$env.NAME = value`, `load-env` and
+//! `hide-env`, and PATH is a list rather than a string. Scripts are read
+//! into statements of expressions and pipelines here and evaluated against
+//! the traced environment, producing the same [`ParsedShellEntry`] values
+//! as [`super::shell`] does for bash and zsh.
+//!
+//! Lists are stored joined with `:`, which is how nushell converts PATH
+//! for other programs (`char esep`).
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use super::common::{BranchWalk, Branching, Guard, PathState, SourcedFile, negate, shell_quote};
+use super::condition::{ShellState, Truth, evaluate_command};
+use super::expand::{Lookup, VarLookup};
+use super::glob::{path_command_output, resolve_path};
+use super::lexer::{Span, Word, WordPart};
+use super::shell::ParsedShellEntry;
+use crate::trace::{
+    Diagnostic, DiagnosticKind, Operation, ShellEnv, ValueCandidate, VariableChange,
+};
+
+/// Binary operators between two expressions (`$a == $b`, `"PATH" in $env`)
+const OPERATORS: &[&str] = &[
+    "==",
+    "!=",
+    "in",
+    "not-in",
+    "and",
+    "or",
+    "++",
+    "starts-with",
+    "ends-with",
+];
+
+/// Nushell commands that only run inside nushell, so they are never handed
+/// to `sh` as external commands
+const BUILTINS: &[&str] = &[
+    "collect",
+    "columns",
+    "complete",
+    "describe",
+    "do",
+    "each",
+    "filter",
+    "find",
+    "first",
+    "flatten",
+    "get",
+    "group-by",
+    "ignore",
+    "input",
+    "insert",
+    "items",
+    "last",
+    "length",
+    "merge",
+    "open",
+    "parse",
+    "print",
+    "reduce",
+    "reverse",
+    "select",
+    "skip",
+    "sort",
+    "sort-by",
+    "sys",
+    "take",
+    "transpose",
+    "update",
+    "upsert",
+    "values",
+    "version",
+    "where",
+    "with-env",
+    "wrap",
+];
+
+/// Parse a Nushell script for environment changes
+///
+/// Recognized:
+/// - $env.NAME = value (`$env.PATH | prepend x` and `[x ...$env.PATH]`
+///   prepend, `append` and `[...$env.PATH x]` append)
+/// - $env.NAME ++= value (append)
+/// - load-env { NAME: value, ... }
+/// - hide-env NAME
+/// - path add [--append] dirs... (from `std/util`)
+/// - let / mut / const variables and `$"...(expr)..."` interpolation
+/// - if / else if / else, for loops, `export-env` and `do --env` blocks
+/// - source / source-env file
+///
+/// Entries are passed to `on_entry` as they are reached, as for
+/// [`super::parse_shell_file`].
+pub fn parse_nushell_file(
+    path: &Path,
+    target_var: &str,
+    env: &mut ShellEnv,
+    guard: &Guard,
+    on_entry: &mut dyn FnMut(ParsedShellEntry, &mut ShellEnv),
+) -> std::io::Result<()> {
+    let content = std::fs::read_to_string(path)?;
+    let statements = parse(&content);
+    let mut walker = NuWalker::new(path, target_var, env, guard, on_entry);
+    walker.walk(&statements);
+    Ok(())
+}
+
+/// Whether a variable holds a list: nushell converts PATH to one on startup
+fn is_list_var(name: &str) -> bool {
+    matches!(name, "PATH" | "Path")
+}
+
+/// A statement's source text on one line
+fn one_line(text: &str) -> String {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Tok {
+    /// A bare word (`prepend`, `--append`, `~/bin`, `==`)
+    Word(String),
+    /// A quoted string
+    Str(String),
+    /// An interpolated string (`$"($env.HOME)/bin"`)
+    Interp(Vec<Piece<String>>),
+    /// A variable with its cell path (`$env.PATH` is `["env", "PATH"]`)
+    Var(Vec<String>),
+    /// `...` before a list or variable
+    Spread,
+    Pipe,
+    Open(char),
+    Close(char),
+    Comma,
+    Colon,
+    /// A newline or `;`
+    End,
+}
+
+/// A piece of an interpolated string: text, or code in parentheses
+#[derive(Debug, Clone, PartialEq)]
+enum Piece<T> {
+    Text(String),
+    Code(T),
+}
+
+#[derive(Debug)]
+struct Token {
+    tok: Tok,
+    line: usize,
+    start: usize,
+    end: usize,
+}
+
+struct Lexer {
+    chars: Vec<(usize, char)>,
+    len: usize,
+    i: usize,
+    line: usize,
+}
+
+impl Lexer {
+    fn new(src: &str) -> Self {
+        Self {
+            chars: src.char_indices().collect(),
+            len: src.len(),
+            i: 0,
+            line: 1,
+        }
+    }
+
+    fn peek(&self, n: usize) -> Option<char> {
+        self.chars.get(self.i + n).map(|&(_, c)| c)
+    }
+
+    fn offset(&self) -> usize {
+        self.chars
+            .get(self.i)
+            .map_or(self.len, |&(offset, _)| offset)
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek(0)?;
+        self.i += 1;
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn tokens(mut self) -> Vec<Token> {
+        let mut tokens = Vec::new();
+        while let Some(c) = self.peek(0) {
+            let (start, line) = (self.offset(), self.line);
+            let tok = match c {
+                '\n' | ';' => {
+                    self.bump();
+                    Tok::End
+                }
+                c if c.is_whitespace() => {
+                    self.bump();
+                    continue;
+                }
+                '#' => {
+                    while self.peek(0).is_some_and(|c| c != '\n') {
+                        self.bump();
+                    }
+                    continue;
+                }
+                '|' | ',' | ':' => {
+                    self.bump();
+                    match c {
+                        '|' => Tok::Pipe,
+                        ',' => Tok::Comma,
+                        _ => Tok::Colon,
+                    }
+                }
+                '(' | '[' | '{' => {
+                    self.bump();
+                    Tok::Open(c)
+                }
+                ')' | ']' | '}' => {
+                    self.bump();
+                    Tok::Close(c)
+                }
+                '\'' | '`' => {
+                    self.bump();
+                    Tok::Str(self.quoted(c, false))
+                }
+                '"' => {
+                    self.bump();
+                    Tok::Str(self.quoted('"', true))
+                }
+                '$' if matches!(self.peek(1), Some('"' | '\'')) => {
+                    self.bump();
+                    let quote = self.bump().unwrap();
+                    Tok::Interp(self.interpolation(quote))
+                }
+                '$' => {
+                    self.bump();
+                    Tok::Var(self.variable())
+                }
+                'r' if self.peek(1) == Some('#') => Tok::Str(self.raw_string()),
+                '.' if self.peek(1) == Some('.')
+                    && self.peek(2) == Some('.')
+                    && matches!(self.peek(3), Some('$' | '[' | '(')) =>
+                {
+                    self.i += 3;
+                    Tok::Spread
+                }
+                _ => Tok::Word(self.bare()),
+            };
+            tokens.push(Token {
+                tok,
+                line,
+                start,
+                end: self.offset(),
+            });
+        }
+        tokens
+    }
+
+    /// A string after its opening quote
+    fn quoted(&mut self, quote: char, escapes: bool) -> String {
+        let mut text = String::new();
+        while let Some(c) = self.bump() {
+            match c {
+                c if c == quote => break,
+                '\\' if escapes => match self.bump() {
+                    Some('n') => text.push('\n'),
+                    Some('t') => text.push('\t'),
+                    Some('r') => text.push('\r'),
+                    Some(c) => text.push(c),
+                    None => break,
+                },
+                c => text.push(c),
+            }
+        }
+        text
+    }
+
+    /// `r#'...'#`
+    fn raw_string(&mut self) -> String {
+        self.bump();
+        let mut hashes = 0;
+        while self.peek(0) == Some('#') {
+            self.bump();
+            hashes += 1;
+        }
+        self.bump();
+        let mut text = String::new();
+        while let Some(c) = self.bump() {
+            if c == '\'' && (0..hashes).all(|n| self.peek(n) == Some('#')) {
+                self.i += hashes;
+                break;
+            }
+            text.push(c);
+        }
+        text
+    }
+
+    /// An interpolated string after its opening quote: `(...)` is code
+    fn interpolation(&mut self, quote: char) -> Vec<Piece<String>> {
+        let mut pieces = Vec::new();
+        let mut text = String::new();
+        while let Some(c) = self.bump() {
+            match c {
+                c if c == quote => break,
+                '\\' if quote == '"' => match self.bump() {
+                    Some('n') => text.push('\n'),
+                    Some('t') => text.push('\t'),
+                    Some(c) => text.push(c),
+                    None => break,
+                },
+                '(' => {
+                    if !text.is_empty() {
+                        pieces.push(Piece::Text(std::mem::take(&mut text)));
+                    }
+                    pieces.push(Piece::Code(self.code()));
+                }
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            pieces.push(Piece::Text(text));
+        }
+        pieces
+    }
+
+    /// Code up to the parenthesis that closes it
+    fn code(&mut self) -> String {
+        let mut code = String::new();
+        let mut depth = 0;
+        let mut quote = None;
+        while let Some(c) = self.bump() {
+            match (quote, c) {
+                (Some(q), c) if c == q => quote = None,
+                (Some(_), _) => {}
+                (None, '"' | '\'') => quote = Some(c),
+                (None, '(') => depth += 1,
+                (None, ')') if depth == 0 => break,
+                (None, ')') => depth -= 1,
+                _ => {}
+            }
+            code.push(c);
+        }
+        code
+    }
+
+    /// A variable name and cell path after `$`
+    fn variable(&mut self) -> Vec<String> {
+        let is_name = |c: char| c.is_alphanumeric() || c == '_' || c == '-';
+        let mut path = Vec::new();
+        loop {
+            let member = match self.peek(0) {
+                Some(q @ ('"' | '\'')) if !path.is_empty() => {
+                    self.bump();
+                    self.quoted(q, q == '"')
+                }
+                _ => {
+                    let mut name = String::new();
+                    while let Some(c) = self.peek(0).filter(|&c| is_name(c)) {
+                        name.push(c);
+                        self.bump();
+                    }
+                    name
+                }
+            };
+            path.push(member);
+            // Optional members (`$env.FOO?`) read as missing instead of failing
+            if self.peek(0) == Some('?') {
+                self.bump();
+            }
+            let next = self.peek(1);
+            if self.peek(0) == Some('.')
+                && next.is_some_and(|c| is_name(c) || c == '"' || c == '\'')
+            {
+                self.bump();
+            } else {
+                return path;
+            }
+        }
+    }
+
+    fn bare(&mut self) -> String {
+        let mut word = String::new();
+        // A lone `=` assigns (`$env.FOO="x"`); `==` and `=~` compare, and
+        // `=>` ends a match pattern
+        if self.peek(0) == Some('=') && !matches!(self.peek(1), Some('=' | '~' | '>')) {
+            self.bump();
+            return "=".to_string();
+        }
+        while let Some(c) = self.peek(0) {
+            let ends = c.is_whitespace()
+                || "|;()[]{},".contains(c)
+                // `KEY: value` in records
+                || (c == ':' && self.peek(1).is_none_or(|n| n.is_whitespace() || n == ','));
+            if ends {
+                break;
+            }
+            word.push(c);
+            self.bump();
+        }
+        word
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Line {
+    number: usize,
+    text: String,
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    /// A quoted string, or a bare word used as a value (`bare`)
+    Str {
+        text: String,
+        bare: bool,
+    },
+    Interp(Vec<Piece<Pipeline>>),
+    Var(Vec<String>),
+    /// List items, with whether each is spread (`...$env.PATH`)
+    List(Vec<(bool, Expr)>),
+    Record(Vec<Field>),
+    /// A parenthesized pipeline
+    Sub(Pipeline),
+    Binary(Box<Expr>, String, Box<Expr>),
+    Not(Box<Expr>),
+    /// `if` or `match` used as a value: each arm's condition (None for
+    /// `else` and `_`) and the pipeline giving its value
+    If {
+        arms: Vec<(Option<Expr>, Pipeline)>,
+        text: String,
+    },
+    Closure,
+}
+
+/// A record entry, with the line it's written on
+#[derive(Debug, Clone)]
+struct Field {
+    key: String,
+    value: Expr,
+    line: Line,
+}
+
+#[derive(Debug, Clone)]
+enum Element {
+    Expr(Expr),
+    /// A command (`prepend /x`, `path add ~/bin`, `brew --prefix`)
+    Call {
+        name: String,
+        args: Vec<Expr>,
+    },
+}
+
+#[derive(Debug, Clone, Default)]
+struct Pipeline {
+    elements: Vec<Element>,
+    text: String,
+}
+
+#[derive(Debug)]
+enum Statement {
+    /// `$env.NAME = value`, `$env.NAME ++= value` or `$name = value`
+    Assign {
+        line: Line,
+        target: Vec<String>,
+        append: bool,
+        value: Pipeline,
+    },
+    /// `let`, `mut` or `const`
+    Let {
+        name: String,
+        value: Pipeline,
+    },
+    If {
+        branches: Vec<(Expr, String, Vec<Statement>)>,
+        else_body: Option<Vec<Statement>>,
+    },
+    For {
+        line: Line,
+        var: String,
+        items: Expr,
+        body: Vec<Statement>,
+    },
+    /// A block whose environment changes last (`export-env`, `do --env`)
+    Block(Vec<Statement>),
+    Pipeline(Line, Pipeline),
+}
+
+fn parse(src: &str) -> Vec<Statement> {
+    let mut parser = Parser::new(src);
+    let mut statements = Vec::new();
+    while parser.peek().is_some() {
+        statements.extend(parser.block());
+    }
+    statements
+}
+
+struct Parser<'s> {
+    src: &'s str,
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl<'s> Parser<'s> {
+    fn new(src: &'s str) -> Self {
+        Self {
+            src,
+            tokens: Lexer::new(src).tokens(),
+            pos: 0,
+        }
+    }
+
+    fn peek(&self) -> Option<&Tok> {
+        self.peek_at(0)
+    }
+
+    fn peek_at(&self, n: usize) -> Option<&Tok> {
+        self.tokens.get(self.pos + n).map(|t| &t.tok)
+    }
+
+    fn at_word(&self, word: &str) -> bool {
+        matches!(self.peek(), Some(Tok::Word(w)) if w == word)
+    }
+
+    fn skip_ends(&mut self) {
+        while self.peek() == Some(&Tok::End) {
+            self.pos += 1;
+        }
+    }
+
+    /// The source text from token `start` to the last token read
+    fn text(&self, start: usize) -> String {
+        if start >= self.pos {
+            return String::new();
+        }
+        one_line(&self.src[self.tokens[start].start..self.tokens[self.pos - 1].end])
+    }
+
+    fn line(&self, start: usize) -> Line {
+        // At the end of the script, the line of the last token
+        let token = self.tokens.get(start).or(self.tokens.last());
+        Line {
+            number: token.map_or(1, |t| t.line),
+            text: self.text(start),
+        }
+    }
+
+    /// The source line a block statement starts on (`if $x {`)
+    fn header(&self, start: usize) -> Line {
+        let number = self.tokens[start].line;
+        let text = self.src.lines().nth(number - 1).unwrap_or("").trim();
+        Line {
+            number,
+            text: text.to_string(),
+        }
+    }
+
+    /// Statements up to the closing `}` (or the end of the script)
+    fn block(&mut self) -> Vec<Statement> {
+        let mut statements = Vec::new();
+        loop {
+            self.skip_ends();
+            match self.peek() {
+                None => break,
+                Some(Tok::Close('}')) => {
+                    self.pos += 1;
+                    break;
+                }
+                Some(Tok::Close(_)) => {
+                    self.pos += 1;
+                    continue;
+                }
+                _ => {}
+            }
+            let start = self.pos;
+            statements.extend(self.statement());
+            if self.pos == start {
+                self.pos += 1;
+            }
+        }
+        statements
+    }
+
+    /// Skip a statement, with any blocks in it
+    fn skip_statement(&mut self) {
+        let mut depth = 0;
+        while let Some(tok) = self.peek() {
+            match tok {
+                Tok::Open(_) => depth += 1,
+                Tok::Close(_) if depth == 0 => break,
+                Tok::Close(_) => depth -= 1,
+                Tok::End if depth == 0 => break,
+                _ => {}
+            }
+            self.pos += 1;
+        }
+    }
+
+    fn statement(&mut self) -> Option<Statement> {
+        let start = self.pos;
+        match self.peek()? {
+            Tok::Word(w) => match w.as_str() {
+                "let" | "mut" | "const" => self.let_statement(),
+                "export" if matches!(self.peek_at(1), Some(Tok::Word(w)) if w == "const") => {
+                    self.pos += 1;
+                    self.let_statement()
+                }
+                "if" => Some(self.if_statement()),
+                "for" => self.for_statement(start),
+                "export-env" | "do" => {
+                    let scoped = w == "do";
+                    self.pos += 1;
+                    let mut keeps_env = !scoped;
+                    while let Some(Tok::Word(flag)) = self.peek() {
+                        keeps_env |= matches!(flag.as_str(), "--env" | "-e");
+                        self.pos += 1;
+                    }
+                    if self.peek() != Some(&Tok::Open('{')) {
+                        self.skip_statement();
+                        return None;
+                    }
+                    self.pos += 1;
+                    let body = self.block();
+                    self.skip_statement();
+                    keeps_env.then_some(Statement::Block(body))
+                }
+                // Definitions and modules don't run here
+                "def" | "alias" | "extern" | "module" | "use" | "overlay" | "export" | "plugin"
+                | "hide" => {
+                    self.skip_statement();
+                    None
+                }
+                _ => Some(self.pipeline_statement(start)),
+            },
+            Tok::Var(target) if matches!(self.peek_at(1), Some(Tok::Word(op)) if op == "=" || op == "++=") =>
+            {
+                let target = target.clone();
+                let append = matches!(self.peek_at(1), Some(Tok::Word(op)) if op == "++=");
+                self.pos += 2;
+                // `$env.EDITOR = if ... { "code" } else { "vim" }` assigns in
+                // each branch, so every branch's value is a candidate
+                if self.at_word("if") || self.at_word("match") {
+                    let conditional = self.conditional();
+                    let line = self.line(start);
+                    return Some(with_values(conditional, |value| Statement::Assign {
+                        line: line.clone(),
+                        target: target.clone(),
+                        append,
+                        value,
+                    }));
+                }
+                let value = self.value();
+                Some(Statement::Assign {
+                    line: self.line(start),
+                    target,
+                    append,
+                    value,
+                })
+            }
+            _ => Some(self.pipeline_statement(start)),
+        }
+    }
+
+    fn pipeline_statement(&mut self, start: usize) -> Statement {
+        let pipeline = self.pipeline(false);
+        Statement::Pipeline(self.line(start), pipeline)
+    }
+
+    /// `let NAME[: type] = value`
+    fn let_statement(&mut self) -> Option<Statement> {
+        self.pos += 1;
+        let Some(Tok::Word(name)) = self.peek().cloned() else {
+            self.skip_statement();
+            return None;
+        };
+        self.pos += 1;
+        while !self.at_word("=") {
+            if matches!(self.peek(), None | Some(Tok::End)) {
+                return None;
+            }
+            self.pos += 1;
+        }
+        self.pos += 1;
+        let value = self.value();
+        Some(Statement::Let { name, value })
+    }
+
+    fn if_statement(&mut self) -> Statement {
+        let mut branches = Vec::new();
+        let mut else_body = None;
+        self.pos += 1;
+        loop {
+            let condition_start = self.pos;
+            let condition = self.expr();
+            let text = self.text(condition_start);
+            let body = if self.peek() == Some(&Tok::Open('{')) {
+                self.pos += 1;
+                self.block()
+            } else {
+                Vec::new()
+            };
+            branches.push((condition, text, body));
+            if !self.at_word("else") {
+                break;
+            }
+            self.pos += 1;
+            if self.at_word("if") {
+                self.pos += 1;
+                continue;
+            }
+            if self.peek() == Some(&Tok::Open('{')) {
+                self.pos += 1;
+                else_body = Some(self.block());
+            }
+            break;
+        }
+        Statement::If {
+            branches,
+            else_body,
+        }
+    }
+
+    /// An `if` or `match` statement
+    fn conditional(&mut self) -> Statement {
+        if self.at_word("match") {
+            self.match_statement()
+        } else {
+            self.if_statement()
+        }
+    }
+
+    /// `match subject { pattern => value, ... }` as the branches of an `if`:
+    /// literal patterns compare with `==`, `_` is the `else`, and other
+    /// patterns (ranges, lists, guards) can't be decided
+    fn match_statement(&mut self) -> Statement {
+        let mut branches = Vec::new();
+        let mut else_body = None;
+        self.pos += 1;
+        let subject_start = self.pos;
+        let subject = self.operand();
+        let subject_text = self.text(subject_start);
+        if self.peek() == Some(&Tok::Open('{')) {
+            self.pos += 1;
+        } else {
+            self.skip_statement();
+        }
+        loop {
+            while matches!(self.peek(), Some(Tok::End | Tok::Comma)) {
+                self.pos += 1;
+            }
+            match self.peek() {
+                None => break,
+                Some(Tok::Close(_)) => {
+                    self.pos += 1;
+                    break;
+                }
+                _ => {}
+            }
+            // The pattern's alternatives, up to `=>`
+            let pattern_start = self.pos;
+            let mut alternatives = vec![Vec::new()];
+            let mut depth = 0;
+            loop {
+                match self.peek().cloned() {
+                    None => break,
+                    Some(Tok::Word(w)) if w == "=>" && depth == 0 => break,
+                    Some(Tok::Close(_)) if depth == 0 => break,
+                    Some(Tok::Pipe) if depth == 0 => alternatives.push(Vec::new()),
+                    Some(tok) => {
+                        match tok {
+                            Tok::Open(_) => depth += 1,
+                            Tok::Close(_) => depth -= 1,
+                            _ => {}
+                        }
+                        if let Some(alternative) = alternatives.last_mut() {
+                            alternative.push(tok);
+                        }
+                    }
+                }
+                self.pos += 1;
+            }
+            let pattern = self.text(pattern_start);
+            if !self.at_word("=>") {
+                continue;
+            }
+            self.pos += 1;
+            let body = if self.peek() == Some(&Tok::Open('{')) {
+                self.pos += 1;
+                self.block()
+            } else {
+                let start = self.pos;
+                let value = self.value();
+                vec![Statement::Pipeline(self.line(start), value)]
+            };
+            if else_body.is_some() {
+                continue;
+            }
+
+            let literals: Option<Vec<Expr>> = alternatives
+                .iter()
+                .map(|alternative| match alternative.as_slice() {
+                    [Tok::Str(text)] => Some(Expr::Str {
+                        text: text.clone(),
+                        bare: false,
+                    }),
+                    [Tok::Word(text)] if text != "_" => Some(Expr::Str {
+                        text: text.clone(),
+                        bare: true,
+                    }),
+                    _ => None,
+                })
+                .collect();
+            let (condition, text) = match literals {
+                _ if alternatives.iter().any(|a| a == &[Tok::Word("_".into())]) => {
+                    else_body = Some(body);
+                    continue;
+                }
+                Some(mut literals) if literals.len() == 1 => (
+                    Expr::Binary(
+                        Box::new(subject.clone()),
+                        "==".to_string(),
+                        Box::new(literals.remove(0)),
+                    ),
+                    format!("({} == {})", subject_text, pattern),
+                ),
+                Some(literals) => (
+                    Expr::Binary(
+                        Box::new(subject.clone()),
+                        "in".to_string(),
+                        Box::new(Expr::List(
+                            literals.into_iter().map(|l| (false, l)).collect(),
+                        )),
+                    ),
+                    format!("({} in [{}])", subject_text, pattern.replace(" | ", " ")),
+                ),
+                None => (
+                    Expr::Closure,
+                    format!("(match {} {})", subject_text, pattern),
+                ),
+            };
+            branches.push((condition, text, body));
+        }
+        Statement::If {
+            branches,
+            else_body,
+        }
+    }
+
+    /// `for NAME in items { body }`
+    fn for_statement(&mut self, start: usize) -> Option<Statement> {
+        self.pos += 1;
+        while matches!(self.peek(), Some(Tok::Word(w)) if w.starts_with('-')) {
+            self.pos += 1;
+        }
+        let Some(Tok::Word(var)) = self.peek().cloned() else {
+            self.skip_statement();
+            return None;
+        };
+        self.pos += 1;
+        if !self.at_word("in") {
+            self.skip_statement();
+            return None;
+        }
+        self.pos += 1;
+        let items = self.operand();
+        if self.peek() != Some(&Tok::Open('{')) {
+            self.skip_statement();
+            return None;
+        }
+        self.pos += 1;
+        let body = self.block();
+        Some(Statement::For {
+            line: self.header(start),
+            var,
+            items,
+            body,
+        })
+    }
+
+    /// The right side of an assignment: an expression, where a bare word is
+    /// a string (`$env.EDITOR = vim`), optionally piped further
+    fn value(&mut self) -> Pipeline {
+        self.pipeline_from(false, true)
+    }
+
+    /// Elements joined by `|`; a `nested` pipeline is in parentheses,
+    /// where it may span lines, and ends at the closing `)`
+    fn pipeline(&mut self, nested: bool) -> Pipeline {
+        self.pipeline_from(nested, false)
+    }
+
+    fn pipeline_from(&mut self, nested: bool, value: bool) -> Pipeline {
+        let start = self.pos;
+        let mut elements = Vec::new();
+        loop {
+            if nested {
+                self.skip_ends();
+            }
+            let element = if value && elements.is_empty() {
+                match self.peek() {
+                    None | Some(Tok::Pipe | Tok::End | Tok::Close(_)) => None,
+                    // `^cmd args` always runs an external command
+                    Some(Tok::Word(w)) if w.starts_with('^') => self.element(),
+                    Some(Tok::Word(w)) if w == "if" || w == "match" => self.element(),
+                    _ => Some(Element::Expr(self.expr())),
+                }
+            } else {
+                self.element()
+            };
+            let Some(element) = element else {
+                break;
+            };
+            elements.push(element);
+            let end = self.pos;
+            self.skip_ends();
+            if self.peek() == Some(&Tok::Pipe) {
+                self.pos += 1;
+                self.skip_ends();
+                continue;
+            }
+            if !nested {
+                self.pos = end;
+            }
+            break;
+        }
+        let text = self.text(start);
+        if nested && self.peek() == Some(&Tok::Close(')')) {
+            self.pos += 1;
+        }
+        Pipeline { elements, text }
+    }
+
+    fn element(&mut self) -> Option<Element> {
+        match self.peek()? {
+            Tok::Pipe | Tok::End | Tok::Close(_) => None,
+            Tok::Word(w) if w == "if" || w == "match" => {
+                let start = self.pos;
+                let conditional = self.conditional();
+                let text = self.text(start);
+                Some(Element::Expr(Expr::If {
+                    arms: value_arms(conditional),
+                    text,
+                }))
+            }
+            Tok::Word(w) if !is_value_word(w) => {
+                let mut name = w.clone();
+                self.pos += 1;
+                if matches!(name.as_str(), "path" | "str" | "split" | "into")
+                    && let Some(Tok::Word(sub)) = self.peek()
+                    && !sub.starts_with('-')
+                {
+                    name = format!("{} {}", name, sub);
+                    self.pos += 1;
+                }
+                let mut args = Vec::new();
+                while !matches!(
+                    self.peek(),
+                    None | Some(Tok::Pipe | Tok::End | Tok::Close(_))
+                ) {
+                    args.push(self.operand());
+                }
+                Some(Element::Call { name, args })
+            }
+            _ => Some(Element::Expr(self.expr())),
+        }
+    }
+
+    /// Operands joined by binary operators
+    fn expr(&mut self) -> Expr {
+        let mut left = self.operand();
+        while let Some(Tok::Word(op)) = self.peek()
+            && OPERATORS.contains(&op.as_str())
+        {
+            let op = op.clone();
+            self.pos += 1;
+            let right = self.operand();
+            left = Expr::Binary(Box::new(left), op, Box::new(right));
+        }
+        left
+    }
+
+    fn operand(&mut self) -> Expr {
+        let empty = Expr::Str {
+            text: String::new(),
+            bare: true,
+        };
+        let Some(tok) = self.peek().cloned() else {
+            return empty;
+        };
+        self.pos += 1;
+        match tok {
+            Tok::Word(w) if w == "not" => Expr::Not(Box::new(self.operand())),
+            Tok::Word(text) => Expr::Str { text, bare: true },
+            Tok::Str(text) => Expr::Str { text, bare: false },
+            Tok::Interp(pieces) => Expr::Interp(
+                pieces
+                    .into_iter()
+                    .map(|piece| match piece {
+                        Piece::Text(text) => Piece::Text(text),
+                        Piece::Code(code) => Piece::Code(Parser::new(&code).pipeline(true)),
+                    })
+                    .collect(),
+            ),
+            Tok::Var(path) => Expr::Var(path),
+            Tok::Spread => self.operand(),
+            Tok::Open('(') => Expr::Sub(self.pipeline(true)),
+            Tok::Open('[') => self.list(),
+            Tok::Open(_) => self.record_or_closure(),
+            Tok::Comma | Tok::Colon => empty,
+            Tok::Pipe | Tok::End | Tok::Close(_) => {
+                self.pos -= 1;
+                empty
+            }
+        }
+    }
+
+    /// A list after its `[`
+    fn list(&mut self) -> Expr {
+        let mut items = Vec::new();
+        loop {
+            while matches!(self.peek(), Some(Tok::End | Tok::Comma)) {
+                self.pos += 1;
+            }
+            match self.peek() {
+                None => break,
+                Some(Tok::Close(_)) => {
+                    self.pos += 1;
+                    break;
+                }
+                Some(Tok::Spread) => {
+                    self.pos += 1;
+                    items.push((true, self.operand()));
+                }
+                // A `|` can't be an item; skip it
+                Some(Tok::Pipe) => self.pos += 1,
+                _ => items.push((false, self.operand())),
+            }
+        }
+        Expr::List(items)
+    }
+
+    /// A record (`{ KEY: value }`) or a closure after its `{`
+    fn record_or_closure(&mut self) -> Expr {
+        let mut look = self.pos;
+        while self.tokens.get(look).is_some_and(|t| t.tok == Tok::End) {
+            look += 1;
+        }
+        let tok = |n: usize| self.tokens.get(n).map(|t| &t.tok);
+        let is_record = matches!(
+            (tok(look), tok(look + 1)),
+            (Some(Tok::Close('}')), _) | (Some(Tok::Word(_) | Tok::Str(_)), Some(Tok::Colon))
+        );
+        if !is_record {
+            let mut depth = 0;
+            while let Some(tok) = self.peek().cloned() {
+                self.pos += 1;
+                match tok {
+                    Tok::Open(_) => depth += 1,
+                    Tok::Close(_) if depth == 0 => break,
+                    Tok::Close(_) => depth -= 1,
+                    _ => {}
+                }
+            }
+            return Expr::Closure;
+        }
+
+        let mut fields = Vec::new();
+        loop {
+            while matches!(self.peek(), Some(Tok::End | Tok::Comma)) {
+                self.pos += 1;
+            }
+            match self.peek().cloned() {
+                None => break,
+                Some(Tok::Close(_)) => {
+                    self.pos += 1;
+                    break;
+                }
+                Some(Tok::Word(key) | Tok::Str(key)) => {
+                    let start = self.pos;
+                    self.pos += 1;
+                    if self.peek() == Some(&Tok::Colon) {
+                        self.pos += 1;
+                    }
+                    let value = self.expr();
+                    fields.push(Field {
+                        key,
+                        value,
+                        line: self.line(start),
+                    });
+                }
+                Some(_) => self.pos += 1,
+            }
+        }
+        Expr::Record(fields)
+    }
+}
+
+/// The pipeline giving a block's value (its last statement), taken out of it
+fn block_value(body: &mut Vec<Statement>) -> Pipeline {
+    match body.pop() {
+        Some(Statement::Pipeline(_, pipeline)) => pipeline,
+        Some(statement) => {
+            body.push(statement);
+            Pipeline::default()
+        }
+        None => Pipeline::default(),
+    }
+}
+
+/// An `if` or `match` whose value is used, with each branch ending in the
+/// statement `assign` makes of its value
+fn with_values(conditional: Statement, assign: impl Fn(Pipeline) -> Statement) -> Statement {
+    let Statement::If {
+        branches,
+        else_body,
+    } = conditional
+    else {
+        return conditional;
+    };
+    let with_value = |mut body: Vec<Statement>| {
+        let value = block_value(&mut body);
+        body.push(assign(value));
+        body
+    };
+    Statement::If {
+        branches: branches
+            .into_iter()
+            .map(|(condition, text, body)| (condition, text, with_value(body)))
+            .collect(),
+        else_body: else_body.map(with_value),
+    }
+}
+
+/// The arms of an `if` or `match` used as an expression
+fn value_arms(conditional: Statement) -> Vec<(Option<Expr>, Pipeline)> {
+    let Statement::If {
+        branches,
+        else_body,
+    } = conditional
+    else {
+        return Vec::new();
+    };
+    branches
+        .into_iter()
+        .map(|(condition, _, mut body)| (Some(condition), block_value(&mut body)))
+        .chain(else_body.map(|mut body| (None, block_value(&mut body))))
+        .collect()
+}
+
+/// Bare words that are values rather than commands
+fn is_value_word(word: &str) -> bool {
+    matches!(word, "true" | "false" | "null" | "not")
+        || word.starts_with(|c: char| c.is_ascii_digit())
+}
+
+/// A value a nushell expression evaluates to
+#[derive(Debug, Clone)]
+enum Value {
+    Str(String),
+    List(Vec<String>),
+    Bool(bool),
+    Record(Vec<(String, Value)>),
+    Nothing,
+    /// A value that can't be known statically
+    Unknown,
+}
+
+impl Value {
+    /// The value as a list of strings (`prepend` and `path add` flatten)
+    fn strings(&self) -> Vec<String> {
+        match self {
+            Value::Str(s) => vec![s.clone()],
+            Value::List(list) => list.clone(),
+            Value::Bool(b) => vec![b.to_string()],
+            Value::Record(_) | Value::Nothing | Value::Unknown => Vec::new(),
+        }
+    }
+
+    /// The value as an environment variable string, lists joined with `:`
+    fn to_env(&self) -> String {
+        match self {
+            Value::Str(s) => s.clone(),
+            _ => self.strings().join(":"),
+        }
+    }
+}
+
+/// What evaluating an expression depended on
+#[derive(Debug, Default)]
+struct Eval {
+    /// Source text of references that couldn't be resolved
+    unresolved: Vec<String>,
+    /// Commands whose output came from actually running them
+    evaluated: Vec<String>,
+    /// Whether commands may run (`--eval-commands`), for assigned values
+    run_commands: bool,
+}
+
+impl Eval {
+    fn assignment() -> Self {
+        Self {
+            run_commands: true,
+            ..Default::default()
+        }
+    }
+}
+
+/// Walks nushell statements in order, tracking every environment variable
+struct NuWalker<'a> {
+    path: &'a Path,
+    target_var: &'a str,
+    env: &'a mut ShellEnv,
+    on_entry: &'a mut dyn FnMut(ParsedShellEntry, &mut ShellEnv),
+    /// The enclosing branches
+    state: PathState,
+    /// `let`/`mut`/`const` variables, innermost block last
+    scopes: Vec<HashMap<String, Value>>,
+}
+
+impl<'a> NuWalker<'a> {
+    fn new(
+        path: &'a Path,
+        target_var: &'a str,
+        env: &'a mut ShellEnv,
+        guard: &Guard,
+        on_entry: &'a mut dyn FnMut(ParsedShellEntry, &mut ShellEnv),
+    ) -> Self {
+        Self {
+            path,
+            target_var,
+            env,
+            on_entry,
+            state: PathState::new(guard),
+            scopes: vec![HashMap::new()],
+        }
+    }
+
+    fn walk(&mut self, statements: &[Statement]) {
+        for statement in statements {
+            self.statement(statement);
+        }
+    }
+
+    /// Walk a block with its own variables
+    fn scoped(&mut self, body: &[Statement], vars: HashMap<String, Value>) {
+        self.scopes.push(vars);
+        self.walk(body);
+        self.scopes.pop();
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Assign {
+                line,
+                target,
+                append,
+                value,
+            } => {
+                let mut eval = Eval::assignment();
+                let new = self.pipeline(value, &mut eval);
+                match target.as_slice() {
+                    [env, name] if env == "env" => {
+                        let (operation, new) = if *append {
+                            let current = self.env_value(name, &mut eval);
+                            (Operation::Append, concat(current, new))
+                        } else {
+                            let operation =
+                                list_operation(name, value).unwrap_or(Operation::Export);
+                            (operation, new)
+                        };
+                        self.store(name, operation, new.to_env(), eval, line);
+                    }
+                    // `$name = value` for a `mut` variable
+                    [name] => {
+                        let new = if *append {
+                            let current = self.variable(target, &mut eval);
+                            concat(current, new)
+                        } else {
+                            new
+                        };
+                        let scope = self
+                            .scopes
+                            .iter_mut()
+                            .rev()
+                            .find(|scope| scope.contains_key(name));
+                        if let Some(scope) = scope {
+                            scope.insert(name.clone(), new);
+                        }
+                    }
+                    // Settings like `$env.config.show_banner`
+                    _ => {}
+                }
+            }
+            Statement::Let { name, value, .. } => {
+                let value = self.pipeline(value, &mut Eval::assignment());
+                if let Some(scope) = self.scopes.last_mut() {
+                    scope.insert(name.clone(), value);
+                }
+            }
+            Statement::If {
+                branches,
+                else_body,
+                ..
+            } => self.if_branches(branches, else_body.as_deref()),
+            Statement::For {
+                line,
+                var,
+                items,
+                body,
+            } => {
+                let mut eval = Eval::default();
+                let items = self.eval(items, &mut eval).strings();
+                self.state.conditions.push(line.text.clone());
+                if eval.unresolved.is_empty() {
+                    for item in items {
+                        self.scoped(body, HashMap::from([(var.clone(), Value::Str(item))]));
+                    }
+                } else {
+                    // Unknown items: walk the body once without knowing the variable
+                    self.state.undecided += 1;
+                    self.scoped(body, HashMap::new());
+                    self.state.undecided -= 1;
+                }
+                self.state.conditions.pop();
+            }
+            Statement::Block(body) => self.scoped(body, HashMap::new()),
+            Statement::Pipeline(line, pipeline) => self.command(pipeline, line),
+        }
+    }
+
+    /// Walk the branches of an `if`: each condition is evaluated in the
+    /// environment before the block, then the branches are followed as for
+    /// the other shells (see [`Branching::branches`])
+    fn if_branches(
+        &mut self,
+        branches: &[(Expr, String, Vec<Statement>)],
+        else_body: Option<&[Statement]>,
+    ) {
+        let mut walks = Vec::new();
+        let mut failed: Vec<String> = Vec::new();
+        for (condition, text, body) in branches {
+            let mut conditions = failed.clone();
+            conditions.push(text.clone());
+            walks.push(BranchWalk {
+                conditions,
+                test: None,
+                matched: self.truth(condition),
+                body: body.as_slice(),
+            });
+            failed.push(negate(text));
+        }
+        if let Some(body) = else_body {
+            walks.push(BranchWalk {
+                conditions: failed,
+                test: None,
+                matched: Truth::True,
+                body,
+            });
+        }
+        self.branches(walks);
+    }
+
+    /// Whether a condition holds
+    fn truth(&self, condition: &Expr) -> Truth {
+        match self.eval(condition, &mut Eval::default()) {
+            Value::Bool(b) => Truth::from_bool(b),
+            _ => Truth::Unknown,
+        }
+    }
+
+    /// Commands that change the environment
+    fn command(&mut self, pipeline: &Pipeline, line: &Line) {
+        let Some((Element::Call { name, args }, input)) = pipeline.elements.split_last() else {
+            return;
+        };
+        match name.as_str() {
+            "load-env" => {
+                let value = match (input, args.first()) {
+                    // Entries of a literal record are reported at their own lines
+                    ([], Some(Expr::Record(fields))) => {
+                        for field in fields {
+                            let mut eval = Eval::assignment();
+                            let value = self.eval(&field.value, &mut eval);
+                            self.store(
+                                &field.key,
+                                Operation::Export,
+                                value.to_env(),
+                                eval,
+                                &field.line,
+                            );
+                        }
+                        return;
+                    }
+                    ([], Some(arg)) => self.eval(arg, &mut Eval::assignment()),
+                    // `open env.json | load-env`
+                    _ => {
+                        let input = Pipeline {
+                            elements: input.to_vec(),
+                            text: String::new(),
+                        };
+                        self.pipeline(&input, &mut Eval::assignment())
+                    }
+                };
+                match value {
+                    Value::Record(entries) => {
+                        for (name, value) in entries {
+                            self.store(
+                                &name,
+                                Operation::Export,
+                                value.to_env(),
+                                Eval::default(),
+                                line,
+                            );
+                        }
+                    }
+                    _ => self.diagnose(
+                        DiagnosticKind::DynamicDeclaration,
+                        vec![pipeline.text.clone()],
+                        line,
+                    ),
+                }
+            }
+            _ if !input.is_empty() => {}
+            "hide-env" => {
+                for arg in args {
+                    let mut eval = Eval::default();
+                    let name = self.eval(arg, &mut eval).to_env();
+                    if !name.starts_with('-') && eval.unresolved.is_empty() {
+                        self.store(&name, Operation::Unset, String::new(), eval, line);
+                    }
+                }
+            }
+            "path add" => self.path_add(args, line),
+            "source" | "source-env" => self.source(args, line),
+            _ => {}
+        }
+    }
+
+    /// `path add` from `std/util`: expands the directories and puts them at
+    /// the front of PATH (or the end with `--append`), dropping duplicates
+    fn path_add(&mut self, args: &[Expr], line: &Line) {
+        let mut append = false;
+        let mut eval = Eval::assignment();
+        let mut dirs = Vec::new();
+        for arg in args {
+            if let Expr::Str { text, bare: true } = arg
+                && text.starts_with('-')
+            {
+                append |= matches!(text.as_str(), "--append" | "-a");
+                continue;
+            }
+            match self.eval(arg, &mut eval) {
+                // Paths for each OS: `{ linux: ..., macos: ... }`
+                Value::Record(entries) => {
+                    let own = entries.iter().find(|(os, _)| os == std::env::consts::OS);
+                    dirs.extend(own.map(|(_, dirs)| dirs.strings()).unwrap_or_default());
+                }
+                value => dirs.extend(value.strings()),
+            }
+        }
+        let dirs: Vec<String> = dirs.iter().map(|dir| self.expand_path(dir)).collect();
+        let current = self.env_value("PATH", &mut eval).strings();
+        let (operation, list) = if append {
+            (Operation::Append, [current, dirs].concat())
+        } else {
+            (Operation::Prepend, [dirs, current].concat())
+        };
+        let mut unique: Vec<String> = Vec::new();
+        for dir in list {
+            if !unique.contains(&dir) {
+                unique.push(dir);
+            }
+        }
+        self.store("PATH", operation, unique.join(":"), eval, line);
+    }
+
+    /// Read a sourced file; nushell resolves relative paths against the
+    /// script's directory
+    fn source(&mut self, args: &[Expr], line: &Line) {
+        let Some(arg) = args.first() else {
+            return;
+        };
+        let mut eval = Eval::default();
+        let path = match self.eval(arg, &mut eval) {
+            Value::Str(path) if eval.unresolved.is_empty() => self.expand_tilde(&path),
+            _ => {
+                self.diagnose(DiagnosticKind::UnresolvedSource, eval.unresolved, line);
+                return;
+            }
+        };
+        let path = match self.path.parent() {
+            Some(dir) if !path.starts_with('/') => dir.join(path),
+            _ => PathBuf::from(path),
+        };
+        let guard = Guard {
+            conditions: self.state.conditions.clone(),
+            undecided: self.state.path_conditions.clone(),
+        };
+        (self.on_entry)(
+            ParsedShellEntry::Source(SourcedFile { path, guard }),
+            self.env,
+        );
+    }
+
+    /// Report a line that couldn't be analyzed
+    fn diagnose(&mut self, kind: DiagnosticKind, unresolved: Vec<String>, line: &Line) {
+        let diagnostic = Diagnostic {
+            file: self.path.to_path_buf(),
+            line_number: line.number,
+            line_content: line.text.clone(),
+            kind,
+            unresolved,
+        };
+        (self.on_entry)(ParsedShellEntry::Diagnostic(diagnostic), self.env);
+    }
+
+    /// Update an environment variable: the target is recorded, anything
+    /// else is only tracked. Nushell exports every environment variable.
+    fn store(&mut self, name: &str, operation: Operation, value: String, eval: Eval, line: &Line) {
+        self.env.set_exported(name, true);
+        if name == self.target_var {
+            self.record(operation, value, eval, line);
+        } else if operation == Operation::Unset {
+            self.env.unset(name);
+        } else {
+            self.env.set_with_unresolved(name, value, eval.unresolved);
+        }
+        let value = self.env.get(name).map(str::to_string);
+        self.env.set_candidates(
+            name,
+            vec![ValueCandidate {
+                value,
+                conditions: self.state.path_conditions.clone(),
+            }],
+        );
+    }
+
+    fn record(&mut self, operation: Operation, value: String, eval: Eval, line: &Line) {
+        // Changes in branches that may or may not run
+        let operation = if self.state.undecided > 0
+            && !matches!(operation, Operation::Unset | Operation::Unexport)
+        {
+            Operation::Conditional
+        } else {
+            operation
+        };
+        let change = VariableChange {
+            file: self.path.to_path_buf(),
+            line_number: line.number,
+            line_content: line.text.clone(),
+            operation,
+            value_before: self.env.get(self.target_var).map(|s| s.to_string()),
+            value_after: value,
+            unresolved: eval.unresolved,
+            evaluated: eval.evaluated,
+            conditions: self.state.conditions.clone(),
+            function: None,
+            emulated: None,
+        };
+        match operation {
+            Operation::Unset => self.env.unset(self.target_var),
+            _ => self.env.set_with_unresolved(
+                self.target_var,
+                change.value_after.clone(),
+                change.unresolved.clone(),
+            ),
+        }
+        (self.on_entry)(ParsedShellEntry::Assignment(change), self.env);
+    }
+
+    fn lookup(&self) -> NuLookup<'_> {
+        NuLookup {
+            env: self.env,
+            target_var: self.target_var,
+        }
+    }
+
+    fn eval(&self, expr: &Expr, eval: &mut Eval) -> Value {
+        match expr {
+            Expr::Str { text, bare: true } => match text.as_str() {
+                "true" => Value::Bool(true),
+                "false" => Value::Bool(false),
+                "null" => Value::Nothing,
+                _ => Value::Str(text.clone()),
+            },
+            Expr::Str { text, .. } => Value::Str(text.clone()),
+            Expr::Interp(pieces) => {
+                let mut text = String::new();
+                for piece in pieces {
+                    match piece {
+                        Piece::Text(t) => text.push_str(t),
+                        Piece::Code(pipeline) => {
+                            text.push_str(&self.pipeline(pipeline, eval).to_env())
+                        }
+                    }
+                }
+                Value::Str(text)
+            }
+            Expr::Var(path) => self.variable(path, eval),
+            Expr::List(items) => Value::List(
+                items
+                    .iter()
+                    .flat_map(|(_, item)| self.eval(item, eval).strings())
+                    .collect(),
+            ),
+            Expr::Record(fields) => Value::Record(
+                fields
+                    .iter()
+                    .map(|field| (field.key.clone(), self.eval(&field.value, eval)))
+                    .collect(),
+            ),
+            Expr::Sub(pipeline) => self.pipeline(pipeline, eval),
+            Expr::Binary(left, op, right) => self.binary(left, op, right, eval),
+            Expr::Not(operand) => match self.truth(operand) {
+                Truth::True => Value::Bool(false),
+                Truth::False => Value::Bool(true),
+                Truth::Unknown => Value::Unknown,
+            },
+            Expr::If { arms, text } => {
+                for (condition, value) in arms {
+                    match condition.as_ref().map_or(Truth::True, |c| self.truth(c)) {
+                        Truth::True => return self.pipeline(value, eval),
+                        Truth::False => {}
+                        Truth::Unknown => {
+                            eval.unresolved.push(text.clone());
+                            return Value::Str(text.clone());
+                        }
+                    }
+                }
+                Value::Nothing
+            }
+            Expr::Closure => Value::Unknown,
+        }
+    }
+
+    fn binary(&self, left: &Expr, op: &str, right: &Expr, eval: &mut Eval) -> Value {
+        match op {
+            "and" | "or" => {
+                let (l, r) = (self.truth(left), self.truth(right));
+                let truth = if op == "and" { l.and(r) } else { l.or(r) };
+                return match truth {
+                    Truth::Unknown => Value::Unknown,
+                    truth => Value::Bool(truth == Truth::True),
+                };
+            }
+            "++" => return concat(self.eval(left, eval), self.eval(right, eval)),
+            _ => {}
+        }
+        // Comparisons need both sides known
+        let mut inner = Eval::default();
+        let l = self.eval(left, &mut inner);
+        if let ("in" | "not-in", Expr::Var(path)) = (op, right)
+            && path.len() == 1
+            && path[0] == "env"
+        {
+            // `"NAME" in $env`
+            if !inner.unresolved.is_empty() {
+                return Value::Unknown;
+            }
+            let set = match self.lookup().lookup(&l.to_env()) {
+                Lookup::Set(_) => true,
+                Lookup::Unset => false,
+                Lookup::Unknown => return Value::Unknown,
+            };
+            return Value::Bool(set == (op == "in"));
+        }
+        let r = self.eval(right, &mut inner);
+        if !inner.unresolved.is_empty()
+            || matches!((&l, &r), (Value::Unknown, _) | (_, Value::Unknown))
+        {
+            return Value::Unknown;
+        }
+        match op {
+            "==" => Value::Bool(l.to_env() == r.to_env()),
+            "!=" => Value::Bool(l.to_env() != r.to_env()),
+            "in" | "not-in" => {
+                let found = match &r {
+                    Value::Str(s) => s.contains(&l.to_env()),
+                    r => r.strings().contains(&l.to_env()),
+                };
+                Value::Bool(found == (op == "in"))
+            }
+            "starts-with" => Value::Bool(l.to_env().starts_with(&r.to_env())),
+            "ends-with" => Value::Bool(l.to_env().ends_with(&r.to_env())),
+            _ => Value::Unknown,
+        }
+    }
+
+    /// The value of `$name`, `$env.NAME` or `$nu.constant`
+    fn variable(&self, path: &[String], eval: &mut Eval) -> Value {
+        let source = format!("${}", path.join("."));
+        let value = match path {
+            [env, name] if env == "env" => match name.as_str() {
+                "FILE_PWD" => self
+                    .path
+                    .parent()
+                    .map(|dir| Value::Str(dir.to_string_lossy().to_string())),
+                "CURRENT_FILE" => Some(Value::Str(self.path.to_string_lossy().to_string())),
+                _ => return self.env_value(name, eval),
+            },
+            [nu, rest @ ..] if nu == "nu" => self.constant(rest),
+            [name, members @ ..] => self
+                .scopes
+                .iter()
+                .rev()
+                .find_map(|scope| scope.get(name))
+                .and_then(|value| {
+                    members
+                        .iter()
+                        .try_fold(value.clone(), |value, member| match value {
+                            Value::Record(entries) => entries
+                                .into_iter()
+                                .find(|(key, _)| key == member)
+                                .map(|(_, value)| value),
+                            _ => None,
+                        })
+                }),
+            [] => None,
+        };
+        value.unwrap_or_else(|| {
+            eval.unresolved.push(source.clone());
+            Value::Str(source)
+        })
+    }
+
+    /// An environment variable: PATH reads as a list
+    fn env_value(&self, name: &str, eval: &mut Eval) -> Value {
+        match self.lookup().lookup(name) {
+            Lookup::Set(value) => {
+                if name != self.target_var {
+                    eval.unresolved.extend_from_slice(self.env.unresolved(name));
+                }
+                if is_list_var(name) {
+                    Value::List(
+                        value
+                            .split(':')
+                            .filter(|dir| !dir.is_empty())
+                            .map(str::to_string)
+                            .collect(),
+                    )
+                } else {
+                    Value::Str(value)
+                }
+            }
+            Lookup::Unset => Value::Nothing,
+            Lookup::Unknown => {
+                let source = format!("$env.{}", name);
+                eval.unresolved.push(source.clone());
+                Value::Str(source)
+            }
+        }
+    }
+
+    /// `$nu` constants that describe the shell and its directories
+    fn constant(&self, path: &[String]) -> Option<Value> {
+        let path: Vec<&str> = path.iter().map(String::as_str).collect();
+        let home = match self.lookup().lookup("HOME") {
+            Lookup::Set(home) => Some(home),
+            _ => None,
+        };
+        let config_dir = || match self.lookup().lookup("XDG_CONFIG_HOME") {
+            Lookup::Set(dir) if dir.starts_with('/') => Some(format!("{}/nushell", dir)),
+            _ if cfg!(target_os = "macos") => home
+                .as_ref()
+                .map(|h| format!("{}/Library/Application Support/nushell", h)),
+            _ => home.as_ref().map(|h| format!("{}/.config/nushell", h)),
+        };
+        let value = match path.as_slice() {
+            ["home-path" | "home-dir"] => return home.map(Value::Str),
+            ["default-config-dir"] => config_dir()?,
+            ["config-path"] => format!("{}/config.nu", config_dir()?),
+            ["env-path"] => format!("{}/env.nu", config_dir()?),
+            ["os-info", "name"] => std::env::consts::OS.to_string(),
+            ["is-interactive"] => return self.env.option("interactive").map(Value::Bool),
+            ["is-login"] => return self.env.option("login").map(Value::Bool),
+            _ => return None,
+        };
+        Some(Value::Str(value))
+    }
+
+    /// Run a pipeline, each element taking the previous one's value
+    fn pipeline(&self, pipeline: &Pipeline, eval: &mut Eval) -> Value {
+        let mut input = None;
+        let start = eval.unresolved.len();
+        for element in &pipeline.elements {
+            let value = match element {
+                Element::Expr(expr) => Some(self.eval(expr, eval)),
+                Element::Call { name, args } => {
+                    // Tests on a value that isn't known can't be decided
+                    let input = match input.take() {
+                        Some(_) if eval.unresolved.len() > start && is_test(name) => {
+                            Some(Value::Unknown)
+                        }
+                        input => input,
+                    };
+                    self.call(name, args, input, &pipeline.text, eval)
+                }
+            };
+            match value {
+                Some(value) => input = Some(value),
+                None => {
+                    let source = format!("({})", pipeline.text);
+                    eval.unresolved.push(source.clone());
+                    return Value::Str(source);
+                }
+            }
+        }
+        input.unwrap_or(Value::Nothing)
+    }
+
+    /// A command's value, or None if it can't be known
+    fn call(
+        &self,
+        name: &str,
+        args: &[Expr],
+        input: Option<Value>,
+        source: &str,
+        eval: &mut Eval,
+    ) -> Option<Value> {
+        let input = input.unwrap_or(Value::Nothing);
+        let mut arg = |i: usize| args.get(i).map(|a| self.eval(a, eval));
+        let value = match name {
+            "prepend" => Value::List([arg(0)?.strings(), input.strings()].concat()),
+            "append" => Value::List([input.strings(), arg(0)?.strings()].concat()),
+            "split row" => {
+                let separator = arg(0)?.to_env();
+                Value::List(
+                    input
+                        .strings()
+                        .iter()
+                        .flat_map(|s| s.split(separator.as_str()).map(str::to_string))
+                        .collect(),
+                )
+            }
+            "str join" => {
+                let separator = arg(0).map(|s| s.to_env()).unwrap_or_default();
+                Value::Str(input.strings().join(&separator))
+            }
+            "str trim" => Value::Str(input.to_env().trim().to_string()),
+            "uniq" => {
+                let mut unique: Vec<String> = Vec::new();
+                for item in input.strings() {
+                    if !unique.contains(&item) {
+                        unique.push(item);
+                    }
+                }
+                Value::List(unique)
+            }
+            "compact" => Value::List(
+                input
+                    .strings()
+                    .into_iter()
+                    .filter(|s| !s.is_empty())
+                    .collect(),
+            ),
+            "lines" => Value::List(input.to_env().lines().map(str::to_string).collect()),
+            "default" => match input {
+                Value::Nothing => arg(0)?,
+                input => input,
+            },
+            "into string" => Value::Str(input.to_env()),
+            "path join" => {
+                let mut path = input.to_env();
+                for i in 0..args.len() {
+                    let part = arg(i)?.to_env();
+                    path = if part.starts_with('/') || path.is_empty() {
+                        part
+                    } else {
+                        format!("{}/{}", path.trim_end_matches('/'), part)
+                    };
+                }
+                Value::Str(path)
+            }
+            "path expand" => match input {
+                Value::List(list) => {
+                    Value::List(list.iter().map(|p| self.expand_path(p)).collect())
+                }
+                input => Value::Str(self.expand_path(&input.to_env())),
+            },
+            "path dirname" => Value::Str(
+                Path::new(&input.to_env())
+                    .parent()
+                    .map(|dir| dir.to_string_lossy().to_string())
+                    .unwrap_or_default(),
+            ),
+            "path exists" => match input {
+                Value::Unknown => Value::Unknown,
+                _ if self.env.is_foreign_host() => Value::Unknown,
+                input => Value::Bool(Path::new(&self.expand_path(&input.to_env())).exists()),
+            },
+            "is-empty" | "is-not-empty" => {
+                let empty = match &input {
+                    Value::Unknown => return Some(Value::Unknown),
+                    Value::Str(s) => s.is_empty(),
+                    Value::Nothing => true,
+                    input => input.strings().is_empty(),
+                };
+                Value::Bool(empty == (name == "is-empty"))
+            }
+            // `which NAME` lists where a command is found
+            "which" => {
+                let name = arg(0)?.to_env();
+                let words = [
+                    Word {
+                        parts: vec![WordPart::Quoted("type".to_string())],
+                        span: Span::default(),
+                    },
+                    Word {
+                        parts: vec![WordPart::Quoted(name.clone())],
+                        span: Span::default(),
+                    },
+                ];
+                match evaluate_command(&words, &self.lookup()) {
+                    Truth::True => Value::List(vec![name]),
+                    Truth::False => Value::List(Vec::new()),
+                    Truth::Unknown => Value::Unknown,
+                }
+            }
+            "char" => match arg(0)?.to_env().as_str() {
+                "esep" | "path_sep" | "path-sep" => Value::Str(":".to_string()),
+                "nl" | "newline" | "lf" => Value::Str("\n".to_string()),
+                "space" => Value::Str(" ".to_string()),
+                "tab" => Value::Str("\t".to_string()),
+                _ => return None,
+            },
+            "echo" => match args.len() {
+                1 => arg(0)?,
+                _ => Value::List(
+                    (0..args.len())
+                        .filter_map(&mut arg)
+                        .flat_map(|v| v.strings())
+                        .collect(),
+                ),
+            },
+            _ => return self.external(name, args, input, source, eval),
+        };
+        Some(value)
+    }
+
+    /// The output of an external command (`^brew --prefix`), trailing
+    /// newline removed as nushell does
+    fn external(
+        &self,
+        name: &str,
+        args: &[Expr],
+        input: Value,
+        source: &str,
+        eval: &mut Eval,
+    ) -> Option<Value> {
+        let name = name.strip_prefix('^').unwrap_or(name);
+        let builtin = BUILTINS.contains(&name) || name.contains(' ') || name.contains('-');
+        if builtin || !matches!(input, Value::Nothing) {
+            return None;
+        }
+        let mut argv = vec![name.to_string()];
+        for arg in args {
+            let mut inner = Eval::default();
+            let value = self.eval(arg, &mut inner);
+            if !inner.unresolved.is_empty() {
+                return None;
+            }
+            argv.extend(value.strings());
+        }
+        let command = argv
+            .iter()
+            .map(|arg| shell_quote(arg))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let output = match self.lookup().command_output(&command) {
+            Some(output) => output,
+            None if eval.run_commands => {
+                let source = format!("({})", source);
+                let output = self.env.evaluate(&command, &source, &HashMap::new())?;
+                eval.evaluated.push(source);
+                output
+            }
+            None => return None,
+        };
+        Some(Value::Str(output.trim_end_matches('\n').to_string()))
+    }
+
+    /// Expand a leading `~` to HOME
+    fn expand_tilde(&self, path: &str) -> String {
+        match (path.strip_prefix('~'), self.lookup().lookup("HOME")) {
+            (Some(rest), Lookup::Set(home)) if rest.is_empty() || rest.starts_with('/') => {
+                format!("{}{}", home, rest)
+            }
+            _ => path.to_string(),
+        }
+    }
+
+    /// `path expand`: `~` and relative paths become absolute
+    fn expand_path(&self, path: &str) -> String {
+        let path = self.expand_tilde(path);
+        match self.lookup().lookup("PWD") {
+            Lookup::Set(cwd) if !path.starts_with('/') => resolve_path(&cwd, &path),
+            _ => path,
+        }
+    }
+}
+
+/// Commands that test their input
+fn is_test(name: &str) -> bool {
+    matches!(name, "path exists" | "is-empty" | "is-not-empty")
+}
+
+/// `a ++ b`: lists concatenate, strings join
+fn concat(left: Value, right: Value) -> Value {
+    match (left, right) {
+        (Value::Str(l), Value::Str(r)) => Value::Str(l + &r),
+        (Value::Nothing, right) => right,
+        (left, right) => Value::List([left.strings(), right.strings()].concat()),
+    }
+}
+
+/// How `$env.NAME = value` changes a list: `$env.NAME | prepend x` and
+/// `[x ...$env.NAME]` prepend, `append` and `[...$env.NAME x]` append
+fn list_operation(name: &str, pipeline: &Pipeline) -> Option<Operation> {
+    let is_own = |expr: &Expr| matches!(expr, Expr::Var(path) if path.len() == 2 && path[0] == "env" && path[1] == name);
+    match pipeline.elements.as_slice() {
+        // `($env.PATH | prepend x)`
+        [Element::Expr(Expr::Sub(inner))] => list_operation(name, inner),
+        [Element::Expr(Expr::List(items))] => {
+            let own = items
+                .iter()
+                .position(|(spread, item)| *spread && is_own(item))?;
+            match (own > 0, own + 1 < items.len()) {
+                (true, false) => Some(Operation::Prepend),
+                (false, true) => Some(Operation::Append),
+                _ => None,
+            }
+        }
+        [Element::Expr(first), rest @ ..] if is_own(first) => {
+            let mut calls = Vec::new();
+            for element in rest {
+                match element {
+                    Element::Call { name, .. }
+                        if matches!(
+                            name.as_str(),
+                            "prepend" | "append" | "split row" | "uniq" | "compact"
+                        ) =>
+                    {
+                        calls.push(name.as_str())
+                    }
+                    _ => return None,
+                }
+            }
+            match (calls.contains(&"prepend"), calls.contains(&"append")) {
+                (true, false) => Some(Operation::Prepend),
+                (false, true) => Some(Operation::Append),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+impl Branching for NuWalker<'_> {
+    type Block = [Statement];
+
+    fn state(&mut self) -> &mut PathState {
+        &mut self.state
+    }
+
+    fn env(&mut self) -> &mut ShellEnv {
+        self.env
+    }
+
+    fn walk_block(&mut self, block: &[Statement]) -> Truth {
+        self.scoped(block, HashMap::new());
+        Truth::Unknown
+    }
+}
+
+/// Variable values for expressions and conditions in a nushell script
+struct NuLookup<'a> {
+    env: &'a ShellEnv,
+    target_var: &'a str,
+}
+
+impl VarLookup for NuLookup<'_> {
+    fn lookup(&self, name: &str) -> Lookup {
+        if self.env.is_tracked(name) {
+            return match self.env.get(name) {
+                Some(value) => Lookup::Set(value.to_string()),
+                None => Lookup::Unset,
+            };
+        }
+        if name == self.target_var {
+            return Lookup::Unset;
+        }
+        if let Some(value) = self.env.session_value(name) {
+            return match value {
+                Some(v) => Lookup::Set(v.to_string()),
+                None => Lookup::Unset,
+            };
+        }
+        match name {
+            "HOME" => match dirs::home_dir() {
+                Some(home) => Lookup::Set(home.to_string_lossy().to_string()),
+                None => Lookup::Unknown,
+            },
+            // Startup files are read in the home directory
+            "PWD" => self.lookup("HOME"),
+            _ => Lookup::Unknown,
+        }
+    }
+
+    fn command_output(&self, command: &str) -> Option<String> {
+        self.env
+            .command_output(command)
+            .or_else(|| path_command_output(command, self))
+    }
+}
+
+impl ShellState for NuLookup<'_> {
+    fn option(&self, name: &str) -> Option<bool> {
+        self.env.option(name)
+    }
+
+    fn foreign_host(&self) -> bool {
+        self.env.is_foreign_host()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_nushell_content(
+        content: &str,
+        target_var: &str,
+        env: &mut ShellEnv,
+    ) -> Vec<VariableChange> {
+        let mut results = Vec::new();
+        let path = Path::new("/test/env.nu");
+        let guard = Guard::default();
+        let mut on_entry = |entry, _: &mut ShellEnv| {
+            if let ParsedShellEntry::Assignment(change) = entry {
+                results.push(change);
+            }
+        };
+        let mut walker = NuWalker::new(path, target_var, env, &guard, &mut on_entry);
+        walker.walk(&parse(content));
+        results
+    }
+
+    #[test]
+    fn test_env_path() {
+        let mut env = ShellEnv::new();
+        env.set("PATH", "/usr/bin");
+        let content = "\
+$env.PATH = ($env.PATH | split row (char esep) | prepend '/opt/bin')
+$env.PATH = ($env.PATH
+    | append /late)
+$env.PATH ++= ['/x']
+$env.PATH = [/first ...$env.PATH]
+$env.PATH = ['/only']
+";
+        let changes = parse_nushell_content(content, "PATH", &mut env);
+        let operations: Vec<Operation> = changes.iter().map(|c| c.operation).collect();
+        assert_eq!(
+            operations,
+            [
+                Operation::Prepend,
+                Operation::Append,
+                Operation::Append,
+                Operation::Prepend,
+                Operation::Export
+            ]
+        );
+        assert_eq!(changes[0].value_after, "/opt/bin:/usr/bin");
+        assert_eq!(
+            changes[1].line_content,
+            "$env.PATH = ($env.PATH | append /late)"
+        );
+        assert_eq!(changes[3].value_after, "/first:/opt/bin:/usr/bin:/late:/x");
+        assert_eq!(changes[4].value_after, "/only");
+    }
+
+    #[test]
+    fn test_path_add() {
+        let mut env = ShellEnv::new();
+        env.set("HOME", "/home/user");
+        env.set("PATH", "/usr/bin:/bin");
+        let content = "\
+use std/util \"path add\"
+path add ~/.cargo/bin /usr/bin
+path add --append /opt/late
+";
+        let changes = parse_nushell_content(content, "PATH", &mut env);
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].operation, Operation::Prepend);
+        assert_eq!(
+            changes[0].value_after,
+            "/home/user/.cargo/bin:/usr/bin:/bin"
+        );
+        assert_eq!(changes[1].operation, Operation::Append);
+        assert_eq!(
+            changes[1].value_after,
+            "/home/user/.cargo/bin:/usr/bin:/bin:/opt/late"
+        );
+    }
+
+    #[test]
+    fn test_load_env_and_variables() {
+        let mut env = ShellEnv::new();
+        env.set("HOME", "/home/user");
+        let content = "\
+let tools = $\"($env.HOME)/tools\"
+load-env {
+    GOPATH: ($tools | path join go)
+    EDITOR: hx
+}
+hide-env EDITOR
+";
+        let changes = parse_nushell_content(content, "GOPATH", &mut env);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].line_number, 3);
+        assert_eq!(changes[0].line_content, "GOPATH: ($tools | path join go)");
+        assert_eq!(changes[0].value_after, "/home/user/tools/go");
+        assert_eq!(env.get("EDITOR"), None);
+        assert_eq!(env.is_exported("GOPATH"), Some(true));
+    }
+
+    #[test]
+    fn test_branches() {
+        let mut env = ShellEnv::new();
+        env.set_option("login", true);
+        let content = "\
+if $nu.is-login {
+    $env.EDITOR = vim
+} else {
+    $env.EDITOR = nano
+}
+if \"SSH_TTY\" in $env {
+    $env.EDITOR = ed
+}
+";
+        let changes = parse_nushell_content(content, "EDITOR", &mut env);
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].operation, Operation::Export);
+        assert_eq!(changes[0].value_after, "vim");
+        assert_eq!(changes[1].operation, Operation::Conditional);
+        assert_eq!(changes[1].conditions, ["\"SSH_TTY\" in $env"]);
+    }
+
+    #[test]
+    fn test_conditional_values() {
+        let mut env = ShellEnv::new();
+        let content = "\
+$env.EDITOR = if ($nu.os-info.name == \"plan9\") { \"acme\" } else { \"vim\" }
+let pager = match $nu.os-info.name { \"plan9\" | \"inferno\" => \"p\", _ => { \"less\" } }
+$env.PAGER = $pager
+$env.VISUAL = match $env.TERM_PROGRAM { \"vscode\" => \"code\", _ => $env.EDITOR }
+";
+        let changes = parse_nushell_content(content, "EDITOR", &mut env);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].value_after, "vim");
+        assert_eq!(env.get("PAGER"), Some("less"));
+
+        let changes = parse_nushell_content(content, "VISUAL", &mut ShellEnv::new());
+        let values: Vec<&str> = changes.iter().map(|c| c.value_after.as_str()).collect();
+        assert_eq!(values, ["code", "vim"]);
+        assert_eq!(changes[0].conditions, ["($env.TERM_PROGRAM == \"vscode\")"]);
+        assert_eq!(changes[1].operation, Operation::Conditional);
+    }
+
+    #[test]
+    fn test_malformed_input_terminates() {
+        for content in [
+            "[|",
+            "$env.A = [|",
+            "match",
+            "$env.A = match $x { =>",
+            "if (",
+            ")]}",
+        ] {
+            parse_nushell_content(content, "A", &mut ShellEnv::new());
+        }
+    }
+
+    #[test]
+    fn test_lexer() {
+        let tokens: Vec<Tok> = Lexer::new("$env.FOO=\"a\\tb\" # note\n{ K: $'x(1)' }")
+            .tokens()
+            .into_iter()
+            .map(|t| t.tok)
+            .collect();
+        assert_eq!(
+            tokens,
+            [
+                Tok::Var(vec!["env".into(), "FOO".into()]),
+                Tok::Word("=".into()),
+                Tok::Str("a\tb".into()),
+                Tok::End,
+                Tok::Open('{'),
+                Tok::Word("K".into()),
+                Tok::Colon,
+                Tok::Interp(vec![Piece::Text("x".into()), Piece::Code("1".into())]),
+                Tok::Close('}'),
+            ]
+        );
+    }
+}
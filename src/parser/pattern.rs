@@ -0,0 +1,282 @@
+//! Shell glob patterns (`*`, `?`, `[...]`)
+//!
+//! Used for `${VAR#pattern}`-style expansions and pathname expansion. Pattern text uses a backslash
+//! to mark characters that came from quoted text and must match literally.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Char(char),
+    /// `*`
+    Any,
+    /// `?`
+    One,
+    /// `[...]`
+    Class {
+        negated: bool,
+        items: Vec<ClassItem>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ClassItem {
+    Char(char),
+    Range(char, char),
+}
+
+/// A compiled glob pattern
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pattern {
+    tokens: Vec<Token>,
+}
+
+impl Pattern {
+    /// Compile a pattern; `\x` matches `x` literally
+    pub fn new(text: &str) -> Self {
+        let chars: Vec<char> = text.chars().collect();
+        let mut tokens = Vec::new();
+        let mut i = 0;
+        while i < chars.len() {
+            match chars[i] {
+                '\\' if i + 1 < chars.len() => {
+                    tokens.push(Token::Char(chars[i + 1]));
+                    i += 2;
+                    continue;
+                }
+                '*' => {
+                    // Collapse runs of * so matching stays linear-ish
+                    if tokens.last() != Some(&Token::Any) {
+                        tokens.push(Token::Any);
+                    }
+                }
+                '?' => tokens.push(Token::One),
+                '[' => {
+                    if let Some((class, next)) = parse_class(&chars, i) {
+                        tokens.push(class);
+                        i = next;
+                        continue;
+                    }
+                    tokens.push(Token::Char('['));
+                }
+                c => tokens.push(Token::Char(c)),
+            }
+            i += 1;
+        }
+        Self { tokens }
+    }
+
+    /// Escape text so every character matches literally
+    pub fn escape(text: &str) -> String {
+        let mut out = String::with_capacity(text.len());
+        for c in text.chars() {
+            if matches!(c, '\\' | '*' | '?' | '[' | ']') {
+                out.push('\\');
+            }
+            out.push(c);
+        }
+        out
+    }
+
+    /// The text the pattern matches if it has no wildcards
+    pub fn literal(&self) -> Option<String> {
+        self.tokens
+            .iter()
+            .map(|token| match token {
+                Token::Char(c) => Some(*c),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Returns true if the pattern starts with a literal `.`
+    pub fn starts_with_dot(&self) -> bool {
+        self.tokens.first() == Some(&Token::Char('.'))
+    }
+
+    /// Returns true if the whole of `text` matches the pattern
+    pub fn matches(&self, text: &str) -> bool {
+        let chars: Vec<char> = text.chars().collect();
+        match_tokens(&self.tokens, &chars)
+    }
+
+    /// Remove the shortest (or longest) matching prefix from `text`
+    pub fn strip_prefix<'a>(&self, text: &'a str, longest: bool) -> &'a str {
+        let mut bounds: Vec<usize> = char_bounds(text);
+        if longest {
+            bounds.reverse();
+        }
+        for end in bounds {
+            if self.matches(&text[..end]) {
+                return &text[end..];
+            }
+        }
+        text
+    }
+
+    /// Remove the shortest (or longest) matching suffix from `text`
+    pub fn strip_suffix<'a>(&self, text: &'a str, longest: bool) -> &'a str {
+        let mut bounds: Vec<usize> = char_bounds(text);
+        if !longest {
+            bounds.reverse();
+        }
+        for start in bounds {
+            if self.matches(&text[start..]) {
+                return &text[..start];
+            }
+        }
+        text
+    }
+
+    /// Replace the first (or every) longest match of the pattern in `text`
+    pub fn replace(&self, text: &str, replacement: &str, all: bool) -> String {
+        let bounds = char_bounds(text);
+        let mut out = String::new();
+        let mut pos = 0;
+        while pos < text.len() {
+            let found = bounds
+                .iter()
+                .rev()
+                .filter(|&&end| end > pos)
+                .find(|&&end| self.matches(&text[pos..end]));
+            match found {
+                Some(&end) => {
+                    out.push_str(replacement);
+                    pos = end;
+                    if !all {
+                        out.push_str(&text[pos..]);
+                        return out;
+                    }
+                }
+                None => {
+                    let c = text[pos..].chars().next().unwrap();
+                    out.push(c);
+                    pos += c.len_utf8();
+                }
+            }
+        }
+        out
+    }
+}
+
+/// Byte offsets of every char boundary in `text`, including both ends
+fn char_bounds(text: &str) -> Vec<usize> {
+    let mut bounds: Vec<usize> = text.char_indices().map(|(i, _)| i).collect();
+    bounds.push(text.len());
+    bounds
+}
+
+fn parse_class(chars: &[char], start: usize) -> Option<(Token, usize)> {
+    let mut i = start + 1;
+    let negated = matches!(chars.get(i), Some('!') | Some('^'));
+    if negated {
+        i += 1;
+    }
+    let mut items = Vec::new();
+    let mut first = true;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == ']' && !first {
+            return Some((Token::Class { negated, items }, i + 1));
+        }
+        first = false;
+        if c == '[' && chars.get(i + 1) == Some(&':') {
+            // Character classes like [:alpha:]
+            let rest: String = chars[i..].iter().collect();
+            if let Some(end) = rest.find(":]") {
+                let name = &rest[2..end];
+                items.extend(named_class(name));
+                i += rest[..end + 2].chars().count();
+                continue;
+            }
+        }
+        if chars.get(i + 1) == Some(&'-') && chars.get(i + 2).is_some_and(|&c| c != ']') {
+            items.push(ClassItem::Range(c, chars[i + 2]));
+            i += 3;
+        } else {
+            items.push(ClassItem::Char(c));
+            i += 1;
+        }
+    }
+    None
+}
+
+fn named_class(name: &str) -> Vec<ClassItem> {
+    match name {
+        "alpha" => vec![ClassItem::Range('a', 'z'), ClassItem::Range('A', 'Z')],
+        "digit" => vec![ClassItem::Range('0', '9')],
+        "alnum" => vec![
+            ClassItem::Range('a', 'z'),
+            ClassItem::Range('A', 'Z'),
+            ClassItem::Range('0', '9'),
+        ],
+        "upper" => vec![ClassItem::Range('A', 'Z')],
+        "lower" => vec![ClassItem::Range('a', 'z')],
+        "space" => " \t\n\r".chars().map(ClassItem::Char).collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn match_tokens(tokens: &[Token], text: &[char]) -> bool {
+    match tokens.first() {
+        None => text.is_empty(),
+        Some(Token::Any) => (0..=text.len()).any(|skip| match_tokens(&tokens[1..], &text[skip..])),
+        Some(token) => {
+            let Some(&c) = text.first() else {
+                return false;
+            };
+            let ok = match token {
+                Token::Char(p) => *p == c,
+                Token::One => true,
+                Token::Class { negated, items } => {
+                    let hit = items.iter().any(|item| match item {
+                        ClassItem::Char(x) => *x == c,
+                        ClassItem::Range(lo, hi) => (*lo..=*hi).contains(&c),
+                    });
+                    hit != *negated
+                }
+                Token::Any => unreachable!(),
+            };
+            ok && match_tokens(&tokens[1..], &text[1..])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wildcards() {
+        assert!(Pattern::new("darwin*").matches("darwin23.0"));
+        assert!(Pattern::new("*i*").matches("himBH"));
+        assert!(!Pattern::new("*i*").matches("hmBH"));
+        assert!(Pattern::new("file?.[ch]").matches("file1.c"));
+        assert!(!Pattern::new("[!a-c]x").matches("bx"));
+    }
+
+    #[test]
+    fn test_escaped_chars_are_literal() {
+        let pattern = Pattern::new(&Pattern::escape("a*b"));
+        assert!(pattern.matches("a*b"));
+        assert!(!pattern.matches("axxb"));
+    }
+
+    #[test]
+    fn test_strip_prefix_and_suffix() {
+        let pattern = Pattern::new("*/");
+        assert_eq!(
+            pattern.strip_prefix("/usr/local/bin", false),
+            "usr/local/bin"
+        );
+        assert_eq!(pattern.strip_prefix("/usr/local/bin", true), "bin");
+        let pattern = Pattern::new(".*");
+        assert_eq!(pattern.strip_suffix("a.tar.gz", false), "a.tar");
+        assert_eq!(pattern.strip_suffix("a.tar.gz", true), "a");
+    }
+
+    #[test]
+    fn test_replace() {
+        let pattern = Pattern::new(":");
+        assert_eq!(pattern.replace("/a:/b:/c", " ", true), "/a /b /c");
+        assert_eq!(pattern.replace("/a:/b:/c", " ", false), "/a /b:/c");
+    }
+}
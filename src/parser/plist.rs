@@ -31,6 +31,11 @@ pub fn parse_plist_file(path: &Path, target_var: &str) -> std::io::Result<Vec<Va
                 operation: Operation::Set,
                 value_before: None,
                 value_after: val.clone(),
+                unresolved: Vec::new(),
+                evaluated: Vec::new(),
+                conditions: Vec::new(),
+                function: None,
+                emulated: None,
             });
         }
 
@@ -61,6 +66,11 @@ pub fn parse_plist_file(path: &Path, target_var: &str) -> std::io::Result<Vec<Va
                         operation: Operation::Set,
                         value_before: None,
                         value_after: window[3].to_string(),
+                        unresolved: Vec::new(),
+                        evaluated: Vec::new(),
+                        conditions: Vec::new(),
+                        function: None,
+                        emulated: None,
                     });
                 }
             }
@@ -0,0 +1,812 @@
+//! Statement parser for bash/zsh scripts
+//!
+//! Groups the tokens produced by the lexer into a statement tree: simple
+//! commands joined by `&&`, `||` and pipes, brace groups, subshells,
+//! function definitions and the compound commands (`if`, `case`, `for`,
+//! `while`, `until`). Each statement keeps its source span so callers can
+//! report the exact text and line number it came from.
+
+use super::lexer::{Operator, Span, Token, TokenKind, Word, normalize, tokenize};
+
+/// Reserved words that close (or continue) a compound command's list
+const CLOSERS: &[&str] = &["then", "elif", "else", "fi", "do", "done", "esac", "}"];
+
+/// A simple command (redirections are parsed but not kept)
+#[derive(Debug, Clone)]
+pub struct SimpleCommand {
+    pub words: Vec<Word>,
+    pub span: Span,
+}
+
+/// A function definition (`name() { ... }` or `function name { ... }`)
+#[derive(Debug, Clone)]
+pub struct FunctionDef {
+    pub name: String,
+    pub body: Vec<Statement>,
+    pub span: Span,
+}
+
+/// One `if`/`elif` branch: the body runs when the condition succeeds
+#[derive(Debug, Clone)]
+pub struct Branch {
+    pub condition: Vec<Statement>,
+    pub body: Vec<Statement>,
+}
+
+/// `if ...; then ...; elif ...; then ...; else ...; fi`
+#[derive(Debug, Clone)]
+pub struct IfCommand {
+    pub branches: Vec<Branch>,
+    pub else_body: Option<Vec<Statement>>,
+    pub span: Span,
+}
+
+/// One `pattern|pattern) ...;;` arm of a case statement
+#[derive(Debug, Clone)]
+pub struct CaseArm {
+    pub patterns: Vec<Word>,
+    pub body: Vec<Statement>,
+}
+
+/// `case word in ... esac`
+#[derive(Debug, Clone)]
+pub struct CaseCommand {
+    pub word: Word,
+    pub arms: Vec<CaseArm>,
+    pub span: Span,
+}
+
+/// The kind of loop and what drives it
+#[derive(Debug, Clone)]
+pub enum LoopKind {
+    /// `for name in words` (`words` is None for `for name; do` and `for ((...))`)
+    For {
+        var: String,
+        words: Option<Vec<Word>>,
+    },
+    /// `while condition`
+    While(Vec<Statement>),
+    /// `until condition`
+    Until(Vec<Statement>),
+}
+
+/// A `for`, `select`, `while` or `until` loop
+#[derive(Debug, Clone)]
+pub struct LoopCommand {
+    pub kind: LoopKind,
+    /// The loop header up to (not including) `do`
+    pub header: Span,
+    pub body: Vec<Statement>,
+    pub span: Span,
+}
+
+/// A command within a statement
+#[derive(Debug, Clone)]
+pub enum Command {
+    Simple(SimpleCommand),
+    /// `{ ...; }`
+    Group(Vec<Statement>, Span),
+    /// `( ... )`
+    Subshell(Vec<Statement>, Span),
+    Function(FunctionDef),
+    If(IfCommand),
+    Case(CaseCommand),
+    Loop(LoopCommand),
+    /// `! command` (negates the exit status)
+    Not(Box<Command>),
+}
+
+impl Command {
+    pub fn span(&self) -> Span {
+        match self {
+            Command::Simple(c) => c.span,
+            Command::Group(_, span) | Command::Subshell(_, span) => *span,
+            Command::Function(f) => f.span,
+            Command::If(c) => c.span,
+            Command::Case(c) => c.span,
+            Command::Loop(c) => c.span,
+            Command::Not(c) => c.span(),
+        }
+    }
+
+    /// Every statement list nested directly in this command, in source order
+    pub fn bodies(&self) -> Vec<&[Statement]> {
+        match self {
+            Command::Simple(_) => Vec::new(),
+            Command::Not(c) => c.bodies(),
+            Command::Group(body, _) | Command::Subshell(body, _) => vec![body],
+            Command::Function(f) => vec![&f.body],
+            Command::If(c) => {
+                let mut bodies: Vec<&[Statement]> = Vec::new();
+                for branch in &c.branches {
+                    bodies.push(&branch.condition);
+                    bodies.push(&branch.body);
+                }
+                bodies.extend(c.else_body.as_deref());
+                bodies
+            }
+            Command::Case(c) => c.arms.iter().map(|arm| arm.body.as_slice()).collect(),
+            Command::Loop(c) => match &c.kind {
+                LoopKind::While(condition) | LoopKind::Until(condition) => {
+                    vec![condition, &c.body]
+                }
+                LoopKind::For { .. } => vec![&c.body],
+            },
+        }
+    }
+}
+
+/// How a command is joined to the one before it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Connector {
+    /// First command of a statement
+    Start,
+    /// `&&`
+    And,
+    /// `||`
+    Or,
+    /// `|` or `|&`
+    Pipe,
+}
+
+/// A statement: one or more commands joined by `&&`, `||` or `|`
+#[derive(Debug, Clone)]
+pub struct Statement {
+    pub commands: Vec<(Connector, Command)>,
+    pub span: Span,
+}
+
+/// A parsed shell script together with its (normalized) source
+#[derive(Debug, Clone)]
+pub struct Script {
+    pub source: String,
+    pub statements: Vec<Statement>,
+}
+
+impl Script {
+    /// Parse shell source into statements
+    pub fn parse(content: &str) -> Self {
+        let source = normalize(content);
+        let tokens = tokenize(&source);
+        let mut parser = Parser { tokens, pos: 0 };
+        let statements = parser.parse_list(false);
+        Self { source, statements }
+    }
+
+    /// The source text covered by a span
+    pub fn text(&self, span: Span) -> &str {
+        self.source[span.start..span.end].trim()
+    }
+
+    /// The source text of a statement list (e.g. an `if` condition)
+    pub fn list_text(&self, statements: &[Statement]) -> &str {
+        match (statements.first(), statements.last()) {
+            (Some(first), Some(last)) => self.text(first.span.to(last.span)),
+            _ => "",
+        }
+    }
+
+    /// A full physical source line (1-indexed)
+    pub fn line(&self, line_number: usize) -> &str {
+        self.source
+            .lines()
+            .nth(line_number.saturating_sub(1))
+            .unwrap_or("")
+    }
+
+    /// The line a span ends on (1-indexed)
+    pub fn end_line(&self, span: Span) -> usize {
+        let end = span.end.max(span.start + 1).min(self.source.len());
+        span.line
+            + self.source[span.start..end]
+                .trim_end()
+                .matches('\n')
+                .count()
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn peek_word(&self) -> Option<&Word> {
+        match self.peek() {
+            Some(Token {
+                kind: TokenKind::Word(w),
+                ..
+            }) => Some(w),
+            _ => None,
+        }
+    }
+
+    fn peek_op(&self, offset: usize) -> Option<Operator> {
+        match self.tokens.get(self.pos + offset) {
+            Some(Token {
+                kind: TokenKind::Op(op),
+                ..
+            }) => Some(*op),
+            _ => None,
+        }
+    }
+
+    fn at_word(&self, text: &str) -> bool {
+        self.peek_word().is_some_and(|w| w.is_literal(text))
+    }
+
+    fn at_newline(&self) -> bool {
+        matches!(self.peek().map(|t| &t.kind), Some(TokenKind::Newline))
+    }
+
+    /// Returns true at a reserved word or operator that ends a nested list
+    fn at_closer(&self) -> bool {
+        if let Some(word) = self.peek_word() {
+            return CLOSERS.iter().any(|c| word.is_literal(c));
+        }
+        matches!(
+            self.peek_op(0),
+            Some(Operator::RParen | Operator::DoubleSemi | Operator::CaseFallthrough)
+        )
+    }
+
+    /// The span of the most recently consumed token
+    fn last_span(&self, fallback: Span) -> Span {
+        self.pos
+            .checked_sub(1)
+            .and_then(|i| self.tokens.get(i))
+            .map_or(fallback, |t| t.span)
+    }
+
+    /// Consume `word` if it is next
+    fn expect_word(&mut self, word: &str) -> bool {
+        let found = self.at_word(word);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    /// Parse statements until end of input, or until a word or operator that
+    /// closes the enclosing compound command when `nested` is set (the closer
+    /// is left for the caller)
+    fn parse_list(&mut self, nested: bool) -> Vec<Statement> {
+        let mut statements = Vec::new();
+        loop {
+            self.skip_separators();
+            if self.peek().is_none() {
+                break;
+            }
+            if self.at_closer() {
+                if nested {
+                    break;
+                }
+                // Stray closing word or operator
+                self.pos += 1;
+                continue;
+            }
+            let start = self.pos;
+            if let Some(statement) = self.parse_statement() {
+                statements.push(statement);
+            }
+            if self.pos == start {
+                // Nothing we can parse here (e.g. a leading `|`)
+                self.pos += 1;
+            }
+        }
+        statements
+    }
+
+    /// Skip newlines and separators between statements
+    fn skip_separators(&mut self) {
+        while let Some(token) = self.peek() {
+            match &token.kind {
+                TokenKind::Newline | TokenKind::Op(Operator::Semi | Operator::Amp) => {}
+                _ => break,
+            }
+            self.pos += 1;
+        }
+    }
+
+    fn skip_newlines(&mut self) {
+        while self.at_newline() {
+            self.pos += 1;
+        }
+    }
+
+    fn parse_statement(&mut self) -> Option<Statement> {
+        let mut commands = Vec::new();
+        let mut connector = Connector::Start;
+
+        while let Some(command) = self.parse_command() {
+            commands.push((connector, command));
+
+            connector = match self.peek_op(0) {
+                Some(Operator::AndIf) => Connector::And,
+                Some(Operator::OrIf) => Connector::Or,
+                Some(Operator::Pipe | Operator::PipeAll) => Connector::Pipe,
+                _ => break,
+            };
+            self.pos += 1;
+            // A newline may follow && || |
+            self.skip_newlines();
+        }
+
+        let first = commands.first()?.1.span();
+        let last = commands.last()?.1.span();
+        Some(Statement {
+            commands,
+            span: first.to(last),
+        })
+    }
+
+    fn parse_command(&mut self) -> Option<Command> {
+        if self.at_closer() {
+            return None;
+        }
+        let token = self.peek()?;
+        let start = token.span;
+
+        if self.peek_op(0) == Some(Operator::LParen) {
+            self.pos += 1;
+            let body = self.parse_list(true);
+            if self.peek_op(0) == Some(Operator::RParen) {
+                self.pos += 1;
+            }
+            let span = self.finish_compound(start);
+            return Some(Command::Subshell(body, span));
+        }
+
+        let TokenKind::Word(w) = &token.kind else {
+            return self.parse_simple_command();
+        };
+
+        // `! cmd` negates the exit status only
+        if w.is_literal("!") {
+            self.pos += 1;
+            return self
+                .parse_command()
+                .map(|command| Command::Not(Box::new(command)));
+        }
+
+        // Brace group
+        if w.is_literal("{") {
+            self.pos += 1;
+            let body = self.parse_list(true);
+            let mut span = start;
+            if self.at_word("}") {
+                span = start.to(self.peek().unwrap().span);
+                self.pos += 1;
+            }
+            let span = self.finish_compound(span);
+            return Some(Command::Group(body, span));
+        }
+
+        if w.is_literal("if") {
+            return Some(self.parse_if(start));
+        }
+        if w.is_literal("while") || w.is_literal("until") {
+            return Some(self.parse_while(start));
+        }
+        if w.is_literal("for") || w.is_literal("select") {
+            return Some(self.parse_for(start));
+        }
+        if w.is_literal("case") {
+            return Some(self.parse_case(start));
+        }
+
+        // function name [()] { ... }
+        if w.is_literal("function")
+            && let Some(TokenKind::Word(name)) = self.tokens.get(self.pos + 1).map(|t| &t.kind)
+            && let Some(name) = name.as_literal()
+        {
+            self.pos += 2;
+            if self.peek_op(0) == Some(Operator::LParen)
+                && self.peek_op(1) == Some(Operator::RParen)
+            {
+                self.pos += 2;
+            }
+            return Some(self.parse_function_body(name, start));
+        }
+
+        // name() { ... }
+        if self.peek_op(1) == Some(Operator::LParen)
+            && self.peek_op(2) == Some(Operator::RParen)
+            && let Some(name) = w.as_literal()
+        {
+            self.pos += 3;
+            return Some(self.parse_function_body(name, start));
+        }
+
+        self.parse_simple_command()
+    }
+
+    /// Skip redirections after a compound command (`done < file`, `fi 2>&1`)
+    fn finish_compound(&mut self, mut span: Span) -> Span {
+        while let Some(Operator::Redirect(..)) = self.peek_op(0) {
+            span = span.to(self.peek().unwrap().span);
+            self.pos += 1;
+            if let Some(target) = self.peek_word() {
+                span = span.to(target.span);
+                self.pos += 1;
+            }
+        }
+        span
+    }
+
+    fn parse_if(&mut self, start: Span) -> Command {
+        self.pos += 1;
+        let mut branches = Vec::new();
+        let mut else_body = None;
+        loop {
+            let condition = self.parse_list(true);
+            self.expect_word("then");
+            let body = self.parse_list(true);
+            branches.push(Branch { condition, body });
+            if self.expect_word("elif") {
+                continue;
+            }
+            if self.expect_word("else") {
+                else_body = Some(self.parse_list(true));
+            }
+            self.expect_word("fi");
+            break;
+        }
+        let span = self.finish_compound(start.to(self.last_span(start)));
+        Command::If(IfCommand {
+            branches,
+            else_body,
+            span,
+        })
+    }
+
+    fn parse_while(&mut self, start: Span) -> Command {
+        let until = self.at_word("until");
+        self.pos += 1;
+        let condition = self.parse_list(true);
+        let header = start.to(self.last_span(start));
+        let body = self.parse_loop_body();
+        let kind = if until {
+            LoopKind::Until(condition)
+        } else {
+            LoopKind::While(condition)
+        };
+        let span = self.finish_compound(start.to(self.last_span(start)));
+        Command::Loop(LoopCommand {
+            kind,
+            header,
+            body,
+            span,
+        })
+    }
+
+    fn parse_for(&mut self, start: Span) -> Command {
+        self.pos += 1;
+        let mut var = String::new();
+        let mut words = None;
+
+        if self.peek_op(0) == Some(Operator::LParen) {
+            // Arithmetic for: for ((init; test; step))
+            let mut depth = 0;
+            while let Some(token) = self.peek() {
+                match token.kind {
+                    TokenKind::Op(Operator::LParen) => depth += 1,
+                    TokenKind::Op(Operator::RParen) => depth -= 1,
+                    _ => {}
+                }
+                self.pos += 1;
+                if depth == 0 {
+                    break;
+                }
+            }
+        } else if let Some(name) = self.peek_word().and_then(|w| w.as_literal()) {
+            var = name;
+            self.pos += 1;
+            self.skip_newlines();
+            if self.expect_word("in") {
+                words = Some(self.collect_words(false));
+            } else if self.peek_op(0) == Some(Operator::LParen) {
+                // zsh short form: for name (words)
+                self.pos += 1;
+                words = Some(self.collect_words(true));
+                if self.peek_op(0) == Some(Operator::RParen) {
+                    self.pos += 1;
+                }
+            }
+        }
+        let header = start.to(self.last_span(start));
+
+        self.skip_separators();
+        let body = if self.at_word("do") {
+            self.parse_loop_body()
+        } else {
+            // zsh allows a brace group (or single command) as the body
+            match self.parse_command() {
+                Some(Command::Group(body, _)) => body,
+                Some(other) => {
+                    let span = other.span();
+                    vec![Statement {
+                        commands: vec![(Connector::Start, other)],
+                        span,
+                    }]
+                }
+                None => Vec::new(),
+            }
+        };
+        let span = self.finish_compound(start.to(self.last_span(start)));
+        Command::Loop(LoopCommand {
+            kind: LoopKind::For { var, words },
+            header,
+            body,
+            span,
+        })
+    }
+
+    /// Collect words up to the end of the line (or a closing paren)
+    fn collect_words(&mut self, until_paren: bool) -> Vec<Word> {
+        let mut words = Vec::new();
+        loop {
+            if until_paren && self.at_newline() {
+                self.pos += 1;
+                continue;
+            }
+            match self.peek_word() {
+                Some(word) => {
+                    words.push(word.clone());
+                    self.pos += 1;
+                }
+                None => break,
+            }
+        }
+        words
+    }
+
+    /// `do ... done`
+    fn parse_loop_body(&mut self) -> Vec<Statement> {
+        self.skip_separators();
+        self.expect_word("do");
+        let body = self.parse_list(true);
+        self.expect_word("done");
+        body
+    }
+
+    fn parse_case(&mut self, start: Span) -> Command {
+        self.pos += 1;
+        let word = match self.peek_word() {
+            Some(w) => {
+                let w = w.clone();
+                self.pos += 1;
+                w
+            }
+            None => Word {
+                parts: Vec::new(),
+                span: start,
+            },
+        };
+        self.skip_newlines();
+        self.expect_word("in");
+
+        let mut arms = Vec::new();
+        loop {
+            self.skip_separators();
+            if self.peek().is_none() || self.expect_word("esac") {
+                break;
+            }
+            if self.peek_op(0) == Some(Operator::LParen) {
+                self.pos += 1;
+            }
+            let mut patterns = Vec::new();
+            while let Some(token) = self.peek() {
+                match &token.kind {
+                    TokenKind::Word(w) => patterns.push(w.clone()),
+                    TokenKind::Op(Operator::Pipe) => {}
+                    TokenKind::Op(Operator::RParen) => {
+                        self.pos += 1;
+                        break;
+                    }
+                    _ => break,
+                }
+                self.pos += 1;
+            }
+            if patterns.is_empty() {
+                // Not a pattern list; give up on this case statement
+                break;
+            }
+            let body = self.parse_list(true);
+            if matches!(
+                self.peek_op(0),
+                Some(Operator::DoubleSemi | Operator::CaseFallthrough)
+            ) {
+                self.pos += 1;
+            }
+            arms.push(CaseArm { patterns, body });
+        }
+        let span = self.finish_compound(start.to(self.last_span(start)));
+        Command::Case(CaseCommand { word, arms, span })
+    }
+
+    fn parse_function_body(&mut self, name: String, start: Span) -> Command {
+        self.skip_newlines();
+        let (body, span) = match self.parse_command() {
+            Some(Command::Group(body, span)) => (body, span),
+            Some(other) => {
+                let span = other.span();
+                let statement = Statement {
+                    commands: vec![(Connector::Start, other)],
+                    span,
+                };
+                (vec![statement], span)
+            }
+            None => (Vec::new(), start),
+        };
+        Command::Function(FunctionDef {
+            name,
+            body,
+            span: start.to(span),
+        })
+    }
+
+    fn parse_simple_command(&mut self) -> Option<Command> {
+        let mut words = Vec::new();
+        let mut span: Option<Span> = None;
+
+        while let Some(token) = self.peek() {
+            let token_span = token.span;
+            match &token.kind {
+                TokenKind::Word(w) => {
+                    words.push(w.clone());
+                    self.pos += 1;
+                }
+                TokenKind::Op(Operator::Redirect(..)) => {
+                    self.pos += 1;
+                    let mut end_span = token_span;
+                    if let Some(target) = self.peek_word() {
+                        end_span = target.span;
+                        self.pos += 1;
+                    }
+                    span = Some(span.map_or(token_span, |s| s.to(token_span)).to(end_span));
+                    continue;
+                }
+                _ => break,
+            }
+            span = Some(span.map_or(token_span, |s| s.to(token_span)));
+        }
+
+        let span = span?;
+        Some(Command::Simple(SimpleCommand { words, span }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn simple(statement: &Statement, index: usize) -> &SimpleCommand {
+        match &statement.commands[index].1 {
+            Command::Simple(c) => c,
+            other => panic!("Expected simple command, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_statements_split_on_separators() {
+        let script = Script::parse("export A=1; export B=2\nexport C=3 &\n");
+        assert_eq!(script.statements.len(), 3);
+        assert_eq!(script.text(script.statements[1].span), "export B=2");
+        assert_eq!(script.statements[2].span.line, 2);
+    }
+
+    #[test]
+    fn test_and_or_connectors() {
+        let script = Script::parse("[ -f x ] && . x || echo missing");
+        let statement = &script.statements[0];
+        let connectors: Vec<Connector> = statement.commands.iter().map(|(c, _)| *c).collect();
+        assert_eq!(
+            connectors,
+            vec![Connector::Start, Connector::And, Connector::Or]
+        );
+        assert_eq!(simple(statement, 1).words[0].as_literal().unwrap(), ".");
+    }
+
+    #[test]
+    fn test_redirects_separated_from_words() {
+        let script = Script::parse("command -v pyenv >/dev/null 2>&1");
+        let cmd = simple(&script.statements[0], 0);
+        let texts: Vec<String> = cmd.words.iter().map(|w| w.unquoted_text()).collect();
+        assert_eq!(texts, vec!["command", "-v", "pyenv"]);
+    }
+
+    #[test]
+    fn test_function_definition() {
+        let script = Script::parse("pathadd() {\n  PATH=\"$1:$PATH\"\n}\npathadd ~/bin\n");
+        assert_eq!(script.statements.len(), 2);
+        let Command::Function(ref f) = script.statements[0].commands[0].1 else {
+            panic!("Expected function");
+        };
+        assert_eq!(f.name, "pathadd");
+        assert_eq!(f.body.len(), 1);
+        assert_eq!(script.end_line(f.span), 3);
+    }
+
+    #[test]
+    fn test_multiline_statement_span() {
+        let script = Script::parse("export \\\n  FOO=bar\n");
+        assert_eq!(script.statements.len(), 1);
+        assert_eq!(
+            script.text(script.statements[0].span),
+            "export \\\n  FOO=bar"
+        );
+    }
+
+    #[test]
+    fn test_if_elif_else() {
+        let script = Script::parse(
+            "if [[ $OSTYPE == darwin* ]]; then\n  export A=1\nelif [ -d /x ]; then export A=2\nelse\n  export A=3\nfi\necho done",
+        );
+        assert_eq!(script.statements.len(), 2);
+        let Command::If(ref c) = script.statements[0].commands[0].1 else {
+            panic!("Expected if");
+        };
+        assert_eq!(c.branches.len(), 2);
+        assert_eq!(
+            script.list_text(&c.branches[0].condition),
+            "[[ $OSTYPE == darwin* ]]"
+        );
+        assert_eq!(script.list_text(&c.branches[1].body), "export A=2");
+        assert_eq!(
+            script.list_text(c.else_body.as_ref().unwrap()),
+            "export A=3"
+        );
+        assert_eq!(script.end_line(c.span), 6);
+    }
+
+    #[test]
+    fn test_case_arms() {
+        let script =
+            Script::parse("case \"$(uname)\" in\n  Darwin|FreeBSD) A=1 ;;\n  (*) A=2\nesac\n");
+        let Command::Case(ref c) = script.statements[0].commands[0].1 else {
+            panic!("Expected case");
+        };
+        assert_eq!(c.arms.len(), 2);
+        assert_eq!(c.arms[0].patterns.len(), 2);
+        assert_eq!(script.list_text(&c.arms[1].body), "A=2");
+    }
+
+    #[test]
+    fn test_loops() {
+        let script = Script::parse(
+            "for f in ~/.bashrc.d/*.sh; do . \"$f\"; done\nwhile read -r line; do :; done < file\nfor ((i=0; i<3; i++)); do :; done",
+        );
+        assert_eq!(script.statements.len(), 3);
+        let Command::Loop(ref l) = script.statements[0].commands[0].1 else {
+            panic!("Expected loop");
+        };
+        assert_eq!(script.text(l.header), "for f in ~/.bashrc.d/*.sh");
+        let LoopKind::For { ref var, ref words } = l.kind else {
+            panic!("Expected for loop");
+        };
+        assert_eq!(var, "f");
+        assert_eq!(words.as_ref().unwrap().len(), 1);
+        assert!(matches!(
+            script.statements[1].commands[0].1,
+            Command::Loop(LoopCommand {
+                kind: LoopKind::While(_),
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_subshell() {
+        let script = Script::parse("(cd /tmp && export A=1)\nexport B=2");
+        assert_eq!(script.statements.len(), 2);
+        assert!(matches!(
+            script.statements[0].commands[0].1,
+            Command::Subshell(..)
+        ));
+    }
+}
@@ -1,25 +1,25 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
-use std::sync::LazyLock;
-
-use regex::Regex;
-
-use super::common::{expand_source_path, strip_quotes};
-use crate::trace::{Operation, VariableChange};
-
-// Static regex patterns (compiled once)
-static EXPORT_ASSIGN: LazyLock<Regex> =
-    LazyLock::new(|| Regex::new(r#"^\s*export\s+([A-Za-z_][A-Za-z0-9_]*)=(.*)$"#).unwrap());
-static SIMPLE_ASSIGN: LazyLock<Regex> =
-    LazyLock::new(|| Regex::new(r#"^\s*([A-Za-z_][A-Za-z0-9_]*)=(.*)$"#).unwrap());
-static EXPORT_ONLY: LazyLock<Regex> =
-    LazyLock::new(|| Regex::new(r#"^\s*export\s+([A-Za-z_][A-Za-z0-9_]*)\s*$"#).unwrap());
-static UNSET_VAR: LazyLock<Regex> =
-    LazyLock::new(|| Regex::new(r#"^\s*unset\s+([A-Za-z_][A-Za-z0-9_]*)"#).unwrap());
-static SOURCE_CMD: LazyLock<Regex> =
-    LazyLock::new(|| Regex::new(r#"^\s*(\.|source)\s+(.+)$"#).unwrap());
-static CONDITIONAL: LazyLock<Regex> = LazyLock::new(|| {
-    Regex::new(r#"^\s*\[.*\]\s*&&\s*export\s+([A-Za-z_][A-Za-z0-9_]*)=(.*)$"#).unwrap()
-});
+use std::rc::Rc;
+
+use super::common::{
+    BranchWalk, Branching, Guard, PathState, SourcedFile, Stopped, is_zsh_file, negate,
+    source_arguments,
+};
+use super::condition::{ShellState, Truth, evaluate_command};
+use super::expand::{
+    Expansion, Lookup, VarLookup, expand_argument, expand_assignment, expand_elements, expand_word,
+    glob_word, references, word_pattern,
+};
+use super::glob::{expand_glob, path_command_output, resolve_path};
+use super::init::{Initializer, eval_initializer, sourced_initializer};
+use super::lexer::{Word, WordPart};
+use super::script::{Command, Connector, FunctionDef, LoopKind, Script, SimpleCommand, Statement};
+use crate::trace::{
+    Diagnostic, DiagnosticKind, FunctionCall, Operation, SavedVariable, ShellEnv, StopKind,
+    StopPoint, ValueCandidate, VariableChange,
+};
 
 /// A parsed entry from a shell script
 #[derive(Debug, Clone)]
@@ -27,318 +27,2787 @@ pub enum ParsedShellEntry {
     /// A variable assignment (export, set, append, prepend, unset, conditional)
     Assignment(VariableChange),
     /// A source/. command pointing to another file
-    Source(PathBuf),
+    Source(SourcedFile),
+    /// A `return`, `exit` or `exec` that stops reading the file
+    Stop(StopPoint),
+    /// A line that couldn't be followed, such as a source path with unknown variables
+    Diagnostic(Diagnostic),
+}
+
+/// A function defined while tracing; its body runs each time it's called
+#[derive(Debug)]
+pub(crate) struct ShellFunction {
+    definition: FunctionDef,
+    /// The script the function was defined in (statement spans refer to it)
+    script: Rc<Script>,
+    file: PathBuf,
 }
 
+/// Deepest chain of nested function calls that is followed
+const MAX_CALL_DEPTH: usize = 16;
+
 #[cfg(test)]
 impl ParsedShellEntry {
     fn as_assignment(&self) -> &VariableChange {
         match self {
             ParsedShellEntry::Assignment(c) => c,
-            ParsedShellEntry::Source(_) => panic!("Expected Assignment variant"),
+            _ => panic!("Expected Assignment variant"),
         }
     }
 }
 
 /// Parse a shell script file for variable assignments
 ///
-/// Handles common bash/zsh patterns:
-/// - export VAR=value
+/// The file is tokenized the way bash/zsh would read it (quotes, escapes,
+/// comments, line continuations and heredocs), then each statement is
+/// checked for:
+/// - export VAR=value [VAR2=value ...]
 /// - VAR=value
-/// - export VAR (export existing variable)
-/// - unset VAR
+/// - export VAR [VAR2 ...] (export existing variables)
+/// - declare/typeset/readonly/local [-flags] VAR=value
+/// - unset VAR [VAR2 ...]
 /// - PATH="$PATH:new" (append)
 /// - PATH="new:$PATH" (prepend)
+/// - PATH+=":new" (append)
+/// - path=(new $path), path+=(new) (zsh tied arrays)
+/// - typeset -U path (zsh de-duplication)
 /// - [ -f x ] && export VAR=y (conditional)
-/// - source file / . file
+/// - source file / . file (also `builtin source`, `command .` and guarded
+///   forms like `[ -f x ] && . x`)
+/// - return / exit / exec (stop reading the file)
+/// - eval of code known from traced values (run in place)
+///
+/// Lines that may change the environment but can't be analyzed (`eval
+/// "$X"`, `export $(...)`, `source <(...)`, `set -- $(...)`, `env -i`) are
+/// passed on as diagnostics.
+///
+/// Every assignment updates `env`, so references to other variables resolve
+/// to their traced values. Entries for the target variable and source
+/// commands are passed to `on_entry` as they are reached, which lets the
+/// caller process a sourced file before the rest of this one.
+///
+/// `guard` holds the conditions the file is read under (for a sourced file,
+/// those of its `source` command); they're attached to every change.
 pub fn parse_shell_file(
     path: &Path,
     target_var: &str,
-    current_value: Option<&str>,
-) -> std::io::Result<Vec<ParsedShellEntry>> {
+    env: &mut ShellEnv,
+    guard: &Guard,
+    on_entry: &mut dyn FnMut(ParsedShellEntry, &mut ShellEnv),
+) -> std::io::Result<()> {
     let content = std::fs::read_to_string(path)?;
-    Ok(parse_shell_content(
-        &content,
-        path,
-        target_var,
-        current_value,
-    ))
+    walk_shell_content(&content, path, target_var, env, guard, on_entry);
+    Ok(())
 }
 
-fn parse_shell_content(
+fn walk_shell_content(
     content: &str,
     path: &Path,
     target_var: &str,
-    current_value: Option<&str>,
-) -> Vec<ParsedShellEntry> {
-    let mut results = Vec::new();
-
-    for (line_num, line) in content.lines().enumerate() {
-        let trimmed = line.trim();
-
-        // Skip empty lines and comments
-        if trimmed.is_empty() || trimmed.starts_with('#') {
-            continue;
-        }
-
-        // Check for source/. command
-        if let Some(caps) = SOURCE_CMD.captures(line) {
-            let source_path = caps.get(2).unwrap().as_str().trim();
-            if let Some(p) = expand_source_path(source_path) {
-                results.push(ParsedShellEntry::Source(p));
-            }
-            continue;
-        }
-
-        // Check for conditional export
-        if let Some(caps) = CONDITIONAL.captures(line) {
-            let var_name = caps.get(1).unwrap().as_str();
-            if var_name == target_var {
-                let value = caps.get(2).unwrap().as_str();
-                let value = strip_quotes(value);
-                results.push(ParsedShellEntry::Assignment(VariableChange {
-                    file: path.to_path_buf(),
-                    line_number: line_num + 1,
-                    line_content: line.to_string(),
-                    operation: Operation::Conditional,
-                    value_before: current_value.map(|s| s.to_string()),
-                    value_after: value,
-                }));
-            }
-            continue;
-        }
-
-        // Check for unset
-        if let Some(caps) = UNSET_VAR.captures(line) {
-            let var_name = caps.get(1).unwrap().as_str();
-            if var_name == target_var {
-                results.push(ParsedShellEntry::Assignment(VariableChange {
-                    file: path.to_path_buf(),
-                    line_number: line_num + 1,
-                    line_content: line.to_string(),
-                    operation: Operation::Unset,
-                    value_before: current_value.map(|s| s.to_string()),
-                    value_after: String::new(),
-                }));
-            }
-            continue;
-        }
-
-        // Check for export VAR=value
-        if let Some(caps) = EXPORT_ASSIGN.captures(line) {
-            let var_name = caps.get(1).unwrap().as_str();
-            if var_name == target_var {
-                let value = caps.get(2).unwrap().as_str();
-                let (operation, final_value) =
-                    analyze_value(target_var, value, current_value, Operation::Export);
-                results.push(ParsedShellEntry::Assignment(VariableChange {
-                    file: path.to_path_buf(),
-                    line_number: line_num + 1,
-                    line_content: line.to_string(),
-                    operation,
-                    value_before: current_value.map(|s| s.to_string()),
-                    value_after: final_value,
-                }));
-            }
-            continue;
-        }
-
-        // Check for export VAR (without assignment)
-        if let Some(caps) = EXPORT_ONLY.captures(line) {
-            let var_name = caps.get(1).unwrap().as_str();
-            if var_name == target_var {
-                // Just exporting, value doesn't change
-                results.push(ParsedShellEntry::Assignment(VariableChange {
-                    file: path.to_path_buf(),
-                    line_number: line_num + 1,
-                    line_content: line.to_string(),
-                    operation: Operation::Export,
-                    value_before: current_value.map(|s| s.to_string()),
-                    value_after: current_value.unwrap_or("").to_string(),
-                }));
-            }
-            continue;
-        }
-
-        // Check for simple VAR=value (without export)
-        if let Some(caps) = SIMPLE_ASSIGN.captures(line) {
-            let var_name = caps.get(1).unwrap().as_str();
-            if var_name == target_var {
-                let value = caps.get(2).unwrap().as_str();
-                let (operation, final_value) =
-                    analyze_value(target_var, value, current_value, Operation::Set);
-                results.push(ParsedShellEntry::Assignment(VariableChange {
-                    file: path.to_path_buf(),
-                    line_number: line_num + 1,
-                    line_content: line.to_string(),
-                    operation,
-                    value_before: current_value.map(|s| s.to_string()),
-                    value_after: final_value,
-                }));
-            }
-        }
-    }
-
-    results
+    env: &mut ShellEnv,
+    guard: &Guard,
+    on_entry: &mut dyn FnMut(ParsedShellEntry, &mut ShellEnv),
+) {
+    let script = Rc::new(Script::parse(content));
+    let outer_script = env.replace_script(Some(path.to_path_buf()));
+    let mut walker = ShellWalker {
+        script: script.clone(),
+        path,
+        target_var,
+        env,
+        on_entry,
+        state: PathState::new(guard),
+        call: None,
+        stack: Vec::new(),
+        locals: Vec::new(),
+    };
+    walker.walk(&script.statements);
+    walker.state.finish_returns(walker.env);
+    walker.env.replace_script(outer_script);
 }
 
-/// Analyze a value to determine if it's a set, append, or prepend operation
-fn analyze_value(
-    var_name: &str,
-    value: &str,
-    current_value: Option<&str>,
-    default_op: Operation,
-) -> (Operation, String) {
-    let value = strip_quotes(value);
-    let var_ref = format!("${}", var_name);
-    let var_ref_braced = format!("${{{}}}", var_name);
-
-    // Check for append pattern: $VAR:new or ${VAR}:new
-    if value.starts_with(&var_ref) || value.starts_with(&var_ref_braced) {
-        let prefix_len = if value.starts_with(&var_ref_braced) {
-            var_ref_braced.len()
-        } else {
-            var_ref.len()
-        };
+/// Walks parsed statements in order, tracking every assigned variable
+struct ShellWalker<'a> {
+    script: Rc<Script>,
+    path: &'a Path,
+    target_var: &'a str,
+    env: &'a mut ShellEnv,
+    on_entry: &'a mut dyn FnMut(ParsedShellEntry, &mut ShellEnv),
+    /// The enclosing branches and how the current path ended
+    state: PathState,
+    /// The outermost call being run, which changes are attributed to
+    call: Option<CallSite>,
+    /// The functions being run, outermost first, with where their locals
+    /// start in `locals`
+    stack: Vec<(String, usize)>,
+    /// Variables shadowed by `local` in the functions being run, as they were
+    /// before
+    locals: Vec<SavedVariable>,
+}
 
-        if value.len() > prefix_len && value.chars().nth(prefix_len) == Some(':') {
-            let appended = &value[prefix_len + 1..];
-            let new_value = if let Some(cur) = current_value {
-                format!("{}:{}", cur, appended)
-            } else {
-                appended.to_string()
+/// The line in the traced file that called a function
+#[derive(Debug, Clone)]
+struct CallSite {
+    line_number: usize,
+    line_content: String,
+    /// The innermost function being run
+    function: Option<FunctionCall>,
+    /// The tool initializer being emulated
+    emulated: Option<String>,
+}
+
+impl ShellWalker<'_> {
+    /// Walk statements in order; returns the exit status of the last one
+    fn walk(&mut self, statements: &[Statement]) -> Truth {
+        let mut status = Truth::True;
+        for statement in statements {
+            if self.state.stopped.is_some() {
+                break;
+            }
+            status = self.statement(statement);
+        }
+        status
+    }
+
+    fn statement(&mut self, statement: &Statement) -> Truth {
+        let commands = &statement.commands;
+        let mut status = Truth::True;
+        for (i, (connector, command)) in commands.iter().enumerate() {
+            // Pipeline members run in subshells and can't change our environment
+            let piped = *connector == Connector::Pipe
+                || commands
+                    .get(i + 1)
+                    .is_some_and(|(next, _)| *next == Connector::Pipe);
+            if piped {
+                status = Truth::Unknown;
+                continue;
+            }
+
+            // `a && b` runs b only if a succeeded, `a || b` only if it failed
+            let runs = match connector {
+                Connector::And => status,
+                Connector::Or => status.not(),
+                _ => Truth::True,
+            };
+            if runs == Truth::False {
+                continue;
+            }
+            let guard = match connector {
+                Connector::And | Connector::Or => {
+                    let previous = commands[0].1.span().to(commands[i - 1].1.span());
+                    let text = self.script.text(previous);
+                    Some(if *connector == Connector::And {
+                        text.to_string()
+                    } else if i > 1 {
+                        format!("! ({})", text)
+                    } else {
+                        format!("! {}", text)
+                    })
+                }
+                _ => None,
+            };
+
+            // The guard is removed by position afterwards, so conditions
+            // assumed by the command (see `assume`) stay in place
+            let depth = self.state.conditions.len();
+            let guarded = guard.is_some();
+            if let Some(ref guard) = guard {
+                self.state.conditions.push(guard.clone());
+            }
+            // With an undecided guard, the command may or may not have run
+            let fork = match (runs, guard) {
+                (Truth::Unknown, Some(guard)) => {
+                    self.state.undecided += 1;
+                    self.state.path_conditions.push(guard.clone());
+                    Some((
+                        guard,
+                        self.state.path_conditions.len() - 1,
+                        self.env.clone(),
+                    ))
+                }
+                _ => None,
+            };
+            let result = self.command(command, statement);
+            if guarded && self.state.conditions.len() > depth {
+                self.state.conditions.remove(depth);
+            }
+            if let Some((guard, index, before)) = fork {
+                self.state.undecided -= 1;
+                let ran = self.state.path_conditions.clone();
+                self.state.path_conditions.remove(index);
+                if self.state.stopped.take().is_some() {
+                    // Only the path that skipped the command goes on
+                    self.env.resume(before);
+                    self.state.assume(negate(&guard));
+                    status = Truth::from_bool(*connector == Connector::Or);
+                    continue;
+                }
+                let mut skipped = self.state.path_conditions.clone();
+                skipped.push(negate(&guard));
+                let after = self.env.clone();
+                self.env.merge(vec![(skipped, before), (ran, after)]);
+            }
+            if self.state.stopped.is_some() {
+                break;
+            }
+
+            status = match connector {
+                Connector::And => status.and(result),
+                Connector::Or => status.or(result),
+                _ => result,
             };
-            return (Operation::Append, new_value);
         }
+        status
     }
 
-    // Check for prepend pattern: new:$VAR or new:${VAR}
-    if value.ends_with(&var_ref) || value.ends_with(&var_ref_braced) {
-        let suffix_len = if value.ends_with(&var_ref_braced) {
-            var_ref_braced.len()
-        } else {
-            var_ref.len()
+    fn command(&mut self, command: &Command, statement: &Statement) -> Truth {
+        match command {
+            Command::Simple(cmd) => self.simple_command(cmd, statement),
+            Command::Group(body, _) => self.walk(body),
+            // The body runs when the function is called
+            Command::Function(func) => {
+                let function = ShellFunction {
+                    definition: func.clone(),
+                    script: self.script.clone(),
+                    file: self.path.to_path_buf(),
+                };
+                self.env.define_function(&func.name, Rc::new(function));
+                Truth::True
+            }
+            // Changes made in a subshell don't reach the parent shell
+            Command::Subshell(..) => Truth::Unknown,
+            Command::Not(inner) => self.command(inner, statement).not(),
+            Command::If(c) => {
+                let mut branches = Vec::new();
+                let mut failed: Vec<String> = Vec::new();
+                for branch in &c.branches {
+                    let condition = self.script.list_text(&branch.condition).to_string();
+                    let mut conditions = failed.clone();
+                    conditions.push(condition.clone());
+                    branches.push(BranchWalk {
+                        conditions,
+                        test: Some(branch.condition.as_slice()),
+                        matched: Truth::Unknown,
+                        body: branch.body.as_slice(),
+                    });
+                    failed.push(format!("! {}", condition));
+                }
+                if let Some(body) = &c.else_body {
+                    branches.push(BranchWalk {
+                        conditions: failed,
+                        test: None,
+                        matched: Truth::True,
+                        body,
+                    });
+                }
+                self.branches(branches)
+            }
+            Command::Case(c) => {
+                let word = self.script.text(c.word.span).to_string();
+                let value = {
+                    let expansion = expand_argument(&c.word, &self.lookup());
+                    expansion.unresolved.is_empty().then_some(expansion.value)
+                };
+                let branches = c
+                    .arms
+                    .iter()
+                    .map(|arm| {
+                        let patterns: Vec<&str> = arm
+                            .patterns
+                            .iter()
+                            .map(|p| self.script.text(p.span))
+                            .collect();
+                        let matched = match &value {
+                            Some(value) => arm
+                                .patterns
+                                .iter()
+                                .map(|p| match word_pattern(p, &self.lookup()) {
+                                    Some(pattern) => Truth::from_bool(pattern.matches(value)),
+                                    None => Truth::Unknown,
+                                })
+                                .fold(Truth::False, Truth::or),
+                            None => Truth::Unknown,
+                        };
+                        BranchWalk {
+                            conditions: vec![format!("case {} in {})", word, patterns.join("|"))],
+                            test: None,
+                            matched,
+                            body: arm.body.as_slice(),
+                        }
+                    })
+                    .collect();
+                self.branches(branches)
+            }
+            Command::Loop(l) => {
+                let depth = (
+                    self.state.conditions.len(),
+                    self.state.path_conditions.len(),
+                );
+                self.state
+                    .conditions
+                    .push(self.script.text(l.header).to_string());
+                let undecided = self.state.undecided;
+                let outer = self.state.enter_loop();
+                match &l.kind {
+                    LoopKind::While(condition) | LoopKind::Until(condition) => {
+                        let status = self.walk(condition);
+                        let runs = match l.kind {
+                            LoopKind::Until(_) => status.not(),
+                            _ => status,
+                        };
+                        // The body runs an unknown number of times
+                        if runs != Truth::False {
+                            self.state.undecided += 1;
+                            self.walk(&l.body);
+                            self.state.next_iteration(self.env);
+                        }
+                    }
+                    LoopKind::For { var, words } => self.for_loop(var, words.as_deref(), &l.body),
+                }
+                // A body that may not run can't stop the file for certain
+                if self.state.undecided > undecided {
+                    self.state.stopped = None;
+                }
+                self.state.undecided = undecided;
+                self.state.conditions.truncate(depth.0);
+                self.state.path_conditions.truncate(depth.1);
+                self.state.exit_loop(self.env, outer);
+                Truth::Unknown
+            }
+        }
+    }
+
+    /// Walk a for loop body once per item, with the loop variable bound
+    fn for_loop(&mut self, var: &str, words: Option<&[Word]>, body: &[Statement]) {
+        let items: Option<Vec<Expansion>> = match words {
+            // `for x; do` and `for x in "$@"` loop over the positional parameters
+            None | Some([_]) if words.is_none_or(|w| is_all_params(&w[0])) => {
+                self.env.positional().map(|params| {
+                    params
+                        .iter()
+                        .map(|(value, unresolved)| Expansion {
+                            value: value.clone(),
+                            unresolved: unresolved.clone(),
+                            ..Default::default()
+                        })
+                        .collect()
+                })
+            }
+            _ => Some(
+                words
+                    .unwrap_or_default()
+                    .iter()
+                    .flat_map(|w| self.loop_items(w))
+                    .collect(),
+            ),
+        };
+        let Some(items) = items.filter(|_| !var.is_empty()) else {
+            // Unknown items: walk the body once without knowing the variable
+            if !var.is_empty() {
+                self.env.forget(var);
+            }
+            self.state.undecided += 1;
+            self.walk(body);
+            self.state.next_iteration(self.env);
+            return;
+        };
+        for item in items {
+            if self.state.stopped.is_some() {
+                break;
+            }
+            self.env
+                .set_with_unresolved(var, item.value, item.unresolved);
+            self.walk(body);
+            self.state.next_iteration(self.env);
+        }
+    }
+
+    /// The items a word in a `for` list expands to: the files a glob
+    /// matches, the fields of an unquoted command substitution, or the
+    /// word's value
+    fn loop_items(&self, word: &Word) -> Vec<Expansion> {
+        let lookup = self.lookup();
+        if let Some(pattern) = glob_word(word, &lookup) {
+            return expand_glob(&pattern, &lookup)
+                .into_iter()
+                .map(|path| Expansion {
+                    value: path.to_string_lossy().to_string(),
+                    ..Default::default()
+                })
+                .collect();
+        }
+        let expansion = expand_argument(word, &lookup);
+        match &word.parts[..] {
+            [WordPart::Command(_)] if expansion.unresolved.is_empty() => expansion
+                .value
+                .split_whitespace()
+                .map(|field| Expansion {
+                    value: field.to_string(),
+                    ..Default::default()
+                })
+                .collect(),
+            _ => vec![expansion],
+        }
+    }
+
+    fn simple_command(&mut self, cmd: &SimpleCommand, statement: &Statement) -> Truth {
+        let words = &cmd.words;
+
+        // Leading NAME=value words; with a command after them they only apply to that command
+        let assignment_count = words
+            .iter()
+            .take_while(|w| w.split_assignment().is_some())
+            .count();
+
+        if assignment_count == words.len() {
+            for word in words {
+                let (name, append, value) = word.split_assignment().unwrap();
+                self.assign(&name, append, &value, Operation::Set, statement);
+            }
+            return Truth::True;
+        }
+
+        if let Some(args) = source_arguments(&words[assignment_count..]) {
+            self.source(args, statement);
+            return Truth::Unknown;
+        }
+
+        let Some(command_name) = words[assignment_count].as_literal() else {
+            return Truth::Unknown;
         };
+        let args = &words[assignment_count + 1..];
 
-        let value_len = value.len();
-        if value_len > suffix_len {
-            let before_var = &value[..value_len - suffix_len];
-            if let Some(prepended) = before_var.strip_suffix(':') {
-                let new_value = if let Some(cur) = current_value {
-                    format!("{}:{}", prepended, cur)
+        match command_name.as_str() {
+            "return" | "exit" | "logout" => {
+                let kind = if command_name == "return" {
+                    StopKind::Return
+                } else {
+                    StopKind::Exit
+                };
+                self.stop(kind, statement);
+                Truth::Unknown
+            }
+            "break" | "continue" => {
+                self.state.leave_loop(command_name == "continue", self.env);
+                Truth::Unknown
+            }
+            // Without a command, exec only applies redirections
+            "exec" if !args.is_empty() => {
+                self.stop(StopKind::Exec, statement);
+                Truth::Unknown
+            }
+            "export" | "declare" | "typeset" | "readonly" | "local" => {
+                self.declaration(&command_name, args, statement);
+                Truth::True
+            }
+            "unset" => {
+                let (options, names) = split_options(args);
+                // unset -f removes functions, not variables
+                if options.flags.contains('f') {
+                    for name in names.iter().filter_map(|w| w.as_literal()) {
+                        self.env.unset_function(&name);
+                    }
                 } else {
-                    prepended.to_string()
+                    for name in names.iter().filter_map(|w| w.as_literal()) {
+                        let name = canonical_name(&name);
+                        let expansion = Expansion::default();
+                        self.store(name, Operation::Unset, expansion, Vec::new(), statement);
+                    }
+                }
+                Truth::True
+            }
+            // The value comes from input the trace can't see
+            "read" | "printf" | "mapfile" | "readarray" | "getopts"
+                if !runtime_targets(&command_name, args).is_empty() =>
+            {
+                for name in runtime_targets(&command_name, args) {
+                    self.read_at_runtime(canonical_name(&name), statement);
+                }
+                Truth::Unknown
+            }
+            "cd" => {
+                self.change_directory(args, statement);
+                Truth::Unknown
+            }
+            "set" => {
+                self.set_builtin(args, statement);
+                Truth::True
+            }
+            "env"
+                if args
+                    .first()
+                    .and_then(|w| w.as_literal())
+                    .is_some_and(|arg| {
+                        matches!(arg.as_str(), "-" | "-i" | "--ignore-environment")
+                    }) =>
+            {
+                self.diagnose(DiagnosticKind::ClearedEnvironment, Vec::new(), statement);
+                Truth::Unknown
+            }
+            "setopt" | "unsetopt" => {
+                for word in args {
+                    let Some(name) = word.as_literal() else {
+                        continue;
+                    };
+                    // zsh ignores case and underscores, and `no` inverts
+                    let name = name.to_lowercase().replace('_', "");
+                    let (name, on) = match name.strip_prefix("no") {
+                        Some(rest) => (rest.to_string(), command_name == "unsetopt"),
+                        None => (name, command_name == "setopt"),
+                    };
+                    // RCS and GLOBAL_RCS decide which startup files are read next
+                    if matches!(name.as_str(), "allexport" | "rcs" | "globalrcs") {
+                        self.env.set_option(&name, on);
+                    }
+                }
+                Truth::True
+            }
+            "shift" => {
+                let n = match args.first() {
+                    Some(word) => word.as_literal().and_then(|n| n.parse().ok()),
+                    None => Some(1),
                 };
-                return (Operation::Prepend, new_value);
+                match n {
+                    Some(n) => self.env.shift(n),
+                    None => {
+                        self.env.replace_positional(None);
+                    }
+                }
+                Truth::Unknown
+            }
+            "eval" if let Some(init) = self.eval_initializer(args) => {
+                self.run_in_place(&init.script, Some(init.tool), statement);
+                Truth::Unknown
+            }
+            "eval" => self.eval(args, statement),
+            _ => {
+                if let Some(function) = self.env.function(&command_name) {
+                    return self.call_function(&command_name, &function, args, statement);
+                }
+                let status = evaluate_command(&words[assignment_count..], &self.lookup());
+                self.default_assignments(&words[assignment_count..], statement);
+                status
             }
         }
     }
 
-    // Simple set - expand any variable references we know about
-    let expanded = expand_variables(&value, var_name, current_value);
-    (default_op, expanded)
-}
-
-/// Expand known variable references
-fn expand_variables(value: &str, var_name: &str, current_value: Option<&str>) -> String {
-    let mut result = value.to_string();
+    /// `set`: `-a`/`+a` and `-o allexport`/`+o allexport` turn exporting of
+    /// assignments on and off; operands after the options (or `--`)
+    /// replace the positional parameters
+    fn set_builtin(&mut self, args: &[Word], statement: &Statement) {
+        let mut i = 0;
+        let mut operands = None;
+        while i < args.len() {
+            let Some(arg) = args[i].as_literal() else {
+                operands = Some(&args[i..]);
+                break;
+            };
+            let on = match arg.chars().next() {
+                _ if arg == "--" || arg == "-" => {
+                    operands = Some(&args[i + 1..]);
+                    break;
+                }
+                Some('-') => true,
+                Some('+') => false,
+                _ => {
+                    operands = Some(&args[i..]);
+                    break;
+                }
+            };
+            let flags = &arg[1..];
+            if flags == "o" {
+                i += 1;
+                if args.get(i).and_then(|w| w.as_literal()).as_deref() == Some("allexport") {
+                    self.env.set_option("allexport", on);
+                }
+            } else if flags.contains('a') {
+                self.env.set_option("allexport", on);
+            }
+            i += 1;
+        }
 
-    // Expand $HOME
-    if let Some(home) = dirs::home_dir() {
-        result = result.replace("$HOME", &home.to_string_lossy());
-        result = result.replace("${HOME}", &home.to_string_lossy());
+        let Some(operands) = operands else {
+            return;
+        };
+        let params: Vec<(String, Vec<String>)> = operands
+            .iter()
+            .map(|word| {
+                let expansion = expand_argument(word, &self.lookup());
+                (expansion.value, expansion.unresolved)
+            })
+            .collect();
+        let unresolved: Vec<String> = params.iter().flat_map(|(_, u)| u.clone()).collect();
+        if unresolved.is_empty() {
+            self.env.replace_positional(Some(params));
+        } else {
+            self.env.replace_positional(None);
+            self.diagnose(DiagnosticKind::DynamicPositional, unresolved, statement);
+        }
     }
 
-    // Expand self-reference if we have a current value
-    if let Some(cur) = current_value {
-        let var_ref = format!("${}", var_name);
-        let var_ref_braced = format!("${{{}}}", var_name);
-        result = result.replace(&var_ref_braced, cur);
-        result = result.replace(&var_ref, cur);
+    /// `eval` with code known from the traced values runs it in place;
+    /// otherwise it's reported as not analyzed
+    fn eval(&mut self, args: &[Word], statement: &Statement) -> Truth {
+        let mut code = Vec::new();
+        let mut unresolved = Vec::new();
+        for word in args {
+            let expansion = expand_argument(word, &self.lookup());
+            code.push(expansion.value);
+            unresolved.extend(expansion.unresolved);
+        }
+        if !unresolved.is_empty() {
+            self.diagnose(DiagnosticKind::DynamicEval, unresolved, statement);
+            return Truth::Unknown;
+        }
+
+        let emulated = self.call.as_ref().and_then(|call| call.emulated.clone());
+        self.run_in_place(&code.join(" "), emulated, statement)
     }
 
-    result
-}
+    /// Report a line that couldn't be followed or analyzed
+    fn diagnose(&mut self, kind: DiagnosticKind, unresolved: Vec<String>, statement: &Statement) {
+        let (line_number, line_content) = self.location(statement);
+        let diagnostic = Diagnostic {
+            file: self.path.to_path_buf(),
+            line_number,
+            line_content,
+            kind,
+            unresolved,
+        };
+        (self.on_entry)(ParsedShellEntry::Diagnostic(diagnostic), self.env);
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// A variable set to a value only known at run time: it's left as a
+    /// reference, and assigning the target is reported
+    fn read_at_runtime(&mut self, name: &str, statement: &Statement) {
+        let reference = format!("${}", name);
+        let expansion = Expansion {
+            value: reference.clone(),
+            unresolved: vec![reference.clone()],
+            ..Default::default()
+        };
+        let target = name == self.target_var && !self.is_local(name);
+        self.store(name, Operation::Set, expansion, Vec::new(), statement);
+        if target {
+            self.diagnose(DiagnosticKind::RuntimeValue, vec![reference], statement);
+        }
+    }
 
-    #[test]
-    fn test_export_assignment() {
-        let content = "export PATH=/usr/bin";
-        let results = parse_shell_content(content, &PathBuf::from("test"), "PATH", None);
-        assert_eq!(results.len(), 1);
-        assert_eq!(results[0].as_assignment().value_after, "/usr/bin");
-        assert_eq!(results[0].as_assignment().operation, Operation::Export);
+    /// Expand a path argument; a relative path is taken from the shell's
+    /// working directory
+    fn resolve_path(&self, word: &Word) -> Expansion {
+        let lookup = self.lookup();
+        let mut path = expand_argument(word, &lookup);
+        // Values that carry unresolved references aren't real paths either
+        for reference in lookup.inherited.take() {
+            if !path.unresolved.contains(&reference) {
+                path.unresolved.push(reference);
+            }
+        }
+        if path.unresolved.is_empty() && !path.value.starts_with('/') {
+            match lookup.lookup("PWD") {
+                Lookup::Set(cwd) if cwd.starts_with('/') => {
+                    path.value = resolve_path(&cwd, &path.value);
+                }
+                _ => path.unresolved.push("$PWD".to_string()),
+            }
+        }
+        path
     }
 
-    #[test]
-    fn test_simple_assignment() {
-        let content = "EDITOR=vim";
-        let results = parse_shell_content(content, &PathBuf::from("test"), "EDITOR", None);
-        assert_eq!(results.len(), 1);
-        assert_eq!(results[0].as_assignment().value_after, "vim");
-        assert_eq!(results[0].as_assignment().operation, Operation::Set);
+    /// `cd dir` changes the working directory relative paths are read from
+    fn change_directory(&mut self, args: &[Word], statement: &Statement) {
+        let (_, operands) = split_options(args);
+        let expansion = match operands.first() {
+            None => match self.lookup().lookup("HOME") {
+                Lookup::Set(home) => Expansion {
+                    value: home,
+                    ..Default::default()
+                },
+                _ => {
+                    self.env.forget("PWD");
+                    return;
+                }
+            },
+            Some(word) if word.as_literal().as_deref() == Some("-") => {
+                self.env.forget("PWD");
+                return;
+            }
+            Some(word) => self.resolve_path(word),
+        };
+        if expansion.unresolved.is_empty() {
+            self.store("PWD", Operation::Set, expansion, Vec::new(), statement);
+        } else {
+            self.env.forget("PWD");
+        }
     }
 
-    #[test]
-    fn test_append() {
-        let content = r#"export PATH="$PATH:/usr/local/bin""#;
-        let results =
-            parse_shell_content(content, &PathBuf::from("test"), "PATH", Some("/usr/bin"));
-        assert_eq!(results.len(), 1);
-        assert_eq!(results[0].as_assignment().operation, Operation::Append);
-        assert_eq!(
-            results[0].as_assignment().value_after,
-            "/usr/bin:/usr/local/bin"
+    /// Read a sourced file; any arguments after the path become its
+    /// positional parameters while it runs
+    fn source(&mut self, args: &[Word], statement: &Statement) {
+        let Some((path, params)) = args.split_first() else {
+            return;
+        };
+        // source <(cmd) reads the output of a command
+        if path
+            .parts
+            .iter()
+            .any(|part| matches!(part, WordPart::Process(_)))
+        {
+            self.diagnose(DiagnosticKind::SourcedCommandOutput, Vec::new(), statement);
+            return;
+        }
+        let path = self.resolve_path(path);
+        if !path.unresolved.is_empty() {
+            self.diagnose(DiagnosticKind::UnresolvedSource, path.unresolved, statement);
+            return;
+        }
+        let path = PathBuf::from(path.value);
+        let params: Vec<(String, Vec<String>)> = params
+            .iter()
+            .map(|word| {
+                let expansion = expand_argument(word, &self.lookup());
+                (expansion.value, expansion.unresolved)
+            })
+            .collect();
+
+        // Loaders like nvm.sh are emulated rather than read
+        let args: Vec<String> = params.iter().map(|(value, _)| value.clone()).collect();
+        if let Some(init) = sourced_initializer(&path, &args, &self.lookup()) {
+            self.run_in_place(&init.script, Some(init.tool), statement);
+            return;
+        }
+
+        let positional = (!params.is_empty()).then(|| self.env.replace_positional(Some(params)));
+
+        let guard = Guard {
+            conditions: self.state.conditions.clone(),
+            undecided: self.state.path_conditions.clone(),
+        };
+        (self.on_entry)(
+            ParsedShellEntry::Source(SourcedFile { path, guard }),
+            self.env,
         );
+        if let Some(positional) = positional {
+            self.env.replace_positional(positional);
+        }
+        if self.env.has_exited() {
+            self.state.stopped = Some(Stopped::File(StopKind::Exit));
+        }
     }
 
-    #[test]
-    fn test_prepend() {
-        let content = r#"export PATH="/usr/local/bin:$PATH""#;
-        let results =
-            parse_shell_content(content, &PathBuf::from("test"), "PATH", Some("/usr/bin"));
-        assert_eq!(results.len(), 1);
-        assert_eq!(results[0].as_assignment().operation, Operation::Prepend);
-        assert_eq!(
-            results[0].as_assignment().value_after,
-            "/usr/local/bin:/usr/bin"
+    /// Run a function's body with the call's arguments as positional parameters
+    fn call_function(
+        &mut self,
+        name: &str,
+        function: &ShellFunction,
+        args: &[Word],
+        statement: &Statement,
+    ) -> Truth {
+        // A recursive call runs until something at run time ends it, so
+        // it isn't followed
+        if self.stack.iter().any(|(f, _)| f == name) || self.stack.len() >= MAX_CALL_DEPTH {
+            self.diagnose(
+                DiagnosticKind::RecursiveCall,
+                vec![name.to_string()],
+                statement,
+            );
+            return Truth::Unknown;
+        }
+        let params = args
+            .iter()
+            .map(|word| {
+                let expansion = expand_argument(word, &self.lookup());
+                (expansion.value, expansion.unresolved)
+            })
+            .collect();
+        let link = FunctionCall {
+            name: name.to_string(),
+            file: function.file.clone(),
+            line_number: function.definition.span.line,
+        };
+        let call = match self.call {
+            Some(ref outer) => CallSite {
+                function: Some(link),
+                ..outer.clone()
+            },
+            None => CallSite {
+                line_number: statement.span.line,
+                line_content: self.script.text(statement.span).to_string(),
+                function: Some(link),
+                emulated: None,
+            },
+        };
+
+        let positional = self.env.replace_positional(Some(params));
+        let outer_file = self.env.replace_script(Some(function.file.clone()));
+        let script = std::mem::replace(&mut self.script, function.script.clone());
+        let returns = std::mem::take(&mut self.state.returns);
+        let outer_call = self.call.replace(call);
+        let depth = (
+            self.state.conditions.len(),
+            self.state.path_conditions.len(),
         );
+        let undecided = self.state.undecided;
+        self.stack.push((name.to_string(), self.locals.len()));
+
+        // `return` ends the function, not the file
+        let status = self.walk(&function.definition.body);
+        self.state.finish_returns(self.env);
+
+        // Locals go out of scope
+        if let Some((_, start)) = self.stack.pop() {
+            for saved in self.locals.drain(start..).rev() {
+                self.env.restore(saved);
+            }
+        }
+        self.state.undecided = undecided;
+        self.state.conditions.truncate(depth.0);
+        self.state.path_conditions.truncate(depth.1);
+        self.call = outer_call;
+        self.state.returns = returns;
+        self.script = script;
+        self.env.replace_script(outer_file);
+        self.env.replace_positional(positional);
+        status
     }
 
-    #[test]
-    fn test_unset() {
-        let content = "unset PATH";
-        let results =
-            parse_shell_content(content, &PathBuf::from("test"), "PATH", Some("/usr/bin"));
-        assert_eq!(results.len(), 1);
-        assert_eq!(results[0].as_assignment().operation, Operation::Unset);
+    /// The known initializer run by `eval "$(tool ...)"`, if `args` is that
+    fn eval_initializer(&self, args: &[Word]) -> Option<Initializer> {
+        let [word] = args else {
+            return None;
+        };
+        let command = match &word.parts[..] {
+            [WordPart::Command(c)] => c,
+            [WordPart::DoubleQuoted(inner)] => match &inner[..] {
+                [WordPart::Command(c)] => c,
+                _ => return None,
+            },
+            _ => return None,
+        };
+        let script = Script::parse(command);
+        let [statement] = &script.statements[..] else {
+            return None;
+        };
+        let [(_, Command::Simple(cmd))] = &statement.commands[..] else {
+            return None;
+        };
+        let lookup = self.lookup();
+        let mut argv = Vec::new();
+        for word in &cmd.words {
+            let expansion = expand_argument(word, &lookup);
+            if !expansion.unresolved.is_empty() {
+                return None;
+            }
+            argv.push(expansion.value);
+        }
+        let shell = if is_zsh_file(self.path) {
+            "zsh"
+        } else {
+            "bash"
+        };
+        eval_initializer(&argv, &lookup, shell)
     }
 
-    #[test]
-    fn test_skip_comments() {
-        let content = "# export PATH=/bad\nexport PATH=/good";
-        let results = parse_shell_content(content, &PathBuf::from("test"), "PATH", None);
-        assert_eq!(results.len(), 1);
-        assert_eq!(results[0].as_assignment().value_after, "/good");
+    /// Run code in place of the statement (`eval`, an emulated initializer);
+    /// its changes are reported at the statement, labelled with the tool
+    /// being emulated if any
+    fn run_in_place(
+        &mut self,
+        code: &str,
+        emulated: Option<String>,
+        statement: &Statement,
+    ) -> Truth {
+        let (line_number, line_content) = self.location(statement);
+        let call = CallSite {
+            line_number,
+            line_content,
+            function: self.call.as_ref().and_then(|call| call.function.clone()),
+            emulated,
+        };
+        let code = Rc::new(Script::parse(code));
+        let script = std::mem::replace(&mut self.script, code.clone());
+        let outer_call = self.call.replace(call);
+        let status = self.walk(&code.statements);
+        self.call = outer_call;
+        self.script = script;
+        status
     }
 
-    #[test]
-    fn test_conditional() {
-        let content = "[ -f /etc/profile ] && export PATH=/usr/bin";
-        let results = parse_shell_content(content, &PathBuf::from("test"), "PATH", None);
-        assert_eq!(results.len(), 1);
-        assert_eq!(results[0].as_assignment().operation, Operation::Conditional);
+    /// The line and text a change or stop is reported at: the statement
+    /// itself, or the call site when it runs inside a function
+    fn location(&self, statement: &Statement) -> (usize, String) {
+        match self.call {
+            Some(ref call) => (call.line_number, call.line_content.clone()),
+            None => (
+                statement.span.line,
+                self.script.text(statement.span).to_string(),
+            ),
+        }
     }
 
-    #[test]
-    fn test_braced_variable() {
-        let content = r#"export PATH="${PATH}:/new""#;
-        let results = parse_shell_content(content, &PathBuf::from("test"), "PATH", Some("/old"));
-        assert_eq!(results.len(), 1);
-        assert_eq!(results[0].as_assignment().operation, Operation::Append);
-        assert_eq!(results[0].as_assignment().value_after, "/old:/new");
+    /// Stop reading the file on the current path
+    fn stop(&mut self, kind: StopKind, statement: &Statement) {
+        let (line_number, line_content) = self.location(statement);
+        // A function's `return` only ends the function
+        let in_function = !self.stack.is_empty() && kind == StopKind::Return;
+        let stop = StopPoint {
+            file: self.path.to_path_buf(),
+            line_number,
+            line_content,
+            kind,
+            conditions: self.state.conditions.clone(),
+            conditional: self.state.undecided > 0,
+        };
+        if !in_function {
+            (self.on_entry)(ParsedShellEntry::Stop(stop), self.env);
+        }
+        self.state.stop(kind, self.env);
+    }
+
+    fn lookup(&self) -> EnvLookup<'_> {
+        EnvLookup {
+            env: self.env,
+            target_var: self.target_var,
+            inherited: RefCell::new(Vec::new()),
+            overrides: HashMap::new(),
+            run_commands: false,
+        }
+    }
+
+    /// `${VAR:=default}` assigns the variable as a side effect of expanding any word
+    fn default_assignments(&mut self, words: &[Word], statement: &Statement) {
+        let mut assigned = Vec::new();
+        for word in words {
+            assigned.extend(expand_word(word, &self.lookup()).assignments);
+        }
+        for (name, value) in assigned {
+            let expansion = Expansion {
+                value,
+                ..Default::default()
+            };
+            let name = canonical_name(&name);
+            self.store(name, Operation::Set, expansion, Vec::new(), statement);
+        }
+    }
+
+    /// Handle export/declare/typeset/readonly/local, which may declare
+    /// several variables (with or without values) in one statement
+    fn declaration(&mut self, builtin: &str, args: &[Word], statement: &Statement) {
+        // bash refuses `local` outside functions
+        if builtin == "local" && self.stack.is_empty() {
+            return;
+        }
+        let (options, operands) = split_options(args);
+
+        // Printing (-p) and function (-f/-F) forms don't set variables
+        let skip_flags = if builtin == "export" { "pf" } else { "pfF" };
+        if options.flags.contains(|c| skip_flags.contains(c)) {
+            return;
+        }
+
+        // `export -n` and `typeset +x` keep the value but stop exporting it
+        let unexported = if builtin == "export" {
+            options.flags.contains('n')
+        } else {
+            options.unflags.contains('x')
+        };
+        let exported = !unexported && (builtin == "export" || options.flags.contains('x'));
+        let operation = if exported {
+            Operation::Export
+        } else {
+            Operation::Set
+        };
+
+        let unique = options.flags.contains('U');
+        let readonly = builtin == "readonly" || options.flags.contains('r');
+        // In a function, `local` (and `declare`/`typeset` without -g) makes
+        // the variables local to the call
+        let local = !self.stack.is_empty()
+            && (builtin == "local"
+                || (builtin != "export" && builtin != "readonly" && !options.flags.contains('g')));
+
+        for word in operands {
+            if local {
+                let name = match word.split_assignment() {
+                    Some((name, _, _)) => Some(name),
+                    None => word.as_literal(),
+                };
+                if let Some(name) = name {
+                    self.make_local(canonical_name(&name));
+                }
+            }
+            if let Some((name, append, value)) = word.split_assignment() {
+                if unique {
+                    self.env.set_unique(canonical_name(&name));
+                }
+                self.assign(&name, append, &value, operation, statement);
+                if exported || unexported {
+                    self.env.set_exported(canonical_name(&name), exported);
+                }
+                if readonly {
+                    self.env.set_readonly(canonical_name(&name));
+                }
+                continue;
+            }
+            let Some(name) = word.as_literal() else {
+                // export $(grep -v '^#' .env | xargs)
+                let text = word.parts.iter().map(|part| part.source_text()).collect();
+                let unresolved = vec![text];
+                self.diagnose(DiagnosticKind::DynamicDeclaration, unresolved, statement);
+                continue;
+            };
+            let name = canonical_name(&name);
+            if readonly {
+                self.env.set_readonly(name);
+            }
+            if unique && !self.env.is_unique(name) {
+                // typeset -U path: drop duplicates from the current value
+                self.env.set_unique(name);
+                if let Some(current) = self.env.get(name) {
+                    let deduped = dedupe_entries(current);
+                    if deduped != current {
+                        let expansion = Expansion {
+                            value: deduped,
+                            unresolved: self.env.unresolved(name).to_vec(),
+                            ..Default::default()
+                        };
+                        self.store(name, Operation::Set, expansion, Vec::new(), statement);
+                    }
+                }
+            }
+            if (exported || unexported) && name == self.target_var && !self.is_local(name) {
+                // Just (un)exporting, value doesn't change
+                let value = self.env.get(name).unwrap_or_default().to_string();
+                let candidates = self.env.candidates(name);
+                let truncated = self.env.is_truncated(name);
+                let expansion = Expansion {
+                    value,
+                    ..Default::default()
+                };
+                let operation = if exported {
+                    Operation::Export
+                } else {
+                    Operation::Unexport
+                };
+                self.record(operation, expansion, statement);
+                self.env.set_candidates(name, candidates);
+                if truncated {
+                    self.env.mark_truncated(name);
+                }
+            }
+            if exported || unexported {
+                self.env.set_exported(name, exported);
+            }
+        }
+    }
+
+    /// Save a variable before the current function makes it local, unless
+    /// it already has
+    fn make_local(&mut self, name: &str) {
+        let start = self.stack.last().map_or(0, |(_, start)| *start);
+        if !self.locals[start..]
+            .iter()
+            .any(|saved| saved.name() == name)
+        {
+            self.locals.push(self.env.save(name));
+        }
+    }
+
+    /// Whether a variable is local to a function being run, so changes to it
+    /// don't last
+    fn is_local(&self, name: &str) -> bool {
+        self.locals.iter().any(|saved| saved.name() == name)
+    }
+
+    fn assign(
+        &mut self,
+        name: &str,
+        append: bool,
+        value: &Word,
+        default_op: Operation,
+        statement: &Statement,
+    ) {
+        let var_name = canonical_name(name);
+        let array = name != var_name || matches!(value.parts[..], [WordPart::Array(_)]);
+        let assignment = Assignment {
+            var_name,
+            append,
+            array,
+            value,
+            default_op,
+        };
+        let (operation, expansion) = assignment.analyze(self.lookup());
+        let (candidates, truncated) = self.assignment_candidates(&assignment);
+        self.store(var_name, operation, expansion, candidates, statement);
+        if truncated {
+            self.env.mark_truncated(var_name);
+        }
+    }
+
+    /// The possible values of an assignment whose value references variables
+    /// that have several possible values, one per combination of them.
+    /// Empty if the value doesn't depend on any; also returns whether some
+    /// combinations were dropped.
+    fn assignment_candidates(&self, assignment: &Assignment<'_>) -> (Vec<ValueCandidate>, bool) {
+        let relevant: Vec<&str> = self
+            .env
+            .ambiguous_names()
+            .into_iter()
+            .filter(|name| {
+                (assignment.append && *name == assignment.var_name)
+                    || references(assignment.value, name)
+                    || tied_pair(name).is_some_and(|tied| references(assignment.value, tied))
+            })
+            .collect();
+        if relevant.is_empty() {
+            return (Vec::new(), false);
+        }
+
+        let mut truncated = false;
+        let mut combinations = vec![(HashMap::new(), Vec::new())];
+        for name in relevant {
+            let mut next = Vec::new();
+            for (overrides, conditions) in &combinations {
+                for candidate in self.env.candidates(name) {
+                    let mut conditions: Vec<String> = Vec::clone(conditions);
+                    if !add_conditions(&mut conditions, &candidate.conditions) {
+                        continue;
+                    }
+                    let mut overrides: HashMap<String, Option<String>> = overrides.clone();
+                    overrides.insert(name.to_string(), candidate.value);
+                    next.push((overrides, conditions));
+                }
+            }
+            truncated |= self.env.is_truncated(name) || next.len() > ShellEnv::MAX_CANDIDATES;
+            next.truncate(ShellEnv::MAX_CANDIDATES);
+            combinations = next;
+        }
+
+        let mut candidates = Vec::new();
+        for (overrides, mut conditions) in combinations {
+            if !add_conditions(&mut conditions, &self.state.path_conditions) {
+                continue;
+            }
+            let vars = EnvLookup {
+                overrides,
+                ..self.lookup()
+            };
+            let (_, expansion) = assignment.analyze(vars);
+            let value = if self.env.is_unique(assignment.var_name) {
+                dedupe_entries(&expansion.value)
+            } else {
+                expansion.value
+            };
+            candidates.push(ValueCandidate {
+                value: Some(value),
+                conditions,
+            });
+        }
+        (candidates, truncated)
+    }
+
+    /// Update a variable: the target is recorded, anything else is only tracked.
+    /// `candidates` are its possible values if they depend on earlier
+    /// undecided branches (see [`Self::assignment_candidates`]).
+    fn store(
+        &mut self,
+        var_name: &str,
+        operation: Operation,
+        expansion: Expansion,
+        candidates: Vec<ValueCandidate>,
+        statement: &Statement,
+    ) {
+        // Assigning or unsetting a readonly variable fails
+        if self.env.is_readonly(var_name) {
+            if var_name == self.target_var {
+                let unresolved = vec![var_name.to_string()];
+                self.diagnose(DiagnosticKind::ReadonlyAssignment, unresolved, statement);
+            }
+            return;
+        }
+        // `set -a` exports every variable assigned while it's on
+        if operation != Operation::Unset && self.env.option("allexport") == Some(true) {
+            self.env.set_exported(var_name, true);
+        }
+        if var_name == self.target_var && !self.is_local(var_name) {
+            self.record(operation, expansion, statement);
+        } else if operation == Operation::Unset {
+            self.env.unset(var_name);
+        } else {
+            let value = if self.env.is_unique(var_name) {
+                dedupe_entries(&expansion.value)
+            } else {
+                expansion.value
+            };
+            self.env
+                .set_with_unresolved(var_name, value, expansion.unresolved);
+        }
+        self.remember(var_name, candidates);
+    }
+
+    /// Record the possible values of a variable that was just assigned. If
+    /// they all agree, the value depends only on the enclosing path.
+    fn remember(&mut self, var_name: &str, candidates: Vec<ValueCandidate>) {
+        let agree = candidates
+            .first()
+            .is_none_or(|first| candidates.iter().all(|c| c.value == first.value));
+        let candidates = if agree {
+            vec![ValueCandidate {
+                value: self.env.get(var_name).map(|v| v.to_string()),
+                conditions: self.state.path_conditions.clone(),
+            }]
+        } else {
+            candidates
+        };
+        self.env.set_candidates(var_name, candidates);
+    }
+
+    fn record(&mut self, operation: Operation, expansion: Expansion, statement: &Statement) {
+        // Changes in branches that may or may not run
+        let operation = if self.state.undecided > 0
+            && !matches!(operation, Operation::Unset | Operation::Unexport)
+        {
+            Operation::Conditional
+        } else {
+            operation
+        };
+        let value_after = if self.env.is_unique(self.target_var) && operation != Operation::Unset {
+            dedupe_entries(&expansion.value)
+        } else {
+            expansion.value
+        };
+        let (line_number, line_content) = self.location(statement);
+        let change = VariableChange {
+            file: self.path.to_path_buf(),
+            line_number,
+            line_content,
+            operation,
+            value_before: self.env.get(self.target_var).map(|s| s.to_string()),
+            value_after,
+            unresolved: expansion.unresolved,
+            evaluated: expansion.evaluated,
+            conditions: self.state.conditions.clone(),
+            function: self.call.as_ref().and_then(|call| call.function.clone()),
+            emulated: self.call.as_ref().and_then(|call| call.emulated.clone()),
+        };
+        match operation {
+            Operation::Unset => self.env.unset(self.target_var),
+            _ => self.env.set_with_unresolved(
+                self.target_var,
+                change.value_after.clone(),
+                change.unresolved.clone(),
+            ),
+        }
+        (self.on_entry)(ParsedShellEntry::Assignment(change), self.env);
+    }
+}
+
+impl Branching for ShellWalker<'_> {
+    type Block = [Statement];
+
+    fn state(&mut self) -> &mut PathState {
+        &mut self.state
+    }
+
+    fn env(&mut self) -> &mut ShellEnv {
+        self.env
+    }
+
+    fn walk_block(&mut self, block: &[Statement]) -> Truth {
+        self.walk(block)
+    }
+}
+
+/// An assignment to analyze, possibly several times with different values
+/// for the variables it references
+struct Assignment<'w> {
+    var_name: &'w str,
+    append: bool,
+    array: bool,
+    value: &'w Word,
+    default_op: Operation,
+}
+
+impl Assignment<'_> {
+    fn analyze(&self, mut vars: EnvLookup<'_>) -> (Operation, Expansion) {
+        vars.run_commands = true;
+        let current = vars.value(self.var_name);
+        let (operation, mut expansion) = if self.append {
+            append_value(self.value, &vars, current.as_deref(), self.array)
+        } else if let [WordPart::Array(elements)] = &self.value.parts[..] {
+            analyze_array(self.var_name, elements, &vars, self.default_op)
+        } else {
+            analyze_value(
+                self.var_name,
+                self.value,
+                &vars,
+                current.as_deref(),
+                self.default_op,
+            )
+        };
+        for reference in vars.inherited.into_inner() {
+            if !expansion.unresolved.contains(&reference) {
+                expansion.unresolved.push(reference);
+            }
+        }
+        (operation, expansion)
+    }
+}
+
+/// Add path conditions to a combination, skipping duplicates. Returns false
+/// if the result contradicts itself (both `c` and `! c`).
+fn add_conditions(conditions: &mut Vec<String>, more: &[String]) -> bool {
+    for condition in more {
+        if conditions.contains(&negate(condition)) {
+            return false;
+        }
+        if !conditions.contains(condition) {
+            conditions.push(condition.clone());
+        }
+    }
+    true
+}
+
+/// Options given to a builtin such as `declare -gx` or `unset -v`
+#[derive(Debug, Default)]
+struct BuiltinOptions {
+    /// Flags turned on with `-`
+    flags: String,
+    /// Flags turned off with `+`
+    unflags: String,
+}
+
+/// Split leading `-x`/`+x` options from the operands of a builtin
+fn split_options(args: &[Word]) -> (BuiltinOptions, &[Word]) {
+    let mut options = BuiltinOptions::default();
+    for (i, word) in args.iter().enumerate() {
+        let Some(text) = word.as_literal() else {
+            return (options, &args[i..]);
+        };
+        if text == "--" {
+            return (options, &args[i + 1..]);
+        }
+        match text.chars().next() {
+            Some('-') if text.len() > 1 => options.flags.push_str(&text[1..]),
+            Some('+') if text.len() > 1 => options.unflags.push_str(&text[1..]),
+            _ => return (options, &args[i..]),
+        }
+    }
+    (options, &[])
+}
+
+/// The variables a builtin assigns from input or arguments only known at
+/// run time: `read` operands (zsh's `name?prompt` included) or REPLY,
+/// `printf -v NAME`, the array `mapfile`/`readarray` fill (MAPFILE by
+/// default) and the variable `getopts` sets
+fn runtime_targets(command: &str, args: &[Word]) -> Vec<String> {
+    let words: Vec<String> = args.iter().filter_map(|w| w.as_literal()).collect();
+    let (with_argument, default) = match command {
+        "read" => ("aAdinNptu", "REPLY"),
+        "mapfile" | "readarray" => ("CcdnOsu", "MAPFILE"),
+        "printf" => {
+            return match words.first().map(String::as_str) {
+                Some("-v") => words.get(1).cloned().into_iter().collect(),
+                Some(flag) => flag
+                    .strip_prefix("-v")
+                    .map(str::to_string)
+                    .into_iter()
+                    .collect(),
+                None => Vec::new(),
+            };
+        }
+        _ => return words.get(1).cloned().into_iter().collect(),
+    };
+    let mut names = Vec::new();
+    let mut words = words.into_iter();
+    while let Some(word) = words.next() {
+        let Some(flags) = word.strip_prefix('-').filter(|f| !f.is_empty()) else {
+            let name = word.split('?').next().unwrap_or_default();
+            names.push(name.to_string());
+            names.extend(words);
+            break;
+        };
+        // Flags that take an argument end a cluster: `-rp prompt`, `-a arr`
+        if let Some(flag) = flags.chars().find(|c| with_argument.contains(*c)) {
+            let argument = match flags.split_once(flag) {
+                Some((_, "")) => words.next(),
+                Some((_, rest)) => Some(rest.to_string()),
+                None => None,
+            };
+            if let (Some(array), 'a' | 'A') = (argument, flag) {
+                names.push(array);
+            }
+        }
+    }
+    if names.is_empty() {
+        names.push(default.to_string());
+    }
+    names
+}
+
+/// Returns true if the word is `$@`, `"$@"`, `$*` or `"$*"`
+fn is_all_params(word: &Word) -> bool {
+    let parts = match &word.parts[..] {
+        [WordPart::DoubleQuoted(inner)] => &inner[..],
+        parts => parts,
+    };
+    matches!(parts, [WordPart::Param(p)] if (p.name == "@" || p.name == "*") && p.modifier.is_empty())
+}
+
+/// The zsh array tied to a colon-separated variable (and vice versa)
+fn tied_pair(name: &str) -> Option<&'static str> {
+    match name {
+        "path" => Some("PATH"),
+        "PATH" => Some("path"),
+        "fpath" => Some("FPATH"),
+        "FPATH" => Some("fpath"),
+        "manpath" => Some("MANPATH"),
+        "MANPATH" => Some("manpath"),
+        "cdpath" => Some("CDPATH"),
+        "CDPATH" => Some("cdpath"),
+        _ => None,
+    }
+}
+
+/// The name a variable is tracked under: zsh tied arrays map to their scalar
+fn canonical_name(name: &str) -> &str {
+    match tied_pair(name) {
+        Some(scalar) if name.starts_with(|c: char| c.is_ascii_lowercase()) => scalar,
+        _ => name,
+    }
+}
+
+/// Keep only the first occurrence of each colon-separated entry
+fn dedupe_entries(value: &str) -> String {
+    let mut seen = Vec::new();
+    for entry in value.split(':') {
+        if !seen.contains(&entry) {
+            seen.push(entry);
+        }
+    }
+    seen.join(":")
+}
+
+/// Variables known while analyzing a value
+///
+/// Traced variables resolve to their values; the target starts out unset,
+/// session variables (HOME, USER, ...) come from the system, and anything
+/// else is unresolved. Unresolved
+/// references carried by other variables' values are collected in
+/// `inherited` so they stay visible on the target.
+struct EnvLookup<'a> {
+    env: &'a ShellEnv,
+    target_var: &'a str,
+    inherited: RefCell<Vec<String>>,
+    /// Values to use instead of the traced ones (`None` for unset)
+    overrides: HashMap<String, Option<String>>,
+    /// Whether command substitutions may be run (only for assignments)
+    run_commands: bool,
+}
+
+impl EnvLookup<'_> {
+    /// The current value of a variable
+    fn value(&self, name: &str) -> Option<String> {
+        match self.overrides.get(name) {
+            Some(value) => value.clone(),
+            None => self.env.get(name).map(|v| v.to_string()),
+        }
+    }
+}
+
+impl VarLookup for EnvLookup<'_> {
+    fn lookup(&self, name: &str) -> Lookup {
+        let name = canonical_name(name);
+        if let Some(script) = self.env.script()
+            && (name == "BASH_SOURCE" || name == "0" && is_zsh_file(script))
+        {
+            return Lookup::Set(script.to_string_lossy().to_string());
+        }
+        if let Some((value, unresolved)) = self.env.positional_param(name) {
+            self.inherited.borrow_mut().extend(unresolved);
+            return Lookup::Set(value);
+        }
+        if let Some(value) = self.overrides.get(name) {
+            return match value {
+                Some(v) => Lookup::Set(v.clone()),
+                None => Lookup::Unset,
+            };
+        }
+        if self.env.is_tracked(name) {
+            return match self.env.get(name) {
+                Some(v) => {
+                    if name != self.target_var {
+                        self.inherited
+                            .borrow_mut()
+                            .extend_from_slice(self.env.unresolved(name));
+                    }
+                    Lookup::Set(v.to_string())
+                }
+                None => Lookup::Unset,
+            };
+        }
+        if name == self.target_var {
+            return Lookup::Unset;
+        }
+        if name == "-"
+            && let Some(flags) = self.env.flags()
+        {
+            return Lookup::Set(flags);
+        }
+        if let Some(value) = self.env.session_value(name) {
+            return match value {
+                Some(v) => Lookup::Set(v.to_string()),
+                None => Lookup::Unset,
+            };
+        }
+        if name == "HOME"
+            && let Some(home) = dirs::home_dir()
+        {
+            return Lookup::Set(home.to_string_lossy().to_string());
+        }
+        // Startup files are read in the home directory
+        if name == "PWD" {
+            return self.lookup("HOME");
+        }
+        Lookup::Unknown
+    }
+
+    fn command_output(&self, command: &str) -> Option<String> {
+        self.env
+            .command_output(command)
+            .or_else(|| path_command_output(command, self))
+    }
+
+    fn evaluate(&self, command: &str, source: &str) -> Option<String> {
+        if !self.run_commands {
+            return None;
+        }
+        self.env.evaluate(command, source, &self.overrides)
+    }
+}
+
+impl ShellState for EnvLookup<'_> {
+    fn option(&self, name: &str) -> Option<bool> {
+        self.env.option(name)
+    }
+
+    fn foreign_host(&self) -> bool {
+        self.env.is_foreign_host()
+    }
+}
+
+/// `VAR+=value` appends text; `path+=(a b)` appends array elements
+fn append_value(
+    value: &Word,
+    vars: &dyn VarLookup,
+    current: Option<&str>,
+    array: bool,
+) -> (Operation, Expansion) {
+    let mut expansion = match &value.parts[..] {
+        [WordPart::Array(elements)] => {
+            let mut expansion = Expansion::default();
+            expansion.value = expand_elements(elements, vars, &mut expansion).join(":");
+            expansion
+        }
+        _ => expand_assignment(value, vars),
+    };
+    let added = &expansion.value;
+    let current = current.unwrap_or("");
+    expansion.value = if current.is_empty() {
+        added.strip_prefix(':').unwrap_or(added).to_string()
+    } else if array && !added.is_empty() {
+        format!("{}:{}", current, added)
+    } else {
+        format!("{}{}", current, added)
+    };
+    (Operation::Append, expansion)
+}
+
+/// `path=(new $path)` prepends and `path=($path new)` appends
+fn analyze_array(
+    var_name: &str,
+    elements: &[Word],
+    vars: &dyn VarLookup,
+    default_op: Operation,
+) -> (Operation, Expansion) {
+    let mut expansion = Expansion::default();
+    let tied = tied_pair(var_name).unwrap_or(var_name);
+    let own = elements
+        .iter()
+        .position(|w| references(w, var_name) || references(w, tied));
+
+    let Some(own) = own else {
+        expansion.value = expand_elements(elements, vars, &mut expansion).join(":");
+        return (default_op, expansion);
+    };
+    let before = expand_elements(&elements[..own], vars, &mut expansion);
+    let current = expand_elements(&elements[own..=own], vars, &mut expansion);
+    let after = expand_elements(&elements[own + 1..], vars, &mut expansion);
+
+    let operation = match (before.is_empty(), after.is_empty()) {
+        (false, true) => Operation::Prepend,
+        (true, false) => Operation::Append,
+        _ => default_op,
+    };
+    expansion.value = [before, current, after].concat().join(":");
+    (operation, expansion)
+}
+
+/// Analyze a value to determine if it's a set, append, or prepend operation
+///
+/// The value is expanded against the traced environment first, so the
+/// operation is inferred from the result: `${VAR:+$VAR:}new`, `"$VAR:new"`
+/// and `${VAR:-/default}:new` are all recognized the same way the shell sees them.
+fn analyze_value(
+    var_name: &str,
+    value: &Word,
+    vars: &dyn VarLookup,
+    current_value: Option<&str>,
+    default_op: Operation,
+) -> (Operation, Expansion) {
+    let mut expansion = expand_assignment(value, vars);
+
+    // Only a value that refers to the variable itself can append or prepend
+    let tied = tied_pair(var_name).unwrap_or(var_name);
+    if !references(value, var_name) && !references(value, tied) {
+        return (default_op, expansion);
+    }
+
+    let expanded = &expansion.value;
+    match current_value {
+        Some(cur) if !cur.is_empty() => {
+            // Append: old:new
+            if expanded
+                .strip_prefix(cur)
+                .is_some_and(|rest| rest.starts_with(':'))
+            {
+                return (Operation::Append, expansion);
+            }
+            // Prepend: new:old
+            if expanded
+                .strip_suffix(cur)
+                .is_some_and(|rest| rest.ends_with(':'))
+            {
+                return (Operation::Prepend, expansion);
+            }
+        }
+        _ => {
+            // Nothing traced yet: "$VAR:new" expands to ":new"
+            if let Some(appended) = expanded.strip_prefix(':') {
+                expansion.value = appended.to_string();
+                return (Operation::Append, expansion);
+            }
+            if let Some(prepended) = expanded.strip_suffix(':') {
+                expansion.value = prepended.to_string();
+                return (Operation::Prepend, expansion);
+            }
+        }
+    }
+
+    (default_op, expansion)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace::EvalPolicy;
+
+    fn parse_shell_content(
+        content: &str,
+        path: &Path,
+        target_var: &str,
+        current_value: Option<&str>,
+    ) -> Vec<ParsedShellEntry> {
+        let mut env = ShellEnv::new();
+        if let Some(value) = current_value {
+            env.set(target_var, value);
+        }
+        let mut results = Vec::new();
+        walk_shell_content(
+            content,
+            path,
+            target_var,
+            &mut env,
+            &Guard::default(),
+            &mut |entry, _| results.push(entry),
+        );
+        results
+    }
+
+    #[test]
+    fn test_export_assignment() {
+        let content = "export PATH=/usr/bin";
+        let results = parse_shell_content(content, &PathBuf::from("test"), "PATH", None);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].as_assignment().value_after, "/usr/bin");
+        assert_eq!(results[0].as_assignment().operation, Operation::Export);
+    }
+
+    #[test]
+    fn test_simple_assignment() {
+        let content = "EDITOR=vim";
+        let results = parse_shell_content(content, &PathBuf::from("test"), "EDITOR", None);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].as_assignment().value_after, "vim");
+        assert_eq!(results[0].as_assignment().operation, Operation::Set);
+    }
+
+    #[test]
+    fn test_append() {
+        let content = r#"export PATH="$PATH:/usr/local/bin""#;
+        let results =
+            parse_shell_content(content, &PathBuf::from("test"), "PATH", Some("/usr/bin"));
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].as_assignment().operation, Operation::Append);
+        assert_eq!(
+            results[0].as_assignment().value_after,
+            "/usr/bin:/usr/local/bin"
+        );
+    }
+
+    #[test]
+    fn test_prepend() {
+        let content = r#"export PATH="/usr/local/bin:$PATH""#;
+        let results =
+            parse_shell_content(content, &PathBuf::from("test"), "PATH", Some("/usr/bin"));
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].as_assignment().operation, Operation::Prepend);
+        assert_eq!(
+            results[0].as_assignment().value_after,
+            "/usr/local/bin:/usr/bin"
+        );
+    }
+
+    #[test]
+    fn test_unset() {
+        let content = "unset PATH";
+        let results =
+            parse_shell_content(content, &PathBuf::from("test"), "PATH", Some("/usr/bin"));
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].as_assignment().operation, Operation::Unset);
+    }
+
+    #[test]
+    fn test_skip_comments() {
+        let content = "# export PATH=/bad\nexport PATH=/good";
+        let results = parse_shell_content(content, &PathBuf::from("test"), "PATH", None);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].as_assignment().value_after, "/good");
+    }
+
+    #[test]
+    fn test_conditional() {
+        let content = r#"[ -f "$PROFILE_OVERRIDE" ] && export PATH=/usr/bin"#;
+        let results = parse_shell_content(content, &PathBuf::from("test"), "PATH", None);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].as_assignment().operation, Operation::Conditional);
+    }
+
+    #[test]
+    fn test_braced_variable() {
+        let content = r#"export PATH="${PATH}:/new""#;
+        let results = parse_shell_content(content, &PathBuf::from("test"), "PATH", Some("/old"));
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].as_assignment().operation, Operation::Append);
+        assert_eq!(results[0].as_assignment().value_after, "/old:/new");
+    }
+
+    #[test]
+    fn test_trailing_comment_not_in_value() {
+        let content = "export EDITOR=vim # my editor";
+        let results = parse_shell_content(content, &PathBuf::from("test"), "EDITOR", None);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].as_assignment().value_after, "vim");
+    }
+
+    #[test]
+    fn test_line_continuation() {
+        let content = "# header\nexport PATH=\\\n/opt/bin:$PATH\n";
+        let results =
+            parse_shell_content(content, &PathBuf::from("test"), "PATH", Some("/usr/bin"));
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].as_assignment().line_number, 2);
+        assert_eq!(results[0].as_assignment().operation, Operation::Prepend);
+        assert_eq!(results[0].as_assignment().value_after, "/opt/bin:/usr/bin");
+    }
+
+    #[test]
+    fn test_escaped_quotes_and_ansi_c() {
+        let content = "export MSG=\"say \\\"hi\\\"\"\nexport SEP=$'a\\tb'";
+        let msg = parse_shell_content(content, &PathBuf::from("test"), "MSG", None);
+        assert_eq!(msg[0].as_assignment().value_after, "say \"hi\"");
+        let sep = parse_shell_content(content, &PathBuf::from("test"), "SEP", None);
+        assert_eq!(sep[0].as_assignment().value_after, "a\tb");
+    }
+
+    #[test]
+    fn test_crlf_line_endings() {
+        let content = "export A=1\r\nexport EDITOR=vim\r\n";
+        let results = parse_shell_content(content, &PathBuf::from("test"), "EDITOR", None);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].as_assignment().value_after, "vim");
+        assert_eq!(results[0].as_assignment().line_number, 2);
+    }
+
+    #[test]
+    fn test_heredoc_body_ignored() {
+        let content = "cat > /tmp/x <<EOF\nexport EDITOR=nano\nEOF\nexport EDITOR=vim\n";
+        let results = parse_shell_content(content, &PathBuf::from("test"), "EDITOR", None);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].as_assignment().value_after, "vim");
+        assert_eq!(results[0].as_assignment().line_number, 4);
+    }
+
+    #[test]
+    fn test_command_prefix_assignment_ignored() {
+        let content = "EDITOR=nano git commit";
+        let results = parse_shell_content(content, &PathBuf::from("test"), "EDITOR", None);
+        assert!(results.is_empty());
+    }
+
+    #[test]
+    fn test_statements_on_one_line_chain_values() {
+        let content = r#"export PATH="/a:$PATH"; export PATH="/b:$PATH""#;
+        let results =
+            parse_shell_content(content, &PathBuf::from("test"), "PATH", Some("/usr/bin"));
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[1].as_assignment().value_after, "/b:/a:/usr/bin");
+        assert_eq!(
+            results[1].as_assignment().line_content,
+            r#"export PATH="/b:$PATH""#
+        );
+    }
+
+    #[test]
+    fn test_export_multiple_assignments() {
+        let content = "export A=1 EDITOR=vim B=2";
+        let results = parse_shell_content(content, &PathBuf::from("test"), "EDITOR", None);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].as_assignment().value_after, "vim");
+        assert_eq!(results[0].as_assignment().operation, Operation::Export);
+    }
+
+    #[test]
+    fn test_export_multiple_names() {
+        let content = "EDITOR=vim\nexport A EDITOR C";
+        let results = parse_shell_content(content, &PathBuf::from("test"), "EDITOR", None);
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[1].as_assignment().operation, Operation::Export);
+        assert_eq!(results[1].as_assignment().value_after, "vim");
+    }
+
+    #[test]
+    fn test_declare_with_export_flag() {
+        let content = "declare -gx JAVA_HOME=/opt/jdk\ntypeset -x JAVA_HOME=/opt/jdk2";
+        let results = parse_shell_content(content, &PathBuf::from("test"), "JAVA_HOME", None);
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].as_assignment().operation, Operation::Export);
+        assert_eq!(results[0].as_assignment().value_after, "/opt/jdk");
+        assert_eq!(results[1].as_assignment().value_after, "/opt/jdk2");
+    }
+
+    #[test]
+    fn test_readonly_and_top_level_local() {
+        let content = "local X=0\nreadonly X=1\nX=2\nunset X\ndeclare -r Y=1\nY=2";
+        let mut env = ShellEnv::new();
+        let mut results = Vec::new();
+        walk_shell_content(
+            content,
+            &PathBuf::from("test"),
+            "X",
+            &mut env,
+            &Guard::default(),
+            &mut |entry, _| results.push(entry),
+        );
+        // `local` outside a function assigns nothing; later writes are refused
+        assert_eq!(results.len(), 3);
+        let change = results[0].as_assignment();
+        assert_eq!((change.line_number, change.value_after.as_str()), (2, "1"));
+        for (entry, line) in results[1..].iter().zip([3, 4]) {
+            let ParsedShellEntry::Diagnostic(diagnostic) = entry else {
+                panic!("Expected Diagnostic variant");
+            };
+            assert_eq!(diagnostic.kind, DiagnosticKind::ReadonlyAssignment);
+            assert_eq!(diagnostic.line_number, line);
+        }
+        assert_eq!(env.get("X"), Some("1"));
+        assert_eq!(env.get("Y"), Some("1"));
+    }
+
+    #[test]
+    fn test_declare_print_and_function_forms_ignored() {
+        let content = "declare -p PATH\ndeclare -f PATH\ndeclare PATH";
+        let results = parse_shell_content(content, &PathBuf::from("test"), "PATH", None);
+        assert!(results.is_empty());
+    }
+
+    #[test]
+    fn test_export_attribute() {
+        let content = r#"EDITOR=vi
+export PAGER=less
+export -n PAGER
+set -a
+VISUAL=vim
+set +a
+setopt all_export
+LESS=-R
+unsetopt allexport
+typeset -x BROWSER=lynx
+typeset +x BROWSER"#;
+        let mut env = ShellEnv::new();
+        let mut results = Vec::new();
+        walk_shell_content(
+            content,
+            &PathBuf::from("test"),
+            "PAGER",
+            &mut env,
+            &Guard::default(),
+            &mut |entry, _| results.push(entry),
+        );
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[1].as_assignment().operation, Operation::Unexport);
+        assert_eq!(results[1].as_assignment().value_after, "less");
+        assert_eq!(env.is_exported("EDITOR"), Some(false));
+        assert_eq!(env.is_exported("PAGER"), Some(false));
+        assert_eq!(env.is_exported("VISUAL"), Some(true));
+        assert_eq!(env.is_exported("LESS"), Some(true));
+        assert_eq!(env.is_exported("BROWSER"), Some(false));
+        assert_eq!(env.get("BROWSER"), Some("lynx"));
+    }
+
+    #[test]
+    fn test_startup_options() {
+        let content = r#"setopt NO_GLOBAL_RCS
+if [ -n "$SSH_TTY" ]; then
+    unsetopt rcs
+fi"#;
+        let mut env = ShellEnv::new();
+        env.set_option("rcs", true);
+        walk_shell_content(
+            content,
+            &PathBuf::from("test"),
+            "PATH",
+            &mut env,
+            &Guard::default(),
+            &mut |_, _| {},
+        );
+        assert_eq!(env.option("globalrcs"), Some(false));
+        assert_eq!(env.option("rcs"), None);
+    }
+
+    #[test]
+    fn test_unset_multiple() {
+        let content = "unset -v A PATH B\nunset -f PATH";
+        let results =
+            parse_shell_content(content, &PathBuf::from("test"), "PATH", Some("/usr/bin"));
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].as_assignment().operation, Operation::Unset);
+    }
+
+    #[test]
+    fn test_multiple_plain_assignments() {
+        let content = "A=1 EDITOR=vim";
+        let results = parse_shell_content(content, &PathBuf::from("test"), "EDITOR", None);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].as_assignment().operation, Operation::Set);
+    }
+
+    #[test]
+    fn test_alternate_value_prepend() {
+        let content = "export LD_LIBRARY_PATH=/opt/x/lib${LD_LIBRARY_PATH:+:$LD_LIBRARY_PATH}";
+        let results = parse_shell_content(
+            content,
+            &PathBuf::from("test"),
+            "LD_LIBRARY_PATH",
+            Some("/usr/lib"),
+        );
+        assert_eq!(results[0].as_assignment().operation, Operation::Prepend);
+        assert_eq!(
+            results[0].as_assignment().value_after,
+            "/opt/x/lib:/usr/lib"
+        );
+
+        let results = parse_shell_content(content, &PathBuf::from("test"), "LD_LIBRARY_PATH", None);
+        assert_eq!(results[0].as_assignment().operation, Operation::Export);
+        assert_eq!(results[0].as_assignment().value_after, "/opt/x/lib");
+    }
+
+    #[test]
+    fn test_default_value_expansion() {
+        let content = "export EDITOR=${EDITOR:-vim}";
+        let results = parse_shell_content(content, &PathBuf::from("test"), "EDITOR", None);
+        assert_eq!(results[0].as_assignment().value_after, "vim");
+        let results = parse_shell_content(content, &PathBuf::from("test"), "EDITOR", Some("nano"));
+        assert_eq!(results[0].as_assignment().value_after, "nano");
+    }
+
+    #[test]
+    fn test_prefix_and_suffix_removal() {
+        let content = "PATH=${PATH#/sbin:}\nPATH=${PATH%:/tmp}";
+        let results = parse_shell_content(
+            content,
+            &PathBuf::from("test"),
+            "PATH",
+            Some("/sbin:/usr/bin:/tmp"),
+        );
+        assert_eq!(results[0].as_assignment().value_after, "/usr/bin:/tmp");
+        assert_eq!(results[1].as_assignment().value_after, "/usr/bin");
+    }
+
+    #[test]
+    fn test_assign_default_statement() {
+        let content = ": ${EDITOR:=vi}";
+        let results = parse_shell_content(content, &PathBuf::from("test"), "EDITOR", None);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].as_assignment().value_after, "vi");
+    }
+
+    #[test]
+    fn test_plus_equals_append() {
+        let content = r#"PATH+=":/opt/bin"
+export PATH+=":/opt/sbin""#;
+        let results =
+            parse_shell_content(content, &PathBuf::from("test"), "PATH", Some("/usr/bin"));
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].as_assignment().operation, Operation::Append);
+        assert_eq!(results[0].as_assignment().value_after, "/usr/bin:/opt/bin");
+        assert_eq!(
+            results[1].as_assignment().value_after,
+            "/usr/bin:/opt/bin:/opt/sbin"
+        );
+    }
+
+    #[test]
+    fn test_path_array_prepend_and_append() {
+        let content = "path=(/opt/homebrew/bin $path)\npath+=(/opt/local/bin)";
+        let results =
+            parse_shell_content(content, &PathBuf::from("test"), "PATH", Some("/usr/bin"));
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].as_assignment().operation, Operation::Prepend);
+        assert_eq!(
+            results[0].as_assignment().value_after,
+            "/opt/homebrew/bin:/usr/bin"
+        );
+        assert_eq!(results[1].as_assignment().operation, Operation::Append);
+        assert_eq!(
+            results[1].as_assignment().value_after,
+            "/opt/homebrew/bin:/usr/bin:/opt/local/bin"
+        );
+    }
+
+    #[test]
+    fn test_path_array_set() {
+        let content = "fpath=(\n  ~/.zfunc  # completions\n  /usr/share/zsh/functions\n)";
+        let results = parse_shell_content(content, &PathBuf::from("test"), "FPATH", None);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].as_assignment().operation, Operation::Set);
+        assert!(
+            results[0]
+                .as_assignment()
+                .value_after
+                .ends_with("/.zfunc:/usr/share/zsh/functions")
+        );
+    }
+
+    #[test]
+    fn test_typeset_unique() {
+        let content = "typeset -U path PATH\npath=(/usr/bin $path)";
+        let results = parse_shell_content(
+            content,
+            &PathBuf::from("test"),
+            "PATH",
+            Some("/bin:/usr/bin:/bin"),
+        );
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].as_assignment().operation, Operation::Set);
+        assert_eq!(results[0].as_assignment().value_after, "/bin:/usr/bin");
+        assert_eq!(results[1].as_assignment().value_after, "/usr/bin:/bin");
+    }
+
+    #[test]
+    fn test_tilde_expansion_in_values() {
+        let home = dirs::home_dir().unwrap().to_string_lossy().to_string();
+        let content = "export PATH=~/bin:$PATH\nexport PATH=\"$PATH\":~root/bin";
+        let results =
+            parse_shell_content(content, &PathBuf::from("test"), "PATH", Some("/usr/bin"));
+        assert_eq!(results[0].as_assignment().operation, Operation::Prepend);
+        assert_eq!(
+            results[0].as_assignment().value_after,
+            format!("{}/bin:/usr/bin", home)
+        );
+        assert_eq!(
+            results[1].as_assignment().value_after,
+            format!("{}/bin:/usr/bin:/root/bin", home)
+        );
+    }
+
+    #[test]
+    fn test_source_other_users_home() {
+        let results =
+            parse_shell_content(". ~root/.shared_env", &PathBuf::from("test"), "PATH", None);
+        let ParsedShellEntry::Source(ref sourced) = results[0] else {
+            panic!("Expected Source variant");
+        };
+        assert_eq!(sourced.path, PathBuf::from("/root/.shared_env"));
+    }
+
+    #[test]
+    fn test_branch_conditions_attached() {
+        let content = r#"if [[ $OSTYPE == darwin* ]]; then
+    export PATH="/opt/homebrew/bin:$PATH"
+else
+    export PATH="/usr/local/bin:$PATH"
+fi"#;
+        let results =
+            parse_shell_content(content, &PathBuf::from("test"), "PATH", Some("/usr/bin"));
+        assert_eq!(results.len(), 2);
+        let first = results[0].as_assignment();
+        assert_eq!(first.conditions, vec!["[[ $OSTYPE == darwin* ]]"]);
+        assert_eq!(first.line_number, 2);
+        let second = results[1].as_assignment();
+        assert_eq!(second.conditions, vec!["! [[ $OSTYPE == darwin* ]]"]);
+        // Each branch starts from the value before the if
+        assert_eq!(second.value_before.as_deref(), Some("/usr/bin"));
+        assert_eq!(second.value_after, "/usr/local/bin:/usr/bin");
+    }
+
+    #[test]
+    fn test_case_and_nested_conditions() {
+        let content = r#"case "$(uname -s)" in
+    Darwin) export EDITOR=mate ;;
+    *)
+        [ -x "$NVIM_BIN" ] && export EDITOR=nvim
+        ;;
+esac"#;
+        let results = parse_shell_content(content, &PathBuf::from("test"), "EDITOR", None);
+        assert_eq!(results.len(), 2);
+        assert_eq!(
+            results[0].as_assignment().conditions,
+            vec![r#"case "$(uname -s)" in Darwin)"#]
+        );
+        assert_eq!(
+            results[1].as_assignment().conditions,
+            vec![r#"case "$(uname -s)" in *)"#, r#"[ -x "$NVIM_BIN" ]"#]
+        );
+        assert_eq!(results[1].as_assignment().operation, Operation::Conditional);
+    }
+
+    #[test]
+    fn test_subshell_changes_ignored() {
+        let content = "(export EDITOR=vi)\nexport EDITOR=nano";
+        let results = parse_shell_content(content, &PathBuf::from("test"), "EDITOR", None);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].as_assignment().value_after, "nano");
+    }
+
+    #[test]
+    fn test_for_loop_items() {
+        let content = "for dir in /opt/a /opt/b; do\n  PATH=\"$dir/bin:$PATH\"\ndone";
+        let results =
+            parse_shell_content(content, &PathBuf::from("test"), "PATH", Some("/usr/bin"));
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[1].as_assignment().operation, Operation::Prepend);
+        assert_eq!(
+            results[1].as_assignment().value_after,
+            "/opt/b/bin:/opt/a/bin:/usr/bin"
+        );
+        assert_eq!(
+            results[1].as_assignment().conditions,
+            vec!["for dir in /opt/a /opt/b"]
+        );
+    }
+
+    #[test]
+    fn test_decided_branches_followed() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let content = format!(
+            r#"if [ -d {dir} ]; then
+    export PATH="{dir}/bin:$PATH"
+elif [ -d /opt/homebrew ]; then
+    export PATH="/opt/homebrew/bin:$PATH"
+fi
+[ -f {dir}/missing ] && export PATH=/broken"#,
+            dir = dir.path().display()
+        );
+        let results =
+            parse_shell_content(&content, &PathBuf::from("test"), "PATH", Some("/usr/bin"));
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].as_assignment().operation, Operation::Prepend);
+    }
+
+    #[test]
+    fn test_interactive_guard() {
+        let content = r#"[[ $- == *i* ]] && export EDITOR=vim
+[ -z "$PS1" ] && export EDITOR=ed"#;
+        let path = PathBuf::from("test");
+        let mut env = ShellEnv::for_context(crate::trace::Context::InteractiveNonLogin);
+        let mut results = Vec::new();
+        walk_shell_content(
+            content,
+            &path,
+            "EDITOR",
+            &mut env,
+            &Guard::default(),
+            &mut |entry, _| results.push(entry),
+        );
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].as_assignment().value_after, "vim");
+        assert_eq!(results[0].as_assignment().operation, Operation::Export);
+    }
+
+    #[test]
+    fn test_simulated_host() {
+        let content = r#"case "$(uname -s)" in
+    Darwin) export BROWSER=open ;;
+    *) export BROWSER=xdg-open ;;
+esac
+if [[ $OSTYPE == darwin* && "$(uname -m)" == arm64 ]]; then
+    BROWSER="$BROWSER -a Safari"
+fi
+[ "$HOSTNAME" = build01 ] && BROWSER=none"#;
+        let host = "os=darwin,arch=arm64,hostname=laptop".parse().unwrap();
+        let mut env = ShellEnv::new().with_host(host);
+        let mut results = Vec::new();
+        walk_shell_content(
+            content,
+            &PathBuf::from("test"),
+            "BROWSER",
+            &mut env,
+            &Guard::default(),
+            &mut |entry, _| results.push(entry),
+        );
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].as_assignment().value_after, "open");
+        assert_eq!(results[1].as_assignment().value_after, "open -a Safari");
+        assert_eq!(results[1].as_assignment().conditions.len(), 1);
+    }
+
+    #[test]
+    fn test_evaluated_command_substitutions() {
+        let content = r#"PREFIX=/opt/tool
+export TOOL_HOME="$(echo $PREFIX/libexec)"
+if [ "$(echo yes)" = yes ]; then TOOL_HOME=$(rm -rf "$PREFIX"); fi
+OTHER=$(echo other)"#;
+        // Only the substitutions that reach the traced variable run
+        let sources = ["$(echo $PREFIX/libexec)", r#"$(rm -rf "$PREFIX")"#]
+            .map(String::from)
+            .into();
+        let mut env = ShellEnv::new().with_eval(EvalPolicy::default(), sources);
+        let mut results = Vec::new();
+        walk_shell_content(
+            content,
+            &PathBuf::from("test"),
+            "TOOL_HOME",
+            &mut env,
+            &Guard::default(),
+            &mut |entry, _| results.push(entry),
+        );
+        assert_eq!(results.len(), 2);
+        let change = results[0].as_assignment();
+        assert_eq!(change.value_after, "/opt/tool/libexec");
+        assert_eq!(change.evaluated, vec!["$(echo $PREFIX/libexec)"]);
+        // Conditions aren't evaluated, and denied commands stay unresolved
+        let change = results[1].as_assignment();
+        assert_eq!(change.operation, Operation::Conditional);
+        assert!(change.evaluated.is_empty());
+        assert_eq!(change.unresolved, vec![r#"$(rm -rf "$PREFIX")"#]);
+        assert_eq!(env.unresolved("OTHER"), ["$(echo other)"]);
+    }
+
+    #[test]
+    fn test_return_guard_stops_file() {
+        let content = r#"export EDITOR=vi
+[[ $- != *i* ]] && return
+case $- in
+    *i*) ;;
+    *) return ;;
+esac
+export EDITOR=vim"#;
+        let path = PathBuf::from("test");
+
+        let mut env = ShellEnv::for_context(crate::trace::Context::NonInteractiveNonLogin);
+        let mut results = Vec::new();
+        walk_shell_content(
+            content,
+            &path,
+            "EDITOR",
+            &mut env,
+            &Guard::default(),
+            &mut |entry, _| results.push(entry),
+        );
+        assert_eq!(results.len(), 2);
+        let ParsedShellEntry::Stop(ref stop) = results[1] else {
+            panic!("Expected Stop variant");
+        };
+        assert_eq!(stop.line_number, 2);
+        assert!(!stop.conditional);
+        assert_eq!(env.get("EDITOR"), Some("vi"));
+
+        let mut env = ShellEnv::for_context(crate::trace::Context::InteractiveNonLogin);
+        let mut results = Vec::new();
+        walk_shell_content(
+            content,
+            &path,
+            "EDITOR",
+            &mut env,
+            &Guard::default(),
+            &mut |entry, _| results.push(entry),
+        );
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[1].as_assignment().operation, Operation::Export);
+        assert_eq!(env.get("EDITOR"), Some("vim"));
+    }
+
+    #[test]
+    fn test_undecided_return() {
+        let content = r#"export EDITOR=vi
+[ -n "$SSH_TTY" ] && return
+export EDITOR=code
+exec zsh
+export EDITOR=never"#;
+        let path = PathBuf::from("test");
+        let mut env = ShellEnv::new();
+        let mut results = Vec::new();
+        walk_shell_content(
+            content,
+            &path,
+            "EDITOR",
+            &mut env,
+            &Guard::default(),
+            &mut |entry, _| results.push(entry),
+        );
+        // vi, may return, code (conditional), exec
+        assert_eq!(results.len(), 4);
+        let change = results[2].as_assignment();
+        assert_eq!(change.operation, Operation::Conditional);
+        assert_eq!(change.conditions, vec![r#"! [ -n "$SSH_TTY" ]"#]);
+        assert!(matches!(results[3], ParsedShellEntry::Stop(ref s) if s.kind == StopKind::Exec));
+        // The path that returned early is still alive; the other one exited
+        assert!(!env.has_exited());
+
+        env.finish();
+        let values: Vec<_> = env
+            .candidates("EDITOR")
+            .into_iter()
+            .map(|c| c.value.unwrap())
+            .collect();
+        assert_eq!(values, vec!["code", "vi"]);
+    }
+
+    #[test]
+    fn test_undecided_return_in_if() {
+        for content in [
+            "if [ -n \"$X\" ]; then return; fi\nexport C=3",
+            "[ -n \"$X\" ] && return\nexport C=3",
+        ] {
+            let mut env = ShellEnv::new();
+            let mut results = Vec::new();
+            walk_shell_content(
+                content,
+                &PathBuf::from("test"),
+                "C",
+                &mut env,
+                &Guard::default(),
+                &mut |entry, _| results.push(entry),
+            );
+            let change = results[1].as_assignment();
+            assert_eq!(change.operation, Operation::Conditional);
+            assert_eq!(change.conditions, vec![r#"! [ -n "$X" ]"#]);
+        }
+    }
+
+    #[test]
+    fn test_break_and_continue() {
+        let dir = tempfile::TempDir::new().unwrap();
+        for name in ["b", "c"] {
+            std::fs::create_dir(dir.path().join(name)).unwrap();
+        }
+        let content = format!(
+            r#"for d in {dir}/a {dir}/b {dir}/c; do [ -d "$d" ] && {{ X=$d; break; }}; done
+Y=
+for d in a b c; do [ "$d" = b ] && continue; Y="$Y$d"; done
+W=none
+for d in a b; do [ -n "$Z" ] && break; W=$d; done"#,
+            dir = dir.path().display()
+        );
+        let mut env = ShellEnv::new();
+        walk_shell_content(
+            &content,
+            &PathBuf::from("test"),
+            "X",
+            &mut env,
+            &Guard::default(),
+            &mut |_, _| {},
+        );
+        env.finish();
+        let values = |name| -> Vec<String> {
+            env.candidates(name)
+                .into_iter()
+                .map(|c| c.value.unwrap())
+                .collect()
+        };
+        // The first match, as bash picks it
+        assert_eq!(values("X"), vec![format!("{}/b", dir.path().display())]);
+        assert_eq!(values("Y"), vec!["ac"]);
+        // An undecided break forks: the loop may stop before either item
+        let mut w = values("W");
+        w.sort();
+        assert_eq!(w, vec!["a", "b", "none"]);
+    }
+
+    #[test]
+    fn test_relative_globs_and_read_loops() {
+        let dir = tempfile::TempDir::new().unwrap();
+        for name in ["b.sh", "a.sh", "notes"] {
+            std::fs::write(dir.path().join(name), "").unwrap();
+        }
+        // The glob is matched in the script's directory, not ours
+        let content = format!(
+            r#"cd {dir}
+X=
+for f in *.sh; do X="$X $f"; done
+while read f; do . "$f"; done < <(find ~/.bashrc.d -name '*.sh')"#,
+            dir = dir.path().display()
+        );
+        let mut env = ShellEnv::new();
+        let mut results = Vec::new();
+        walk_shell_content(
+            &content,
+            &PathBuf::from("test"),
+            "X",
+            &mut env,
+            &Guard::default(),
+            &mut |entry, _| results.push(entry),
+        );
+        assert_eq!(env.get("X"), Some(" a.sh b.sh"));
+        let diagnostic = results
+            .iter()
+            .find_map(|entry| match entry {
+                ParsedShellEntry::Diagnostic(d) => Some(d),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(diagnostic.kind, DiagnosticKind::UnresolvedSource);
+        assert_eq!(diagnostic.unresolved, vec!["$f"]);
+    }
+
+    #[test]
+    fn test_values_read_at_runtime() {
+        let content = r#"X=known
+read -r -p "name? " X
+printf -v Y '%s' x
+mapfile -t LINES < file
+getopts "ab:" opt
+read
+printf '%s\n' done"#;
+        let mut env = ShellEnv::new();
+        let mut results = Vec::new();
+        walk_shell_content(
+            content,
+            &PathBuf::from("test"),
+            "X",
+            &mut env,
+            &Guard::default(),
+            &mut |entry, _| results.push(entry),
+        );
+        let change = results[1].as_assignment();
+        assert_eq!(change.value_after, "$X");
+        assert_eq!(change.unresolved, vec!["$X"]);
+        // Only assigning the traced variable is reported
+        let diagnostics: Vec<_> = results
+            .iter()
+            .filter_map(|entry| match entry {
+                ParsedShellEntry::Diagnostic(d) => Some(d),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].kind, DiagnosticKind::RuntimeValue);
+        assert_eq!(diagnostics[0].line_number, 2);
+        for name in ["Y", "LINES", "opt", "REPLY"] {
+            assert_eq!(env.unresolved(name), [format!("${}", name)]);
+        }
+    }
+
+    #[test]
+    fn test_candidates_truncated() {
+        // Six undecided appends give 64 possible values
+        let content: String = (1..=6)
+            .map(|i| format!("[ -n \"$A{i}\" ] && X=\"${{X}}{i}\"\n"))
+            .collect();
+        let mut env = ShellEnv::new();
+        env.set("X", "");
+        walk_shell_content(
+            &format!("{content}Y=$X"),
+            &PathBuf::from("test"),
+            "Z",
+            &mut env,
+            &Guard::default(),
+            &mut |_, _| {},
+        );
+        env.finish();
+        for name in ["X", "Y"] {
+            assert_eq!(env.candidates(name).len(), ShellEnv::MAX_CANDIDATES);
+            assert!(env.is_truncated(name));
+        }
+        env.set("X", "known");
+        assert!(!env.is_truncated("X"));
+    }
+
+    #[test]
+    fn test_variable_source_paths() {
+        let content = r#"export ZSH="$HOME/.oh-my-zsh"
+source $ZSH/oh-my-zsh.sh
+unset ALIASES_DIR
+. "${ALIASES_DIR:-$HOME}/.aliases"
+. "$NVM_DIR/nvm.sh""#;
+        let mut env = ShellEnv::new();
+        env.set("HOME", "/home/user");
+        let mut results = Vec::new();
+        walk_shell_content(
+            content,
+            &PathBuf::from("test"),
+            "PATH",
+            &mut env,
+            &Guard::default(),
+            &mut |entry, _| results.push(entry),
+        );
+        let paths: Vec<_> = results
+            .iter()
+            .filter_map(|entry| match entry {
+                ParsedShellEntry::Source(sourced) => Some(sourced.path.clone()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            paths,
+            vec![
+                PathBuf::from("/home/user/.oh-my-zsh/oh-my-zsh.sh"),
+                PathBuf::from("/home/user/.aliases"),
+            ]
+        );
+        let ParsedShellEntry::Diagnostic(ref diagnostic) = results[2] else {
+            panic!("Expected Diagnostic variant");
+        };
+        assert_eq!(diagnostic.kind, DiagnosticKind::UnresolvedSource);
+        assert_eq!(diagnostic.line_number, 5);
+        assert_eq!(diagnostic.unresolved, vec!["$NVM_DIR"]);
+    }
+
+    #[test]
+    fn test_script_relative_source_paths() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let dir = dir.path().canonicalize().unwrap();
+        let content = r#"source "$(dirname "${BASH_SOURCE[0]}")/common.sh"
+. "$( cd "$( dirname "${BASH_SOURCE[0]}" )" &> /dev/null && pwd )/lib.sh"
+source ${0:A:h}/lib.zsh
+. ./local.sh
+cd /opt
+source ../etc/rel.sh"#;
+        let mut env = ShellEnv::new();
+        env.set("HOME", "/home/user");
+        let mut results = Vec::new();
+        walk_shell_content(
+            content,
+            &dir.join("rc.zsh"),
+            "PATH",
+            &mut env,
+            &Guard::default(),
+            &mut |entry, _| results.push(entry),
+        );
+        let paths: Vec<_> = results
+            .iter()
+            .map(|entry| match entry {
+                ParsedShellEntry::Source(sourced) => sourced.path.clone(),
+                _ => panic!("Expected Source variant"),
+            })
+            .collect();
+        assert_eq!(
+            paths,
+            vec![
+                dir.join("common.sh"),
+                dir.join("lib.sh"),
+                dir.join("lib.zsh"),
+                PathBuf::from("/home/user/local.sh"),
+                PathBuf::from("/etc/rel.sh"),
+            ]
+        );
+        assert_eq!(env.script(), None);
+    }
+
+    #[test]
+    fn test_emulated_initializers() {
+        let home = tempfile::tempdir().unwrap();
+        let home = home.path().to_string_lossy().to_string();
+        std::fs::create_dir_all(format!("{}/.pyenv", home)).unwrap();
+        std::fs::create_dir_all(format!("{}/homebrew/bin", home)).unwrap();
+        std::fs::write(format!("{}/homebrew/bin/brew", home), "").unwrap();
+        let content = r#"export PATH=/usr/bin
+export PYENV_ROOT="$HOME/.pyenv"
+[ -n "$SSH_TTY" ] || eval "$(pyenv init -)"
+eval "$($HOME/homebrew/bin/brew shellenv)"
+eval "$(rbenv init - bash)"
+eval "$(direnv hook bash)""#;
+        let mut env = ShellEnv::new();
+        env.set("HOME", &home);
+        let mut results = Vec::new();
+        walk_shell_content(
+            content,
+            &PathBuf::from("test"),
+            "PATH",
+            &mut env,
+            &Guard::default(),
+            &mut |entry, _| results.push(entry),
+        );
+        // rbenv isn't installed and direnv isn't known, so their evals are
+        // reported instead
+        assert_eq!(results.len(), 5);
+        assert!(matches!(results[3], ParsedShellEntry::Diagnostic(_)));
+        assert!(matches!(results[4], ParsedShellEntry::Diagnostic(_)));
+        let pyenv = results[1].as_assignment();
+        assert_eq!(pyenv.emulated.as_deref(), Some("pyenv init"));
+        assert_eq!(pyenv.line_number, 3);
+        assert_eq!(pyenv.operation, Operation::Conditional);
+        assert_eq!(pyenv.value_after, format!("{}/.pyenv/shims:/usr/bin", home));
+        let brew = results[2].as_assignment();
+        assert_eq!(brew.emulated.as_deref(), Some("brew shellenv"));
+        assert_eq!(
+            brew.line_content,
+            r#"eval "$($HOME/homebrew/bin/brew shellenv)""#
+        );
+        let prefix = format!("{}/homebrew", home);
+        assert!(
+            brew.value_after
+                .starts_with(&format!("{0}/bin:{0}/sbin:", prefix))
+        );
+        assert_eq!(env.get("HOMEBREW_PREFIX"), Some(prefix.as_str()));
+    }
+
+    #[test]
+    fn test_opaque_statements() {
+        let content = r#"CODE='export EDITOR=nano'
+eval "$CODE"
+eval "$(direnv hook bash)"
+export $(grep -v '^#' .env | xargs)
+source <(kubectl completion bash)
+set -- $(cat args)
+env -i HOME="$HOME" bash -l"#;
+        let mut env = ShellEnv::new();
+        let mut results = Vec::new();
+        walk_shell_content(
+            content,
+            &PathBuf::from("test"),
+            "EDITOR",
+            &mut env,
+            &Guard::default(),
+            &mut |entry, _| results.push(entry),
+        );
+        let change = results[0].as_assignment();
+        assert_eq!(
+            (change.line_number, change.value_after.as_str()),
+            (2, "nano")
+        );
+        let kinds: Vec<(usize, DiagnosticKind)> = results[1..]
+            .iter()
+            .map(|entry| match entry {
+                ParsedShellEntry::Diagnostic(d) => (d.line_number, d.kind),
+                _ => panic!("Expected Diagnostic variant"),
+            })
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (3, DiagnosticKind::DynamicEval),
+                (4, DiagnosticKind::DynamicDeclaration),
+                (5, DiagnosticKind::SourcedCommandOutput),
+                (6, DiagnosticKind::DynamicPositional),
+                (7, DiagnosticKind::ClearedEnvironment),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_function_call_sites() {
+        let content = r#"pathadd() {
+    [ -n "$1" ] || return
+    PATH="$1:$PATH"
+}
+pathadd /opt/bin
+pathadd
+pathadd "$HOME/bin"
+unset -f pathadd
+pathadd /never"#;
+        let mut env = ShellEnv::new();
+        env.set("PATH", "/usr/bin");
+        env.set("HOME", "/home/user");
+        let mut results = Vec::new();
+        walk_shell_content(
+            content,
+            &PathBuf::from("test"),
+            "PATH",
+            &mut env,
+            &Guard::default(),
+            &mut |entry, _| results.push(entry),
+        );
+        assert_eq!(results.len(), 2);
+        let first = results[0].as_assignment();
+        assert_eq!(first.line_number, 5);
+        assert_eq!(first.line_content, "pathadd /opt/bin");
+        assert_eq!(first.operation, Operation::Prepend);
+        let function = first.function.as_ref().unwrap();
+        assert_eq!(function.name, "pathadd");
+        assert_eq!(function.line_number, 1);
+        let second = results[1].as_assignment();
+        assert_eq!(second.line_number, 7);
+        assert_eq!(second.value_after, "/home/user/bin:/opt/bin:/usr/bin");
+        assert_eq!(env.get("PATH"), Some("/home/user/bin:/opt/bin:/usr/bin"));
+    }
+
+    #[test]
+    fn test_recursive_calls_not_followed() {
+        let content = "f() { export X=$1; f a; g; }\ng() { f; }\nf b";
+        let mut env = ShellEnv::new();
+        let mut results = Vec::new();
+        walk_shell_content(
+            content,
+            &PathBuf::from("test"),
+            "X",
+            &mut env,
+            &Guard::default(),
+            &mut |entry, _| results.push(entry),
+        );
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].as_assignment().value_after, "b");
+        for entry in &results[1..] {
+            match entry {
+                ParsedShellEntry::Diagnostic(d) => {
+                    assert_eq!(d.kind, DiagnosticKind::RecursiveCall);
+                    assert_eq!(d.unresolved, ["f"]);
+                }
+                _ => panic!("Expected Diagnostic variant"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_locals_restored_after_call() {
+        let content = "export V=outer\nf() { local V=inner; W=$V; declare -g G=1; }\nf\ng() { typeset V; export V=2; }\ng";
+        let mut env = ShellEnv::new();
+        let mut results = Vec::new();
+        walk_shell_content(
+            content,
+            &PathBuf::from("test"),
+            "V",
+            &mut env,
+            &Guard::default(),
+            &mut |entry, _| results.push(entry),
+        );
+        // Changes to the local copies aren't recorded
+        assert_eq!(results.len(), 1);
+        assert_eq!(env.get("V"), Some("outer"));
+        assert_eq!(env.is_exported("V"), Some(true));
+        assert_eq!(env.get("W"), Some("inner"));
+        assert_eq!(env.get("G"), Some("1"));
     }
 }
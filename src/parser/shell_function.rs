@@ -1,25 +1,20 @@
-use std::path::{Path, PathBuf};
-use std::sync::LazyLock;
+use std::path::Path;
 
-use regex::Regex;
-
-use super::common::expand_source_path;
+use super::common::{Guard, SourcedFile, source_arguments, source_path};
+use super::lexer::Word;
+use super::script::{Command, Connector, FunctionDef, Script, Statement};
 use crate::trace::function::{FunctionChange, FunctionOperation};
 
 /// Maximum number of body lines to include in output
 const MAX_BODY_PREVIEW_LINES: usize = 5;
 
-// Static regex for source commands (compiled once)
-static SOURCE_CMD: LazyLock<Regex> =
-    LazyLock::new(|| Regex::new(r"^\s*(\.|source)\s+(.+)$").unwrap());
-
 /// A parsed entry from a shell script (function context)
 #[derive(Debug, Clone)]
 pub enum ParsedFunctionEntry {
     /// A function definition, autoload, or unset
     Definition(FunctionChange),
     /// A source/. command pointing to another file
-    Source(PathBuf),
+    Source(SourcedFile),
 }
 
 #[cfg(test)]
@@ -55,123 +50,163 @@ fn parse_shell_content_for_function(
     path: &Path,
     target_func: &str,
 ) -> Vec<ParsedFunctionEntry> {
-    let mut results = Vec::new();
-    let lines: Vec<&str> = content.lines().collect();
-
-    // Dynamic regex patterns (depend on target function name)
-    let escaped = regex::escape(target_func);
-    let posix_func = Regex::new(&format!(r"^\s*{}\s*\(\)\s*\{{", escaped)).unwrap();
-    let bash_func = Regex::new(&format!(r"^\s*function\s+{}\s*\{{", escaped)).unwrap();
-    let hybrid_func = Regex::new(&format!(r"^\s*function\s+{}\s*\(\)\s*\{{", escaped)).unwrap();
-    let autoload = Regex::new(&format!(
-        r"^\s*autoload\s+(?:-[A-Za-z]+\s+)*{}(?:\s|$)",
-        escaped
-    ))
-    .unwrap();
-    let unset_func = Regex::new(&format!(r"^\s*unset\s+-f\s+{}(?:\s|$)", escaped)).unwrap();
-
-    let mut i = 0;
-    while i < lines.len() {
-        let line = lines[i];
-        let trimmed = line.trim();
-
-        // Skip empty lines and comments
-        if trimmed.is_empty() || trimmed.starts_with('#') {
-            i += 1;
-            continue;
-        }
-
-        // Check for source/. command
-        if let Some(caps) = SOURCE_CMD.captures(line) {
-            let source_path = caps.get(2).unwrap().as_str().trim();
-            if let Some(p) = expand_source_path(source_path) {
-                results.push(ParsedFunctionEntry::Source(p));
-            }
-            i += 1;
-            continue;
-        }
+    let script = Script::parse(content);
+    let mut collector = Collector {
+        script: &script,
+        path,
+        target_func,
+        conditions: Vec::new(),
+        results: Vec::new(),
+    };
+    collector.collect(&script.statements);
+    collector.results
+}
 
-        // Check for unset -f
-        if unset_func.is_match(line) {
-            results.push(ParsedFunctionEntry::Definition(FunctionChange {
-                file: path.to_path_buf(),
-                line_number: i + 1,
-                line_content: line.to_string(),
-                operation: FunctionOperation::Unset,
-                body: None,
-                body_lines: 0,
-            }));
-            i += 1;
-            continue;
-        }
+/// Collects function entries from statements, with the conditions of the
+/// enclosing branches and `&&`/`||` guards
+struct Collector<'a> {
+    script: &'a Script,
+    path: &'a Path,
+    target_func: &'a str,
+    conditions: Vec<String>,
+    results: Vec<ParsedFunctionEntry>,
+}
 
-        // Check for autoload
-        if autoload.is_match(line) {
-            results.push(ParsedFunctionEntry::Definition(FunctionChange {
-                file: path.to_path_buf(),
-                line_number: i + 1,
-                line_content: line.to_string(),
-                operation: FunctionOperation::Autoload,
-                body: None,
-                body_lines: 0,
-            }));
-            i += 1;
-            continue;
+impl Collector<'_> {
+    fn collect(&mut self, statements: &[Statement]) {
+        for statement in statements {
+            let commands = &statement.commands;
+            for (i, (connector, command)) in commands.iter().enumerate() {
+                let depth = self.conditions.len();
+                if matches!(connector, Connector::And | Connector::Or) {
+                    let previous = commands[0].1.span().to(commands[i - 1].1.span());
+                    let text = self.script.text(previous);
+                    self.conditions.push(match connector {
+                        Connector::And => text.to_string(),
+                        _ if i > 1 => format!("! ({})", text),
+                        _ => format!("! {}", text),
+                    });
+                }
+                self.command(command, statement);
+                self.conditions.truncate(depth);
+            }
         }
+    }
 
-        // Check for function definitions (hybrid first, then bash, then POSIX)
-        if hybrid_func.is_match(line) || bash_func.is_match(line) || posix_func.is_match(line) {
-            let (body, body_lines) = extract_function_body(&lines, i);
-            results.push(ParsedFunctionEntry::Definition(FunctionChange {
-                file: path.to_path_buf(),
-                line_number: i + 1,
-                line_content: line.to_string(),
-                operation: FunctionOperation::Define,
-                body: Some(body),
-                body_lines,
-            }));
-            // Skip past the function body
-            i += body_lines.max(1);
-            continue;
+    fn command(&mut self, command: &Command, statement: &Statement) {
+        let script = self.script;
+        match command {
+            Command::Simple(cmd) => {
+                if let Some(args) = source_arguments(&cmd.words) {
+                    if let Some(path) = args.first().and_then(|w| source_path(w, self.path)) {
+                        let guard = Guard {
+                            conditions: self.conditions.clone(),
+                            undecided: Vec::new(),
+                        };
+                        self.results
+                            .push(ParsedFunctionEntry::Source(SourcedFile { path, guard }));
+                    }
+                    return;
+                }
+                let Some(name) = cmd.words.first().and_then(|w| w.as_literal()) else {
+                    return;
+                };
+                let args = &cmd.words[1..];
+                let operation = match name.as_str() {
+                    // unset -f
+                    "unset" if has_flag(args, 'f') => FunctionOperation::Unset,
+                    "autoload" => FunctionOperation::Autoload,
+                    _ => return,
+                };
+                let names_target = args
+                    .iter()
+                    .filter_map(|w| w.as_literal())
+                    .filter(|a| !a.starts_with('-') && !a.starts_with('+'))
+                    .any(|a| a == self.target_func);
+                if names_target {
+                    self.results
+                        .push(ParsedFunctionEntry::Definition(FunctionChange {
+                            file: self.path.to_path_buf(),
+                            line_number: statement.span.line,
+                            line_content: script.text(statement.span).to_string(),
+                            operation,
+                            body: None,
+                            body_lines: 0,
+                            conditions: self.conditions.clone(),
+                        }));
+                }
+            }
+            Command::Function(func) if func.name == self.target_func => {
+                let (body, body_lines) = function_body(script, func);
+                self.results
+                    .push(ParsedFunctionEntry::Definition(FunctionChange {
+                        file: self.path.to_path_buf(),
+                        line_number: func.span.line,
+                        line_content: script.line(func.span.line).trim().to_string(),
+                        operation: FunctionOperation::Define,
+                        body: Some(body),
+                        body_lines,
+                        conditions: self.conditions.clone(),
+                    }));
+            }
+            Command::If(c) => {
+                let mut failed = Vec::new();
+                for branch in &c.branches {
+                    let condition = script.list_text(&branch.condition).to_string();
+                    self.collect_under(&failed, &branch.condition);
+                    let mut conditions = failed.clone();
+                    conditions.push(condition.clone());
+                    self.collect_under(&conditions, &branch.body);
+                    failed.push(format!("! {}", condition));
+                }
+                if let Some(body) = &c.else_body {
+                    self.collect_under(&failed, body);
+                }
+            }
+            Command::Case(c) => {
+                let word = script.text(c.word.span);
+                for arm in &c.arms {
+                    let patterns: Vec<&str> =
+                        arm.patterns.iter().map(|p| script.text(p.span)).collect();
+                    let condition = format!("case {} in {})", word, patterns.join("|"));
+                    self.collect_under(&[condition], &arm.body);
+                }
+            }
+            other => {
+                for body in other.bodies() {
+                    self.collect(body);
+                }
+            }
         }
+    }
 
-        i += 1;
+    /// Collect from statements that only run when `conditions` hold
+    fn collect_under(&mut self, conditions: &[String], statements: &[Statement]) {
+        let depth = self.conditions.len();
+        self.conditions.extend(conditions.iter().cloned());
+        self.collect(statements);
+        self.conditions.truncate(depth);
     }
+}
 
-    results
+/// Returns true if any option argument (e.g. `-f`, `-fv`) contains the flag
+fn has_flag(args: &[Word], flag: char) -> bool {
+    args.iter()
+        .filter_map(|w| w.as_literal())
+        .any(|a| a.starts_with('-') && a[1..].contains(flag))
 }
 
-/// Extract the function body by tracking brace depth
+/// Extract the function body preview from the definition's source lines
 ///
 /// Returns (body_preview, total_lines) where body_preview contains
-/// at most MAX_BODY_PREVIEW_LINES of the body content.
-fn extract_function_body(lines: &[&str], start: usize) -> (String, usize) {
-    let mut depth: i32 = 0;
-    let mut body_lines: Vec<&str> = Vec::new();
-    let mut total_lines: usize = 0;
-
-    for (offset, &line) in lines[start..].iter().enumerate() {
-        // Count braces (simple approach - doesn't handle braces in strings/comments)
-        for ch in line.chars() {
-            match ch {
-                '{' => depth += 1,
-                '}' => depth -= 1,
-                _ => {}
-            }
-        }
-
-        total_lines = offset + 1;
+/// at most MAX_BODY_PREVIEW_LINES of the body content (the lines after the
+/// one with the function name).
+fn function_body(script: &Script, func: &FunctionDef) -> (String, usize) {
+    let start = func.span.line;
+    let end = script.end_line(func.span);
+    let total_lines = end - start + 1;
 
-        // Collect body lines (skip the opening line with the function name)
-        if offset > 0 {
-            body_lines.push(line);
-        }
-
-        // Closing brace at depth 0 means function is complete
-        if depth == 0 && offset > 0 {
-            break;
-        }
-    }
+    let body_lines: Vec<&str> = (start + 1..=end).map(|n| script.line(n)).collect();
 
     // Build preview: first MAX_BODY_PREVIEW_LINES of the body
     let preview = if body_lines.len() <= MAX_BODY_PREVIEW_LINES {
@@ -187,6 +222,8 @@ fn extract_function_body(lines: &[&str], start: usize) -> (String, usize) {
 
 #[cfg(test)]
 mod tests {
+    use std::path::PathBuf;
+
     use super::*;
 
     #[test]
@@ -329,6 +366,34 @@ mod tests {
         assert!(results[1].is_source());
     }
 
+    #[test]
+    fn test_compound_source_forms() {
+        let content = r#"[ -f ~/.bashrc ] && . ~/.bashrc
+test -r /etc/a || source /etc/b
+if [ -f /etc/c ]; then . /etc/c; fi
+builtin source /etc/d
+command . /etc/e
+source /etc/f one two
+command -v . >/dev/null
+"#;
+        let results = parse_shell_content_for_function(content, &PathBuf::from("test"), "my_func");
+        let sourced: Vec<&SourcedFile> = results
+            .iter()
+            .map(|entry| match entry {
+                ParsedFunctionEntry::Source(sourced) => sourced,
+                _ => panic!("Expected Source variant"),
+            })
+            .collect();
+        assert_eq!(sourced.len(), 6);
+        assert_eq!(sourced[0].guard.conditions, vec!["[ -f ~/.bashrc ]"]);
+        assert_eq!(sourced[1].path, PathBuf::from("/etc/b"));
+        assert_eq!(sourced[1].guard.conditions, vec!["! test -r /etc/a"]);
+        assert_eq!(sourced[2].guard.conditions, vec!["[ -f /etc/c ]"]);
+        assert!(sourced[3].guard.conditions.is_empty());
+        assert_eq!(sourced[4].path, PathBuf::from("/etc/e"));
+        assert_eq!(sourced[5].path, PathBuf::from("/etc/f"));
+    }
+
     #[test]
     fn test_multiple_definitions_same_file() {
         let content = "my_func() {\n    echo first\n}\nmy_func() {\n    echo second\n}\n";
@@ -353,4 +418,12 @@ mod tests {
         assert!(body.contains("echo hello"));
         assert!(body.contains("echo world"));
     }
+
+    #[test]
+    fn test_brace_on_next_line() {
+        let content = "my_func()\n{\n    echo \"}\"\n}\necho after\n";
+        let results = parse_shell_content_for_function(content, &PathBuf::from("test"), "my_func");
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].as_definition().body_lines, 4);
+    }
 }
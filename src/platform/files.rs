@@ -13,6 +13,22 @@ pub enum FileType {
     SystemdEnvironmentD,
     /// macOS launchd plist
     Plist,
+    /// fish script (`config.fish`, `conf.d/*.fish`)
+    Fish,
+    /// fish universal variables (`fish_variables`)
+    FishVariables,
+    /// Nushell script (`env.nu`, `config.nu`, autoload scripts)
+    Nushell,
+}
+
+/// Kind of zsh startup file, which decides how zsh finds it and whether
+/// the RCS/GLOBAL_RCS options skip it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ZshStartup {
+    /// A system file (`/etc/zshrc`), skipped once GLOBAL_RCS or RCS is unset
+    Global,
+    /// A user file, read from `$ZDOTDIR` (or HOME) and skipped once RCS is unset
+    User,
 }
 
 /// A configuration file that may contain environment variable definitions
@@ -21,6 +37,8 @@ pub struct ConfigFile {
     pub path: PathBuf,
     pub file_type: FileType,
     pub description: &'static str,
+    /// Set for zsh startup files
+    pub zsh: Option<ZshStartup>,
 }
 
 impl ConfigFile {
@@ -29,6 +47,7 @@ impl ConfigFile {
             path: path.into(),
             file_type,
             description,
+            zsh: None,
         }
     }
 
@@ -36,6 +55,26 @@ impl ConfigFile {
         Self::new(path, FileType::Shell, description)
     }
 
+    /// A zsh startup file; the path of a user file is the one under HOME
+    pub fn zsh(path: impl Into<PathBuf>, kind: ZshStartup, description: &'static str) -> Self {
+        Self {
+            zsh: Some(kind),
+            ..Self::shell(path, description)
+        }
+    }
+
+    pub fn fish(path: impl Into<PathBuf>, description: &'static str) -> Self {
+        Self::new(path, FileType::Fish, description)
+    }
+
+    pub fn fish_variables(path: impl Into<PathBuf>, description: &'static str) -> Self {
+        Self::new(path, FileType::FishVariables, description)
+    }
+
+    pub fn nushell(path: impl Into<PathBuf>, description: &'static str) -> Self {
+        Self::new(path, FileType::Nushell, description)
+    }
+
     pub fn environment(path: impl Into<PathBuf>, description: &'static str) -> Self {
         Self::new(path, FileType::Environment, description)
     }
@@ -0,0 +1,156 @@
+use std::cmp::Ordering;
+use std::path::{Path, PathBuf};
+
+use super::files::ConfigFile;
+
+/// fish's own configuration directory: `$XDG_CONFIG_HOME/fish` or `~/.config/fish`
+pub fn config_dir(home: Option<&Path>) -> Option<PathBuf> {
+    match std::env::var("XDG_CONFIG_HOME") {
+        Ok(dir) if dir.starts_with('/') => Some(PathBuf::from(dir).join("fish")),
+        _ => home.map(|h| h.join(".config/fish")),
+    }
+}
+
+/// `vendor_conf.d` directories under `$XDG_DATA_DIRS` (or its default),
+/// followed by `extra` data directories such as Homebrew's `share`
+pub fn vendor_conf_dirs(extra: &[&str]) -> Vec<PathBuf> {
+    let data_dirs = std::env::var("XDG_DATA_DIRS")
+        .ok()
+        .filter(|dirs| !dirs.is_empty())
+        .unwrap_or_else(|| "/usr/local/share:/usr/share".to_string());
+    let mut dirs: Vec<PathBuf> = data_dirs
+        .split(':')
+        .chain(extra.iter().copied())
+        .filter(|dir| !dir.is_empty())
+        .map(|dir| Path::new(dir).join("fish/vendor_conf.d"))
+        .collect();
+    dirs.dedup();
+    dirs
+}
+
+/// fish startup files in the order fish reads them. Universal variables
+/// are loaded first, then the `conf.d` snippets (a name found in several
+/// directories is only read from the first, user before system before
+/// vendor), then the system and user `config.fish`. fish reads the same
+/// files for every kind of shell; they check `status is-login` themselves.
+pub fn startup_files(
+    sysconf: &Path,
+    vendor_dirs: &[PathBuf],
+    config_dir: Option<&Path>,
+) -> Vec<ConfigFile> {
+    let mut files = Vec::new();
+    if let Some(dir) = config_dir {
+        files.push(ConfigFile::fish_variables(
+            dir.join("fish_variables"),
+            "fish universal variables",
+        ));
+    }
+
+    let mut snippet_dirs: Vec<(PathBuf, &'static str)> = Vec::new();
+    if let Some(dir) = config_dir {
+        snippet_dirs.push((dir.join("conf.d"), "user fish conf.d"));
+    }
+    snippet_dirs.push((sysconf.join("conf.d"), "system fish conf.d"));
+    for dir in vendor_dirs {
+        snippet_dirs.push((dir.clone(), "vendor fish conf.d"));
+    }
+    let mut snippets: Vec<(String, ConfigFile)> = Vec::new();
+    for (dir, description) in snippet_dirs {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !name.ends_with(".fish") || !path.is_file() {
+                continue;
+            }
+            if !snippets.iter().any(|(seen, _)| *seen == name) {
+                snippets.push((name, ConfigFile::fish(path, description)));
+            }
+        }
+    }
+    snippets.sort_by(|(a, _), (b, _)| natural_cmp(a, b));
+    files.extend(snippets.into_iter().map(|(_, file)| file));
+
+    files.push(ConfigFile::fish(
+        sysconf.join("config.fish"),
+        "system config.fish",
+    ));
+    if let Some(dir) = config_dir {
+        files.push(ConfigFile::fish(
+            dir.join("config.fish"),
+            "user config.fish",
+        ));
+    }
+    files
+}
+
+/// Compare names the way fish sorts snippets: runs of digits by their
+/// number, so `2-b.fish` comes before `10-a.fish`
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a, b);
+    loop {
+        let (Some(x), Some(y)) = (a.chars().next(), b.chars().next()) else {
+            return a.len().cmp(&b.len());
+        };
+        if x.is_ascii_digit() && y.is_ascii_digit() {
+            let a_end = a.find(|c: char| !c.is_ascii_digit()).unwrap_or(a.len());
+            let b_end = b.find(|c: char| !c.is_ascii_digit()).unwrap_or(b.len());
+            let (a_num, b_num) = (
+                a[..a_end].trim_start_matches('0'),
+                b[..b_end].trim_start_matches('0'),
+            );
+            let order = a_num.len().cmp(&b_num.len()).then_with(|| a_num.cmp(b_num));
+            if order != Ordering::Equal {
+                return order;
+            }
+            (a, b) = (&a[a_end..], &b[b_end..]);
+        } else {
+            if x != y {
+                return x.cmp(&y);
+            }
+            (a, b) = (&a[x.len_utf8()..], &b[y.len_utf8()..]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_startup_files() {
+        let root = tempfile::tempdir().unwrap();
+        let config = root.path().join("config/fish");
+        let sysconf = root.path().join("etc/fish");
+        let vendor = root.path().join("share/fish/vendor_conf.d");
+        for dir in [
+            config.join("conf.d"),
+            sysconf.join("conf.d"),
+            vendor.clone(),
+        ] {
+            fs::create_dir_all(dir).unwrap();
+        }
+        fs::write(config.join("conf.d/10-path.fish"), "").unwrap();
+        fs::write(config.join("conf.d/notes.txt"), "").unwrap();
+        fs::write(sysconf.join("conf.d/2-system.fish"), "").unwrap();
+        fs::write(vendor.join("10-path.fish"), "").unwrap();
+        fs::write(vendor.join("brew.fish"), "").unwrap();
+
+        let files = startup_files(&sysconf, std::slice::from_ref(&vendor), Some(&config));
+        let paths: Vec<&Path> = files.iter().map(|f| f.path.as_path()).collect();
+        assert_eq!(
+            paths,
+            [
+                config.join("fish_variables").as_path(),
+                &sysconf.join("conf.d/2-system.fish"),
+                &config.join("conf.d/10-path.fish"),
+                &vendor.join("brew.fish"),
+                &sysconf.join("config.fish"),
+                &config.join("config.fish"),
+            ]
+        );
+    }
+}
@@ -1,6 +1,7 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use super::files::ConfigFile;
+use super::{fish, nushell, zsh};
 use crate::trace::Context;
 
 fn home_dir() -> Option<PathBuf> {
@@ -132,6 +133,27 @@ pub fn uwsm_env_files(desktop: &str) -> Vec<ConfigFile> {
     files
 }
 
+/// The system copy of a zsh startup file: Debian and Ubuntu build zsh to read
+/// `/etc/zsh/`, Arch and Fedora read `/etc/` directly
+fn etc_zsh(name: &str) -> PathBuf {
+    let debian = Path::new("/etc/zsh").join(name);
+    if debian.exists() {
+        debian
+    } else {
+        Path::new("/etc").join(name)
+    }
+}
+
+/// PAM environment followed by the zsh startup files
+fn zsh_startup_files(home: Option<&Path>, login: bool, interactive: bool) -> Vec<ConfigFile> {
+    let mut files = vec![ConfigFile::environment(
+        "/etc/environment",
+        "PAM environment",
+    )];
+    files.extend(zsh::startup_files(etc_zsh, home, login, interactive));
+    files
+}
+
 /// Get config files for a specific context on Linux
 pub fn config_files_for_context(context: Context) -> Vec<ConfigFile> {
     let home = home_dir();
@@ -261,6 +283,42 @@ pub fn config_files_for_context(context: Context) -> Vec<ConfigFile> {
             files
         }
 
+        Context::ZshInteractiveLogin => zsh_startup_files(home.as_deref(), true, true),
+        Context::ZshInteractiveNonLogin => zsh_startup_files(home.as_deref(), false, true),
+        Context::ZshNonInteractive => zsh_startup_files(home.as_deref(), false, false),
+
+        Context::FishInteractiveLogin
+        | Context::FishInteractiveNonLogin
+        | Context::FishNonInteractive => {
+            let mut files = vec![ConfigFile::environment(
+                "/etc/environment",
+                "PAM environment",
+            )];
+            files.extend(fish::startup_files(
+                Path::new("/etc/fish"),
+                &fish::vendor_conf_dirs(&[]),
+                fish::config_dir(home.as_deref()).as_deref(),
+            ));
+            files
+        }
+
+        Context::NuInteractiveLogin | Context::NuInteractiveNonLogin => {
+            let mut files = vec![ConfigFile::environment(
+                "/etc/environment",
+                "PAM environment",
+            )];
+            files.extend(nushell::startup_files(
+                nushell::config_dir(home.as_deref(), ".config").as_deref(),
+                &nushell::vendor_autoload_dirs(&[], dirs::data_dir().as_deref()),
+                context == Context::NuInteractiveLogin,
+            ));
+            files
+        }
+        Context::NuNonInteractive => vec![ConfigFile::environment(
+            "/etc/environment",
+            "PAM environment",
+        )],
+
         // macOS contexts on Linux - return empty
         _ => vec![],
     }
@@ -281,6 +339,14 @@ pub fn all_config_files() -> Vec<ConfigFile> {
         files.push(ConfigFile::shell("/etc/bashrc", "system bashrc"));
     }
 
+    // System zsh files, wherever the distro keeps them
+    for name in ["zshenv", "zprofile", "zshrc", "zlogin"] {
+        let path = etc_zsh(name);
+        if path.exists() {
+            files.push(ConfigFile::shell(path, "system zsh startup file"));
+        }
+    }
+
     // /etc/profile.d/*.sh
     if let Ok(entries) = std::fs::read_dir("/etc/profile.d") {
         for entry in entries.flatten() {
@@ -291,6 +357,20 @@ pub fn all_config_files() -> Vec<ConfigFile> {
         }
     }
 
+    // fish universal variables, snippets and config.fish
+    files.extend(fish::startup_files(
+        Path::new("/etc/fish"),
+        &fish::vendor_conf_dirs(&[]),
+        fish::config_dir(home.as_deref()).as_deref(),
+    ));
+
+    // Nushell env.nu, config.nu, autoload scripts and login.nu
+    files.extend(nushell::startup_files(
+        nushell::config_dir(home.as_deref(), ".config").as_deref(),
+        &nushell::vendor_autoload_dirs(&[], dirs::data_dir().as_deref()),
+        true,
+    ));
+
     // System-level environment.d directories
     files.extend(collect_env_d_confs(
         &PathBuf::from("/usr/lib/environment.d"),
@@ -317,6 +397,10 @@ pub fn all_config_files() -> Vec<ConfigFile> {
             h.join(".bash_aliases"),
             "user bash_aliases",
         ));
+        files.push(ConfigFile::shell(h.join(".zshenv"), "user zshenv"));
+        files.push(ConfigFile::shell(h.join(".zprofile"), "user zprofile"));
+        files.push(ConfigFile::shell(h.join(".zshrc"), "user zshrc"));
+        files.push(ConfigFile::shell(h.join(".zlogin"), "user zlogin"));
 
         // User environment.d
         files.extend(collect_env_d_confs(
@@ -1,87 +1,115 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use super::files::ConfigFile;
+use super::{fish, nushell, zsh};
 use crate::trace::Context;
 
 fn home_dir() -> Option<PathBuf> {
     dirs::home_dir()
 }
 
+/// macOS keeps the system zsh files directly in `/etc`
+fn etc(name: &str) -> PathBuf {
+    Path::new("/etc").join(name)
+}
+
+/// Homebrew's fish reads its system files from its own prefix
+const FISH_PREFIXES: &[&str] = &["/opt/homebrew", "/usr/local"];
+
+/// fish's system configuration directory and extra vendor directories
+fn fish_dirs() -> (PathBuf, Vec<PathBuf>) {
+    let sysconf = FISH_PREFIXES
+        .iter()
+        .map(|prefix| Path::new(prefix).join("etc/fish"))
+        .find(|dir| dir.is_dir())
+        .unwrap_or_else(|| PathBuf::from("/etc/fish"));
+    let shares: Vec<String> = FISH_PREFIXES
+        .iter()
+        .map(|prefix| format!("{prefix}/share"))
+        .collect();
+    let shares: Vec<&str> = shares.iter().map(String::as_str).collect();
+    (sysconf, fish::vendor_conf_dirs(&shares))
+}
+
+/// Nushell's configuration directory (`~/Library/Application Support/nushell`
+/// unless XDG_CONFIG_HOME is set) and vendor autoload directories
+fn nushell_dirs(home: Option<&Path>) -> (Option<PathBuf>, Vec<PathBuf>) {
+    let shares: Vec<String> = FISH_PREFIXES
+        .iter()
+        .map(|prefix| format!("{prefix}/share"))
+        .chain(["/Library/Application Support".to_string()])
+        .collect();
+    let shares: Vec<&str> = shares.iter().map(String::as_str).collect();
+    (
+        nushell::config_dir(home, "Library/Application Support"),
+        nushell::vendor_autoload_dirs(&shares, dirs::data_dir().as_deref()),
+    )
+}
+
 /// Get config files for a specific context on macOS
 pub fn config_files_for_context(context: Context) -> Vec<ConfigFile> {
     let home = home_dir();
 
     match context {
-        Context::MacInteractiveLogin => {
-            let mut files = vec![
-                ConfigFile::shell("/etc/zshenv", "system zshenv (all zsh)"),
-                ConfigFile::shell("/etc/zprofile", "system zprofile (login)"),
-            ];
+        Context::MacInteractiveLogin => zsh::startup_files(etc, home.as_deref(), true, true),
+        Context::MacInteractiveNonLogin => zsh::startup_files(etc, home.as_deref(), false, true),
+        Context::MacNonInteractive => zsh::startup_files(etc, home.as_deref(), false, false),
 
-            if let Some(ref h) = home {
-                files.push(ConfigFile::shell(
-                    h.join(".zshenv"),
-                    "user zshenv (all zsh)",
-                ));
-                files.push(ConfigFile::shell(
-                    h.join(".zprofile"),
-                    "user zprofile (login)",
-                ));
-            }
-
-            files.push(ConfigFile::shell(
-                "/etc/zshrc",
-                "system zshrc (interactive)",
-            ));
+        Context::MacBashInteractiveLogin => {
+            // /etc/profile runs path_helper and reads /etc/bashrc
+            let mut files = vec![ConfigFile::shell("/etc/profile", "system profile")];
 
             if let Some(ref h) = home {
-                files.push(ConfigFile::shell(
-                    h.join(".zshrc"),
-                    "user zshrc (interactive)",
-                ));
-                files.push(ConfigFile::shell(h.join(".zlogin"), "user zlogin (login)"));
+                // First found of: ~/.bash_profile, ~/.bash_login, ~/.profile.
+                // ~/.bashrc is only read if one of them sources it.
+                let bash_profile = h.join(".bash_profile");
+                let bash_login = h.join(".bash_login");
+                let profile = h.join(".profile");
+
+                if bash_profile.exists() {
+                    files.push(ConfigFile::shell(bash_profile, "user bash_profile"));
+                } else if bash_login.exists() {
+                    files.push(ConfigFile::shell(bash_login, "user bash_login"));
+                } else if profile.exists() {
+                    files.push(ConfigFile::shell(profile, "user profile"));
+                }
             }
 
             files
         }
 
-        Context::MacInteractiveNonLogin => {
-            let mut files = vec![ConfigFile::shell("/etc/zshenv", "system zshenv (all zsh)")];
-
-            if let Some(ref h) = home {
-                files.push(ConfigFile::shell(
-                    h.join(".zshenv"),
-                    "user zshenv (all zsh)",
-                ));
-            }
-
-            files.push(ConfigFile::shell(
-                "/etc/zshrc",
-                "system zshrc (interactive)",
-            ));
+        Context::MacBashInteractiveNonLogin => {
+            // The system bash reads /etc/bashrc; Homebrew's bash skips it
+            let mut files = vec![ConfigFile::shell("/etc/bashrc", "system bashrc")];
 
             if let Some(ref h) = home {
-                files.push(ConfigFile::shell(
-                    h.join(".zshrc"),
-                    "user zshrc (interactive)",
-                ));
+                files.push(ConfigFile::shell(h.join(".bashrc"), "user bashrc"));
             }
 
             files
         }
 
-        Context::MacNonInteractive => {
-            let mut files = vec![ConfigFile::shell("/etc/zshenv", "system zshenv (all zsh)")];
+        // Only $BASH_ENV, which is rarely set
+        Context::MacBashNonInteractive => Vec::new(),
 
-            if let Some(ref h) = home {
-                files.push(ConfigFile::shell(
-                    h.join(".zshenv"),
-                    "user zshenv (all zsh)",
-                ));
-            }
+        Context::FishInteractiveLogin
+        | Context::FishInteractiveNonLogin
+        | Context::FishNonInteractive => {
+            let (sysconf, vendor_dirs) = fish_dirs();
+            let config_dir = fish::config_dir(home.as_deref());
+            fish::startup_files(&sysconf, &vendor_dirs, config_dir.as_deref())
+        }
 
-            files
+        Context::NuInteractiveLogin | Context::NuInteractiveNonLogin => {
+            let (config_dir, vendor_dirs) = nushell_dirs(home.as_deref());
+            nushell::startup_files(
+                config_dir.as_deref(),
+                &vendor_dirs,
+                context == Context::NuInteractiveLogin,
+            )
         }
+        // Scripts and `nu -c` read no startup files
+        Context::NuNonInteractive => Vec::new(),
 
         Context::LaunchdAgent | Context::LaunchdDaemon => {
             // launchd doesn't source shell files - only plist files
@@ -148,9 +176,27 @@ pub fn all_config_files() -> Vec<ConfigFile> {
             h.join(".bash_profile"),
             "user bash_profile",
         ));
+        files.push(ConfigFile::shell(h.join(".bash_login"), "user bash_login"));
         files.push(ConfigFile::shell(h.join(".bashrc"), "user bashrc"));
         files.push(ConfigFile::shell(h.join(".profile"), "user profile"));
 
+        // fish universal variables, snippets and config.fish
+        let (sysconf, vendor_dirs) = fish_dirs();
+        let config_dir = fish::config_dir(Some(h));
+        files.extend(fish::startup_files(
+            &sysconf,
+            &vendor_dirs,
+            config_dir.as_deref(),
+        ));
+
+        // Nushell env.nu, config.nu, autoload scripts and login.nu
+        let (config_dir, vendor_dirs) = nushell_dirs(Some(h));
+        files.extend(nushell::startup_files(
+            config_dir.as_deref(),
+            &vendor_dirs,
+            true,
+        ));
+
         // LaunchAgents
         let user_agents = h.join("Library/LaunchAgents");
         if user_agents.exists()
@@ -1,11 +1,17 @@
 mod files;
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+mod fish;
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+mod nushell;
 
 #[cfg(target_os = "linux")]
 mod linux;
 #[cfg(target_os = "macos")]
 mod macos;
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+mod zsh;
 
-pub use files::{ConfigFile, FileType};
+pub use files::{ConfigFile, FileType, ZshStartup};
 
 /// Detected platform
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -49,6 +55,48 @@ impl Platform {
     }
 }
 
+/// Login shell whose startup files are traced
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+    Nu,
+}
+
+impl Shell {
+    /// Detect the user's shell from `$SHELL`, falling back to the platform's default
+    pub fn detect(platform: Platform) -> Self {
+        let shell = std::env::var("SHELL").unwrap_or_default();
+        match shell.rsplit('/').next() {
+            Some("zsh") => Shell::Zsh,
+            Some("bash") => Shell::Bash,
+            Some("fish") => Shell::Fish,
+            Some("nu") => Shell::Nu,
+            _ => Self::default_for(platform),
+        }
+    }
+
+    /// The shell new accounts get on a platform
+    pub fn default_for(platform: Platform) -> Self {
+        match platform {
+            Platform::MacOS => Shell::Zsh,
+            Platform::Linux => Shell::Bash,
+        }
+    }
+}
+
+impl std::fmt::Display for Shell {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Shell::Bash => write!(f, "bash"),
+            Shell::Zsh => write!(f, "zsh"),
+            Shell::Fish => write!(f, "fish"),
+            Shell::Nu => write!(f, "nu"),
+        }
+    }
+}
+
 impl std::fmt::Display for Platform {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -0,0 +1,124 @@
+use std::path::{Path, PathBuf};
+
+use super::files::ConfigFile;
+
+/// Nushell's `$nu.default-config-dir`: `$XDG_CONFIG_HOME/nushell`, or
+/// `nushell` under the platform's configuration directory (`base`, relative
+/// to HOME)
+pub fn config_dir(home: Option<&Path>, base: &str) -> Option<PathBuf> {
+    match std::env::var("XDG_CONFIG_HOME") {
+        Ok(dir) if dir.starts_with('/') => Some(PathBuf::from(dir).join("nushell")),
+        _ => home.map(|h| h.join(base).join("nushell")),
+    }
+}
+
+/// `$nu.vendor-autoload-dirs`: `$NU_VENDOR_AUTOLOAD_DIR`, `nushell/vendor/autoload`
+/// under each of `$XDG_DATA_DIRS` (or its default) and `extra`, then the
+/// user's data directory (`data_dir`)
+pub fn vendor_autoload_dirs(extra: &[&str], data_dir: Option<&Path>) -> Vec<PathBuf> {
+    let data_dirs = std::env::var("XDG_DATA_DIRS")
+        .ok()
+        .filter(|dirs| !dirs.is_empty())
+        .unwrap_or_else(|| "/usr/local/share:/usr/share".to_string());
+    let mut dirs: Vec<PathBuf> = std::env::var("NU_VENDOR_AUTOLOAD_DIR")
+        .ok()
+        .filter(|dir| dir.starts_with('/'))
+        .map(PathBuf::from)
+        .into_iter()
+        .collect();
+    dirs.extend(
+        data_dirs
+            .split(':')
+            .chain(extra.iter().copied())
+            .filter(|dir| !dir.is_empty())
+            .map(|dir| Path::new(dir).join("nushell/vendor/autoload")),
+    );
+    if let Some(dir) = data_dir {
+        dirs.push(dir.join("nushell/vendor/autoload"));
+    }
+    dirs.dedup();
+    dirs
+}
+
+/// Nushell startup files in the order an interactive shell reads them:
+/// `env.nu`, `config.nu`, the vendor and then the user autoload scripts
+/// (sorted by name within each directory) and, for login shells,
+/// `login.nu`. Scripts and `nu -c` read none of them.
+pub fn startup_files(
+    config_dir: Option<&Path>,
+    vendor_dirs: &[PathBuf],
+    login: bool,
+) -> Vec<ConfigFile> {
+    let mut files = Vec::new();
+    if let Some(dir) = config_dir {
+        files.push(ConfigFile::nushell(dir.join("env.nu"), "nushell env.nu"));
+        files.push(ConfigFile::nushell(
+            dir.join("config.nu"),
+            "nushell config.nu",
+        ));
+    }
+    let mut autoload_dirs: Vec<(PathBuf, &'static str)> = vendor_dirs
+        .iter()
+        .map(|dir| (dir.clone(), "nushell vendor autoload"))
+        .collect();
+    if let Some(dir) = config_dir {
+        autoload_dirs.push((dir.join("autoload"), "nushell user autoload"));
+    }
+    for (dir, description) in autoload_dirs {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        let mut scripts: Vec<PathBuf> = entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|e| e == "nu") && path.is_file())
+            .collect();
+        scripts.sort();
+        files.extend(
+            scripts
+                .into_iter()
+                .map(|path| ConfigFile::nushell(path, description)),
+        );
+    }
+    if login && let Some(dir) = config_dir {
+        files.push(ConfigFile::nushell(
+            dir.join("login.nu"),
+            "nushell login.nu",
+        ));
+    }
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_startup_files() {
+        let root = tempfile::tempdir().unwrap();
+        let config = root.path().join("config/nushell");
+        let vendor = root.path().join("share/nushell/vendor/autoload");
+        fs::create_dir_all(config.join("autoload")).unwrap();
+        fs::create_dir_all(&vendor).unwrap();
+        fs::write(config.join("autoload/b.nu"), "").unwrap();
+        fs::write(config.join("autoload/a.nu"), "").unwrap();
+        fs::write(config.join("autoload/notes.txt"), "").unwrap();
+        fs::write(vendor.join("starship.nu"), "").unwrap();
+
+        let files = startup_files(Some(&config), std::slice::from_ref(&vendor), true);
+        let paths: Vec<&Path> = files.iter().map(|f| f.path.as_path()).collect();
+        assert_eq!(
+            paths,
+            [
+                config.join("env.nu").as_path(),
+                &config.join("config.nu"),
+                &vendor.join("starship.nu"),
+                &config.join("autoload/a.nu"),
+                &config.join("autoload/b.nu"),
+                &config.join("login.nu"),
+            ]
+        );
+        assert_eq!(startup_files(Some(&config), &[], false).len(), 4);
+    }
+}
@@ -0,0 +1,99 @@
+use std::path::{Path, PathBuf};
+
+use super::files::{ConfigFile, ZshStartup};
+
+/// zsh startup files in the order zsh reads them: zshenv always, zprofile
+/// for login shells, zshrc for interactive shells, then zlogin for login
+/// shells, each system file (located by `etc`) before the user's
+pub fn startup_files(
+    etc: impl Fn(&str) -> PathBuf,
+    home: Option<&Path>,
+    login: bool,
+    interactive: bool,
+) -> Vec<ConfigFile> {
+    let steps = [
+        (
+            "zshenv",
+            true,
+            "system zshenv (all zsh)",
+            "user zshenv (all zsh)",
+        ),
+        (
+            "zprofile",
+            login,
+            "system zprofile (login)",
+            "user zprofile (login)",
+        ),
+        (
+            "zshrc",
+            interactive,
+            "system zshrc (interactive)",
+            "user zshrc (interactive)",
+        ),
+        (
+            "zlogin",
+            login,
+            "system zlogin (login)",
+            "user zlogin (login)",
+        ),
+    ];
+
+    let mut files = Vec::new();
+    for (name, read, system, user) in steps {
+        if !read {
+            continue;
+        }
+        files.push(ConfigFile::zsh(etc(name), ZshStartup::Global, system));
+        if let Some(h) = home {
+            files.push(ConfigFile::zsh(
+                h.join(format!(".{name}")),
+                ZshStartup::User,
+                user,
+            ));
+        }
+    }
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_startup_order() {
+        let etc = |name: &str| Path::new("/etc").join(name);
+        let home = Path::new("/home/user");
+        let names = |files: Vec<ConfigFile>| -> Vec<String> {
+            files
+                .iter()
+                .map(|f| f.path.file_name().unwrap().to_string_lossy().to_string())
+                .collect()
+        };
+
+        assert_eq!(
+            names(startup_files(etc, Some(home), true, true)),
+            [
+                "zshenv",
+                ".zshenv",
+                "zprofile",
+                ".zprofile",
+                "zshrc",
+                ".zshrc",
+                "zlogin",
+                ".zlogin"
+            ]
+        );
+        assert_eq!(
+            names(startup_files(etc, Some(home), false, true)),
+            ["zshenv", ".zshenv", "zshrc", ".zshrc"]
+        );
+        assert_eq!(
+            names(startup_files(etc, Some(home), false, false)),
+            ["zshenv", ".zshenv"]
+        );
+
+        let files = startup_files(etc, Some(home), false, false);
+        assert_eq!(files[0].zsh, Some(ZshStartup::Global));
+        assert_eq!(files[1].zsh, Some(ZshStartup::User));
+    }
+}
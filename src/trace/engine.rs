@@ -6,12 +6,16 @@ use std::path::PathBuf;
 
 use crate::files::discover_files;
 use crate::parser::{
-    ParsedFunctionEntry, ParsedShellEntry, parse_environment_file, parse_plist_file,
-    parse_shell_file, parse_shell_file_for_function,
+    Guard, ParsedFunctionEntry, ParsedShellEntry, parse_environment_file, parse_fish_file,
+    parse_fish_variables, parse_nushell_file, parse_plist_file, parse_shell_file,
+    parse_shell_file_for_function,
 };
-use crate::platform::{ConfigFile, FileType, Platform};
+use crate::platform::{ConfigFile, FileType, Platform, ZshStartup};
 use crate::trace::function::{FunctionChange, FunctionOperation, FunctionTrace};
-use crate::trace::{Context, Operation, VariableChange, VariableTrace};
+use crate::trace::{
+    Context, Diagnostic, EvalPolicy, HostProfile, Operation, ShellEnv, StopPoint, ValueCandidate,
+    VariableChange, VariableTrace,
+};
 
 /// Configuration for the tracing engine
 pub struct TraceConfig {
@@ -19,6 +23,10 @@ pub struct TraceConfig {
     pub follow_sources: bool,
     /// Include verbose information about skipped files
     pub verbose: bool,
+    /// Machine to simulate while evaluating conditions and expansions
+    pub host: HostProfile,
+    /// Run command substitutions in assignments under this policy
+    pub eval: Option<EvalPolicy>,
 }
 
 impl Default for TraceConfig {
@@ -26,6 +34,8 @@ impl Default for TraceConfig {
         Self {
             follow_sources: true,
             verbose: false,
+            host: HostProfile::default(),
+            eval: None,
         }
     }
 }
@@ -36,6 +46,10 @@ pub struct TraceEngine {
     config: TraceConfig,
     /// Track sourced files to prevent infinite loops
     sourced_files: HashSet<PathBuf>,
+    /// `return`/`exit`/`exec` statements reached while tracing
+    stops: Vec<StopPoint>,
+    /// Lines that couldn't be followed while tracing
+    diagnostics: Vec<Diagnostic>,
 }
 
 impl TraceEngine {
@@ -44,6 +58,8 @@ impl TraceEngine {
             platform,
             config: TraceConfig::default(),
             sourced_files: HashSet::new(),
+            stops: Vec::new(),
+            diagnostics: Vec::new(),
         }
     }
 
@@ -52,40 +68,125 @@ impl TraceEngine {
         self
     }
 
-    /// Trace a variable through the startup sequence for a given context
-    pub fn trace(&mut self, var_name: &str, context: Context) -> VariableTrace {
-        self.sourced_files.clear();
+    /// Apply the simulated host and evaluation policy to a starting
+    /// environment, running the command substitutions in `sources`
+    fn configure(&self, env: ShellEnv, sources: Option<HashSet<String>>) -> ShellEnv {
+        let env = env.with_host(self.config.host.clone());
+        match (&self.config.eval, sources) {
+            (Some(policy), Some(sources)) => env.with_eval(policy.clone(), sources),
+            _ => env,
+        }
+    }
 
-        let files = discover_files(self.platform, context);
-        let mut changes: Vec<VariableChange> = Vec::new();
-        let mut current_value: Option<String> = None;
+    /// The command substitutions whose output reaches `var_name`, found by
+    /// tracing it once without running any: those left unresolved in its
+    /// changes. None if commands don't run at all.
+    fn reaching_commands(
+        &mut self,
+        run: impl Fn(&mut Self, Option<HashSet<String>>) -> Vec<VariableChange>,
+    ) -> Option<HashSet<String>> {
+        self.config.eval.as_ref()?;
+        let changes = run(self, None);
+        Some(
+            changes
+                .into_iter()
+                .flat_map(|change| change.unresolved)
+                .collect(),
+        )
+    }
 
-        // Get the current environment value as a starting point reference
-        let env_value = env::var(var_name).ok();
+    /// Trace a variable through the startup sequence for a given context
+    pub fn trace(&mut self, var_name: &str, context: Context) -> VariableTrace {
+        let sources = self
+            .reaching_commands(|engine, sources| engine.trace_files(var_name, context, sources).1);
+        let (shell_env, changes) = self.trace_files(var_name, context, sources);
 
-        for config_file in files {
-            self.process_file(&config_file, var_name, &mut current_value, &mut changes);
-        }
+        let final_value = final_candidates(&shell_env, var_name);
+        let exported = if final_value.is_empty() {
+            None
+        } else {
+            shell_env.is_exported(var_name)
+        };
 
         VariableTrace {
             name: var_name.to_string(),
-            final_value: current_value.or(env_value),
+            final_value,
+            truncated: shell_env.is_truncated(var_name),
             changes,
             context,
+            stops: std::mem::take(&mut self.stops),
+            diagnostics: std::mem::take(&mut self.diagnostics),
+            exported,
+            simulated: (!self.config.host.is_empty()).then(|| self.config.host.clone()),
         }
     }
 
+    /// Walk the startup files of a context, returning the environment at
+    /// the end and the changes to the variable
+    fn trace_files(
+        &mut self,
+        var_name: &str,
+        context: Context,
+        sources: Option<HashSet<String>>,
+    ) -> (ShellEnv, Vec<VariableChange>) {
+        self.sourced_files.clear();
+        self.stops.clear();
+        self.diagnostics.clear();
+
+        let files = discover_files(self.platform, context);
+        let mut changes: Vec<VariableChange> = Vec::new();
+        let mut shell_env = self.configure(ShellEnv::for_context(context), sources);
+        inherit_environment(&mut shell_env, var_name, |name| env::var(name).ok());
+
+        for config_file in files {
+            // exit/exec end the shell, so later startup files are never read
+            if shell_env.has_exited() {
+                break;
+            }
+            let Some(config_file) = zsh_startup_file(config_file, &shell_env) else {
+                continue;
+            };
+            self.process_file(
+                &config_file,
+                var_name,
+                &Guard::default(),
+                &mut shell_env,
+                &mut changes,
+            );
+        }
+        shell_env.finish();
+        (shell_env, changes)
+    }
+
     /// Find all definitions of a variable across all config files
     pub fn find_all(&mut self, var_name: &str) -> Vec<VariableChange> {
+        let sources =
+            self.reaching_commands(|engine, sources| engine.find_all_with(var_name, sources));
+        self.find_all_with(var_name, sources)
+    }
+
+    fn find_all_with(
+        &mut self,
+        var_name: &str,
+        sources: Option<HashSet<String>>,
+    ) -> Vec<VariableChange> {
         self.sourced_files.clear();
 
         let files = self.platform.all_config_files();
         let mut changes: Vec<VariableChange> = Vec::new();
-        let mut current_value: Option<String> = None;
+        let mut shell_env = self.configure(ShellEnv::new().with_session(), sources);
 
         for config_file in files {
             if config_file.path.exists() {
-                self.process_file(&config_file, var_name, &mut current_value, &mut changes);
+                self.process_file(
+                    &config_file,
+                    var_name,
+                    &Guard::default(),
+                    &mut shell_env,
+                    &mut changes,
+                );
+                // Every file is searched, even after one that exits the shell
+                shell_env.finish();
             }
         }
 
@@ -100,7 +201,9 @@ impl TraceEngine {
         let mut changes: Vec<FunctionChange> = Vec::new();
 
         for config_file in files {
-            self.process_file_for_function(&config_file, func_name, &mut changes);
+            if config_file.path.exists() {
+                self.process_file_for_function(&config_file, func_name, &[], &mut changes);
+            }
         }
 
         // Determine if function is defined based on the last change
@@ -130,17 +233,19 @@ impl TraceEngine {
 
         for config_file in files {
             if config_file.path.exists() {
-                self.process_file_for_function(&config_file, func_name, &mut changes);
+                self.process_file_for_function(&config_file, func_name, &[], &mut changes);
             }
         }
 
         changes
     }
 
+    /// `conditions` are the guards of the `source` commands that led to the file
     fn process_file_for_function(
         &mut self,
         config_file: &ConfigFile,
         func_name: &str,
+        conditions: &[String],
         changes: &mut Vec<FunctionChange>,
     ) {
         // Only shell files can contain function definitions
@@ -162,13 +267,21 @@ impl TraceEngine {
             Ok(entries) => {
                 for entry in entries {
                     match entry {
-                        ParsedFunctionEntry::Source(source_path) => {
+                        ParsedFunctionEntry::Source(sourced) => {
                             if self.config.follow_sources {
-                                let source_file = ConfigFile::shell(source_path, "sourced file");
-                                self.process_file_for_function(&source_file, func_name, changes);
+                                let source_file = ConfigFile::shell(sourced.path, "sourced file");
+                                let mut conditions = conditions.to_vec();
+                                conditions.extend(sourced.guard.conditions);
+                                self.process_file_for_function(
+                                    &source_file,
+                                    func_name,
+                                    &conditions,
+                                    changes,
+                                );
                             }
                         }
-                        ParsedFunctionEntry::Definition(change) => {
+                        ParsedFunctionEntry::Definition(mut change) => {
+                            change.conditions.splice(0..0, conditions.iter().cloned());
                             changes.push(change);
                         }
                     }
@@ -214,7 +327,8 @@ impl TraceEngine {
         &mut self,
         config_file: &ConfigFile,
         var_name: &str,
-        current_value: &mut Option<String>,
+        guard: &Guard,
+        shell_env: &mut ShellEnv,
         changes: &mut Vec<VariableChange>,
     ) {
         // Prevent infinite loops from circular sources
@@ -229,28 +343,52 @@ impl TraceEngine {
 
         let result = match config_file.file_type {
             FileType::Environment => parse_environment_file(&config_file.path, var_name),
-            FileType::Shell => parse_shell_file(
-                &config_file.path,
-                var_name,
-                current_value.as_deref(),
-            )
-            .map(|entries| {
-                let mut file_changes = Vec::new();
-                for entry in entries {
-                    match entry {
-                        ParsedShellEntry::Source(source_path) => {
-                            if self.config.follow_sources {
-                                let source_file = ConfigFile::shell(source_path, "sourced file");
-                                self.process_file(&source_file, var_name, current_value, changes);
-                            }
+            // Shell entries are handled as they are reached, so sourced files
+            // update the environment before the rest of the file is read
+            FileType::Shell | FileType::Fish | FileType::FishVariables | FileType::Nushell => {
+                // Sourced files are in the same language as the script
+                let file_type = match config_file.file_type {
+                    FileType::FishVariables => FileType::Fish,
+                    file_type => file_type,
+                };
+                let mut on_entry = |entry, shell_env: &mut ShellEnv| match entry {
+                    ParsedShellEntry::Source(sourced) => {
+                        if self.config.follow_sources {
+                            let source_file =
+                                ConfigFile::new(sourced.path, file_type, "sourced file");
+                            self.process_file(
+                                &source_file,
+                                var_name,
+                                &sourced.guard,
+                                shell_env,
+                                changes,
+                            );
                         }
-                        ParsedShellEntry::Assignment(change) => {
-                            file_changes.push(change);
+                    }
+                    ParsedShellEntry::Assignment(change) => changes.push(change),
+                    ParsedShellEntry::Stop(stop) => self.stops.push(stop),
+                    // A line run several times (in a function, a loop) is reported once
+                    ParsedShellEntry::Diagnostic(diagnostic) => {
+                        if !self.diagnostics.contains(&diagnostic) {
+                            self.diagnostics.push(diagnostic);
                         }
                     }
+                };
+                let path = &config_file.path;
+                match config_file.file_type {
+                    FileType::FishVariables => {
+                        parse_fish_variables(path, var_name, shell_env, &mut on_entry)
+                    }
+                    FileType::Fish => {
+                        parse_fish_file(path, var_name, shell_env, guard, &mut on_entry)
+                    }
+                    FileType::Nushell => {
+                        parse_nushell_file(path, var_name, shell_env, guard, &mut on_entry)
+                    }
+                    _ => parse_shell_file(path, var_name, shell_env, guard, &mut on_entry),
                 }
-                file_changes
-            }),
+                .map(|()| Vec::new())
+            }
             FileType::Plist => parse_plist_file(&config_file.path, var_name),
             FileType::SystemdEnvironmentD => parse_environment_file(&config_file.path, var_name),
             FileType::SystemdUnit => {
@@ -263,15 +401,16 @@ impl TraceEngine {
             Ok(file_changes) => {
                 for mut change in file_changes {
                     // Update value_before with the current tracked value
-                    change.value_before = current_value.clone();
+                    change.value_before = shell_env.get(var_name).map(|v| v.to_string());
 
                     // Update the current value based on the operation
+                    // environment.d, /etc/environment and launchd set
+                    // the environment itself
                     match change.operation {
-                        Operation::Unset => {
-                            *current_value = None;
-                        }
+                        Operation::Unset => shell_env.unset(var_name),
                         _ => {
-                            *current_value = Some(change.value_after.clone());
+                            shell_env.set(var_name, change.value_after.clone());
+                            shell_env.set_exported(var_name, true);
                         }
                     }
 
@@ -291,6 +430,63 @@ impl TraceEngine {
     }
 }
 
+/// Take what the shell inherits from the environment envtrace runs in: the
+/// traced variable's value, kept unless the files change it, and ZDOTDIR,
+/// which moves zsh's user files from the start
+fn inherit_environment(env: &mut ShellEnv, var_name: &str, var: impl Fn(&str) -> Option<String>) {
+    if let Some(value) = var(var_name) {
+        env.inherit(var_name, value);
+    }
+    if let Some(dir) = var("ZDOTDIR").filter(|dir| !dir.is_empty()) {
+        env.set("ZDOTDIR", dir);
+        env.set_exported("ZDOTDIR", true);
+    }
+}
+
+/// Where zsh reads a startup file given what's been traced so far, or None
+/// if it isn't read: user files move to `$ZDOTDIR` once it's set, and
+/// unsetting RCS (or GLOBAL_RCS, for system files) skips the rest
+fn zsh_startup_file(config_file: ConfigFile, env: &ShellEnv) -> Option<ConfigFile> {
+    let rcs = env.option("rcs") != Some(false);
+    let config_file = match config_file.zsh {
+        None => return Some(config_file),
+        Some(ZshStartup::Global) if rcs && env.option("globalrcs") != Some(false) => config_file,
+        Some(ZshStartup::User) if rcs => match env.get("ZDOTDIR") {
+            Some(dir)
+                if !dir.is_empty()
+                    && !env.is_ambiguous("ZDOTDIR")
+                    && env.unresolved("ZDOTDIR").is_empty() =>
+            {
+                let name = config_file.path.file_name()?;
+                ConfigFile {
+                    path: PathBuf::from(dir).join(name),
+                    ..config_file
+                }
+            }
+            _ => config_file,
+        },
+        Some(_) => return None,
+    };
+    config_file.path.exists().then_some(config_file)
+}
+
+/// The possible final values of a variable. Paths on which the startup
+/// files never assign or unset it keep the inherited value.
+fn final_candidates(shell_env: &ShellEnv, var_name: &str) -> Vec<ValueCandidate> {
+    let mut candidates: Vec<ValueCandidate> = Vec::new();
+    for candidate in shell_env.candidates(var_name) {
+        if !candidates.contains(&candidate) {
+            candidates.push(candidate);
+        }
+    }
+    if let [only] = candidates.as_slice()
+        && only.value.is_none()
+    {
+        candidates.clear();
+    }
+    candidates
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -313,7 +509,7 @@ mod tests {
         let mut engine = TraceEngine::new(Platform::detect());
         let mut changes = Vec::new();
 
-        engine.process_file_for_function(&config, "my_func", &mut changes);
+        engine.process_file_for_function(&config, "my_func", &[], &mut changes);
 
         assert_eq!(changes.len(), 1);
         assert_eq!(changes[0].operation, FunctionOperation::Define);
@@ -331,8 +527,8 @@ mod tests {
 
         let config1 = ConfigFile::shell(file1, "file1");
         let config2 = ConfigFile::shell(file2, "file2");
-        engine.process_file_for_function(&config1, "my_func", &mut changes);
-        engine.process_file_for_function(&config2, "my_func", &mut changes);
+        engine.process_file_for_function(&config1, "my_func", &[], &mut changes);
+        engine.process_file_for_function(&config2, "my_func", &[], &mut changes);
 
         assert_eq!(changes.len(), 2);
         assert_eq!(changes[0].operation, FunctionOperation::Define);
@@ -352,13 +548,52 @@ mod tests {
         let mut changes = Vec::new();
 
         let config = ConfigFile::shell(file, "test");
-        engine.process_file_for_function(&config, "my_func", &mut changes);
+        engine.process_file_for_function(&config, "my_func", &[], &mut changes);
 
         assert_eq!(changes.len(), 2);
         assert_eq!(changes[0].operation, FunctionOperation::Define);
         assert_eq!(changes[1].operation, FunctionOperation::Unset);
     }
 
+    #[test]
+    fn test_zsh_startup_file() {
+        let dir = TempDir::new().unwrap();
+        let home = dir.path().join("home");
+        let zdotdir = dir.path().join("zsh");
+        std::fs::create_dir_all(&home).unwrap();
+        std::fs::create_dir_all(&zdotdir).unwrap();
+        std::fs::write(home.join(".zshrc"), "").unwrap();
+        std::fs::write(zdotdir.join(".zshrc"), "").unwrap();
+        let global = ConfigFile::zsh(home.join(".zshrc"), ZshStartup::Global, "system");
+        let user = ConfigFile::zsh(home.join(".zshrc"), ZshStartup::User, "user");
+        let missing = ConfigFile::zsh(home.join(".zlogin"), ZshStartup::User, "user");
+
+        let mut env = ShellEnv::new();
+        let path =
+            |file: &ConfigFile, env: &ShellEnv| zsh_startup_file(file.clone(), env).map(|f| f.path);
+        assert_eq!(path(&user, &env), Some(home.join(".zshrc")));
+        assert_eq!(path(&missing, &env), None);
+
+        env.set("ZDOTDIR", zdotdir.to_string_lossy());
+        assert_eq!(path(&user, &env), Some(zdotdir.join(".zshrc")));
+
+        env.set_option("globalrcs", false);
+        assert_eq!(path(&global, &env), None);
+        assert!(path(&user, &env).is_some());
+
+        env.set_option("rcs", false);
+        assert_eq!(path(&user, &env), None);
+
+        // A ZDOTDIR exported before zsh starts applies to the first file
+        let mut env = ShellEnv::new();
+        let zdotdir = zdotdir.to_string_lossy().to_string();
+        inherit_environment(&mut env, "PATH", |name| {
+            (name == "ZDOTDIR").then(|| zdotdir.clone())
+        });
+        assert_eq!(path(&user, &env), Some(dir.path().join("zsh/.zshrc")));
+        assert_eq!(env.is_exported("ZDOTDIR"), Some(true));
+    }
+
     #[test]
     fn test_trace_simple_export() {
         let dir = TempDir::new().unwrap();
@@ -368,14 +603,20 @@ mod tests {
         let config = ConfigFile::shell(bashrc, "test bashrc");
 
         let mut engine = TraceEngine::new(Platform::detect());
-        let mut current_value = None;
+        let mut shell_env = ShellEnv::new();
         let mut changes = Vec::new();
 
-        engine.process_file(&config, "TEST_VAR", &mut current_value, &mut changes);
+        engine.process_file(
+            &config,
+            "TEST_VAR",
+            &Guard::default(),
+            &mut shell_env,
+            &mut changes,
+        );
 
         assert_eq!(changes.len(), 1);
         assert_eq!(changes[0].value_after, "hello");
-        assert_eq!(current_value, Some("hello".to_string()));
+        assert_eq!(shell_env.get("TEST_VAR"), Some("hello"));
     }
 
     #[test]
@@ -386,16 +627,93 @@ mod tests {
         let config = ConfigFile::shell(bashrc, "test bashrc");
 
         let mut engine = TraceEngine::new(Platform::detect());
-        let mut current_value = Some("/usr/bin".to_string());
+        let mut shell_env = ShellEnv::new();
+        shell_env.set("PATH", "/usr/bin");
         let mut changes = Vec::new();
 
-        engine.process_file(&config, "PATH", &mut current_value, &mut changes);
+        engine.process_file(
+            &config,
+            "PATH",
+            &Guard::default(),
+            &mut shell_env,
+            &mut changes,
+        );
 
         assert_eq!(changes.len(), 1);
         assert_eq!(changes[0].operation, Operation::Append);
         assert_eq!(changes[0].value_after, "/usr/bin:/new/path");
     }
 
+    #[test]
+    fn test_guarded_source_with_arguments() {
+        let dir = TempDir::new().unwrap();
+        let rc = create_test_file(&dir, "rc", "export EDITOR=\"$1\"\n");
+        let profile = create_test_file(
+            &dir,
+            ".profile",
+            &format!(
+                "[ -n \"$SSH_TTY\" ] && builtin source {} vim\n",
+                rc.display()
+            ),
+        );
+
+        let config = ConfigFile::shell(profile, "test profile");
+        let mut engine = TraceEngine::new(Platform::detect());
+        let mut shell_env = ShellEnv::new();
+        let mut changes = Vec::new();
+
+        engine.process_file(
+            &config,
+            "EDITOR",
+            &Guard::default(),
+            &mut shell_env,
+            &mut changes,
+        );
+
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].file, rc);
+        assert_eq!(changes[0].value_after, "vim");
+        assert_eq!(changes[0].operation, Operation::Conditional);
+        assert_eq!(changes[0].conditions, vec![r#"[ -n "$SSH_TTY" ]"#]);
+        assert!(shell_env.is_ambiguous("EDITOR"));
+    }
+
+    #[test]
+    fn test_drop_in_directory_sourced_in_order() {
+        let dir = TempDir::new().unwrap();
+        let drop_in = dir.path().join("bashrc.d");
+        std::fs::create_dir(&drop_in).unwrap();
+        std::fs::write(drop_in.join("20-editor"), "export EDITOR=vim\n").unwrap();
+        std::fs::write(drop_in.join("10-editor"), "export EDITOR=nano\n").unwrap();
+        std::fs::write(drop_in.join("30-editor.zsh"), "export EDITOR=hx\n").unwrap();
+        let bashrc = create_test_file(
+            &dir,
+            ".bashrc",
+            &format!(
+                "for rc in {0}/*; do\n  [ -f \"$rc\" ] && . \"$rc\"\ndone\n\
+                 for rc in $(find {0} -name '*.zsh'); do . $rc; done\n",
+                drop_in.display()
+            ),
+        );
+
+        let config = ConfigFile::shell(bashrc, "test bashrc");
+        let mut engine = TraceEngine::new(Platform::detect());
+        let mut shell_env = ShellEnv::new();
+        let mut changes = Vec::new();
+
+        engine.process_file(
+            &config,
+            "EDITOR",
+            &Guard::default(),
+            &mut shell_env,
+            &mut changes,
+        );
+
+        let values: Vec<&str> = changes.iter().map(|c| c.value_after.as_str()).collect();
+        assert_eq!(values, vec!["nano", "vim", "hx"]);
+        assert_eq!(shell_env.get("EDITOR"), Some("hx"));
+    }
+
     #[test]
     fn test_systemd_env_d_parsed() {
         let dir = TempDir::new().unwrap();
@@ -408,15 +726,21 @@ mod tests {
         let config = ConfigFile::new(conf, FileType::SystemdEnvironmentD, "test env.d");
 
         let mut engine = TraceEngine::new(Platform::detect());
-        let mut current_value = None;
+        let mut shell_env = ShellEnv::new();
         let mut changes = Vec::new();
 
-        engine.process_file(&config, "SYSTEMD_EDITOR", &mut current_value, &mut changes);
+        engine.process_file(
+            &config,
+            "SYSTEMD_EDITOR",
+            &Guard::default(),
+            &mut shell_env,
+            &mut changes,
+        );
 
         assert_eq!(changes.len(), 1);
         assert_eq!(changes[0].value_after, "micro");
         assert_eq!(changes[0].operation, Operation::Set);
-        assert_eq!(current_value, Some("micro".to_string()));
+        assert_eq!(shell_env.get("SYSTEMD_EDITOR"), Some("micro"));
     }
 
     #[test]
@@ -431,13 +755,133 @@ mod tests {
         let config = ConfigFile::new(conf, FileType::SystemdEnvironmentD, "test env.d");
 
         let mut engine = TraceEngine::new(Platform::detect());
-        let mut current_value = None;
+        let mut shell_env = ShellEnv::new();
         let mut changes = Vec::new();
 
-        engine.process_file(&config, "MY_VAR", &mut current_value, &mut changes);
+        engine.process_file(
+            &config,
+            "MY_VAR",
+            &Guard::default(),
+            &mut shell_env,
+            &mut changes,
+        );
 
         assert_eq!(changes.len(), 1);
         assert_eq!(changes[0].value_after, "hello");
         assert_eq!(changes[0].line_number, 4);
     }
+
+    #[test]
+    fn test_trace_resolves_other_variables() {
+        let dir = TempDir::new().unwrap();
+        let bashrc = create_test_file(
+            &dir,
+            ".bashrc",
+            "export GOPATH=/opt/go\nexport PATH=\"$PATH:$GOPATH/bin:$TOOLS/bin\"\n",
+        );
+
+        let config = ConfigFile::shell(bashrc, "test bashrc");
+
+        let mut engine = TraceEngine::new(Platform::detect());
+        let mut shell_env = ShellEnv::new();
+        shell_env.set("PATH", "/usr/bin");
+        let mut changes = Vec::new();
+
+        engine.process_file(
+            &config,
+            "PATH",
+            &Guard::default(),
+            &mut shell_env,
+            &mut changes,
+        );
+
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].value_after, "/usr/bin:/opt/go/bin:$TOOLS/bin");
+        assert_eq!(changes[0].unresolved, vec!["$TOOLS"]);
+    }
+
+    #[test]
+    fn test_sourced_file_applied_in_order() {
+        let dir = TempDir::new().unwrap();
+        let vars = create_test_file(&dir, "vars.sh", "export PATH=\"$PATH:/sourced\"\n");
+        let bashrc = create_test_file(
+            &dir,
+            ".bashrc",
+            &format!(". {}\nexport PATH=\"$PATH:/after\"\n", vars.display()),
+        );
+
+        let config = ConfigFile::shell(bashrc, "test bashrc");
+
+        let mut engine = TraceEngine::new(Platform::detect());
+        let mut shell_env = ShellEnv::new();
+        shell_env.set("PATH", "/usr/bin");
+        let mut changes = Vec::new();
+
+        engine.process_file(
+            &config,
+            "PATH",
+            &Guard::default(),
+            &mut shell_env,
+            &mut changes,
+        );
+
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].value_after, "/usr/bin:/sourced");
+        assert_eq!(
+            changes[1].value_before.as_deref(),
+            Some("/usr/bin:/sourced")
+        );
+        assert_eq!(changes[1].value_after, "/usr/bin:/sourced:/after");
+    }
+
+    #[test]
+    fn test_undecided_branches_give_candidates() {
+        let dir = TempDir::new().unwrap();
+        let bashrc = create_test_file(
+            &dir,
+            ".bashrc",
+            r#"if [ -n "$SSH_CONNECTION" ]; then
+    GOBIN=/srv/go/bin
+else
+    GOBIN="$HOME/go/bin"
+fi
+export PATH="$GOBIN:$PATH"
+[ -d "$JDK" ] && PATH="$PATH:$JDK/bin"
+export PATH="$PATH:/opt/bin"
+"#,
+        );
+        let config = ConfigFile::shell(bashrc, "test bashrc");
+
+        let mut engine = TraceEngine::new(Platform::detect());
+        let mut shell_env = ShellEnv::new();
+        shell_env.set("HOME", "/home/me");
+        shell_env.set("PATH", "/usr/bin");
+        let mut changes = Vec::new();
+        engine.process_file(
+            &config,
+            "PATH",
+            &Guard::default(),
+            &mut shell_env,
+            &mut changes,
+        );
+
+        let candidates = final_candidates(&shell_env, "PATH");
+        let values: Vec<&str> = candidates
+            .iter()
+            .map(|c| c.value.as_deref().unwrap())
+            .collect();
+        assert_eq!(
+            values,
+            vec![
+                "/srv/go/bin:/usr/bin:/opt/bin",
+                "/home/me/go/bin:/usr/bin:/opt/bin",
+                "/srv/go/bin:/usr/bin:$JDK/bin:/opt/bin",
+                "/home/me/go/bin:/usr/bin:$JDK/bin:/opt/bin",
+            ]
+        );
+        assert_eq!(
+            candidates[1].conditions,
+            vec![r#"! [ -n "$SSH_CONNECTION" ]"#, r#"! [ -d "$JDK" ]"#]
+        );
+    }
 }
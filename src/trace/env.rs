@@ -0,0 +1,698 @@
+//! Symbolic shell environment built up while tracing
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use super::eval::{CommandRunner, EvalPolicy};
+use super::{Context, HostProfile, ValueCandidate};
+use crate::parser::ShellFunction;
+
+/// Variables a shell inherits from the session it starts in (login, launchd,
+/// systemd), rather than setting them in its startup files
+const SESSION_VARS: &[&str] = &[
+    "HOME",
+    "USER",
+    "LOGNAME",
+    "SHELL",
+    "TMPDIR",
+    "XDG_CONFIG_HOME",
+    "XDG_DATA_HOME",
+    "XDG_CACHE_HOME",
+    "XDG_STATE_HOME",
+    "XDG_RUNTIME_DIR",
+];
+
+/// A traced variable value
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct TracedValue {
+    value: String,
+    /// References in the value that couldn't be resolved (e.g. `$(cmd)`, `$UNKNOWN`)
+    unresolved: Vec<String>,
+}
+
+/// Everything known about one variable, put back when a function's
+/// `local` copy of it goes out of scope
+#[derive(Debug, Clone)]
+pub struct SavedVariable {
+    name: String,
+    value: Option<Option<TracedValue>>,
+    exported: Option<Option<bool>>,
+    candidates: Option<Vec<ValueCandidate>>,
+    truncated: bool,
+    unique: bool,
+}
+
+impl SavedVariable {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Every variable assigned so far in the startup sequence
+///
+/// Variables that were never assigned are unknown rather than empty, so
+/// references to them can be reported as unresolved.
+#[derive(Debug, Clone, Default)]
+pub struct ShellEnv {
+    /// `None` marks a variable that was explicitly unset
+    vars: HashMap<String, Option<TracedValue>>,
+    /// Variables marked with `typeset -U`
+    unique: HashSet<String>,
+    /// Variables marked with `readonly` (or `typeset -r`)
+    readonly: HashSet<String>,
+    /// Export attribute of variables whose attribute was changed while
+    /// tracing (`None` if it depends on undecided branches)
+    exported: HashMap<String, Option<bool>>,
+    /// Shell options (`interactive`, `login`, ...) whose state is known
+    options: HashMap<String, bool>,
+    /// The machine being simulated
+    host: HostProfile,
+    /// Possible values of variables that depend on undecided branches
+    candidates: HashMap<String, Vec<ValueCandidate>>,
+    /// Variables that had more possible values than are kept
+    truncated: HashSet<String>,
+    /// Whether the shell has exited (`exit`/`exec`) on the current path
+    exited: bool,
+    /// Paths on which the shell exited early, with their conditions
+    exits: Vec<(Vec<String>, ShellEnv)>,
+    /// Functions defined so far, run when they are called
+    functions: HashMap<String, Rc<ShellFunction>>,
+    /// Positional parameters (`$1`, `$@`, ...) with their unresolved
+    /// references, when known (inside a function call)
+    positional: Option<Vec<(String, Vec<String>)>>,
+    /// Values the shell starts with (`None` if the session leaves it unset)
+    session: HashMap<String, Option<String>>,
+    /// Exported values inherited from the environment envtrace runs in,
+    /// which variables keep unless the startup files assign or unset them
+    inherited: HashMap<String, String>,
+    /// The file being read (`$BASH_SOURCE`, and `$0` in zsh)
+    script: Option<PathBuf>,
+    /// Runs command substitutions in assignments (`--eval-commands`)
+    runner: Option<Rc<CommandRunner>>,
+    /// The command substitutions the runner may run, as written
+    eval_sources: Rc<HashSet<String>>,
+}
+
+impl ShellEnv {
+    /// Most possible values kept per variable; beyond this, later paths are dropped
+    pub const MAX_CANDIDATES: usize = 32;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The environment a shell starts with in the given context
+    ///
+    /// Interactive shells get a default PS1; non-interactive shells have
+    /// none, so `[ -z "$PS1" ] && return` guards can be decided. bash sets
+    /// `$BASH`, which `/etc/profile` checks before reading the bashrc.
+    pub fn for_context(context: Context) -> Self {
+        let mut env = Self::new().with_session();
+        env.set_option("interactive", context.is_interactive());
+        env.set_option("login", context.is_login());
+        if context.is_interactive() {
+            env.set("PS1", "\\s-\\v\\$ ");
+        } else {
+            env.unset("PS1");
+        }
+        if context.is_bash() {
+            env.set("BASH", "/bin/bash");
+        }
+        env
+    }
+
+    /// Take the session variables (HOME, USER, XDG base directories, ...)
+    /// from the current environment, for references the startup files
+    /// don't assign
+    pub fn with_session(mut self) -> Self {
+        for name in SESSION_VARS {
+            self.session
+                .insert(name.to_string(), std::env::var(name).ok());
+        }
+        self
+    }
+
+    /// The value a variable has when the shell starts: `Some(None)` if
+    /// it's known to be unset, `None` if nothing is known
+    pub fn session_value(&self, name: &str) -> Option<Option<&str>> {
+        self.session.get(name).map(|v| v.as_deref())
+    }
+
+    /// A variable inherited (exported) from the parent environment
+    pub fn inherit(&mut self, name: &str, value: impl Into<String>) {
+        self.inherited.insert(name.to_string(), value.into());
+    }
+
+    /// Simulate another machine: its variables are set from the start and
+    /// command substitutions it can answer are resolved
+    pub fn with_host(mut self, host: HostProfile) -> Self {
+        for (name, value) in host.variables() {
+            self.set(name, value);
+        }
+        self.host = host;
+        self
+    }
+
+    /// True if tracing for another machine, so the local filesystem and PATH
+    /// say nothing about it
+    pub fn is_foreign_host(&self) -> bool {
+        self.host.is_foreign()
+    }
+
+    /// The output of a command substitution, if it is known
+    pub fn command_output(&self, command: &str) -> Option<String> {
+        self.host.command_output(command)
+    }
+
+    /// Run the command substitutions in `sources` (as written) when they
+    /// are assigned, under the given policy
+    pub fn with_eval(mut self, policy: EvalPolicy, sources: HashSet<String>) -> Self {
+        self.runner = Some(Rc::new(CommandRunner::new(policy)));
+        self.eval_sources = Rc::new(sources);
+        self
+    }
+
+    /// Run a command substitution with only the traced variables (and
+    /// `overrides`) set. None if evaluation is off, the substitution isn't
+    /// one to run or the command isn't permitted, fails to start or times out.
+    pub fn evaluate(
+        &self,
+        command: &str,
+        source: &str,
+        overrides: &HashMap<String, Option<String>>,
+    ) -> Option<String> {
+        let runner = self.runner.as_ref()?;
+        if !self.eval_sources.contains(source) {
+            return None;
+        }
+        let mut vars: HashMap<&str, &str> = HashMap::new();
+        for (name, value) in &self.session {
+            if let Some(value) = value {
+                vars.insert(name, value);
+            }
+        }
+        for (name, value) in &self.vars {
+            match value {
+                Some(v) if v.unresolved.is_empty() => vars.insert(name, &v.value),
+                _ => vars.remove(name.as_str()),
+            };
+        }
+        for (name, value) in overrides {
+            match value {
+                Some(v) => vars.insert(name, v),
+                None => vars.remove(name.as_str()),
+            };
+        }
+        let home = dirs::home_dir().map(|h| h.to_string_lossy().to_string());
+        if let Some(ref home) = home {
+            vars.entry("HOME").or_insert(home);
+        }
+        let vars = vars
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        runner.run(command, vars)
+    }
+
+    /// The traced value of a variable, if it is known and set
+    pub fn get(&self, name: &str) -> Option<&str> {
+        match self.vars.get(name) {
+            Some(Some(v)) => Some(&v.value),
+            _ => None,
+        }
+    }
+
+    /// Returns true if the variable has been assigned or unset while tracing
+    pub fn is_tracked(&self, name: &str) -> bool {
+        self.vars.contains_key(name)
+    }
+
+    /// Unresolved references carried in a variable's value
+    pub fn unresolved(&self, name: &str) -> &[String] {
+        match self.vars.get(name) {
+            Some(Some(v)) => &v.unresolved,
+            _ => &[],
+        }
+    }
+
+    pub fn set(&mut self, name: &str, value: impl Into<String>) {
+        self.set_with_unresolved(name, value, Vec::new());
+    }
+
+    pub fn set_with_unresolved(
+        &mut self,
+        name: &str,
+        value: impl Into<String>,
+        unresolved: Vec<String>,
+    ) {
+        let value = TracedValue {
+            value: value.into(),
+            unresolved,
+        };
+        self.vars.insert(name.to_string(), Some(value));
+        self.candidates.remove(name);
+        self.truncated.remove(name);
+    }
+
+    pub fn unset(&mut self, name: &str) {
+        self.vars.insert(name.to_string(), None);
+        self.candidates.remove(name);
+        self.truncated.remove(name);
+        // A variable assigned again after `unset` is no longer exported
+        self.exported.insert(name.to_string(), Some(false));
+    }
+
+    /// Whether a variable is passed to child processes: it was exported
+    /// (`export`, `declare -x`, an assignment under `set -a`) or inherited
+    /// from the session, and not `unset` or `export -n`'d since. `None` if
+    /// it depends on undecided branches.
+    pub fn is_exported(&self, name: &str) -> Option<bool> {
+        match self.exported.get(name) {
+            Some(state) => *state,
+            None => Some(
+                name == "PATH" || SESSION_VARS.contains(&name) || self.inherited.contains_key(name),
+            ),
+        }
+    }
+
+    /// Set or clear a variable's export attribute (`export -n`, `typeset +x`)
+    pub fn set_exported(&mut self, name: &str, exported: bool) {
+        self.exported.insert(name.to_string(), Some(exported));
+    }
+
+    /// Everything known about a variable, to restore later
+    pub fn save(&self, name: &str) -> SavedVariable {
+        SavedVariable {
+            name: name.to_string(),
+            value: self.vars.get(name).cloned(),
+            exported: self.exported.get(name).copied(),
+            candidates: self.candidates.get(name).cloned(),
+            truncated: self.truncated.contains(name),
+            unique: self.unique.contains(name),
+        }
+    }
+
+    /// Put a variable back the way it was saved
+    pub fn restore(&mut self, saved: SavedVariable) {
+        let name = saved.name;
+        match saved.value {
+            Some(value) => self.vars.insert(name.clone(), value),
+            None => self.vars.remove(&name),
+        };
+        match saved.exported {
+            Some(exported) => self.exported.insert(name.clone(), exported),
+            None => self.exported.remove(&name),
+        };
+        match saved.candidates {
+            Some(candidates) => self.candidates.insert(name.clone(), candidates),
+            None => self.candidates.remove(&name),
+        };
+        if saved.truncated {
+            self.truncated.insert(name.clone());
+        } else {
+            self.truncated.remove(&name);
+        }
+        if saved.unique {
+            self.unique.insert(name);
+        } else {
+            self.unique.remove(&name);
+        }
+    }
+
+    /// Drop what is known about a variable, so references become unresolved
+    pub fn forget(&mut self, name: &str) {
+        self.vars.remove(name);
+        self.candidates.remove(name);
+        self.truncated.remove(name);
+    }
+
+    /// The possible values of a variable with the conditions each depends on
+    ///
+    /// A variable that doesn't depend on undecided branches has a single
+    /// candidate: its traced value, or the inherited one if it was never
+    /// assigned or unset.
+    pub fn candidates(&self, name: &str) -> Vec<ValueCandidate> {
+        match self.candidates.get(name) {
+            Some(candidates) => candidates.clone(),
+            None => vec![ValueCandidate {
+                value: match self.vars.get(name) {
+                    Some(value) => value.as_ref().map(|v| v.value.clone()),
+                    None => self.inherited.get(name).cloned(),
+                },
+                conditions: Vec::new(),
+            }],
+        }
+    }
+
+    /// Returns true if the variable has more than one possible value
+    pub fn is_ambiguous(&self, name: &str) -> bool {
+        self.candidates.get(name).is_some_and(|c| c.len() > 1)
+    }
+
+    /// Variables with more than one possible value, sorted by name
+    pub fn ambiguous_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self
+            .candidates
+            .iter()
+            .filter(|(_, c)| c.len() > 1)
+            .map(|(name, _)| name.as_str())
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Record the possible values of a variable after it was assigned
+    pub fn set_candidates(&mut self, name: &str, mut candidates: Vec<ValueCandidate>) {
+        if candidates.len() > Self::MAX_CANDIDATES {
+            candidates.truncate(Self::MAX_CANDIDATES);
+            self.mark_truncated(name);
+        }
+        match candidates.as_slice() {
+            [only] if only.conditions.is_empty() => {
+                self.candidates.remove(name);
+            }
+            _ => {
+                self.candidates.insert(name.to_string(), candidates);
+            }
+        }
+    }
+
+    /// Note that some possible values of a variable were dropped
+    pub fn mark_truncated(&mut self, name: &str) {
+        self.truncated.insert(name.to_string());
+    }
+
+    /// Whether a variable had more possible values than [`Self::candidates`]
+    /// returns
+    pub fn is_truncated(&self, name: &str) -> bool {
+        self.truncated.contains(name)
+    }
+
+    /// Combine the environments left by the possible paths through an
+    /// undecided branch, each with the conditions that lead to it
+    ///
+    /// Every candidate value gets the conditions of its path (values assigned
+    /// on the path already carry them). Variables that ended up the same on
+    /// every path are left alone. The last path's values are used for further
+    /// expansion; recorded exits are kept from `self`.
+    pub fn merge(&mut self, paths: Vec<(Vec<String>, ShellEnv)>) {
+        let Some((_, last)) = paths.last() else {
+            return;
+        };
+        let mut merged = last.clone();
+        merged.exits = std::mem::take(&mut self.exits);
+        for (_, env) in &paths {
+            merged.truncated.extend(env.truncated.iter().cloned());
+        }
+        let mut names: Vec<&String> = paths.iter().flat_map(|(_, env)| env.vars.keys()).collect();
+        names.sort();
+        names.dedup();
+
+        for name in names {
+            let outcomes: Vec<Vec<ValueCandidate>> =
+                paths.iter().map(|(_, env)| env.candidates(name)).collect();
+            if outcomes.iter().all(|c| *c == outcomes[0]) {
+                continue;
+            }
+
+            let mut candidates: Vec<ValueCandidate> = Vec::new();
+            for ((conditions, _), outcome) in paths.iter().zip(outcomes) {
+                for mut candidate in outcome {
+                    for condition in conditions {
+                        if !candidate.conditions.contains(condition) {
+                            candidate.conditions.push(condition.clone());
+                        }
+                    }
+                    if !candidates.contains(&candidate) {
+                        candidates.push(candidate);
+                    }
+                }
+            }
+
+            let first = &candidates[0].value;
+            if candidates.iter().all(|c| c.value == *first) {
+                merged.candidates.remove(name);
+            } else {
+                merged.set_candidates(name, candidates);
+            }
+        }
+
+        // A variable exported on some paths only may or may not be exported
+        let mut exported: Vec<&String> = paths
+            .iter()
+            .flat_map(|(_, env)| env.exported.keys())
+            .collect();
+        exported.sort();
+        exported.dedup();
+        for name in exported {
+            let states: Vec<Option<bool>> =
+                paths.iter().map(|(_, env)| env.is_exported(name)).collect();
+            let agree = states.iter().all(|state| *state == states[0]);
+            merged
+                .exported
+                .insert(name.clone(), if agree { states[0] } else { None });
+        }
+
+        // A variable made readonly on some paths only can still be assigned
+        merged
+            .readonly
+            .retain(|name| paths.iter().all(|(_, env)| env.readonly.contains(name)));
+
+        // An option set on some paths only is unknown afterwards
+        merged.options.retain(|name, on| {
+            paths
+                .iter()
+                .all(|(_, env)| env.options.get(name) == Some(on))
+        });
+        *self = merged;
+    }
+
+    /// Record that the shell exits on the current path (`exit` or `exec`),
+    /// keeping its environment for the final values
+    pub fn exit(&mut self, conditions: Vec<String>) {
+        let mut snapshot = self.clone();
+        snapshot.exits.clear();
+        self.exits.push((conditions, snapshot));
+        self.exited = true;
+    }
+
+    /// Returns true if the shell has exited on the current path
+    pub fn has_exited(&self) -> bool {
+        self.exited
+    }
+
+    /// Continue on another path: `env` replaces this environment, keeping
+    /// the exits recorded so far
+    pub fn resume(&mut self, env: ShellEnv) {
+        let exits = std::mem::take(&mut self.exits);
+        *self = env;
+        self.exits = exits;
+    }
+
+    /// Fold the environments of the paths that exited early back in, so the
+    /// final candidates include them
+    pub fn finish(&mut self) {
+        let mut paths = std::mem::take(&mut self.exits);
+        if paths.is_empty() {
+            return;
+        }
+        if !self.exited {
+            paths.push((Vec::new(), self.clone()));
+        }
+        self.merge(paths);
+        self.exited = false;
+    }
+
+    pub(crate) fn define_function(&mut self, name: &str, function: Rc<ShellFunction>) {
+        self.functions.insert(name.to_string(), function);
+    }
+
+    pub(crate) fn function(&self, name: &str) -> Option<Rc<ShellFunction>> {
+        self.functions.get(name).cloned()
+    }
+
+    pub fn unset_function(&mut self, name: &str) {
+        self.functions.remove(name);
+    }
+
+    /// Replace the file being read, returning the previous one
+    pub fn replace_script(&mut self, script: Option<PathBuf>) -> Option<PathBuf> {
+        std::mem::replace(&mut self.script, script)
+    }
+
+    /// The file being read, if known
+    pub fn script(&self) -> Option<&Path> {
+        self.script.as_deref()
+    }
+
+    /// Replace the positional parameters, returning the previous ones
+    pub fn replace_positional(
+        &mut self,
+        params: Option<Vec<(String, Vec<String>)>>,
+    ) -> Option<Vec<(String, Vec<String>)>> {
+        std::mem::replace(&mut self.positional, params)
+    }
+
+    /// The positional parameters, if known
+    pub fn positional(&self) -> Option<&[(String, Vec<String>)]> {
+        self.positional.as_deref()
+    }
+
+    /// `shift n`: drop the first `n` positional parameters
+    pub fn shift(&mut self, n: usize) {
+        if let Some(ref mut params) = self.positional {
+            params.drain(..n.min(params.len()));
+        }
+    }
+
+    /// The value of a positional or special parameter (`1`, `#`, `@`, `*`)
+    /// with its unresolved references, if the parameters are known
+    pub fn positional_param(&self, name: &str) -> Option<(String, Vec<String>)> {
+        let params = self.positional.as_ref()?;
+        match name {
+            "#" => Some((params.len().to_string(), Vec::new())),
+            "@" | "*" => {
+                let values: Vec<&str> = params.iter().map(|(v, _)| v.as_str()).collect();
+                let unresolved = params.iter().flat_map(|(_, u)| u.clone()).collect();
+                Some((values.join(" "), unresolved))
+            }
+            _ => {
+                let index: usize = name.parse().ok().filter(|i| *i > 0)?;
+                Some(params.get(index - 1).cloned().unwrap_or_default())
+            }
+        }
+    }
+
+    /// Returns true if the variable keeps only unique entries (`typeset -U`)
+    pub fn is_unique(&self, name: &str) -> bool {
+        self.unique.contains(name)
+    }
+
+    pub fn set_unique(&mut self, name: &str) {
+        self.unique.insert(name.to_string());
+    }
+
+    /// Returns true if later assignments to the variable fail (`readonly`)
+    pub fn is_readonly(&self, name: &str) -> bool {
+        self.readonly.contains(name)
+    }
+
+    pub fn set_readonly(&mut self, name: &str) {
+        self.readonly.insert(name.to_string());
+    }
+
+    /// The state of a shell option, if known
+    pub fn option(&self, name: &str) -> Option<bool> {
+        self.options.get(name).copied()
+    }
+
+    pub fn set_option(&mut self, name: &str, on: bool) {
+        self.options.insert(name.to_string(), on);
+    }
+
+    /// The value of `$-` for the known shell options
+    pub fn flags(&self) -> Option<String> {
+        let interactive = self.option("interactive")?;
+        Some(if interactive { "himBH" } else { "hB" }.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_untracked_vs_unset() {
+        let mut env = ShellEnv::new();
+        assert!(!env.is_tracked("FOO"));
+        env.set("FOO", "bar");
+        assert_eq!(env.get("FOO"), Some("bar"));
+        env.unset("FOO");
+        assert!(env.is_tracked("FOO"));
+        assert_eq!(env.get("FOO"), None);
+    }
+
+    #[test]
+    fn test_for_context() {
+        let env = ShellEnv::for_context(Context::NonInteractiveNonLogin);
+        assert_eq!(env.option("interactive"), Some(false));
+        assert!(env.is_tracked("PS1"));
+        assert_eq!(env.get("PS1"), None);
+        assert_eq!(env.flags().as_deref(), Some("hB"));
+    }
+
+    #[test]
+    fn test_merge_paths() {
+        let mut before = ShellEnv::new();
+        before.set("EDITOR", "vi");
+        before.set("PAGER", "less");
+
+        let mut taken = before.clone();
+        taken.set("EDITOR", "nvim");
+        taken.set_candidates(
+            "EDITOR",
+            vec![ValueCandidate {
+                value: Some("nvim".to_string()),
+                conditions: vec!["[ -x ~/bin/nvim ]".to_string()],
+            }],
+        );
+        let skipped = before.clone();
+
+        let mut env = before.clone();
+        env.merge(vec![
+            (vec!["! [ -x ~/bin/nvim ]".to_string()], skipped),
+            (vec!["[ -x ~/bin/nvim ]".to_string()], taken),
+        ]);
+        assert!(env.is_ambiguous("EDITOR"));
+        assert!(!env.is_ambiguous("PAGER"));
+        let values: Vec<_> = env
+            .candidates("EDITOR")
+            .into_iter()
+            .map(|c| (c.value.unwrap(), c.conditions))
+            .collect();
+        assert_eq!(
+            values,
+            vec![
+                ("vi".to_string(), vec!["! [ -x ~/bin/nvim ]".to_string()]),
+                ("nvim".to_string(), vec!["[ -x ~/bin/nvim ]".to_string()]),
+            ]
+        );
+        assert_eq!(env.get("EDITOR"), Some("nvim"));
+    }
+
+    #[test]
+    fn test_export_attribute() {
+        let mut env = ShellEnv::new();
+        env.set("PATH", "/usr/bin");
+        env.set("PAGER", "less");
+        assert_eq!(env.is_exported("PATH"), Some(true));
+        assert_eq!(env.is_exported("PAGER"), Some(false));
+
+        let mut exported = env.clone();
+        exported.set_exported("PAGER", true);
+        env.merge(vec![
+            (vec!["[ -n \"$SSH_TTY\" ]".to_string()], exported),
+            (vec!["! [ -n \"$SSH_TTY\" ]".to_string()], env.clone()),
+        ]);
+        assert_eq!(env.is_exported("PAGER"), None);
+
+        env.unset("PATH");
+        env.set("PATH", "/bin");
+        assert_eq!(env.is_exported("PATH"), Some(false));
+    }
+
+    #[test]
+    fn test_inherited_variables() {
+        let mut env = ShellEnv::new();
+        env.inherit("FOO", "inherited");
+        env.inherit("BAR", "inherited");
+        assert_eq!(env.candidates("FOO")[0].value.as_deref(), Some("inherited"));
+        // Assigning keeps the export attribute, unsetting drops both
+        env.set("FOO", "bar");
+        assert_eq!(env.is_exported("FOO"), Some(true));
+        env.unset("BAR");
+        assert_eq!(env.candidates("BAR")[0].value, None);
+        assert_eq!(env.is_exported("BAR"), Some(false));
+    }
+}
@@ -0,0 +1,447 @@
+//! Sandboxed evaluation of command substitutions (`--eval-commands`)
+//!
+//! Values like `$(brew --prefix)` or `$(/usr/libexec/java_home)` can only be
+//! known by running the command. When enabled, commands in traced
+//! assignments that reach the traced variable run with `sh -c` in an
+//! environment built from the traced variables alone, with a timeout, and
+//! only if every program they call is a known read-only query or allowed
+//! with `--eval-allow`.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::Read;
+use std::iter::Peekable;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::parser::{Script, ScriptCommand, Word};
+
+/// What may follow the fixed arguments of a query
+#[derive(Debug, Clone, Copy)]
+enum Rest {
+    Nothing,
+    /// Literal operands, but no options
+    Operands,
+    /// Any arguments: the program has no mode that changes anything
+    Anything,
+}
+
+/// Read-only queries that run by default: the program, the arguments it
+/// must start with, and what may follow them
+const DEFAULT_ALLOW: &[(&str, &[&str], Rest)] = &[
+    ("uname", &[], Rest::Anything),
+    ("hostname", &[], Rest::Nothing),
+    ("hostname", &["-s"], Rest::Nothing),
+    ("hostname", &["-f"], Rest::Nothing),
+    ("whoami", &[], Rest::Nothing),
+    ("id", &[], Rest::Anything),
+    ("arch", &[], Rest::Nothing),
+    ("sw_vers", &[], Rest::Anything),
+    ("lsb_release", &[], Rest::Anything),
+    ("getconf", &[], Rest::Anything),
+    ("locale", &[], Rest::Anything),
+    ("tty", &[], Rest::Anything),
+    ("pwd", &[], Rest::Anything),
+    ("echo", &[], Rest::Anything),
+    ("printf", &[], Rest::Anything),
+    ("dirname", &[], Rest::Anything),
+    ("basename", &[], Rest::Anything),
+    ("realpath", &[], Rest::Anything),
+    ("readlink", &[], Rest::Anything),
+    ("cat", &[], Rest::Anything),
+    ("head", &[], Rest::Anything),
+    ("tail", &[], Rest::Anything),
+    ("cut", &[], Rest::Anything),
+    ("tr", &[], Rest::Anything),
+    ("grep", &[], Rest::Anything),
+    ("wc", &[], Rest::Anything),
+    ("ls", &[], Rest::Anything),
+    ("which", &[], Rest::Anything),
+    ("type", &[], Rest::Anything),
+    ("command", &["-v"], Rest::Operands),
+    ("command", &["-V"], Rest::Operands),
+    ("true", &[], Rest::Anything),
+    ("manpath", &[], Rest::Anything),
+    ("path_helper", &[], Rest::Anything),
+    ("java_home", &[], Rest::Nothing),
+    ("java_home", &["-V"], Rest::Nothing),
+    ("java_home", &["-v"], Rest::Operands),
+    ("java_home", &["--version"], Rest::Operands),
+    ("brew", &["--prefix"], Rest::Operands),
+    ("brew", &["--repository"], Rest::Operands),
+    ("brew", &["--cellar"], Rest::Operands),
+    ("brew", &["--caskroom"], Rest::Nothing),
+    ("brew", &["shellenv"], Rest::Operands),
+    ("xcode-select", &["-p"], Rest::Nothing),
+    ("xcode-select", &["--print-path"], Rest::Nothing),
+    ("go", &["env"], Rest::Operands),
+    ("rbenv", &["root"], Rest::Nothing),
+    ("pyenv", &["root"], Rest::Nothing),
+    ("nodenv", &["root"], Rest::Nothing),
+    ("npm", &["config", "get"], Rest::Operands),
+    ("npm", &["prefix", "-g"], Rest::Nothing),
+    ("npm", &["root", "-g"], Rest::Nothing),
+    ("gem", &["env"], Rest::Operands),
+    ("gpgconf", &["--list-dirs"], Rest::Operands),
+    ("rustc", &["--print", "sysroot"], Rest::Nothing),
+    ("python3", &["-m", "site", "--user-base"], Rest::Nothing),
+    ("python", &["-m", "site", "--user-base"], Rest::Nothing),
+    ("systemd-path", &[], Rest::Operands),
+    ("xdg-user-dir", &[], Rest::Operands),
+];
+
+/// Programs that run the command in their arguments, which is checked in
+/// their place
+const WRAPPERS: &[&str] = &[
+    "command", "builtin", "nohup", "time", "env", "nice", "timeout",
+];
+
+/// PATH for commands when the traced PATH isn't known
+const DEFAULT_PATH: &str = "/usr/local/bin:/usr/bin:/bin:/usr/sbin:/sbin";
+
+/// Redirections that only discard output
+const DISCARDS: &[&str] = &["2>/dev/null", ">/dev/null", "&>/dev/null", "2>&1"];
+
+/// Which commands may run, and for how long
+///
+/// Only the read-only queries in [`DEFAULT_ALLOW`] run unless `allow`
+/// names more programs; `deny` takes precedence over both.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EvalPolicy {
+    /// Longest a command may run before it's killed and left unresolved
+    pub timeout: Duration,
+    /// Programs that may also run, with any arguments
+    pub allow: Vec<String>,
+    /// Programs that never run
+    pub deny: Vec<String>,
+}
+
+impl Default for EvalPolicy {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(2),
+            allow: Vec::new(),
+            deny: Vec::new(),
+        }
+    }
+}
+
+impl EvalPolicy {
+    /// Returns true if every program the command calls may run
+    pub fn permits(&self, command: &str) -> bool {
+        let Some(calls) = calls(command) else {
+            return false;
+        };
+        !calls.is_empty()
+            && calls.iter().all(|call| {
+                let listed = |list: &[String]| list.contains(&call.program);
+                let allowed = call.wrapper || listed(&self.allow) || call.is_query();
+                allowed && !listed(&self.deny)
+            })
+    }
+}
+
+/// A command and the variables it ran with
+type RunKey = (String, Vec<(String, String)>);
+
+/// Runs permitted commands, remembering their output
+#[derive(Debug)]
+pub struct CommandRunner {
+    policy: EvalPolicy,
+    outputs: RefCell<HashMap<RunKey, Option<String>>>,
+}
+
+impl CommandRunner {
+    pub fn new(policy: EvalPolicy) -> Self {
+        Self {
+            policy,
+            outputs: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// The output of a command run with only the given variables set, or
+    /// None if it isn't permitted, can't start or times out
+    pub fn run(&self, command: &str, mut vars: Vec<(String, String)>) -> Option<String> {
+        if !self.policy.permits(command) {
+            return None;
+        }
+        vars.sort();
+        let key = (command.to_string(), vars);
+        if let Some(output) = self.outputs.borrow().get(&key) {
+            return output.clone();
+        }
+        let output = run_sandboxed(command, &key.1, self.policy.timeout);
+        self.outputs.borrow_mut().insert(key, output.clone());
+        output
+    }
+}
+
+fn run_sandboxed(command: &str, vars: &[(String, String)], timeout: Duration) -> Option<String> {
+    let mut process = Command::new("/bin/sh");
+    process
+        .arg("-c")
+        .arg(command)
+        .env_clear()
+        .envs(vars.iter().map(|(k, v)| (k, v)))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null());
+    if !vars.iter().any(|(name, _)| name == "PATH") {
+        process.env("PATH", DEFAULT_PATH);
+    }
+    if let Some((_, home)) = vars.iter().find(|(name, _)| name == "HOME") {
+        process.current_dir(home);
+    }
+    // Its own process group, so pipelines can be killed as a whole
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut process, 0);
+    let mut child = process.spawn().ok()?;
+
+    // Read on another thread so a full pipe can't block the child
+    let mut stdout = child.stdout.take()?;
+    let reader = thread::spawn(move || {
+        let mut output = Vec::new();
+        stdout.read_to_end(&mut output).ok().map(|_| output)
+    });
+
+    let deadline = Instant::now() + timeout;
+    loop {
+        match child.try_wait() {
+            Ok(Some(_)) => break,
+            Ok(None) if Instant::now() < deadline => thread::sleep(Duration::from_millis(10)),
+            _ => {
+                #[cfg(unix)]
+                let _ = nix::sys::signal::killpg(
+                    nix::unistd::Pid::from_raw(child.id() as i32),
+                    nix::sys::signal::Signal::SIGKILL,
+                );
+                let _ = child.kill();
+                let _ = child.wait();
+                return None;
+            }
+        }
+    }
+    let output = reader.join().ok()??;
+    Some(String::from_utf8_lossy(&output).into_owned())
+}
+
+/// A program a command line runs
+#[derive(Debug)]
+struct Call {
+    program: String,
+    /// Its arguments (None where they aren't literal)
+    args: Vec<Option<String>>,
+    /// Whether it runs the next call, which is checked instead
+    wrapper: bool,
+}
+
+impl Call {
+    /// Returns true if this is one of the read-only queries run by default
+    fn is_query(&self) -> bool {
+        DEFAULT_ALLOW.iter().any(|(program, prefix, rest)| {
+            let Some(rest_args) = self.args.get(prefix.len()..) else {
+                return false;
+            };
+            *program == self.program
+                && prefix
+                    .iter()
+                    .zip(&self.args)
+                    .all(|(p, arg)| arg.as_deref() == Some(*p))
+                && match rest {
+                    Rest::Nothing => rest_args.is_empty(),
+                    Rest::Operands => rest_args
+                        .iter()
+                        .all(|arg| arg.as_ref().is_some_and(|a| !a.starts_with('-'))),
+                    Rest::Anything => true,
+                }
+        })
+    }
+}
+
+/// The programs a command line calls, or None if it does something that
+/// can't be checked (nested substitutions, redirections to files, compound
+/// commands, programs named by expansions)
+fn calls(command: &str) -> Option<Vec<Call>> {
+    if command.contains(['`', '<']) || command.contains("$(") || command.contains(">(") {
+        return None;
+    }
+    let mut line = command.to_string();
+    for discard in DISCARDS {
+        line = line.replace(discard, " ");
+    }
+    if line.contains('>') {
+        return None;
+    }
+
+    let script = Script::parse(&line);
+    let mut calls = Vec::new();
+    for statement in &script.statements {
+        for (_, command) in &statement.commands {
+            command_calls(command, &mut calls)?;
+        }
+    }
+    Some(calls)
+}
+
+/// Add the programs a simple command runs, looking through `!` and command
+/// wrappers like `env` and `nohup`; None for anything else
+fn command_calls(command: &ScriptCommand, calls: &mut Vec<Call>) -> Option<()> {
+    let words = match command {
+        ScriptCommand::Simple(simple) => &simple.words,
+        ScriptCommand::Not(inner) => return command_calls(inner, calls),
+        _ => return None,
+    };
+    let mut words = words
+        .iter()
+        .skip_while(|w| w.split_assignment().is_some())
+        .peekable();
+    while let Some(word) = words.next() {
+        let program = program_name(word)?;
+        // `command -v` only looks the program up
+        let lookup = program == "command"
+            && words
+                .peek()
+                .is_some_and(|w| w.is_literal("-v") || w.is_literal("-V"));
+        if !WRAPPERS.contains(&program.as_str()) || lookup {
+            calls.push(Call {
+                program,
+                args: words.by_ref().map(Word::as_literal).collect(),
+                wrapper: false,
+            });
+            break;
+        }
+        match program.as_str() {
+            "env" => {
+                skip_options(&mut words, &["-u", "-C"])?;
+                while words.next_if(|w| w.split_assignment().is_some()).is_some() {}
+            }
+            "nice" => skip_options(&mut words, &["-n"])?,
+            "timeout" => {
+                skip_options(&mut words, &["-s", "-k"])?;
+                words.next()?;
+            }
+            _ => skip_options(&mut words, &[])?,
+        }
+        calls.push(Call {
+            program,
+            args: Vec::new(),
+            wrapper: true,
+        });
+    }
+    Some(())
+}
+
+/// A program's name from the word that calls it, or None if it's chosen by
+/// an expansion or a glob
+fn program_name(word: &Word) -> Option<String> {
+    let text = word.unquoted_text();
+    let name = text.rsplit('/').next().unwrap_or(&text);
+    if name.is_empty() || name.contains(['$', '*', '?', '[', '{']) {
+        return None;
+    }
+    Some(name.to_string())
+}
+
+/// Skip a wrapper's options (and the arguments of those in `with_arg`),
+/// failing on options whose meaning can't be checked
+fn skip_options<'a>(
+    words: &mut Peekable<impl Iterator<Item = &'a Word>>,
+    with_arg: &[&str],
+) -> Option<()> {
+    while let Some(option) = words.next_if(|w| w.unquoted_text().starts_with('-')) {
+        let option = option.as_literal()?;
+        if option == "--" {
+            break;
+        }
+        // `env -S` splits its argument into a command line
+        if option.starts_with("-S") || option.starts_with("--split") {
+            return None;
+        }
+        if with_arg.contains(&option.as_str()) {
+            words.next()?;
+        }
+    }
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policy() {
+        let policy = EvalPolicy::default();
+        assert!(policy.permits("brew --prefix"));
+        assert!(policy.permits("brew --prefix openssl@3"));
+        assert!(policy.permits("/usr/libexec/java_home -v 17 2>/dev/null"));
+        assert!(policy.permits("go env GOPATH | tr -d '\\n'"));
+        assert!(policy.permits("command -v go"));
+        assert!(policy.permits("env GOFLAGS= go env GOPATH"));
+        assert!(policy.permits("! uname -m | grep -q arm"));
+        assert!(!policy.permits("rm -rf ~/tmp"));
+        assert!(!policy.permits("echo hi | sudo tee /etc/x"));
+        assert!(!policy.permits("echo hi > ~/file"));
+        assert!(!policy.permits("dirname $(which go)"));
+
+        // Programs that aren't read-only queries don't run
+        assert!(!policy.permits("dash -c 'touch /tmp/p'"));
+        assert!(!policy.permits("awk 'BEGIN{system(\"touch /tmp/p\")}'"));
+        assert!(!policy.permits("sed -i s/a/b/ ~/.profile"));
+        assert!(!policy.permits("python3 -c 'open(\"/tmp/p\",\"w\")'"));
+        assert!(!policy.permits("go env -w GOPATH=/tmp"));
+        assert!(!policy.permits("go env $FLAG"));
+        assert!(!policy.permits("brew install wget"));
+        assert!(!policy.permits("hostname pwned"));
+        assert!(!policy.permits("/usr/libexec/java_home --exec touch /tmp/p"));
+
+        // Compound commands, subshells, groups and functions aren't checked
+        assert!(!policy.permits("if true; then touch /tmp/x; fi; echo a"));
+        assert!(!policy.permits("(touch /tmp/x); echo a"));
+        assert!(!policy.permits("{ touch /tmp/x;}; echo a"));
+        assert!(!policy.permits("f() { touch /tmp/x; }; f"));
+        assert!(!policy.permits("for f in a; do touch $f; done"));
+        // Wrappers run their arguments
+        assert!(!policy.permits("env touch /tmp/x"));
+        assert!(!policy.permits("env -i FOO=1 touch /tmp/x"));
+        assert!(!policy.permits("env -S 'touch /tmp/x'"));
+        assert!(!policy.permits("nice -n 5 rm /tmp/x"));
+        assert!(!policy.permits("timeout 5 rm /tmp/x"));
+        assert!(!policy.permits("command rm /tmp/x"));
+        assert!(!policy.permits("exec uname"));
+        assert!(!policy.permits("echo /tmp/x | xargs rm"));
+        // Programs chosen by expansions or globs
+        assert!(!policy.permits("$CMD /tmp/x"));
+        assert!(!policy.permits("/bin/r? /tmp/x"));
+
+        let policy = EvalPolicy {
+            allow: vec!["mise".to_string()],
+            deny: vec!["go".to_string()],
+            ..Default::default()
+        };
+        assert!(policy.permits("mise where node"));
+        assert!(policy.permits("uname"));
+        assert!(!policy.permits("go env GOPATH"));
+        assert!(!policy.permits("env go env GOPATH"));
+    }
+
+    #[test]
+    fn test_run_scrubbed_environment() {
+        let runner = CommandRunner::new(EvalPolicy::default());
+        let vars = vec![("GREETING".to_string(), "hello".to_string())];
+        assert_eq!(
+            runner.run("echo $GREETING $HOME", vars).as_deref(),
+            Some("hello\n")
+        );
+        assert_eq!(runner.run("rm -rf /nonexistent", Vec::new()), None);
+    }
+
+    #[test]
+    fn test_run_timeout() {
+        let runner = CommandRunner::new(EvalPolicy {
+            timeout: Duration::from_millis(100),
+            ..Default::default()
+        });
+        assert_eq!(runner.run("sleep 5", Vec::new()), None);
+    }
+}
@@ -27,6 +27,10 @@ pub struct FunctionChange {
     pub body: Option<String>,
     /// Total number of lines in the function body
     pub body_lines: usize,
+    /// Conditions under which this line runs (enclosing branches, guards
+    /// and the guards of the `source` commands that read the file)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub conditions: Vec<String>,
 }
 
 /// Represents the full trace of a function through the startup sequence
@@ -0,0 +1,308 @@
+//! Simulated host profile (`--simulate os=darwin,arch=arm64,...`)
+//!
+//! Dotfiles shared between machines branch on `$(uname)`, `$OSTYPE`,
+//! `$HOSTNAME` and friends. A profile supplies those inputs so the same files
+//! can be traced as they would run on another machine.
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::Serialize;
+
+/// Operating systems a profile can simulate: (name, `uname -s`, `$OSTYPE`)
+const SYSTEMS: &[(&str, &str, &str)] = &[
+    ("darwin", "Darwin", "darwin"),
+    ("linux", "Linux", "linux-gnu"),
+    ("freebsd", "FreeBSD", "freebsd"),
+];
+
+/// Values describing the machine the startup files are traced for
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct HostProfile {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub os: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub arch: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+}
+
+impl HostProfile {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    fn system(&self) -> Option<&'static (&'static str, &'static str, &'static str)> {
+        let os = self.os.as_deref()?;
+        SYSTEMS.iter().find(|(name, _, _)| *name == os)
+    }
+
+    /// The machine name `uname -m` reports (`arm64` on macOS, `aarch64` elsewhere)
+    fn machine(&self) -> Option<String> {
+        let arch = self.arch.as_deref()?;
+        let darwin = self.os.as_deref() == Some("darwin");
+        Some(match arch {
+            "arm64" | "aarch64" if darwin => "arm64".to_string(),
+            "arm64" | "aarch64" => "aarch64".to_string(),
+            "amd64" | "x86_64" => "x86_64".to_string(),
+            other => other.to_string(),
+        })
+    }
+
+    /// The OS and architecture of the machine envtrace runs on
+    fn local() -> Self {
+        let os = match std::env::consts::OS {
+            "macos" => "darwin",
+            os => os,
+        };
+        HostProfile {
+            os: Some(os.to_string()),
+            arch: Some(std::env::consts::ARCH.to_string()),
+            ..Default::default()
+        }
+    }
+
+    /// True if the profile describes another machine (a different OS,
+    /// architecture or hostname), whose files and programs can't be inspected
+    pub fn is_foreign(&self) -> bool {
+        let os = Self::local().os.unwrap_or_default();
+        let arch = |arch: &str| match arch {
+            "arm64" => "aarch64".to_string(),
+            arch => arch.to_string(),
+        };
+        self.os.as_deref().is_some_and(|o| o != os)
+            || self
+                .machine()
+                .is_some_and(|m| arch(&m) != arch(std::env::consts::ARCH))
+            || self
+                .hostname
+                .as_deref()
+                .is_some_and(|h| !is_local_hostname(h))
+    }
+
+    /// Shell variables the profile determines. OSTYPE and HOSTTYPE describe
+    /// the local machine unless the profile sets the OS or architecture.
+    pub fn variables(&self) -> Vec<(&'static str, String)> {
+        let local = Self::local();
+        let system = HostProfile {
+            os: self.os.clone().or(local.os),
+            arch: self.arch.clone().or(local.arch),
+            ..Default::default()
+        };
+        let mut vars = Vec::new();
+        if let Some((_, _, ostype)) = system.system() {
+            vars.push(("OSTYPE", ostype.to_string()));
+        }
+        if let Some(machine) = system.machine() {
+            vars.push(("HOSTTYPE", machine));
+        }
+        if let Some(ref hostname) = self.hostname {
+            vars.push(("HOSTNAME", hostname.clone()));
+            vars.push(("HOST", hostname.clone()));
+        }
+        if let Some(ref user) = self.user {
+            vars.push(("USER", user.clone()));
+            vars.push(("LOGNAME", user.clone()));
+        }
+        vars
+    }
+
+    /// The output of a command substitution the profile can answer, such as
+    /// `uname -s`, `hostname` or `whoami`
+    pub fn command_output(&self, command: &str) -> Option<String> {
+        if command.contains(|c: char| "|;&<>$`(".contains(c)) {
+            return None;
+        }
+        let mut words = command.split_whitespace();
+        let program = words.next()?;
+        let program = program.rsplit('/').next().unwrap_or(program);
+        let args: Vec<&str> = words.collect();
+
+        match (program, args.as_slice()) {
+            ("uname", _) => self.uname(&args),
+            ("arch", []) => match (self.os.as_deref(), self.machine()?.as_str()) {
+                (Some("darwin"), "x86_64") => Some("i386".to_string()),
+                (_, machine) => Some(machine.to_string()),
+            },
+            ("hostname", []) => self.hostname.clone(),
+            ("hostname", ["-s"]) => {
+                let hostname = self.hostname.as_deref()?;
+                Some(hostname.split('.').next().unwrap_or(hostname).to_string())
+            }
+            ("whoami", []) | ("id", ["-un" | "-nu"] | ["-u", "-n"] | ["-n", "-u"]) => {
+                self.user.clone()
+            }
+            _ => None,
+        }
+    }
+
+    /// `uname` with any combination of `-s`, `-n` and `-m`
+    fn uname(&self, args: &[&str]) -> Option<String> {
+        let mut flags = String::new();
+        for arg in args {
+            flags.push_str(arg.strip_prefix('-')?);
+        }
+        if flags.is_empty() {
+            flags.push('s');
+        }
+        if !flags.chars().all(|c| "snm".contains(c)) {
+            return None;
+        }
+
+        // uname prints fields in a fixed order regardless of the flag order
+        let mut fields = Vec::new();
+        if flags.contains('s') {
+            fields.push(self.system()?.1.to_string());
+        }
+        if flags.contains('n') {
+            fields.push(self.hostname.clone()?);
+        }
+        if flags.contains('m') {
+            fields.push(self.machine()?);
+        }
+        Some(fields.join(" "))
+    }
+}
+
+/// True if `name` is this machine's hostname (or its short form)
+fn is_local_hostname(name: &str) -> bool {
+    #[cfg(unix)]
+    if let Ok(local) = nix::unistd::gethostname() {
+        let local = local.to_string_lossy();
+        let short = |h: &str| h.split('.').next().unwrap_or(h).to_string();
+        return local == name || short(&local) == short(name);
+    }
+    let _ = name;
+    false
+}
+
+impl FromStr for HostProfile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut profile = HostProfile::default();
+        for item in s.split(',').filter(|item| !item.trim().is_empty()) {
+            let (key, value) = item
+                .split_once('=')
+                .ok_or_else(|| format!("expected key=value, got '{}'", item))?;
+            let value = value.trim().to_string();
+            match key.trim() {
+                "os" => {
+                    let os = match value.to_lowercase().as_str() {
+                        "macos" | "osx" => "darwin".to_string(),
+                        other => other.to_string(),
+                    };
+                    if !SYSTEMS.iter().any(|(name, _, _)| *name == os) {
+                        return Err(format!(
+                            "unknown os '{}' (expected darwin, linux or freebsd)",
+                            value
+                        ));
+                    }
+                    profile.os = Some(os);
+                }
+                "arch" => profile.arch = Some(value),
+                "hostname" | "host" => profile.hostname = Some(value),
+                "user" => profile.user = Some(value),
+                other => {
+                    return Err(format!(
+                        "unknown key '{}' (expected os, arch, hostname or user)",
+                        other
+                    ));
+                }
+            }
+        }
+        Ok(profile)
+    }
+}
+
+impl fmt::Display for HostProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fields = [
+            ("os", &self.os),
+            ("arch", &self.arch),
+            ("hostname", &self.hostname),
+            ("user", &self.user),
+        ];
+        let parts: Vec<String> = fields
+            .iter()
+            .filter_map(|(key, value)| value.as_ref().map(|v| format!("{}={}", key, v)))
+            .collect();
+        write!(f, "{}", parts.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_profile() {
+        let profile: HostProfile = "os=macos,arch=arm64,hostname=build01".parse().unwrap();
+        assert_eq!(profile.os.as_deref(), Some("darwin"));
+        assert_eq!(profile.to_string(), "os=darwin,arch=arm64,hostname=build01");
+        assert!("os=plan9".parse::<HostProfile>().is_err());
+        assert!("shell=zsh".parse::<HostProfile>().is_err());
+    }
+
+    #[test]
+    fn test_command_output() {
+        let profile: HostProfile = "os=linux,arch=arm64,hostname=build01.example.com,user=ci"
+            .parse()
+            .unwrap();
+        assert_eq!(profile.command_output("uname").as_deref(), Some("Linux"));
+        assert_eq!(
+            profile.command_output("uname -m").as_deref(),
+            Some("aarch64")
+        );
+        assert_eq!(
+            profile.command_output("uname -m -s").as_deref(),
+            Some("Linux aarch64")
+        );
+        assert_eq!(
+            profile.command_output("hostname -s").as_deref(),
+            Some("build01")
+        );
+        assert_eq!(profile.command_output("id -un").as_deref(), Some("ci"));
+        assert_eq!(profile.command_output("uname -r"), None);
+        assert_eq!(profile.command_output("uname | tr A-Z a-z"), None);
+
+        let empty = HostProfile::default();
+        assert_eq!(empty.command_output("uname"), None);
+    }
+
+    #[test]
+    fn test_foreign_profile() {
+        let other = if cfg!(target_os = "macos") {
+            "linux"
+        } else {
+            "darwin"
+        };
+        let profile: HostProfile = format!("os={}", other).parse().unwrap();
+        assert!(profile.is_foreign());
+        assert!(!HostProfile::default().is_foreign());
+        assert!(!"user=ci".parse::<HostProfile>().unwrap().is_foreign());
+    }
+
+    #[test]
+    fn test_local_variables() {
+        let value = |profile: &HostProfile, name: &str| {
+            let vars = profile.variables();
+            vars.into_iter().find(|(n, _)| *n == name).map(|(_, v)| v)
+        };
+        // Without a profile, $OSTYPE describes this machine
+        let local = HostProfile::default();
+        let ostype = value(&local, "OSTYPE");
+        if cfg!(target_os = "linux") {
+            assert_eq!(ostype.as_deref(), Some("linux-gnu"));
+        } else if cfg!(target_os = "macos") {
+            assert_eq!(ostype.as_deref(), Some("darwin"));
+        }
+        assert!(value(&local, "HOSTTYPE").is_some());
+
+        let profile: HostProfile = "os=darwin,arch=arm64".parse().unwrap();
+        assert_eq!(value(&profile, "OSTYPE").as_deref(), Some("darwin"));
+        assert_eq!(value(&profile, "HOSTTYPE").as_deref(), Some("arm64"));
+    }
+}
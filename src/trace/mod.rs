@@ -1,7 +1,16 @@
 mod engine;
+mod env;
+mod eval;
 pub mod function;
+mod host;
 mod variable;
 
 pub use engine::{TraceConfig, TraceEngine};
+pub use env::{SavedVariable, ShellEnv};
+pub use eval::EvalPolicy;
 pub use function::{FunctionChange, FunctionOperation, FunctionTrace};
-pub use variable::{Context, Operation, VariableChange, VariableTrace};
+pub use host::HostProfile;
+pub use variable::{
+    Context, Diagnostic, DiagnosticKind, FunctionCall, Operation, StopKind, StopPoint,
+    ValueCandidate, VariableChange, VariableTrace,
+};
@@ -2,6 +2,8 @@ use std::path::PathBuf;
 
 use serde::Serialize;
 
+use super::HostProfile;
+
 /// Represents a single modification to a variable
 #[derive(Debug, Clone, Serialize)]
 pub struct VariableChange {
@@ -11,6 +13,31 @@ pub struct VariableChange {
     pub operation: Operation,
     pub value_before: Option<String>,
     pub value_after: String,
+    /// Parts of the value that couldn't be resolved while tracing (e.g. `$(cmd)`)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub unresolved: Vec<String>,
+    /// Command substitutions that were run to get the value (`--eval-commands`)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub evaluated: Vec<String>,
+    /// Conditions of the branches the change sits in, outermost first
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub conditions: Vec<String>,
+    /// The function that made the change, when it was made by a call (the
+    /// file and line above are then the call site)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub function: Option<FunctionCall>,
+    /// The tool initializer the change was emulated from (`brew shellenv`,
+    /// `nvm.sh`, ...) rather than read from a file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub emulated: Option<String>,
+}
+
+/// A function whose body made a change, and where it is defined
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FunctionCall {
+    pub name: String,
+    pub file: PathBuf,
+    pub line_number: usize,
 }
 
 /// The type of operation performed on a variable
@@ -27,17 +54,110 @@ pub enum Operation {
     Prepend,
     /// unset VAR
     Unset,
+    /// export -n VAR or typeset +x VAR (keeps the value, stops exporting it)
+    Unexport,
     /// [ -f x ] && export VAR=y (conditional assignment)
     Conditional,
 }
 
+/// A `return`, `exit` or `exec` that stops reading startup files
+#[derive(Debug, Clone, Serialize)]
+pub struct StopPoint {
+    pub file: PathBuf,
+    pub line_number: usize,
+    pub line_content: String,
+    pub kind: StopKind,
+    /// Conditions of the branches the statement sits in, outermost first
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub conditions: Vec<String>,
+    /// Whether the statement may or may not run (its conditions couldn't be decided)
+    pub conditional: bool,
+}
+
+/// How a [`StopPoint`] stops processing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StopKind {
+    /// `return`: the rest of the current file is skipped
+    Return,
+    /// `exit`: the shell exits, so no later files are read
+    Exit,
+    /// `exec cmd`: the shell is replaced, so no later files are read
+    Exec,
+}
+
+/// A line whose effect on the trace couldn't be worked out
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Diagnostic {
+    pub file: PathBuf,
+    pub line_number: usize,
+    pub line_content: String,
+    pub kind: DiagnosticKind,
+    /// The references that couldn't be resolved
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub unresolved: Vec<String>,
+}
+
+/// What a [`Diagnostic`] reports
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiagnosticKind {
+    /// A `source`/`.` path depends on variables that aren't known, so the
+    /// file wasn't followed
+    UnresolvedSource,
+    /// `source <(cmd)`: the code read is the output of a command
+    SourcedCommandOutput,
+    /// `eval` of code that isn't known until run time
+    DynamicEval,
+    /// `export $(...)`: the names declared aren't known until run time
+    DynamicDeclaration,
+    /// `set -- ...` with values that aren't known, replacing `$1`, `$@`, ...
+    DynamicPositional,
+    /// `env -i`: a command runs with an empty environment
+    ClearedEnvironment,
+    /// `read`, `printf -v`, `mapfile` or `getopts` sets the variable to a
+    /// value only known at run time
+    RuntimeValue,
+    /// An assignment to a `readonly` variable, which the shell refuses
+    ReadonlyAssignment,
+    /// A function calls itself (directly or through others), so the call
+    /// wasn't followed
+    RecursiveCall,
+}
+
+/// One possible value of a variable and the undecided conditions it depends on
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ValueCandidate {
+    /// `None` if the variable is not set on this path
+    pub value: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub conditions: Vec<String>,
+}
+
 /// Represents the full trace of a variable through the startup sequence
 #[derive(Debug, Serialize)]
 pub struct VariableTrace {
     pub name: String,
-    pub final_value: Option<String>,
+    /// Possible final values; more than one when branches couldn't be decided,
+    /// empty when the variable ends up unset
+    pub final_value: Vec<ValueCandidate>,
+    /// Whether there were more possible final values than are kept
+    pub truncated: bool,
     pub changes: Vec<VariableChange>,
     pub context: Context,
+    /// Statements that stopped (or may have stopped) reading startup files
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub stops: Vec<StopPoint>,
+    /// Lines that couldn't be followed
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub diagnostics: Vec<Diagnostic>,
+    /// Whether the final value is passed to child processes; `None` if the
+    /// variable isn't set or it depends on undecided branches
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exported: Option<bool>,
+    /// The machine simulated with `--simulate`, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub simulated: Option<HostProfile>,
 }
 
 /// Shell context determines which files are sourced
@@ -59,6 +179,12 @@ pub enum Context {
     SystemdUser,
     /// UWSM Wayland compositor session
     Uwsm,
+    /// zsh interactive login shell on Linux
+    ZshInteractiveLogin,
+    /// zsh interactive non-login shell on Linux (new terminal window)
+    ZshInteractiveNonLogin,
+    /// zsh non-interactive shell on Linux (scripts)
+    ZshNonInteractive,
 
     // macOS contexts
     /// macOS interactive login shell (zsh default)
@@ -67,10 +193,47 @@ pub enum Context {
     MacInteractiveNonLogin,
     /// macOS non-interactive shell (scripts)
     MacNonInteractive,
+    /// macOS bash interactive login shell
+    MacBashInteractiveLogin,
+    /// macOS bash interactive non-login shell
+    MacBashInteractiveNonLogin,
+    /// macOS bash non-interactive shell (scripts)
+    MacBashNonInteractive,
     /// macOS launchd agent (GUI apps, user services)
     LaunchdAgent,
     /// macOS launchd daemon (system services)
     LaunchdDaemon,
+
+    // fish contexts (either platform)
+    /// fish interactive login shell
+    FishInteractiveLogin,
+    /// fish interactive non-login shell
+    FishInteractiveNonLogin,
+    /// fish non-interactive shell (scripts)
+    FishNonInteractive,
+
+    // Nushell contexts (either platform)
+    /// Nushell interactive login shell
+    NuInteractiveLogin,
+    /// Nushell interactive non-login shell
+    NuInteractiveNonLogin,
+    /// Nushell non-interactive shell (scripts, `nu -c`)
+    NuNonInteractive,
+}
+
+impl VariableTrace {
+    /// The final value, if it is set and doesn't depend on undecided branches
+    pub fn value(&self) -> Option<&str> {
+        match self.final_value.as_slice() {
+            [only] => only.value.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Returns true if the final value depends on branches that couldn't be decided
+    pub fn is_ambiguous(&self) -> bool {
+        self.final_value.len() > 1
+    }
 }
 
 impl Context {
@@ -81,11 +244,60 @@ impl Context {
             Context::MacInteractiveLogin
                 | Context::MacInteractiveNonLogin
                 | Context::MacNonInteractive
+                | Context::MacBashInteractiveLogin
+                | Context::MacBashInteractiveNonLogin
+                | Context::MacBashNonInteractive
                 | Context::LaunchdAgent
                 | Context::LaunchdDaemon
         )
     }
 
+    /// Returns true if the shell reads its interactive startup files
+    pub fn is_interactive(&self) -> bool {
+        matches!(
+            self,
+            Context::InteractiveLogin
+                | Context::InteractiveNonLogin
+                | Context::ZshInteractiveLogin
+                | Context::ZshInteractiveNonLogin
+                | Context::MacInteractiveLogin
+                | Context::MacInteractiveNonLogin
+                | Context::MacBashInteractiveLogin
+                | Context::MacBashInteractiveNonLogin
+                | Context::FishInteractiveLogin
+                | Context::FishInteractiveNonLogin
+                | Context::NuInteractiveLogin
+                | Context::NuInteractiveNonLogin
+        )
+    }
+
+    /// Returns true if the shell is a login shell
+    pub fn is_login(&self) -> bool {
+        matches!(
+            self,
+            Context::InteractiveLogin
+                | Context::NonInteractiveLogin
+                | Context::ZshInteractiveLogin
+                | Context::MacInteractiveLogin
+                | Context::MacBashInteractiveLogin
+                | Context::FishInteractiveLogin
+                | Context::NuInteractiveLogin
+        )
+    }
+
+    /// Returns true if the shell is bash (which sets `$BASH`)
+    pub fn is_bash(&self) -> bool {
+        matches!(
+            self,
+            Context::InteractiveLogin
+                | Context::InteractiveNonLogin
+                | Context::NonInteractiveLogin
+                | Context::MacBashInteractiveLogin
+                | Context::MacBashInteractiveNonLogin
+                | Context::MacBashNonInteractive
+        )
+    }
+
     /// Returns true if this is a Linux-specific context
     pub fn is_linux(&self) -> bool {
         !self.is_macos()
@@ -101,20 +313,23 @@ impl Context {
             Context::SystemdService => "systemd system service",
             Context::SystemdUser => "systemd user service",
             Context::Uwsm => "UWSM Wayland session",
+            Context::ZshInteractiveLogin => "zsh interactive login shell",
+            Context::ZshInteractiveNonLogin => "zsh interactive non-login shell",
+            Context::ZshNonInteractive => "zsh non-interactive shell",
             Context::MacInteractiveLogin => "zsh interactive login shell",
             Context::MacInteractiveNonLogin => "zsh interactive non-login shell",
             Context::MacNonInteractive => "zsh non-interactive shell",
+            Context::MacBashInteractiveLogin => "bash interactive login shell",
+            Context::MacBashInteractiveNonLogin => "bash interactive non-login shell",
+            Context::MacBashNonInteractive => "bash non-interactive shell",
             Context::LaunchdAgent => "launchd agent (GUI apps)",
             Context::LaunchdDaemon => "launchd daemon (system service)",
-        }
-    }
-
-    /// Returns the default context for the current platform
-    pub fn default_for_platform() -> Self {
-        if cfg!(target_os = "macos") {
-            Context::MacInteractiveLogin
-        } else {
-            Context::InteractiveLogin
+            Context::FishInteractiveLogin => "fish interactive login shell",
+            Context::FishInteractiveNonLogin => "fish interactive non-login shell",
+            Context::FishNonInteractive => "fish non-interactive shell",
+            Context::NuInteractiveLogin => "nushell interactive login shell",
+            Context::NuInteractiveNonLogin => "nushell interactive non-login shell",
+            Context::NuNonInteractive => "nushell non-interactive shell",
         }
     }
 }
@@ -125,6 +340,16 @@ impl std::fmt::Display for Context {
     }
 }
 
+impl std::fmt::Display for StopKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StopKind::Return => write!(f, "return"),
+            StopKind::Exit => write!(f, "exit"),
+            StopKind::Exec => write!(f, "exec"),
+        }
+    }
+}
+
 impl std::fmt::Display for Operation {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -133,6 +358,7 @@ impl std::fmt::Display for Operation {
             Operation::Append => write!(f, "append"),
             Operation::Prepend => write!(f, "prepend"),
             Operation::Unset => write!(f, "unset"),
+            Operation::Unexport => write!(f, "unexport"),
             Operation::Conditional => write!(f, "conditional"),
         }
     }